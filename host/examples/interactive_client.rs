@@ -8,7 +8,8 @@ use camino::Utf8PathBuf;
 use colored::Colorize;
 use futures_util::{SinkExt, StreamExt};
 use tapestry_host::{
-    Request, RequestPayload, Response, ResponsePayload, codec::NativeMessagingCodec,
+    OutputFormat, Request, RequestPayload, Response, ResponsePayload, StreamMode,
+    codec::NativeMessagingCodec,
 };
 use tokio::process::{Child, ChildStdin, ChildStdout, Command};
 use tokio_util::codec::{FramedRead, FramedWrite};
@@ -189,12 +190,33 @@ impl ClientState {
         let request = Request {
             id: Uuid::new_v4(),
             path: self.path.clone(),
+            validate_only: false,
             payload: RequestPayload::ProcessContent {
-                content: self.content.clone(),
+                content: self.content.clone().into(),
                 model: self.model.clone(),
                 pattern: self.pattern.clone(),
                 context: None,
                 custom_prompt: self.custom_prompt.clone(),
+                system_prompt: None,
+                locale: None,
+                detach: false,
+                aggregate: false,
+                summarize: false,
+                summary_model: None,
+                output_format: OutputFormat::Markdown,
+                deterministic: false,
+                quick_look: None,
+                selection: None,
+                composition: None,
+                stream_mode: StreamMode::Lines,
+                stream_flush_ms: None,
+                stream_chunk_bytes: None,
+                strict: None,
+                structured: false,
+                timeout_ms: None,
+                heartbeat_ms: None,
+                cwd: None,
+                env: None,
             },
         };
 
@@ -212,9 +234,27 @@ impl ClientState {
                     println!("{content}");
                 }
                 Ok(Response {
-                    payload: ResponsePayload::Done { exit_code },
+                    payload: ResponsePayload::StderrLine { line },
                     ..
                 }) => {
+                    eprint!("{}", line.yellow());
+                }
+                Ok(Response {
+                    payload:
+                        ResponsePayload::Done {
+                            exit_code,
+                            stderr_tail,
+                            error,
+                            ..
+                        },
+                    ..
+                }) => {
+                    if let Some(tail) = stderr_tail {
+                        eprint!("{}", tail.yellow());
+                    }
+                    if let Some(hint) = error.and_then(|error| error.hint) {
+                        println!("  {}", hint.dimmed());
+                    }
                     match exit_code {
                         Some(0) | None => {
                             println!("{}", "✓ Completed successfully".green());
@@ -226,12 +266,28 @@ impl ClientState {
                     break;
                 }
                 Ok(Response {
-                    payload: ResponsePayload::Error { message },
+                    payload: ResponsePayload::Error { message, details },
                     ..
                 }) => {
                     println!("{} {}", "✗ Error:".red(), message);
+                    if let Some(hint) = details.and_then(|details| details.hint) {
+                        println!("  {}", hint.dimmed());
+                    }
                     break;
                 }
+                Ok(Response {
+                    payload: ResponsePayload::SetupRequired { instructions, .. },
+                    ..
+                }) => {
+                    println!("{} {}", "⚠ Setup required:".yellow(), instructions);
+                    break;
+                }
+                Ok(Response {
+                    payload: ResponsePayload::Warning { message },
+                    ..
+                }) => {
+                    println!("{} {}", "⚠ Warning:".yellow(), message);
+                }
                 Ok(Response {
                     payload: ResponsePayload::Cancelled { request_id },
                     ..
@@ -247,7 +303,38 @@ impl ClientState {
                     payload:
                         ResponsePayload::PatternsList { .. }
                         | ResponsePayload::Pong { .. }
-                        | ResponsePayload::ContextsList { .. },
+                        | ResponsePayload::HelloAck { .. }
+                        | ResponsePayload::ChunkAccepted { .. }
+                        | ResponsePayload::JobEvent(_)
+                        | ResponsePayload::Notification(_)
+                        | ResponsePayload::HeartbeatAck { .. }
+                        | ResponsePayload::ShutdownAck
+                        | ResponsePayload::ConfigReloaded
+                        | ResponsePayload::PinnedPatterns { .. }
+                        | ResponsePayload::SecretNames { .. }
+                        | ResponsePayload::Metrics(_)
+                        | ResponsePayload::DebugState(_)
+                        | ResponsePayload::StoreInfo { .. }
+                        | ResponsePayload::FabricChecksum { .. }
+                        | ResponsePayload::Config { .. }
+                        | ResponsePayload::Heartbeat { .. }
+                        | ResponsePayload::HostHeartbeat { .. }
+                        | ResponsePayload::HostReady(_)
+                        | ResponsePayload::ValidationResult { .. }
+                        | ResponsePayload::PartialResult { .. }
+                        | ResponsePayload::Json { .. }
+                        | ResponsePayload::EditorOpened { .. }
+                        | ResponsePayload::ContextsList { .. }
+                        | ResponsePayload::Progress { .. }
+                        | ResponsePayload::Queued { .. }
+                        | ResponsePayload::StreamStart { .. }
+                        | ResponsePayload::BatchItem { .. }
+                        | ResponsePayload::BatchDone { .. }
+                        | ResponsePayload::JobAccepted { .. }
+                        | ResponsePayload::JobResult { .. }
+                        | ResponsePayload::JobsList { .. }
+                        | ResponsePayload::OrphanedJobs { .. }
+                        | ResponsePayload::JobDeleted { .. },
                     ..
                 }) => {}
                 Err(e) => {
@@ -276,6 +363,7 @@ impl ClientState {
         let request = Request {
             id: Uuid::new_v4(),
             path: self.path.clone(),
+            validate_only: false,
             payload: RequestPayload::ListPatterns,
         };
 
@@ -284,7 +372,12 @@ impl ClientState {
         if let Some(response) = self.reader.next().await {
             match response {
                 Ok(Response {
-                    payload: ResponsePayload::PatternsList { patterns },
+                    payload:
+                        ResponsePayload::PatternsList {
+                            patterns,
+                            aliases,
+                            pinned,
+                        },
                     ..
                 }) => {
                     if patterns.is_empty() {
@@ -293,13 +386,20 @@ impl ClientState {
                         println!("\n{}", "Available Patterns:".green().bold());
                         println!("{}", "─".repeat(30).dimmed());
                         for pattern in patterns {
-                            println!("  • {}", pattern.bright_white());
+                            if pinned.contains(&pattern) {
+                                println!("  ★ {}", pattern.bright_white().bold());
+                            } else {
+                                println!("  • {}", pattern.bright_white());
+                            }
+                        }
+                        for (alias, pattern) in aliases {
+                            println!("  • {} → {}", alias.bright_white(), pattern.dimmed());
                         }
                         println!("{}", "─".repeat(30).dimmed());
                     }
                 }
                 Ok(Response {
-                    payload: ResponsePayload::Error { message },
+                    payload: ResponsePayload::Error { message, .. },
                     ..
                 }) => {
                     println!("{} {}", "✗ Error:".red(), message);
@@ -322,6 +422,7 @@ impl ClientState {
         let request = Request {
             id: Uuid::new_v4(),
             path: None,
+            validate_only: false,
             payload: RequestPayload::Ping,
         };
 
@@ -333,8 +434,11 @@ impl ClientState {
                     payload:
                         ResponsePayload::Pong {
                             resolved_path,
+                            binary,
                             version,
                             valid,
+                            stats,
+                            ..
                         },
                     ..
                 }) => {
@@ -343,9 +447,20 @@ impl ClientState {
                         if let Some(path) = resolved_path {
                             println!("  Path: {}", path.dimmed());
                         }
+                        if let Some(binary) = binary {
+                            println!("  Binary: {}", binary.dimmed());
+                        }
                         if let Some(ver) = version {
                             println!("  Version: {}", ver.dimmed());
                         }
+                        if let Some(stats) = stats {
+                            println!(
+                                "  Uptime: {}s, active: {}, queued: {}",
+                                stats.uptime_ms / 1000,
+                                stats.active_requests,
+                                stats.queued_requests
+                            );
+                        }
                     } else {
                         println!("{}", "✗ Pong received but Fabric validation failed".red());
                         if let Some(path) = resolved_path {
@@ -354,7 +469,7 @@ impl ClientState {
                     }
                 }
                 Ok(Response {
-                    payload: ResponsePayload::Error { message },
+                    payload: ResponsePayload::Error { message, .. },
                     ..
                 }) => {
                     println!("{} {}", "✗ Error:".red(), message);
@@ -0,0 +1,103 @@
+use bytes::BytesMut;
+use criterion::{BatchSize, Criterion, Throughput, criterion_group, criterion_main};
+use tapestry_protocol::{
+    Compression, OutputFormat, Request, RequestPayload, StreamMode,
+    codec::{ContentCompression, MAX_INBOUND_MESSAGE_SIZE, NativeMessagingCodec},
+};
+use tokio_util::codec::{Decoder, Encoder};
+use uuid::Uuid;
+
+const PAGE_SIZE: usize = 512 * 1024;
+
+fn page_request(content: String) -> Request {
+    Request {
+        id: Uuid::new_v4(),
+        path: None,
+        validate_only: false,
+        payload: RequestPayload::ProcessContent {
+            content: content.into(),
+            model: None,
+            pattern: Some("summarize".to_string()),
+            context: None,
+            custom_prompt: None,
+            system_prompt: None,
+            locale: None,
+            detach: false,
+            aggregate: false,
+            summarize: false,
+            summary_model: None,
+            output_format: OutputFormat::Markdown,
+            deterministic: false,
+            quick_look: None,
+            selection: None,
+            composition: None,
+            stream_mode: StreamMode::Lines,
+            stream_flush_ms: None,
+            stream_chunk_bytes: None,
+            strict: None,
+            structured: false,
+            timeout_ms: None,
+            heartbeat_ms: None,
+            cwd: None,
+            env: None,
+        },
+    }
+}
+
+fn plain_page() -> String {
+    "Lorem ipsum dolor sit amet, consectetur adipiscing elit. "
+        .chars()
+        .cycle()
+        .take(PAGE_SIZE)
+        .collect()
+}
+
+fn escaped_page() -> String {
+    "He said \"stop\".\n\tThen: C:\\path\\to\\file.\n"
+        .chars()
+        .cycle()
+        .take(PAGE_SIZE)
+        .collect()
+}
+
+fn codec(compressed: bool) -> NativeMessagingCodec<Request> {
+    let compression = ContentCompression::default();
+    compression.set(compressed.then_some(Compression::Gzip));
+    NativeMessagingCodec::new(MAX_INBOUND_MESSAGE_SIZE).with_compression(compression)
+}
+
+fn frame(content: String, compressed: bool) -> BytesMut {
+    let mut frame = BytesMut::new();
+    codec(compressed)
+        .encode(page_request(content), &mut frame)
+        .unwrap();
+    frame
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+    group.throughput(Throughput::Bytes(PAGE_SIZE as u64));
+
+    let cases = [
+        ("plain", plain_page(), false, false),
+        ("escaped", escaped_page(), false, false),
+        ("negotiated_uncompressed", plain_page(), false, true),
+        ("gzip", plain_page(), true, true),
+    ];
+    for (name, content, compressed_frame, negotiated) in cases {
+        let frame = frame(content, compressed_frame);
+        let mut codec = codec(negotiated);
+        group.bench_function(name, |b| {
+            b.iter_batched_ref(
+                || frame.clone(),
+                |src| codec.decode(src).unwrap().unwrap(),
+                BatchSize::LargeInput,
+            );
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_decode);
+criterion_main!(benches);
@@ -0,0 +1,41 @@
+use bytes::BytesMut;
+use criterion::{BatchSize, Criterion, Throughput, criterion_group, criterion_main};
+use tapestry_protocol::{Response, ResponsePayload, codec::NativeMessagingCodec};
+use tokio_util::codec::Encoder;
+use uuid::Uuid;
+
+const LINES: usize = 1000;
+
+fn bench_encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode");
+    group.throughput(Throughput::Elements(LINES as u64));
+
+    let id = Uuid::new_v4();
+    let lines: Vec<Response> = (0..LINES)
+        .map(|index| Response {
+            id,
+            payload: ResponsePayload::Content {
+                content: format!("- Point {index}: the quick brown fox jumps over the lazy dog\n"),
+            },
+        })
+        .collect();
+    let mut codec = NativeMessagingCodec::<Response>::default();
+    group.bench_function("streamed_content", |b| {
+        b.iter_batched(
+            || lines.clone(),
+            |lines| {
+                let mut dst = BytesMut::new();
+                for line in lines {
+                    codec.encode(line, &mut dst).unwrap();
+                }
+                dst
+            },
+            BatchSize::SmallInput,
+        );
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_encode);
+criterion_main!(benches);
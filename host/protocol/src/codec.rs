@@ -0,0 +1,1183 @@
+use std::{
+    io::{self, Read, Write},
+    marker::PhantomData,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use flate2::{Compression as Level, read::GzDecoder, write::GzEncoder};
+use memchr::memmem;
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::Value;
+use thiserror::Error;
+use tokio_util::codec::{Decoder, Encoder};
+use uuid::Uuid;
+
+use crate::{
+    Compression,
+    trace::{Direction, ProtocolTracer},
+};
+
+pub const MAX_OUTBOUND_MESSAGE_SIZE: usize = 1024 * 1024;
+pub const MAX_INBOUND_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+pub const SUPPORTED_COMPRESSION: &[Compression] = &[Compression::Gzip];
+const CONTENT_TYPE: &str = "native.content";
+const CONTENT_FIELD: &str = "content";
+const ENCODING_FIELD: &str = "encoding";
+const GZIP_ENCODING: &str = "gzip";
+const GZIP_OVERHEAD: usize = 32;
+const DEFLATE_BLOCK_SIZE: usize = 65535;
+
+#[derive(Debug, Error)]
+pub enum CodecError {
+    #[error("IO error")]
+    IoError(#[from] io::Error),
+    #[error("Message size {size} exceeds limit {limit}")]
+    MessageTooLarge { size: usize, limit: usize },
+    #[error("Serialization error: {0}")]
+    SerdeError(#[from] serde_json::Error),
+    #[error("Invalid message length bytes")]
+    InvalidMessageLength,
+    #[error("Invalid compressed content")]
+    InvalidCompressedContent,
+    #[cfg(feature = "msgpack")]
+    #[error("MessagePack encoding error: {0}")]
+    MessagePackEncode(#[from] rmp_serde::encode::Error),
+    #[cfg(feature = "msgpack")]
+    #[error("MessagePack decoding error: {0}")]
+    MessagePackDecode(#[from] rmp_serde::decode::Error),
+}
+
+pub trait Format {
+    const MAX_STRING_HEADER: usize = 0;
+
+    fn to_writer<W: Write, T: Serialize>(writer: W, value: &T) -> Result<(), CodecError>;
+    fn from_slice<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CodecError>;
+
+    fn to_vec<T: Serialize>(value: &T) -> Result<Vec<u8>, CodecError> {
+        let mut bytes = Vec::new();
+        Self::to_writer(&mut bytes, value)?;
+        Ok(bytes)
+    }
+
+    fn char_len(c: char) -> usize {
+        c.len_utf8()
+    }
+}
+
+pub struct Json;
+
+impl Format for Json {
+    fn to_writer<W: Write, T: Serialize>(writer: W, value: &T) -> Result<(), CodecError> {
+        Ok(serde_json::to_writer(writer, value)?)
+    }
+
+    fn from_slice<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CodecError> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+
+    fn char_len(c: char) -> usize {
+        escaped_len(c)
+    }
+}
+
+#[cfg(feature = "msgpack")]
+pub struct MessagePack;
+
+#[cfg(feature = "msgpack")]
+impl Format for MessagePack {
+    const MAX_STRING_HEADER: usize = 4;
+
+    fn to_writer<W: Write, T: Serialize>(writer: W, value: &T) -> Result<(), CodecError> {
+        value.serialize(
+            &mut rmp_serde::Serializer::new(writer)
+                .with_struct_map()
+                .with_human_readable(),
+        )?;
+        Ok(())
+    }
+
+    fn from_slice<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CodecError> {
+        Ok(T::deserialize(
+            &mut rmp_serde::Deserializer::from_read_ref(bytes).with_human_readable(),
+        )?)
+    }
+}
+
+pub type NativeMessagingCodec<T> = FramedCodec<T, Json>;
+#[cfg(feature = "msgpack")]
+pub type MessagePackCodec<T> = FramedCodec<T, MessagePack>;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ByteOrder {
+    Little,
+    Big,
+    #[default]
+    Native,
+}
+
+impl ByteOrder {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "le" | "little" => Some(Self::Little),
+            "be" | "big" => Some(Self::Big),
+            "native" => Some(Self::Native),
+            _ => None,
+        }
+    }
+
+    fn encode(self, length: u32) -> [u8; 4] {
+        match self {
+            Self::Little => length.to_le_bytes(),
+            Self::Big => length.to_be_bytes(),
+            Self::Native => length.to_ne_bytes(),
+        }
+    }
+
+    fn decode(self, bytes: [u8; 4]) -> u32 {
+        match self {
+            Self::Little => u32::from_le_bytes(bytes),
+            Self::Big => u32::from_be_bytes(bytes),
+            Self::Native => u32::from_ne_bytes(bytes),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MessageSizeLimit(Arc<AtomicUsize>);
+
+impl MessageSizeLimit {
+    pub fn new(size: usize) -> Self {
+        Self(Arc::new(AtomicUsize::new(size)))
+    }
+
+    pub fn get(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn set(&self, size: usize) {
+        self.0.store(size, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ContentCompression(Arc<AtomicBool>);
+
+impl ContentCompression {
+    pub fn get(&self) -> Option<Compression> {
+        self.0.load(Ordering::Relaxed).then_some(Compression::Gzip)
+    }
+
+    pub fn set(&self, compression: Option<Compression>) {
+        self.0
+            .store(compression == Some(Compression::Gzip), Ordering::Relaxed);
+    }
+}
+
+pub struct FramedCodec<T, F> {
+    max_message_size: MessageSizeLimit,
+    size_hint: usize,
+    compression: ContentCompression,
+    byte_order: ByteOrder,
+    tracer: Option<Arc<ProtocolTracer>>,
+    _phantom: PhantomData<(T, F)>,
+}
+
+impl<T, F> FramedCodec<T, F> {
+    pub fn new(max_message_size: usize) -> Self {
+        Self {
+            max_message_size: MessageSizeLimit::new(max_message_size),
+            size_hint: 0,
+            compression: ContentCompression::default(),
+            byte_order: ByteOrder::default(),
+            tracer: None,
+            _phantom: PhantomData,
+        }
+    }
+
+    pub fn size_limit(&self) -> MessageSizeLimit {
+        self.max_message_size.clone()
+    }
+
+    pub fn with_compression(mut self, compression: ContentCompression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn with_byte_order(mut self, byte_order: ByteOrder) -> Self {
+        self.byte_order = byte_order;
+        self
+    }
+
+    pub fn with_tracer(mut self, tracer: Option<Arc<ProtocolTracer>>) -> Self {
+        self.tracer = tracer;
+        self
+    }
+}
+
+// Clones share the size limit, compression, and tracer, so each writer can
+// encode with its own codec while negotiation still applies to all of them.
+impl<T, F> Clone for FramedCodec<T, F> {
+    fn clone(&self) -> Self {
+        Self {
+            max_message_size: self.max_message_size.clone(),
+            size_hint: 0,
+            compression: self.compression.clone(),
+            byte_order: self.byte_order,
+            tracer: self.tracer.clone(),
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<T, F> Default for FramedCodec<T, F> {
+    fn default() -> Self {
+        Self::new(MAX_OUTBOUND_MESSAGE_SIZE)
+    }
+}
+
+impl<T, F> Encoder<T> for FramedCodec<T, F>
+where
+    T: Serialize,
+    F: Format,
+{
+    type Error = CodecError;
+
+    fn encode(&mut self, item: T, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let start = dst.len();
+        dst.reserve(4 + self.size_hint);
+        dst.put_slice(&[0; 4]);
+        if let Err(e) = F::to_writer(dst.writer(), &item) {
+            dst.truncate(start);
+            return Err(e);
+        }
+        let encoded_len = dst.len() - start - 4;
+        self.size_hint = encoded_len.min(self.max_message_size.get());
+
+        let limit = self.max_message_size.get();
+        let compressed = self.compression.get().is_some();
+        if !compressed && encoded_len <= limit {
+            #[allow(clippy::cast_possible_truncation)]
+            let length = encoded_len as u32;
+            dst[start..start + 4].copy_from_slice(&self.byte_order.encode(length));
+            self.trace(Direction::Outbound, &dst[start + 4..]);
+            return Ok(());
+        }
+
+        let encoded = dst.split_off(start + 4);
+        dst.truncate(start);
+        let message = compressed
+            .then(|| compress_message::<F>(&encoded))
+            .flatten()
+            .filter(|message| message.len() < encoded.len());
+        let message = message.as_deref().unwrap_or(&encoded);
+
+        if message.len() <= limit {
+            self.write_frame(message, dst);
+            return Ok(());
+        }
+
+        let frames = split_content::<F>(&encoded, limit, compressed)
+            .filter(|frames| frames.iter().all(|frame| frame.len() <= limit))
+            .ok_or(CodecError::MessageTooLarge {
+                size: message.len(),
+                limit,
+            })?;
+        for frame in frames {
+            self.write_frame(&frame, dst);
+        }
+
+        Ok(())
+    }
+}
+
+impl<T, F: Format> FramedCodec<T, F> {
+    fn write_frame(&self, frame: &[u8], dst: &mut BytesMut) {
+        self.trace(Direction::Outbound, frame);
+
+        #[allow(clippy::cast_possible_truncation)]
+        let length = frame.len() as u32;
+        dst.put_slice(&self.byte_order.encode(length));
+        dst.put_slice(frame);
+    }
+
+    fn trace(&self, direction: Direction, frame: &[u8]) {
+        if let Some(tracer) = &self.tracer {
+            tracer.record_frame(direction, frame.len(), F::from_slice(frame).ok());
+        }
+    }
+}
+
+fn split_content<F: Format>(
+    encoded: &[u8],
+    limit: usize,
+    compressed: bool,
+) -> Option<Vec<Vec<u8>>> {
+    let mut message: Value = F::from_slice(encoded).ok()?;
+    if message.get("type")?.as_str()? != CONTENT_TYPE {
+        return None;
+    }
+
+    let content = message.get_mut(CONTENT_FIELD)?.take();
+    let content = content.as_str()?;
+    message[CONTENT_FIELD] = Value::String(String::new());
+    if compressed {
+        message[ENCODING_FIELD] = Value::String(GZIP_ENCODING.to_string());
+    }
+    let mut budget = limit.checked_sub(F::to_vec(&message).ok()?.len() + F::MAX_STRING_HEADER)?;
+    if compressed {
+        budget =
+            (budget / 4 * 3).checked_sub(GZIP_OVERHEAD + 5 * (budget / DEFLATE_BLOCK_SIZE + 1))?;
+    }
+
+    let mut frames = Vec::new();
+    let mut chunk = String::new();
+    let mut chunk_size = 0;
+    let mut push_frame = |chunk: String| -> Option<()> {
+        message[CONTENT_FIELD] = Value::String(if compressed { gzip(&chunk)? } else { chunk });
+        frames.push(F::to_vec(&message).ok()?);
+        Some(())
+    };
+    for c in content.chars() {
+        let size = if compressed {
+            c.len_utf8()
+        } else {
+            F::char_len(c)
+        };
+        if size > budget {
+            return None;
+        }
+        if chunk_size + size > budget {
+            push_frame(std::mem::take(&mut chunk))?;
+            chunk_size = 0;
+        }
+        chunk.push(c);
+        chunk_size += size;
+    }
+    push_frame(chunk)?;
+
+    Some(frames)
+}
+
+fn compress_message<F: Format>(encoded: &[u8]) -> Option<Vec<u8>> {
+    let mut message: Value = F::from_slice(encoded).ok()?;
+    let content = message.get(CONTENT_FIELD)?.as_str()?;
+    message[CONTENT_FIELD] = Value::String(gzip(content)?);
+    message[ENCODING_FIELD] = Value::String(GZIP_ENCODING.to_string());
+    F::to_vec(&message).ok()
+}
+
+fn decompress_message<F: Format>(encoded: &[u8], limit: usize) -> Result<Value, CodecError> {
+    let mut message: Value = F::from_slice(encoded)?;
+    let Some(object) = message.as_object_mut() else {
+        return Ok(message);
+    };
+    match object.remove(ENCODING_FIELD) {
+        None => Ok(message),
+        Some(Value::String(encoding)) if encoding == GZIP_ENCODING => {
+            let content = object
+                .get(CONTENT_FIELD)
+                .and_then(Value::as_str)
+                .and_then(|content| gunzip(content, limit))
+                .ok_or(CodecError::InvalidCompressedContent)?;
+            object.insert(CONTENT_FIELD.to_string(), Value::String(content));
+            Ok(message)
+        }
+        Some(_) => Err(CodecError::InvalidCompressedContent),
+    }
+}
+
+fn gzip(text: &str) -> Option<String> {
+    let mut encoder = GzEncoder::new(Vec::new(), Level::default());
+    encoder.write_all(text.as_bytes()).ok()?;
+    Some(STANDARD.encode(encoder.finish().ok()?))
+}
+
+fn gunzip(encoded: &str, limit: usize) -> Option<String> {
+    let compressed = STANDARD.decode(encoded).ok()?;
+    let mut text = String::new();
+    GzDecoder::new(compressed.as_slice())
+        .take(limit as u64 + 1)
+        .read_to_string(&mut text)
+        .ok()?;
+    (text.len() <= limit).then_some(text)
+}
+
+fn escaped_len(c: char) -> usize {
+    match c {
+        '"' | '\\' | '\n' | '\r' | '\t' | '\u{08}' | '\u{0c}' => 2,
+        '\0'..='\u{1f}' => 6,
+        _ => c.len_utf8(),
+    }
+}
+
+impl<T, F> Decoder for FramedCodec<T, F>
+where
+    T: DeserializeOwned,
+    F: Format,
+{
+    type Item = T;
+    type Error = CodecError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self.next_frame(src)? {
+            Some(frame) => self.parse(&frame).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+impl<T, F> FramedCodec<T, F>
+where
+    T: DeserializeOwned,
+    F: Format,
+{
+    pub fn recovering(self) -> RecoveringCodec<T, F> {
+        RecoveringCodec {
+            codec: self,
+            discarding: 0,
+        }
+    }
+
+    fn next_frame(&mut self, src: &mut BytesMut) -> Result<Option<Bytes>, CodecError> {
+        if src.len() < 4 {
+            return Ok(None);
+        }
+
+        let length_bytes: [u8; 4] = src[0..4].try_into().unwrap();
+        let message_length = self.byte_order.decode(length_bytes) as usize;
+
+        let limit = self.max_message_size.get();
+        if message_length > limit {
+            return Err(CodecError::MessageTooLarge {
+                size: message_length,
+                limit,
+            });
+        }
+
+        let total_length = 4 + message_length;
+        if src.len() < total_length {
+            return Ok(None);
+        }
+
+        src.advance(4);
+        let frame = src.split_to(message_length).freeze();
+        self.trace(Direction::Inbound, &frame);
+        Ok(Some(frame))
+    }
+
+    fn parse(&self, frame: &[u8]) -> Result<T, CodecError> {
+        if self.compression.get().is_some()
+            && memmem::find(frame, ENCODING_FIELD.as_bytes()).is_some()
+        {
+            let limit = self.max_message_size.get();
+            Ok(serde_json::from_value(decompress_message::<F>(
+                frame, limit,
+            )?)?)
+        } else {
+            F::from_slice(frame)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MalformedFrame {
+    pub id: Option<Uuid>,
+    pub size: usize,
+    pub error: String,
+    pub limit: Option<usize>,
+}
+
+pub struct RecoveringCodec<T, F> {
+    codec: FramedCodec<T, F>,
+    discarding: usize,
+}
+
+impl<T, F> RecoveringCodec<T, F> {
+    pub fn inner(&self) -> &FramedCodec<T, F> {
+        &self.codec
+    }
+}
+
+fn peek_id(prefix: &[u8]) -> Option<Uuid> {
+    let start = memmem::find(prefix, br#""id""#)? + 4;
+    let rest = &prefix[start..];
+    let quote = rest
+        .iter()
+        .position(|&byte| !matches!(byte, b' ' | b'\t' | b'\n' | b'\r' | b':'))?;
+    if rest[quote] != b'"' {
+        return None;
+    }
+    let id = rest.get(quote + 1..quote + 37)?;
+    Uuid::try_parse_ascii(id).ok()
+}
+
+impl<T, F> Decoder for RecoveringCodec<T, F>
+where
+    T: DeserializeOwned,
+    F: Format,
+{
+    type Item = Result<T, MalformedFrame>;
+    type Error = CodecError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if self.discarding > 0 {
+            let skipped = self.discarding.min(src.len());
+            src.advance(skipped);
+            self.discarding -= skipped;
+            if self.discarding > 0 {
+                return Ok(None);
+            }
+        }
+
+        let frame = match self.codec.next_frame(src) {
+            Ok(Some(frame)) => frame,
+            Ok(None) => return Ok(None),
+            Err(e @ CodecError::MessageTooLarge { size, limit }) => {
+                src.advance(4);
+                self.discarding = size;
+                return Ok(Some(Err(MalformedFrame {
+                    id: peek_id(&src[..size.min(src.len())]),
+                    size,
+                    error: e.to_string(),
+                    limit: Some(limit),
+                })));
+            }
+            Err(e) => return Err(e),
+        };
+
+        Ok(Some(self.codec.parse(&frame).map_err(|e| {
+            MalformedFrame {
+                id: F::from_slice::<Value>(&frame)
+                    .ok()
+                    .and_then(|message| message.get("id")?.as_str()?.parse().ok()),
+                size: frame.len(),
+                error: e.to_string(),
+                limit: None,
+            }
+        })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+    struct TestMessage {
+        text: String,
+        number: i32,
+    }
+
+    #[test]
+    fn test_encode_message() {
+        let mut codec: NativeMessagingCodec<TestMessage> = NativeMessagingCodec::default();
+        let message = TestMessage {
+            text: "hello".to_string(),
+            number: 42,
+        };
+        let mut buf = BytesMut::new();
+
+        codec
+            .encode(message, &mut buf)
+            .expect("encoding should succeed");
+
+        assert!(buf.len() >= 4);
+
+        let length = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+        let expected_json = r#"{"text":"hello","number":42}"#;
+        assert_eq!(length, expected_json.len());
+
+        assert_eq!(buf.len(), 4 + expected_json.len());
+
+        let json_payload = std::str::from_utf8(&buf[4..]).expect("valid UTF-8");
+        assert_eq!(json_payload, expected_json);
+    }
+
+    #[test]
+    fn test_decode_message() {
+        let mut codec: NativeMessagingCodec<TestMessage> = NativeMessagingCodec::default();
+
+        let json = r#"{"text":"world","number":123}"#;
+        let json_bytes = json.as_bytes();
+        #[allow(clippy::cast_possible_truncation)]
+        let length = json_bytes.len() as u32;
+
+        let mut src = BytesMut::new();
+        src.put_u32_le(length);
+        src.put_slice(json_bytes);
+
+        let decoded = codec.decode(&mut src).expect("decoding should succeed");
+        let message = decoded.expect("should have a message");
+        assert_eq!(message.text, "world");
+        assert_eq!(message.number, 123);
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn test_decode_partial_message() {
+        let mut codec: NativeMessagingCodec<TestMessage> = NativeMessagingCodec::default();
+
+        let mut src = BytesMut::new();
+        src.put_u32_le(20);
+
+        let result = codec.decode(&mut src).expect("decode should not fail");
+        assert!(result.is_none());
+        assert_eq!(src.len(), 4);
+    }
+
+    #[test]
+    fn test_decode_message_too_large() {
+        let mut codec: NativeMessagingCodec<TestMessage> = NativeMessagingCodec::default();
+
+        let mut src = BytesMut::new();
+        #[allow(clippy::cast_possible_truncation)]
+        let len = (MAX_OUTBOUND_MESSAGE_SIZE + 1) as u32;
+        src.put_u32_le(len);
+
+        let result = codec.decode(&mut src);
+        assert_matches!(result, Err(CodecError::MessageTooLarge { .. }));
+    }
+
+    #[test]
+    fn test_decode_respects_configured_limit() {
+        let mut codec: NativeMessagingCodec<TestMessage> =
+            NativeMessagingCodec::new(MAX_INBOUND_MESSAGE_SIZE);
+
+        let mut src = BytesMut::new();
+        #[allow(clippy::cast_possible_truncation)]
+        let len = (MAX_OUTBOUND_MESSAGE_SIZE + 1) as u32;
+        src.put_u32_le(len);
+        assert!(codec.decode(&mut src).unwrap().is_none());
+
+        let mut codec: NativeMessagingCodec<TestMessage> = NativeMessagingCodec::new(8);
+        assert_matches!(
+            codec.decode(&mut src),
+            Err(CodecError::MessageTooLarge { limit: 8, .. })
+        );
+    }
+
+    #[test]
+    fn test_decode_invalid_json() {
+        let mut codec: NativeMessagingCodec<TestMessage> = NativeMessagingCodec::default();
+
+        let invalid_json = b"not valid json";
+        let mut src = BytesMut::new();
+        #[allow(clippy::cast_possible_truncation)]
+        let len = invalid_json.len() as u32;
+        src.put_u32_le(len);
+        src.put_slice(invalid_json);
+
+        let result = codec.decode(&mut src);
+        assert_matches!(result, Err(CodecError::SerdeError(_)));
+    }
+
+    #[test]
+    fn test_decode_zero_length_message() {
+        let mut codec: NativeMessagingCodec<TestMessage> = NativeMessagingCodec::default();
+
+        let mut src = BytesMut::new();
+        src.put_u32_le(0);
+
+        let result = codec.decode(&mut src);
+        assert_matches!(result, Err(CodecError::SerdeError(_)));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut codec: NativeMessagingCodec<TestMessage> = NativeMessagingCodec::default();
+        let message = TestMessage {
+            text: "hello".to_string(),
+            number: 42,
+        };
+        let mut buf = BytesMut::new();
+        codec
+            .encode(message.clone(), &mut buf)
+            .expect("encode should succeed");
+
+        let decoded = codec
+            .decode(&mut buf)
+            .expect("decode should succeed")
+            .expect("should have message");
+        assert_eq!(decoded, message);
+    }
+
+    #[test]
+    fn test_codec_error_invalid_message_length() {
+        let error = CodecError::InvalidMessageLength;
+        assert_eq!(error.to_string(), "Invalid message length bytes");
+    }
+
+    #[test]
+    fn test_encode_message_too_large() {
+        let mut codec: NativeMessagingCodec<TestMessage> = NativeMessagingCodec::new(10);
+
+        let message = TestMessage {
+            text: "This is a very long message that exceeds the limit".to_string(),
+            number: 42,
+        };
+        let mut buf = BytesMut::new();
+
+        let result = codec.encode(message, &mut buf);
+        assert_matches!(result, Err(CodecError::MessageTooLarge { .. }));
+    }
+
+    #[test]
+    fn test_encode_appends_frames_in_place() {
+        let mut codec: NativeMessagingCodec<TestMessage> = NativeMessagingCodec::new(40);
+        let mut buf = BytesMut::new();
+
+        for number in [1, 2] {
+            codec
+                .encode(
+                    TestMessage {
+                        text: "hello".to_string(),
+                        number,
+                    },
+                    &mut buf,
+                )
+                .unwrap();
+        }
+        let frames_len = buf.len();
+        let result = codec.encode(
+            TestMessage {
+                text: "This is a very long message that exceeds the limit".to_string(),
+                number: 3,
+            },
+            &mut buf,
+        );
+        assert_matches!(result, Err(CodecError::MessageTooLarge { .. }));
+        assert_eq!(buf.len(), frames_len);
+
+        for number in [1, 2] {
+            let decoded = codec.decode(&mut buf).unwrap().unwrap();
+            assert_eq!(decoded.number, number);
+        }
+        assert!(buf.is_empty());
+    }
+
+    fn decode_frames(buf: &mut BytesMut) -> Vec<(usize, crate::Response)> {
+        let mut codec: NativeMessagingCodec<crate::Response> =
+            NativeMessagingCodec::new(MAX_INBOUND_MESSAGE_SIZE);
+        let mut frames = Vec::new();
+        while !buf.is_empty() {
+            let size = u32::from_le_bytes(buf[0..4].try_into().unwrap()) as usize;
+            frames.push((size, codec.decode(buf).unwrap().unwrap()));
+        }
+        frames
+    }
+
+    fn content_response(content: &str) -> crate::Response {
+        crate::Response {
+            id: uuid::Uuid::nil(),
+            payload: crate::ResponsePayload::Content {
+                content: content.to_string(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_encode_splits_oversized_content() {
+        let response = content_response("");
+        let overhead = serde_json::to_vec(&response).unwrap().len();
+        let content = "ab\"c\u{1}é".repeat(20);
+
+        for limit in [overhead + 6, overhead + 7, overhead + 64] {
+            let mut codec: NativeMessagingCodec<crate::Response> = NativeMessagingCodec::new(limit);
+            let mut buf = BytesMut::new();
+            codec.encode(content_response(&content), &mut buf).unwrap();
+
+            let frames = decode_frames(&mut buf);
+            assert!(frames.len() > 1);
+            let mut reassembled = String::new();
+            for (size, frame) in frames {
+                assert!(size <= limit);
+                assert_eq!(frame.id, uuid::Uuid::nil());
+                match frame.payload {
+                    crate::ResponsePayload::Content { content } => reassembled.push_str(&content),
+                    other => panic!("Expected Content, got {other:?}"),
+                }
+            }
+            assert_eq!(reassembled, content);
+        }
+    }
+
+    #[test]
+    fn test_encode_content_at_limit_is_not_split() {
+        let response = content_response("exactly at the limit");
+        let size = serde_json::to_vec(&response).unwrap().len();
+
+        let mut codec: NativeMessagingCodec<crate::Response> = NativeMessagingCodec::new(size);
+        let mut buf = BytesMut::new();
+        codec.encode(response, &mut buf).unwrap();
+        assert_eq!(decode_frames(&mut buf).len(), 1);
+
+        let mut codec: NativeMessagingCodec<crate::Response> = NativeMessagingCodec::new(size - 1);
+        let mut buf = BytesMut::new();
+        codec
+            .encode(content_response("exactly at the limit"), &mut buf)
+            .unwrap();
+        assert_eq!(decode_frames(&mut buf).len(), 2);
+    }
+
+    #[test]
+    fn test_size_limit_handle_updates_codec() {
+        let mut codec: NativeMessagingCodec<crate::Response> = NativeMessagingCodec::new(1024);
+        let limit = codec.size_limit();
+        let content = "x".repeat(200);
+
+        let mut buf = BytesMut::new();
+        codec.encode(content_response(&content), &mut buf).unwrap();
+        assert_eq!(decode_frames(&mut buf).len(), 1);
+
+        limit.set(100);
+        codec.encode(content_response(&content), &mut buf).unwrap();
+        let frames = decode_frames(&mut buf);
+        assert!(frames.len() > 1);
+        assert!(frames.iter().all(|(size, _)| *size <= 100));
+    }
+
+    #[test]
+    fn test_encode_fails_when_escaped_char_exceeds_budget() {
+        let overhead = serde_json::to_vec(&content_response("")).unwrap().len();
+        let mut codec: NativeMessagingCodec<crate::Response> =
+            NativeMessagingCodec::new(overhead + 5);
+        let mut buf = BytesMut::new();
+
+        let result = codec.encode(content_response("\u{1}"), &mut buf);
+        assert_matches!(result, Err(CodecError::MessageTooLarge { .. }));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_decode_handles_length_bytes_safely() {
+        let mut codec: NativeMessagingCodec<TestMessage> = NativeMessagingCodec::default();
+        let mut buf = BytesMut::new();
+
+        buf.extend_from_slice(&[1, 2, 3]);
+        let result = codec.decode(&mut buf);
+        assert!(matches!(result, Ok(None)));
+        buf.clear();
+
+        let json = r#"{"text":"hi","number":1}"#;
+        #[allow(clippy::cast_possible_truncation)]
+        let len_bytes = (json.len() as u32).to_le_bytes();
+        buf.extend_from_slice(&len_bytes);
+        buf.extend_from_slice(json.as_bytes());
+        let result = codec.decode(&mut buf);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_decode_malformed_length_prefix() {
+        let mut codec: NativeMessagingCodec<TestMessage> = NativeMessagingCodec::default();
+        let mut src = BytesMut::new();
+
+        src.put_slice(&[0x01, 0x02, 0x03]);
+
+        let result = codec.decode(&mut src);
+        assert!(matches!(result, Ok(None)));
+        assert_eq!(src.len(), 3);
+    }
+
+    #[test]
+    fn test_decode_buffer_underrun() {
+        let mut codec: NativeMessagingCodec<TestMessage> = NativeMessagingCodec::default();
+        let mut src = BytesMut::new();
+
+        src.put_u32_le(10);
+        src.put_slice(b"hello");
+
+        let result = codec.decode(&mut src);
+        assert!(matches!(result, Ok(None)));
+        assert_eq!(src.len(), 9);
+    }
+
+    #[test]
+    fn test_encode_serialization_error() {
+        let io_error = std::io::Error::other("test error");
+        let error = CodecError::SerdeError(serde_json::Error::io(io_error));
+        assert!(error.to_string().contains("Serialization error"));
+    }
+
+    #[test]
+    fn test_encode_output_buffer_handling() {
+        let mut codec: NativeMessagingCodec<TestMessage> = NativeMessagingCodec::default();
+        let message = TestMessage {
+            text: "test".to_string(),
+            number: 1,
+        };
+        let mut buf = BytesMut::with_capacity(1);
+        let result = codec.encode(message, &mut buf);
+        assert!(result.is_ok());
+        assert!(buf.len() > 1);
+    }
+
+    #[test]
+    fn test_message_size_boundary_conditions() {
+        let mut codec = NativeMessagingCodec::<TestMessage>::new(100);
+
+        let mut src = BytesMut::new();
+        src.put_u32_le(100);
+        src.put_slice(&[b'x'; 100]);
+
+        let result = codec.decode(&mut src);
+        assert_matches!(result, Err(CodecError::SerdeError(_)));
+
+        let mut src = BytesMut::new();
+        src.put_u32_le(101);
+
+        let result = codec.decode(&mut src);
+        assert_matches!(
+            result,
+            Err(CodecError::MessageTooLarge {
+                size: 101,
+                limit: 100
+            })
+        );
+    }
+
+    #[test]
+    fn test_tracer_records_both_directions() {
+        let dir = camino_tempfile::tempdir().unwrap();
+        let path = dir.path().join("trace.jsonl");
+        let tracer = Arc::new(ProtocolTracer::open(&path).unwrap());
+        let mut codec =
+            NativeMessagingCodec::<TestMessage>::default().with_tracer(Some(tracer.clone()));
+
+        let message = TestMessage {
+            text: "traced".to_string(),
+            number: 7,
+        };
+        let mut buf = BytesMut::new();
+        codec.encode(message, &mut buf).unwrap();
+        codec.decode(&mut buf).unwrap();
+
+        let trace = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = trace.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("\"direction\":\"outbound\""));
+        assert!(lines[1].contains("\"direction\":\"inbound\""));
+    }
+
+    fn gzip_codec(limit: usize) -> NativeMessagingCodec<crate::Response> {
+        let compression = ContentCompression::default();
+        compression.set(Some(Compression::Gzip));
+        NativeMessagingCodec::new(limit).with_compression(compression)
+    }
+
+    #[test]
+    fn test_gzip_compresses_large_content() {
+        let content = "The quick brown fox jumps over the lazy dog. ".repeat(200);
+        let mut codec = gzip_codec(MAX_OUTBOUND_MESSAGE_SIZE);
+
+        let mut buf = BytesMut::new();
+        codec.encode(content_response("short"), &mut buf).unwrap();
+        assert!(!String::from_utf8_lossy(&buf[4..]).contains(ENCODING_FIELD));
+        buf.clear();
+
+        codec.encode(content_response(&content), &mut buf).unwrap();
+        let frame: Value = serde_json::from_slice(&buf[4..]).unwrap();
+        assert_eq!(frame[ENCODING_FIELD], GZIP_ENCODING);
+        assert!(buf.len() < content.len() / 10);
+
+        let decoded = codec.decode(&mut buf).unwrap().unwrap();
+        assert_matches!(decoded.payload, crate::ResponsePayload::Content { content: decoded } if decoded == content);
+    }
+
+    #[test]
+    fn test_gzip_splits_each_frame_independently() {
+        let content: String = (0..4000u32)
+            .map(|i| char::from_u32(0x4e00 + (i * 7919) % 20000).unwrap())
+            .collect();
+        let limit = 2048;
+        let mut codec = gzip_codec(limit);
+
+        let mut buf = BytesMut::new();
+        codec.encode(content_response(&content), &mut buf).unwrap();
+
+        let mut reassembled = String::new();
+        let mut frames = 0;
+        while !buf.is_empty() {
+            let size = u32::from_le_bytes(buf[0..4].try_into().unwrap()) as usize;
+            assert!(size <= limit);
+            let frame: Value = serde_json::from_slice(&buf[4..4 + size]).unwrap();
+            assert_eq!(frame[ENCODING_FIELD], GZIP_ENCODING);
+            match codec.decode(&mut buf).unwrap().unwrap().payload {
+                crate::ResponsePayload::Content { content } => reassembled.push_str(&content),
+                other => panic!("Expected Content, got {other:?}"),
+            }
+            frames += 1;
+        }
+        assert!(frames > 1);
+        assert_eq!(reassembled, content);
+    }
+
+    #[test]
+    fn test_gzip_decodes_uncompressed_frames() {
+        let mut plain = NativeMessagingCodec::<crate::Response>::default();
+        let mut codec = gzip_codec(MAX_OUTBOUND_MESSAGE_SIZE);
+
+        for content in ["plain text", "a field named \"encoding\""] {
+            let mut buf = BytesMut::new();
+            plain.encode(content_response(content), &mut buf).unwrap();
+
+            let decoded = codec.decode(&mut buf).unwrap().unwrap();
+            assert_matches!(decoded.payload, crate::ResponsePayload::Content { content: decoded } if decoded == content);
+        }
+    }
+
+    #[test]
+    fn test_gzip_decode_rejects_invalid_content() {
+        let mut codec = gzip_codec(MAX_OUTBOUND_MESSAGE_SIZE);
+        let json = br#"{"id":"00000000-0000-0000-0000-000000000000","type":"native.content","content":"not gzip","encoding":"gzip"}"#;
+        let mut src = BytesMut::new();
+        #[allow(clippy::cast_possible_truncation)]
+        src.put_u32_le(json.len() as u32);
+        src.put_slice(json);
+
+        assert_matches!(
+            codec.decode(&mut src),
+            Err(CodecError::InvalidCompressedContent)
+        );
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn test_message_pack_round_trip() {
+        let mut codec: MessagePackCodec<crate::Response> = MessagePackCodec::default();
+        let response = crate::Response {
+            id: uuid::Uuid::new_v4(),
+            payload: crate::ResponsePayload::Warning {
+                message: "careful".to_string(),
+            },
+        };
+
+        let mut buf = BytesMut::new();
+        codec.encode(response.clone(), &mut buf).unwrap();
+        assert!(serde_json::from_slice::<Value>(&buf[4..]).is_err());
+
+        let decoded = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(decoded.id, response.id);
+        assert_matches!(decoded.payload, crate::ResponsePayload::Warning { message } if message == "careful");
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn test_message_pack_splits_oversized_content() {
+        let content = "ab\"c\u{1}é".repeat(500);
+        let limit = 256;
+        let mut codec: MessagePackCodec<crate::Response> = MessagePackCodec::new(limit);
+
+        let mut buf = BytesMut::new();
+        codec.encode(content_response(&content), &mut buf).unwrap();
+
+        let mut codec: MessagePackCodec<crate::Response> =
+            MessagePackCodec::new(MAX_INBOUND_MESSAGE_SIZE);
+        let mut reassembled = String::new();
+        while !buf.is_empty() {
+            let size = u32::from_le_bytes(buf[0..4].try_into().unwrap()) as usize;
+            assert!(size <= limit);
+            match codec.decode(&mut buf).unwrap().unwrap().payload {
+                crate::ResponsePayload::Content { content } => reassembled.push_str(&content),
+                other => panic!("Expected Content, got {other:?}"),
+            }
+        }
+        assert_eq!(reassembled, content);
+    }
+
+    #[test]
+    fn test_byte_order_modes() {
+        assert_eq!(ByteOrder::from_name("BE"), Some(ByteOrder::Big));
+        assert_eq!(ByteOrder::from_name("little"), Some(ByteOrder::Little));
+        assert_eq!(ByteOrder::from_name("native"), Some(ByteOrder::Native));
+        assert_eq!(ByteOrder::from_name("middle"), None);
+
+        let message = TestMessage {
+            text: "hello".to_string(),
+            number: 42,
+        };
+        for (byte_order, prefix) in [
+            (ByteOrder::Little, 28u32.to_le_bytes()),
+            (ByteOrder::Big, 28u32.to_be_bytes()),
+            (ByteOrder::Native, 28u32.to_ne_bytes()),
+        ] {
+            let mut codec =
+                NativeMessagingCodec::<TestMessage>::default().with_byte_order(byte_order);
+            let mut buf = BytesMut::new();
+            codec.encode(message.clone(), &mut buf).unwrap();
+            assert_eq!(buf[0..4], prefix);
+            assert_eq!(codec.decode(&mut buf).unwrap(), Some(message.clone()));
+        }
+    }
+
+    #[test]
+    fn test_recovering_codec_skips_malformed_frames() {
+        let mut codec = NativeMessagingCodec::<TestMessage>::default().recovering();
+        let id = uuid::Uuid::new_v4();
+        let mut src = BytesMut::new();
+        for json in [
+            format!(r#"{{"id":"{id}","text":1}}"#),
+            "{not json".to_string(),
+            r#"{"text":"ok","number":1}"#.to_string(),
+        ] {
+            #[allow(clippy::cast_possible_truncation)]
+            src.put_u32_le(json.len() as u32);
+            src.put_slice(json.as_bytes());
+        }
+
+        assert_matches!(
+            codec.decode(&mut src),
+            Ok(Some(Err(MalformedFrame { id: Some(found), size: 54, .. }))) if found == id
+        );
+        assert_matches!(
+            codec.decode(&mut src),
+            Ok(Some(Err(MalformedFrame {
+                id: None,
+                size: 9,
+                ..
+            })))
+        );
+        assert_matches!(
+            codec.decode(&mut src),
+            Ok(Some(Ok(TestMessage { number: 1, .. })))
+        );
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn test_recovering_codec_skips_oversized_frames() {
+        let mut codec = NativeMessagingCodec::<TestMessage>::new(64).recovering();
+        let id = uuid::Uuid::new_v4();
+        let oversized = format!(r#"{{"id": "{id}", "text":"{}"}}"#, "x".repeat(100));
+        let valid = r#"{"text":"ok","number":1}"#;
+        let mut src = BytesMut::new();
+        #[allow(clippy::cast_possible_truncation)]
+        src.put_u32_le(oversized.len() as u32);
+        src.put_slice(&oversized.as_bytes()[..70]);
+
+        assert_matches!(
+            codec.decode(&mut src),
+            Ok(Some(Err(MalformedFrame { id: Some(found), limit: Some(64), .. }))) if found == id
+        );
+        assert_matches!(codec.decode(&mut src), Ok(None));
+        assert!(src.is_empty());
+
+        src.put_slice(&oversized.as_bytes()[70..]);
+        #[allow(clippy::cast_possible_truncation)]
+        src.put_u32_le(valid.len() as u32);
+        src.put_slice(valid.as_bytes());
+        assert_matches!(
+            codec.decode(&mut src),
+            Ok(Some(Ok(TestMessage { number: 1, .. })))
+        );
+        assert!(src.is_empty());
+    }
+}
@@ -0,0 +1,1143 @@
+use std::collections::BTreeMap;
+
+use camino::Utf8PathBuf;
+use serde::{Deserialize, Deserializer, Serialize};
+use uuid::Uuid;
+
+pub mod codec;
+pub mod trace;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Request {
+    pub id: Uuid,
+    pub path: Option<Utf8PathBuf>,
+    #[serde(default)]
+    pub validate_only: bool,
+    #[serde(flatten)]
+    pub payload: RequestPayload,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+#[allow(clippy::large_enum_variant)]
+pub enum RequestPayload {
+    #[serde(rename = "native.hello")]
+    Hello {
+        #[serde(rename = "maxMessageSize")]
+        max_message_size: Option<usize>,
+        compression: Option<Compression>,
+        locale: Option<String>,
+    },
+    #[serde(rename = "native.ping")]
+    Ping,
+    #[serde(rename = "native.heartbeat")]
+    Heartbeat,
+    #[serde(rename = "native.shutdown")]
+    Shutdown,
+    #[serde(rename = "native.reloadConfig")]
+    ReloadConfig,
+    #[serde(rename = "native.getConfig")]
+    GetConfig,
+    #[serde(rename = "native.setConfig")]
+    SetConfig { config: HostConfigUpdate },
+    #[serde(rename = "native.pinPattern")]
+    PinPattern { pattern: String },
+    #[serde(rename = "native.unpinPattern")]
+    UnpinPattern { pattern: String },
+    #[serde(rename = "native.pinFabricChecksum")]
+    PinFabricChecksum,
+    #[serde(rename = "native.setSecret")]
+    SetSecret { name: String, secret: String },
+    #[serde(rename = "native.deleteSecret")]
+    DeleteSecret { name: String },
+    #[serde(rename = "native.listSecretNames")]
+    ListSecretNames,
+    #[serde(rename = "native.getMetrics")]
+    GetMetrics,
+    #[serde(rename = "native.debugDump")]
+    DebugDump,
+    #[serde(rename = "native.getStoreInfo")]
+    GetStoreInfo,
+    #[serde(rename = "native.listPatterns")]
+    ListPatterns,
+    #[serde(rename = "native.listContexts")]
+    ListContexts,
+    #[serde(rename = "native.processContent")]
+    ProcessContent {
+        #[serde(default)]
+        content: ContentInput,
+        model: Option<String>,
+        pattern: Option<String>,
+        context: Option<String>,
+        custom_prompt: Option<String>,
+        #[serde(rename = "systemPrompt")]
+        system_prompt: Option<String>,
+        locale: Option<String>,
+        #[serde(default)]
+        detach: bool,
+        #[serde(default)]
+        aggregate: bool,
+        #[serde(default)]
+        summarize: bool,
+        #[serde(rename = "summaryModel")]
+        summary_model: Option<String>,
+        #[serde(rename = "outputFormat", default)]
+        output_format: OutputFormat,
+        #[serde(default)]
+        deterministic: bool,
+        #[serde(rename = "quickLook")]
+        quick_look: Option<QuickLook>,
+        selection: Option<String>,
+        composition: Option<Composition>,
+        #[serde(rename = "streamMode", default)]
+        stream_mode: StreamMode,
+        #[serde(rename = "streamFlushMs")]
+        stream_flush_ms: Option<u64>,
+        #[serde(rename = "streamChunkBytes")]
+        stream_chunk_bytes: Option<usize>,
+        strict: Option<bool>,
+        #[serde(default)]
+        structured: bool,
+        #[serde(rename = "timeoutMs")]
+        timeout_ms: Option<u64>,
+        #[serde(rename = "heartbeatMs")]
+        heartbeat_ms: Option<u64>,
+        cwd: Option<Utf8PathBuf>,
+        env: Option<BTreeMap<String, String>>,
+    },
+    #[serde(rename = "native.getJobResult")]
+    GetJobResult {
+        #[serde(rename = "jobId")]
+        job_id: Uuid,
+    },
+    #[serde(rename = "native.listJobs")]
+    ListJobs {
+        #[serde(default)]
+        state: Option<JobState>,
+    },
+    #[serde(rename = "native.cancelJob")]
+    CancelJob {
+        #[serde(rename = "jobId")]
+        job_id: Uuid,
+    },
+    #[serde(rename = "native.deleteJob")]
+    DeleteJob {
+        #[serde(rename = "jobId")]
+        job_id: Uuid,
+    },
+    #[serde(rename = "native.contentChunk")]
+    ContentChunk {
+        #[serde(rename = "requestId")]
+        request_id: Uuid,
+        seq: u64,
+        data: String,
+        #[serde(default)]
+        last: bool,
+    },
+    #[serde(rename = "native.processBatch")]
+    ProcessBatch { items: Vec<BatchContent> },
+    #[serde(rename = "native.replayRequest")]
+    ReplayRequest {
+        #[serde(rename = "requestId")]
+        request_id: Uuid,
+    },
+    #[serde(rename = "native.cancelProcess")]
+    CancelProcess {
+        #[serde(rename = "requestId")]
+        request_id: Uuid,
+    },
+    #[serde(rename = "native.openInEditor")]
+    OpenInEditor {
+        #[serde(rename = "requestId")]
+        request_id: Uuid,
+    },
+}
+
+impl RequestPayload {
+    /// The wire `type` tag, e.g. `native.processContent`.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            RequestPayload::Hello { .. } => "native.hello",
+            RequestPayload::Ping => "native.ping",
+            RequestPayload::Heartbeat => "native.heartbeat",
+            RequestPayload::Shutdown => "native.shutdown",
+            RequestPayload::ReloadConfig => "native.reloadConfig",
+            RequestPayload::GetConfig => "native.getConfig",
+            RequestPayload::SetConfig { .. } => "native.setConfig",
+            RequestPayload::PinPattern { .. } => "native.pinPattern",
+            RequestPayload::UnpinPattern { .. } => "native.unpinPattern",
+            RequestPayload::PinFabricChecksum => "native.pinFabricChecksum",
+            RequestPayload::SetSecret { .. } => "native.setSecret",
+            RequestPayload::DeleteSecret { .. } => "native.deleteSecret",
+            RequestPayload::ListSecretNames => "native.listSecretNames",
+            RequestPayload::GetMetrics => "native.getMetrics",
+            RequestPayload::DebugDump => "native.debugDump",
+            RequestPayload::GetStoreInfo => "native.getStoreInfo",
+            RequestPayload::ListPatterns => "native.listPatterns",
+            RequestPayload::ListContexts => "native.listContexts",
+            RequestPayload::ProcessContent { .. } => "native.processContent",
+            RequestPayload::GetJobResult { .. } => "native.getJobResult",
+            RequestPayload::ListJobs { .. } => "native.listJobs",
+            RequestPayload::CancelJob { .. } => "native.cancelJob",
+            RequestPayload::DeleteJob { .. } => "native.deleteJob",
+            RequestPayload::ContentChunk { .. } => "native.contentChunk",
+            RequestPayload::ProcessBatch { .. } => "native.processBatch",
+            RequestPayload::ReplayRequest { .. } => "native.replayRequest",
+            RequestPayload::CancelProcess { .. } => "native.cancelProcess",
+            RequestPayload::OpenInEditor { .. } => "native.openInEditor",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ContentInput {
+    Text(String),
+    Documents(Vec<Document>),
+}
+
+impl ContentInput {
+    pub fn byte_len(&self) -> usize {
+        match self {
+            ContentInput::Text(text) => text.len(),
+            ContentInput::Documents(documents) => documents
+                .iter()
+                .map(|document| document.title.len() + document.text.len())
+                .sum(),
+        }
+    }
+
+    pub fn into_text(self) -> String {
+        match self {
+            ContentInput::Text(text) => text,
+            ContentInput::Documents(documents) => {
+                let total = documents.len();
+                documents
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, document)| {
+                        format!(
+                            "=== Document {} of {total}: {} ===\n{}\n=== End of document {} ===\n",
+                            i + 1,
+                            document.title,
+                            document.text.trim_end(),
+                            i + 1
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }
+        }
+    }
+}
+
+impl Default for ContentInput {
+    fn default() -> Self {
+        ContentInput::Text(String::new())
+    }
+}
+
+impl From<String> for ContentInput {
+    fn from(text: String) -> Self {
+        ContentInput::Text(text)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Document {
+    pub title: String,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchContent {
+    pub content: String,
+    pub pattern: Option<String>,
+    pub model: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Response {
+    pub id: Uuid,
+    #[serde(flatten)]
+    pub payload: ResponsePayload,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum ResponsePayload {
+    #[serde(rename = "native.helloAck")]
+    HelloAck {
+        #[serde(rename = "maxMessageSize")]
+        max_message_size: usize,
+        compression: Option<Compression>,
+    },
+    #[serde(rename = "native.hostReady")]
+    HostReady(BootReport),
+    #[serde(rename = "native.pong")]
+    Pong {
+        #[serde(rename = "resolvedPath")]
+        resolved_path: Option<String>,
+        #[serde(default)]
+        binary: Option<String>,
+        version: Option<String>,
+        valid: bool,
+        stats: Option<HostStats>,
+        compression: Vec<Compression>,
+    },
+    #[serde(rename = "native.streamStart")]
+    StreamStart {
+        model: Option<String>,
+        pattern: Option<String>,
+        inputs: Option<ResolvedInputs>,
+        #[serde(rename = "outputFormat")]
+        output_format: OutputFormat,
+    },
+    #[serde(rename = "native.queued")]
+    Queued { limit: usize },
+    #[serde(rename = "native.content")]
+    Content { content: String },
+    #[serde(rename = "native.json")]
+    Json { value: serde_json::Value },
+    #[serde(rename = "native.stderrLine")]
+    StderrLine { line: String },
+    #[serde(rename = "native.progress")]
+    Progress {
+        #[serde(rename = "bytesOut")]
+        bytes_out: u64,
+        #[serde(rename = "elapsedMs")]
+        elapsed_ms: u64,
+    },
+    #[serde(rename = "native.shutdownAck")]
+    ShutdownAck,
+    #[serde(rename = "native.configReloaded")]
+    ConfigReloaded,
+    #[serde(rename = "native.config")]
+    Config {
+        path: Option<Utf8PathBuf>,
+        config: HostConfig,
+    },
+    #[serde(rename = "native.heartbeatAck")]
+    HeartbeatAck {
+        #[serde(rename = "uptimeMs")]
+        uptime_ms: u64,
+        #[serde(rename = "activeRequests")]
+        active_requests: usize,
+    },
+    #[serde(rename = "native.heartbeat")]
+    Heartbeat {
+        #[serde(rename = "elapsedMs")]
+        elapsed_ms: u64,
+    },
+    #[serde(rename = "native.hostHeartbeat")]
+    HostHeartbeat {
+        sequence: u64,
+        #[serde(rename = "uptimeMs")]
+        uptime_ms: u64,
+    },
+    #[serde(rename = "native.partialResult")]
+    PartialResult {
+        content: String,
+        #[serde(rename = "elapsedMs")]
+        elapsed_ms: u64,
+    },
+    #[serde(rename = "native.done")]
+    Done {
+        #[serde(rename = "exitCode")]
+        exit_code: Option<i32>,
+        usage: Option<TokenUsage>,
+        generation: Option<GenerationStats>,
+        #[serde(rename = "durationMs")]
+        duration_ms: Option<u64>,
+        #[serde(rename = "timeToFirstChunkMs")]
+        time_to_first_chunk_ms: Option<u64>,
+        #[serde(rename = "spawnTimeMs")]
+        spawn_time_ms: Option<u64>,
+        #[serde(rename = "contentHash")]
+        content_hash: Option<String>,
+        #[serde(rename = "stderrTail")]
+        stderr_tail: Option<String>,
+        error: Option<ErrorDetails>,
+        content: Option<String>,
+        summary: Option<String>,
+        #[serde(rename = "outputFormat")]
+        output_format: Option<OutputFormat>,
+        #[serde(default)]
+        truncated: bool,
+    },
+    #[serde(rename = "native.jobAccepted")]
+    JobAccepted {
+        #[serde(rename = "jobId")]
+        job_id: Uuid,
+    },
+    #[serde(rename = "native.jobResult")]
+    JobResult {
+        #[serde(rename = "jobId")]
+        job_id: Uuid,
+        state: JobState,
+        content: String,
+        #[serde(rename = "exitCode")]
+        exit_code: Option<i32>,
+        error: Option<String>,
+        generation: Option<GenerationStats>,
+    },
+    #[serde(rename = "native.jobsList")]
+    JobsList { jobs: Vec<JobSummary> },
+    #[serde(rename = "native.orphanedJobs")]
+    OrphanedJobs { jobs: Vec<JobSummary> },
+    #[serde(rename = "native.jobDeleted")]
+    JobDeleted {
+        #[serde(rename = "jobId")]
+        job_id: Uuid,
+    },
+    #[serde(rename = "native.jobEvent")]
+    JobEvent(JobEvent),
+    #[serde(rename = "native.notification")]
+    Notification(Notification),
+    #[serde(rename = "native.chunkAccepted")]
+    ChunkAccepted {
+        #[serde(rename = "requestId")]
+        request_id: Uuid,
+        seq: u64,
+        complete: bool,
+    },
+    #[serde(rename = "native.batchItem")]
+    BatchItem {
+        index: usize,
+        item: Box<ResponsePayload>,
+    },
+    #[serde(rename = "native.batchDone")]
+    BatchDone { total: usize, failed: usize },
+    #[serde(rename = "native.error")]
+    Error {
+        message: String,
+        details: Option<ErrorDetails>,
+    },
+    #[serde(rename = "native.setupRequired")]
+    SetupRequired {
+        #[serde(rename = "configDir")]
+        config_dir: String,
+        instructions: String,
+    },
+    #[serde(rename = "native.validationResult")]
+    ValidationResult {
+        backend: String,
+        command: Option<Vec<String>>,
+        #[serde(rename = "estimatedPromptTokens")]
+        estimated_prompt_tokens: Option<u64>,
+        warnings: Vec<String>,
+    },
+    #[serde(rename = "native.warning")]
+    Warning { message: String },
+    #[serde(rename = "native.patternsList")]
+    PatternsList {
+        patterns: Vec<String>,
+        #[serde(default)]
+        aliases: BTreeMap<String, String>,
+        #[serde(default)]
+        pinned: Vec<String>,
+    },
+    #[serde(rename = "native.pinnedPatterns")]
+    PinnedPatterns { patterns: Vec<String> },
+    #[serde(rename = "native.fabricChecksum")]
+    FabricChecksum { path: Utf8PathBuf, sha256: String },
+    #[serde(rename = "native.secretNames")]
+    SecretNames { names: Vec<String> },
+    #[serde(rename = "native.metrics")]
+    Metrics(HostMetrics),
+    #[serde(rename = "native.debugState")]
+    DebugState(DebugState),
+    #[serde(rename = "native.storeInfo")]
+    StoreInfo { stores: Vec<StoreInfo> },
+    #[serde(rename = "native.contextsList")]
+    ContextsList { contexts: Vec<String> },
+    #[serde(rename = "native.cancelled")]
+    Cancelled {
+        #[serde(rename = "requestId")]
+        request_id: Uuid,
+    },
+    #[serde(rename = "native.editorOpened")]
+    EditorOpened {
+        #[serde(rename = "requestId")]
+        request_id: Uuid,
+        path: String,
+        editor: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorDetails {
+    pub code: String,
+    pub hint: Option<String>,
+    pub fields: Vec<FieldError>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum JobState {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResolvedInputs {
+    pub model: Option<String>,
+    pub pattern: Option<String>,
+    pub context: Option<String>,
+    pub prompt: Option<String>,
+    pub temperature: f64,
+    pub seed: Option<u64>,
+    pub input_hash: String,
+    pub fabric_version: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Compression {
+    Gzip,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum OutputFormat {
+    #[default]
+    Markdown,
+    Plaintext,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum OrphanPolicy {
+    #[default]
+    Finish,
+    Cancel,
+}
+
+impl OrphanPolicy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "finish" => Some(OrphanPolicy::Finish),
+            "cancel" => Some(OrphanPolicy::Cancel),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+}
+
+impl LogLevel {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "error" => Some(LogLevel::Error),
+            "warn" => Some(LogLevel::Warn),
+            "info" => Some(LogLevel::Info),
+            "debug" => Some(LogLevel::Debug),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HostConfig {
+    pub default_model: Option<String>,
+    pub patterns_dir: Option<Utf8PathBuf>,
+    pub max_concurrent_processes: Option<usize>,
+    pub log_level: Option<LogLevel>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HostConfigUpdate {
+    #[serde(
+        default,
+        deserialize_with = "present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub default_model: Option<Option<String>>,
+    #[serde(
+        default,
+        deserialize_with = "present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub patterns_dir: Option<Option<Utf8PathBuf>>,
+    #[serde(
+        default,
+        deserialize_with = "present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_concurrent_processes: Option<Option<usize>>,
+    #[serde(
+        default,
+        deserialize_with = "present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub log_level: Option<Option<LogLevel>>,
+}
+
+// Distinguishes an explicit `null` (clear the setting) from an absent field (leave it alone).
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobSummary {
+    pub job_id: Uuid,
+    pub state: JobState,
+    pub exit_code: Option<i32>,
+    pub error: Option<String>,
+    pub created_ms: u64,
+    pub orphan_policy: Option<OrphanPolicy>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum JobEventKind {
+    Started,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobEvent {
+    pub job_id: Uuid,
+    pub event: JobEventKind,
+    pub timestamp_ms: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum NotificationKind {
+    JobCompleted,
+    JobFailed,
+    JobCancelled,
+    JobsOrphaned,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Notification {
+    pub kind: NotificationKind,
+    pub severity: Severity,
+    pub title: String,
+    pub message: String,
+    pub job_id: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HostStats {
+    pub uptime_ms: u64,
+    pub active_requests: usize,
+    pub queued_requests: usize,
+    pub last_error_code: Option<String>,
+    pub write_stalls: u64,
+    pub write_stalled_ms: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HostMetrics {
+    pub uptime_ms: u64,
+    /// Requests received, keyed by wire type.
+    pub requests: BTreeMap<String, u64>,
+    /// Failed requests, keyed by error code.
+    pub failures: BTreeMap<String, u64>,
+    pub time_to_first_chunk: Histogram,
+    pub spawn_time: Histogram,
+}
+
+/// Schema versions of one of the host's on-disk stores.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StoreInfo {
+    pub name: String,
+    pub path: Option<Utf8PathBuf>,
+    /// Version the file is written with; `None` if it does not exist yet or
+    /// cannot be read.
+    pub version: Option<u32>,
+    /// Version this host reads and writes; older files are migrated on load.
+    pub supported_version: u32,
+}
+
+/// A snapshot of host internals for attaching to bug reports.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DebugState {
+    pub version: String,
+    pub uptime_ms: u64,
+    /// Requests the host has read and not yet finished.
+    pub active_requests: Vec<Uuid>,
+    /// Requests and jobs with a running fabric process.
+    pub running_processes: Vec<Uuid>,
+    pub fabric_path: Option<Utf8PathBuf>,
+    /// Why fabric could not be resolved or verified, if it could not.
+    pub fabric_error: Option<String>,
+    pub config_path: Option<Utf8PathBuf>,
+    /// The config file as written, before defaults are applied.
+    pub config: Option<serde_json::Value>,
+    pub config_error: Option<String>,
+    pub caches: CacheStates,
+    /// The most recent failures, oldest first.
+    pub recent_errors: Vec<RecentError>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheStates {
+    pub replay_entries: usize,
+    pub pending_chunks: usize,
+    pub fabric_digests: usize,
+    pub jobs: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecentError {
+    pub request_id: Uuid,
+    pub code: String,
+    pub message: String,
+    pub timestamp_ms: u64,
+}
+
+/// A millisecond latency distribution. Each bucket counts observations above
+/// the previous bucket's bound and at most `le_ms`; the last bucket has no
+/// bound and holds the rest.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Histogram {
+    pub count: u64,
+    pub sum_ms: u64,
+    pub max_ms: u64,
+    pub buckets: Vec<HistogramBucket>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistogramBucket {
+    pub le_ms: Option<u64>,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Composition {
+    pub max_tokens: Option<u64>,
+    pub selection_ratio: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum StreamMode {
+    #[default]
+    Lines,
+    Chunks,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuickLook {
+    pub max_seconds: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BootReport {
+    pub version: String,
+    pub config_path: Option<String>,
+    pub transports: Vec<String>,
+    pub features: Vec<String>,
+    pub fabric_path: Option<String>,
+    pub fabric_version: Option<String>,
+    pub fabric_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub estimated: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerationStats {
+    pub prompt_eval_count: Option<u64>,
+    pub eval_count: u64,
+    pub eval_duration_ms: Option<u64>,
+    pub tokens_per_second: f64,
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+    use camino::Utf8PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn test_list_patterns_request_serialization() {
+        let request = Request {
+            id: Uuid::new_v4(),
+            path: None,
+            validate_only: false,
+            payload: RequestPayload::ListPatterns,
+        };
+
+        let json = serde_json::to_string(&request).unwrap();
+        assert!(json.contains("\"type\":\"native.listPatterns\""));
+        assert!(json.contains("\"id\""));
+    }
+
+    #[test]
+    fn test_request_type_name_matches_wire_tag() {
+        for payload in [
+            RequestPayload::Ping,
+            RequestPayload::ListSecretNames,
+            RequestPayload::GetMetrics,
+            RequestPayload::DebugDump,
+            RequestPayload::GetStoreInfo,
+            RequestPayload::ProcessBatch { items: Vec::new() },
+            RequestPayload::PinPattern {
+                pattern: "summarize".to_string(),
+            },
+        ] {
+            let json = serde_json::to_value(&payload).unwrap();
+            assert_eq!(json["type"], payload.type_name());
+        }
+    }
+
+    #[test]
+    fn test_list_patterns_request_deserialization() {
+        let json = r#"{
+            "id": "550e8400-e29b-41d4-a716-446655440000",
+            "type": "native.listPatterns"
+        }"#;
+
+        let request: Request = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            request.id.to_string(),
+            "550e8400-e29b-41d4-a716-446655440000"
+        );
+        assert_matches!(request.payload, RequestPayload::ListPatterns);
+    }
+
+    #[test]
+    fn test_patterns_list_response_serialization() {
+        let response = Response {
+            id: Uuid::new_v4(),
+            payload: ResponsePayload::PatternsList {
+                patterns: vec!["pattern1".to_string(), "pattern2".to_string()],
+                aliases: BTreeMap::from([("tldr".to_string(), "pattern1".to_string())]),
+                pinned: vec!["pattern2".to_string()],
+            },
+        };
+
+        let json = serde_json::to_string(&response).unwrap();
+        assert!(json.contains("\"type\":\"native.patternsList\""));
+        assert!(json.contains("\"patterns\""));
+        assert!(json.contains("pattern1"));
+        assert!(json.contains("\"aliases\":{\"tldr\":\"pattern1\"}"));
+        assert!(json.contains("\"pinned\":[\"pattern2\"]"));
+        assert!(json.contains("pattern2"));
+    }
+
+    #[test]
+    fn test_host_heartbeat_response_serialization() {
+        let response = Response {
+            id: Uuid::nil(),
+            payload: ResponsePayload::HostHeartbeat {
+                sequence: 3,
+                uptime_ms: 45_000,
+            },
+        };
+
+        let json = serde_json::to_string(&response).unwrap();
+        assert!(json.contains("\"type\":\"native.hostHeartbeat\""));
+        assert!(json.contains("\"sequence\":3"));
+        assert!(json.contains("\"uptimeMs\":45000"));
+    }
+
+    #[test]
+    fn test_heartbeat_round_trip() {
+        let request: Request = serde_json::from_str(
+            r#"{"id":"550e8400-e29b-41d4-a716-446655440000","type":"native.heartbeat"}"#,
+        )
+        .unwrap();
+        assert_eq!(request.payload, RequestPayload::Heartbeat);
+
+        let json = serde_json::to_string(&Response {
+            id: request.id,
+            payload: ResponsePayload::HeartbeatAck {
+                uptime_ms: 1_500,
+                active_requests: 2,
+            },
+        })
+        .unwrap();
+        assert!(json.contains("\"type\":\"native.heartbeatAck\""));
+        assert!(json.contains("\"uptimeMs\":1500"));
+        assert!(json.contains("\"activeRequests\":2"));
+    }
+
+    #[test]
+    fn test_set_config_distinguishes_null_from_absent() {
+        let request: Request = serde_json::from_str(
+            r#"{"id":"550e8400-e29b-41d4-a716-446655440000","type":"native.setConfig","config":{"defaultModel":null,"maxConcurrentProcesses":2,"logLevel":"debug"}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            request.payload,
+            RequestPayload::SetConfig {
+                config: HostConfigUpdate {
+                    default_model: Some(None),
+                    patterns_dir: None,
+                    max_concurrent_processes: Some(Some(2)),
+                    log_level: Some(Some(LogLevel::Debug)),
+                },
+            }
+        );
+        assert_eq!(LogLevel::from_name(" WARN "), Some(LogLevel::Warn));
+        assert!(LogLevel::Warn < LogLevel::Info);
+    }
+
+    #[test]
+    fn test_process_content_request() {
+        let json = r#"{
+            "id": "550e8400-e29b-41d4-a716-446655440000",
+            "path": "/usr/bin/fabric",
+            "type": "native.processContent",
+            "content": "test content",
+            "model": "gpt-4",
+            "pattern": "summarize"
+        }"#;
+
+        let request: Request = serde_json::from_str(json).unwrap();
+        assert_eq!(request.path, Some(Utf8PathBuf::from("/usr/bin/fabric")));
+        match request.payload {
+            RequestPayload::ProcessContent {
+                content,
+                model,
+                pattern,
+                ..
+            } => {
+                assert_eq!(content, ContentInput::Text("test content".to_string()));
+                assert_eq!(model, Some("gpt-4".to_string()));
+                assert_eq!(pattern, Some("summarize".to_string()));
+            }
+            _ => panic!("Expected ProcessContent request"),
+        }
+    }
+
+    #[test]
+    fn test_process_content_documents_request() {
+        let json = r#"{
+            "id": "550e8400-e29b-41d4-a716-446655440000",
+            "path": null,
+            "type": "native.processContent",
+            "content": [
+                {"title": "First article", "text": "Alpha text\n"},
+                {"title": "Second article", "text": "Beta text"}
+            ],
+            "pattern": "compare"
+        }"#;
+
+        let request: Request = serde_json::from_str(json).unwrap();
+        let RequestPayload::ProcessContent { content, .. } = request.payload else {
+            panic!("Expected ProcessContent request");
+        };
+
+        assert_eq!(
+            content.into_text(),
+            "=== Document 1 of 2: First article ===\n\
+             Alpha text\n\
+             === End of document 1 ===\n\
+             \n\
+             === Document 2 of 2: Second article ===\n\
+             Beta text\n\
+             === End of document 2 ===\n"
+        );
+    }
+
+    #[test]
+    fn test_list_contexts_request_serialization() {
+        let request = Request {
+            id: Uuid::new_v4(),
+            path: None,
+            validate_only: false,
+            payload: RequestPayload::ListContexts,
+        };
+
+        let json = serde_json::to_string(&request).unwrap();
+        assert!(json.contains("\"type\":\"native.listContexts\""));
+        assert!(json.contains("\"id\""));
+    }
+
+    #[test]
+    fn test_list_contexts_request_deserialization() {
+        let json = r#"{
+            "id": "550e8400-e29b-41d4-a716-446655440000",
+            "type": "native.listContexts"
+        }"#;
+
+        let request: Request = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            request.id.to_string(),
+            "550e8400-e29b-41d4-a716-446655440000"
+        );
+        assert_matches!(request.payload, RequestPayload::ListContexts);
+    }
+
+    #[test]
+    fn test_contexts_list_response_serialization() {
+        let response = Response {
+            id: Uuid::new_v4(),
+            payload: ResponsePayload::ContextsList {
+                contexts: vec!["context1".to_string(), "context2".to_string()],
+            },
+        };
+
+        let json = serde_json::to_string(&response).unwrap();
+        assert!(json.contains("\"type\":\"native.contextsList\""));
+        assert!(json.contains("\"contexts\""));
+        assert!(json.contains("context1"));
+        assert!(json.contains("context2"));
+    }
+
+    #[test]
+    fn test_process_content_with_context() {
+        let json = r#"{
+            "id": "550e8400-e29b-41d4-a716-446655440000",
+            "path": "/usr/bin/fabric",
+            "type": "native.processContent",
+            "content": "test content",
+            "model": "gpt-4",
+            "pattern": "summarize",
+            "context": "tapestry"
+        }"#;
+
+        let request: Request = serde_json::from_str(json).unwrap();
+        match request.payload {
+            RequestPayload::ProcessContent {
+                content,
+                model,
+                pattern,
+                context,
+                ..
+            } => {
+                assert_eq!(content, ContentInput::Text("test content".to_string()));
+                assert_eq!(model, Some("gpt-4".to_string()));
+                assert_eq!(pattern, Some("summarize".to_string()));
+                assert_eq!(context, Some("tapestry".to_string()));
+            }
+            _ => panic!("Expected ProcessContent request"),
+        }
+    }
+
+    #[test]
+    fn test_metrics_response_serialization() {
+        let response = Response {
+            id: Uuid::new_v4(),
+            payload: ResponsePayload::Metrics(HostMetrics {
+                uptime_ms: 1000,
+                requests: BTreeMap::from([("native.ping".to_string(), 2)]),
+                failures: BTreeMap::new(),
+                time_to_first_chunk: Histogram::default(),
+                spawn_time: Histogram {
+                    count: 1,
+                    sum_ms: 7,
+                    max_ms: 7,
+                    buckets: vec![HistogramBucket {
+                        le_ms: None,
+                        count: 1,
+                    }],
+                },
+            }),
+        };
+
+        let json = serde_json::to_value(&response).unwrap();
+        assert_eq!(json["type"], "native.metrics");
+        assert_eq!(json["requests"]["native.ping"], 2);
+        assert_eq!(json["spawnTime"]["sumMs"], 7);
+        assert_eq!(
+            json["spawnTime"]["buckets"][0]["leMs"],
+            serde_json::Value::Null
+        );
+        assert_eq!(serde_json::from_value::<Response>(json).unwrap(), response);
+    }
+
+    #[test]
+    fn test_done_response_serialization_with_usage() {
+        let response = Response {
+            id: Uuid::new_v4(),
+            payload: ResponsePayload::Done {
+                exit_code: Some(0),
+                usage: Some(TokenUsage {
+                    prompt_tokens: 12,
+                    completion_tokens: 34,
+                    estimated: true,
+                }),
+                generation: None,
+                duration_ms: Some(1500),
+                time_to_first_chunk_ms: Some(200),
+                spawn_time_ms: Some(5),
+                content_hash: None,
+                stderr_tail: None,
+                error: None,
+                content: None,
+                summary: None,
+                output_format: None,
+                truncated: false,
+            },
+        };
+
+        let json = serde_json::to_string(&response).unwrap();
+        assert!(json.contains("\"type\":\"native.done\""));
+        assert!(json.contains("\"promptTokens\":12"));
+        assert!(json.contains("\"completionTokens\":34"));
+        assert!(json.contains("\"estimated\":true"));
+        assert!(json.contains("\"durationMs\":1500"));
+        assert!(json.contains("\"timeToFirstChunkMs\":200"));
+        assert!(json.contains("\"spawnTimeMs\":5"));
+    }
+}
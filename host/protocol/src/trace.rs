@@ -0,0 +1,299 @@
+use std::{
+    env,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use camino::Utf8Path;
+use serde::Serialize;
+use serde_json::Value;
+
+pub const TRACE_ENV_VAR: &str = "TAPESTRY_PROTOCOL_TRACE";
+const MAX_TRACED_FRAME_SIZE: usize = 4096;
+const REDACTED_KEYS: &[&str] = &[
+    "content",
+    "text",
+    "data",
+    "selection",
+    "line",
+    "stderrTail",
+    "summary",
+    "custom_prompt",
+    "customPrompt",
+    "systemPrompt",
+    "secret",
+];
+// Keys whose whole value is redacted, whatever its shape: parsed model output
+// and request environment maps.
+const REDACTED_VALUE_KEYS: &[&str] = &["value", "env"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TraceEntry {
+    seq: u64,
+    timestamp_ms: u128,
+    direction: Direction,
+    size: usize,
+    frame: Value,
+}
+
+pub struct ProtocolTracer {
+    file: Mutex<File>,
+    sequence: AtomicU64,
+}
+
+impl ProtocolTracer {
+    pub fn open<P: AsRef<Utf8Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path.as_ref())?;
+
+        Ok(Self {
+            file: Mutex::new(file),
+            sequence: AtomicU64::new(0),
+        })
+    }
+
+    pub fn from_env() -> io::Result<Option<Self>> {
+        match env::var(TRACE_ENV_VAR) {
+            Ok(path) if !path.is_empty() => Self::open(path).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn record(&self, direction: Direction, frame: &[u8]) {
+        self.record_frame(direction, frame.len(), serde_json::from_slice(frame).ok());
+    }
+
+    pub fn record_frame(&self, direction: Direction, size: usize, frame: Option<Value>) {
+        let entry = TraceEntry {
+            seq: self.sequence.fetch_add(1, Ordering::Relaxed) + 1,
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_millis()),
+            direction,
+            size,
+            frame: redact_frame(size, frame),
+        };
+
+        if let Ok(mut line) = serde_json::to_vec(&entry) {
+            line.push(b'\n');
+            if let Ok(mut file) = self.file.lock() {
+                let _ = file.write_all(&line);
+            }
+        }
+    }
+}
+
+fn redact_frame(size: usize, frame: Option<Value>) -> Value {
+    let Some(mut value) = frame else {
+        return Value::String(format!("<invalid json, {size} bytes>"));
+    };
+
+    redact_value(&mut value);
+
+    let serialized = value.to_string();
+    if serialized.len() > MAX_TRACED_FRAME_SIZE {
+        let mut end = MAX_TRACED_FRAME_SIZE;
+        while !serialized.is_char_boundary(end) {
+            end -= 1;
+        }
+        Value::String(format!("{}...<truncated>", &serialized[..end]))
+    } else {
+        value
+    }
+}
+
+fn redact_value(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, field) in map.iter_mut() {
+                match field {
+                    Value::String(text)
+                        if REDACTED_KEYS.contains(&key.as_str())
+                            || REDACTED_VALUE_KEYS.contains(&key.as_str()) =>
+                    {
+                        *field = Value::String(format!("<redacted, {} bytes>", text.len()));
+                    }
+                    Value::Null => {}
+                    _ if REDACTED_VALUE_KEYS.contains(&key.as_str()) => {
+                        let size = field.to_string().len();
+                        *field = Value::String(format!("<redacted, {size} bytes>"));
+                    }
+                    _ => redact_value(field),
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact_value),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use camino_tempfile::tempdir;
+
+    use super::*;
+
+    fn read_entries(path: &Utf8Path) -> Vec<Value> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_record_redacts_content() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("trace.jsonl");
+        let tracer = ProtocolTracer::open(&path).unwrap();
+
+        tracer.record(
+            Direction::Inbound,
+            br#"{"id":"1","type":"native.processContent","content":"secret page"}"#,
+        );
+
+        let entries = read_entries(&path);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["seq"], 1);
+        assert_eq!(entries[0]["direction"], "inbound");
+        assert_eq!(entries[0]["frame"]["type"], "native.processContent");
+        assert_eq!(entries[0]["frame"]["content"], "<redacted, 11 bytes>");
+    }
+
+    #[test]
+    fn test_record_redacts_document_text() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("trace.jsonl");
+        let tracer = ProtocolTracer::open(&path).unwrap();
+
+        tracer.record(
+            Direction::Inbound,
+            br#"{"type":"native.processContent","content":[{"title":"Notes","text":"private"}]}"#,
+        );
+
+        let entries = read_entries(&path);
+        assert_eq!(entries[0]["frame"]["content"][0]["title"], "Notes");
+        assert_eq!(
+            entries[0]["frame"]["content"][0]["text"],
+            "<redacted, 7 bytes>"
+        );
+    }
+
+    #[test]
+    fn test_record_redacts_chunks_and_stderr() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("trace.jsonl");
+        let tracer = ProtocolTracer::open(&path).unwrap();
+
+        tracer.record(
+            Direction::Inbound,
+            br#"{"type":"native.contentChunk","requestId":"1","seq":0,"data":"page part"}"#,
+        );
+        tracer.record(
+            Direction::Outbound,
+            br#"{"id":"1","type":"native.stderrLine","line":"token sk-123 rejected"}"#,
+        );
+        tracer.record(
+            Direction::Inbound,
+            br#"{"type":"native.processContent","selection":"picked","env":{"TOKEN":"abc"}}"#,
+        );
+        tracer.record(
+            Direction::Outbound,
+            br#"{"id":"1","type":"native.json","value":{"answer":"private"}}"#,
+        );
+        tracer.record(
+            Direction::Outbound,
+            br#"{"id":"1","type":"native.json","value":["private"]}"#,
+        );
+
+        let log = fs::read_to_string(&path).unwrap();
+        for text in ["page part", "sk-123", "picked", "abc", "private"] {
+            assert!(!log.contains(text), "{text} leaked into the trace");
+        }
+        let entries = read_entries(&path);
+        assert_eq!(entries[0]["frame"]["data"], "<redacted, 9 bytes>");
+        assert_eq!(entries[0]["frame"]["seq"], 0);
+        assert_eq!(entries[1]["frame"]["line"], "<redacted, 21 bytes>");
+        assert_eq!(entries[2]["frame"]["selection"], "<redacted, 6 bytes>");
+        assert_eq!(entries[2]["frame"]["env"], "<redacted, 15 bytes>");
+        assert_eq!(entries[3]["frame"]["value"], "<redacted, 20 bytes>");
+        assert_eq!(entries[4]["frame"]["value"], "<redacted, 11 bytes>");
+    }
+
+    #[test]
+    fn test_record_redacts_secret() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("trace.jsonl");
+        let tracer = ProtocolTracer::open(&path).unwrap();
+
+        tracer.record(
+            Direction::Inbound,
+            br#"{"type":"native.setSecret","name":"openai","secret":"sk-123"}"#,
+        );
+
+        let entries = read_entries(&path);
+        assert_eq!(entries[0]["frame"]["name"], "openai");
+        assert_eq!(entries[0]["frame"]["secret"], "<redacted, 6 bytes>");
+    }
+
+    #[test]
+    fn test_record_sequences_and_directions() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("trace.jsonl");
+        let tracer = ProtocolTracer::open(&path).unwrap();
+
+        tracer.record(Direction::Inbound, br#"{"type":"native.ping"}"#);
+        tracer.record(Direction::Outbound, br#"{"type":"native.pong"}"#);
+
+        let entries = read_entries(&path);
+        assert_eq!(entries[0]["seq"], 1);
+        assert_eq!(entries[1]["seq"], 2);
+        assert_eq!(entries[1]["direction"], "outbound");
+    }
+
+    #[test]
+    fn test_record_invalid_json() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("trace.jsonl");
+        let tracer = ProtocolTracer::open(&path).unwrap();
+
+        tracer.record(Direction::Inbound, b"not json");
+
+        let entries = read_entries(&path);
+        assert_eq!(entries[0]["frame"], "<invalid json, 8 bytes>");
+        assert_eq!(entries[0]["size"], 8);
+    }
+
+    #[test]
+    fn test_record_caps_frame_size() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("trace.jsonl");
+        let tracer = ProtocolTracer::open(&path).unwrap();
+
+        let patterns: Vec<String> = (0..2000).map(|i| format!("pattern_{i}")).collect();
+        let frame = serde_json::to_vec(&serde_json::json!({ "patterns": patterns })).unwrap();
+        tracer.record(Direction::Outbound, &frame);
+
+        let entries = read_entries(&path);
+        let traced = entries[0]["frame"].as_str().unwrap();
+        assert!(traced.ends_with("...<truncated>"));
+        assert!(traced.len() < MAX_TRACED_FRAME_SIZE + 32);
+    }
+}
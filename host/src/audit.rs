@@ -0,0 +1,391 @@
+use std::{
+    collections::HashMap,
+    env,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use bytes::BytesMut;
+use camino::Utf8Path;
+use serde::Serialize;
+use serde_json::Value;
+use tokio_util::codec::Encoder;
+use uuid::Uuid;
+
+use crate::{Request, Response, ResponsePayload};
+
+pub const AUDIT_ENV_VAR: &str = "TAPESTRY_AUDIT_LOG";
+
+// String fields the log keeps as written. Every other string (page content,
+// selections, prompts, secrets, env values, parsed output) is replaced by its
+// size, so a field added later is redacted unless it is listed here.
+const AUDITED_KEYS: &[&str] = &[
+    "type",
+    "id",
+    "path",
+    "requestId",
+    "jobId",
+    "model",
+    "pattern",
+    "patterns",
+    "context",
+    "summaryModel",
+    "locale",
+    "outputFormat",
+    "streamMode",
+    "compression",
+    "title",
+    "name",
+    "names",
+    "state",
+    "code",
+    "hint",
+    "field",
+    "message",
+    "contentHash",
+    "resolvedPath",
+    "binary",
+    "version",
+];
+// Some failures end a request without a reply; their start times are dropped
+// after this long so the map cannot grow without bound.
+const PENDING_TTL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+enum AuditEvent {
+    Request,
+    Response,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AuditEntry {
+    timestamp_ms: u128,
+    event: AuditEvent,
+    id: Uuid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration_ms: Option<u128>,
+    frame: Value,
+}
+
+/// Opt-in JSON lines record of each request and the response that ended it.
+///
+/// Unlike the protocol trace, streamed frames are skipped and only the
+/// metadata fields in `AUDITED_KEYS` keep their text, so the log shows what
+/// was asked for and how it finished without holding any page content.
+pub struct AuditLog {
+    file: Mutex<File>,
+    pending: Mutex<HashMap<Uuid, Instant>>,
+}
+
+impl AuditLog {
+    pub fn open<P: AsRef<Utf8Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path.as_ref())?;
+
+        Ok(Self {
+            file: Mutex::new(file),
+            pending: Mutex::default(),
+        })
+    }
+
+    pub fn from_env() -> io::Result<Option<Self>> {
+        match env::var(AUDIT_ENV_VAR) {
+            Ok(path) if !path.is_empty() => Self::open(path).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn record_request(&self, request: &Request) {
+        let mut pending = self.pending();
+        pending.retain(|_, started| started.elapsed() < PENDING_TTL);
+        pending.insert(request.id, Instant::now());
+        drop(pending);
+        self.write(AuditEvent::Request, request.id, None, request);
+    }
+
+    /// Whether `response` ends its request; host-initiated frames (nil id)
+    /// and intermediate stream frames are not recorded.
+    pub fn records(response: &Response) -> bool {
+        !response.id.is_nil() && is_terminal(&response.payload)
+    }
+
+    pub fn record_response(&self, response: &Response) {
+        if !Self::records(response) {
+            return;
+        }
+        let duration_ms = self
+            .pending()
+            .remove(&response.id)
+            .map(|started| started.elapsed().as_millis());
+        self.write(AuditEvent::Response, response.id, duration_ms, response);
+    }
+
+    fn pending(&self) -> MutexGuard<'_, HashMap<Uuid, Instant>> {
+        self.pending.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write<T: Serialize>(
+        &self,
+        event: AuditEvent,
+        id: Uuid,
+        duration_ms: Option<u128>,
+        frame: &T,
+    ) {
+        let Ok(mut frame) = serde_json::to_value(frame) else {
+            return;
+        };
+        redact_content(&mut frame, false);
+        let entry = AuditEntry {
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_millis()),
+            event,
+            id,
+            duration_ms,
+            frame,
+        };
+
+        if let Ok(mut line) = serde_json::to_vec(&entry) {
+            line.push(b'\n');
+            if let Ok(mut file) = self.file.lock() {
+                let _ = file.write_all(&line);
+            }
+        }
+    }
+}
+
+fn is_terminal(payload: &ResponsePayload) -> bool {
+    !matches!(
+        payload,
+        ResponsePayload::StreamStart { .. }
+            | ResponsePayload::Queued { .. }
+            | ResponsePayload::Content { .. }
+            | ResponsePayload::Json { .. }
+            | ResponsePayload::StderrLine { .. }
+            | ResponsePayload::Progress { .. }
+            | ResponsePayload::Heartbeat { .. }
+            | ResponsePayload::BatchItem { .. }
+            | ResponsePayload::Warning { .. }
+    )
+}
+
+/// Replaces every string outside `AUDITED_KEYS` with its size. `audited`
+/// carries the key of an enclosing array so lists like `patterns` survive.
+fn redact_content(value: &mut Value, audited: bool) {
+    match value {
+        Value::Object(map) => {
+            for (key, field) in map.iter_mut() {
+                redact_content(field, AUDITED_KEYS.contains(&key.as_str()));
+            }
+        }
+        Value::Array(items) => items
+            .iter_mut()
+            .for_each(|item| redact_content(item, audited)),
+        Value::String(text) if !audited => {
+            *value = Value::String(format!("<redacted, {} bytes>", text.len()));
+        }
+        _ => {}
+    }
+}
+
+/// Wraps the outbound codec so every response passes through the audit log
+/// on its way to the extension. A response is recorded only once it has been
+/// encoded, so a frame that never reached the extension is not logged.
+#[derive(Clone)]
+pub struct AuditEncoder<E> {
+    inner: E,
+    audit: Option<Arc<AuditLog>>,
+}
+
+impl<E> AuditEncoder<E> {
+    pub fn new(inner: E, audit: Option<Arc<AuditLog>>) -> Self {
+        Self { inner, audit }
+    }
+}
+
+impl<E: Encoder<Response>> Encoder<Response> for AuditEncoder<E> {
+    type Error = E::Error;
+
+    fn encode(&mut self, item: Response, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let Some(audit) = &self.audit else {
+            return self.inner.encode(item, dst);
+        };
+        let recorded = AuditLog::records(&item).then(|| item.clone());
+        self.inner.encode(item, dst)?;
+        if let Some(response) = recorded {
+            audit.record_response(&response);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use camino_tempfile::tempdir;
+
+    use super::*;
+    use crate::{RequestPayload, codec::NativeMessagingCodec};
+
+    fn read_entries(path: &Utf8Path) -> Vec<Value> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    fn set_secret(id: Uuid) -> Request {
+        Request {
+            id,
+            path: None,
+            validate_only: false,
+            payload: RequestPayload::SetSecret {
+                name: "openai".to_string(),
+                secret: "sk-123".to_string(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_records_request_and_terminal_response() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let audit = Arc::new(AuditLog::open(&path).unwrap());
+        let mut encoder = AuditEncoder::new(
+            NativeMessagingCodec::<Response>::default(),
+            Some(audit.clone()),
+        );
+        let id = Uuid::new_v4();
+
+        audit.record_request(&set_secret(id));
+        let mut dst = BytesMut::new();
+        for payload in [
+            ResponsePayload::Queued { limit: 4 },
+            ResponsePayload::Content {
+                content: "partial".to_string(),
+            },
+            ResponsePayload::SecretNames {
+                names: vec!["openai".to_string()],
+            },
+        ] {
+            encoder.encode(Response { id, payload }, &mut dst).unwrap();
+        }
+        encoder
+            .encode(
+                Response {
+                    id: Uuid::nil(),
+                    payload: ResponsePayload::ShutdownAck,
+                },
+                &mut dst,
+            )
+            .unwrap();
+        assert!(!dst.is_empty());
+
+        let entries = read_entries(&path);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["event"], "request");
+        assert_eq!(entries[0]["id"], id.to_string());
+        assert_eq!(entries[0]["frame"]["type"], "native.setSecret");
+        assert_eq!(entries[0]["frame"]["secret"], "<redacted, 6 bytes>");
+        assert!(entries[0].get("durationMs").is_none());
+        assert_eq!(entries[1]["event"], "response");
+        assert_eq!(entries[1]["frame"]["type"], "native.secretNames");
+        assert!(entries[1]["durationMs"].is_u64());
+    }
+
+    #[test]
+    fn test_does_not_record_response_that_fails_to_encode() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let audit = Arc::new(AuditLog::open(&path).unwrap());
+        let mut encoder = AuditEncoder::new(
+            NativeMessagingCodec::<Response>::new(64),
+            Some(audit.clone()),
+        );
+
+        let response = Response {
+            id: Uuid::new_v4(),
+            payload: ResponsePayload::Error {
+                message: "x".repeat(128),
+                details: None,
+            },
+        };
+        assert!(encoder.encode(response, &mut BytesMut::new()).is_err());
+
+        assert!(read_entries(&path).is_empty());
+    }
+
+    #[test]
+    fn test_chunk_and_selection_content_stay_out_of_the_log() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let audit = AuditLog::open(&path).unwrap();
+        let id = Uuid::new_v4();
+
+        audit.record_request(&Request {
+            id: Uuid::new_v4(),
+            path: None,
+            validate_only: false,
+            payload: RequestPayload::ContentChunk {
+                request_id: id,
+                seq: 0,
+                data: "chunk of the page".to_string(),
+                last: false,
+            },
+        });
+        let request: Request = serde_json::from_value(serde_json::json!({
+            "id": id,
+            "type": "native.processContent",
+            "content": "the page",
+            "selection": "selected words",
+            "custom_prompt": "my prompt",
+            "env": {"API_TOKEN": "tok-1"},
+            "pattern": "summarize",
+        }))
+        .unwrap();
+        audit.record_request(&request);
+
+        let log = fs::read_to_string(&path).unwrap();
+        for text in [
+            "chunk of the page",
+            "the page",
+            "selected words",
+            "my prompt",
+            "tok-1",
+        ] {
+            assert!(!log.contains(text), "{text} leaked into the audit log");
+        }
+        let entries = read_entries(&path);
+        assert_eq!(entries[0]["frame"]["data"], "<redacted, 17 bytes>");
+        assert_eq!(entries[1]["frame"]["selection"], "<redacted, 14 bytes>");
+        assert_eq!(entries[1]["frame"]["pattern"], "summarize");
+    }
+
+    #[test]
+    fn test_redact_content_keeps_audited_keys() {
+        let mut frame = serde_json::json!({
+            "type": "native.processContent",
+            "content": [{"title": "Notes", "text": "private"}],
+            "patterns": ["summarize", "extract_wisdom"],
+            "value": {"answer": "model output", "score": 3},
+            "model": "gpt-4o",
+        });
+
+        redact_content(&mut frame, false);
+
+        assert_eq!(frame["content"][0]["title"], "Notes");
+        assert_eq!(frame["content"][0]["text"], "<redacted, 7 bytes>");
+        assert_eq!(frame["patterns"][1], "extract_wisdom");
+        assert_eq!(frame["value"]["answer"], "<redacted, 12 bytes>");
+        assert_eq!(frame["value"]["score"], 3);
+        assert_eq!(frame["model"], "gpt-4o");
+    }
+}
@@ -0,0 +1,146 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Mutex, OnceLock},
+};
+
+use camino::{Utf8Path, Utf8PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FabricVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl FabricVersion {
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    pub fn parse(output: &str) -> Option<Self> {
+        output.split_whitespace().find_map(|token| {
+            let token = token.trim_start_matches(['v', 'V']);
+            let mut parts = token.split('.').map(|part| {
+                let digits: String = part.chars().take_while(char::is_ascii_digit).collect();
+                digits.parse::<u32>().ok()
+            });
+
+            let major = parts.next()??;
+            let minor = parts.next()??;
+            let patch = parts.next().flatten().unwrap_or(0);
+            Some(Self::new(major, minor, patch))
+        })
+    }
+}
+
+impl fmt::Display for FabricVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "v{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FabricFeature {
+    Contexts,
+    Seed,
+}
+
+impl FabricFeature {
+    pub fn name(self) -> &'static str {
+        match self {
+            FabricFeature::Contexts => "contexts",
+            FabricFeature::Seed => "seed",
+        }
+    }
+
+    pub fn min_version(self) -> FabricVersion {
+        match self {
+            FabricFeature::Contexts => FabricVersion::new(1, 1, 0),
+            FabricFeature::Seed => FabricVersion::new(1, 4, 0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    version: Option<FabricVersion>,
+}
+
+impl Capabilities {
+    pub fn new(version: Option<FabricVersion>) -> Self {
+        Self { version }
+    }
+
+    pub fn version(&self) -> Option<FabricVersion> {
+        self.version
+    }
+
+    pub fn supports(&self, feature: FabricFeature) -> bool {
+        self.version
+            .is_none_or(|version| version >= feature.min_version())
+    }
+}
+
+fn cache() -> &'static Mutex<HashMap<Utf8PathBuf, FabricVersion>> {
+    static CACHE: OnceLock<Mutex<HashMap<Utf8PathBuf, FabricVersion>>> = OnceLock::new();
+    CACHE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+pub fn cached_version(fabric_path: &Utf8Path) -> Option<FabricVersion> {
+    cache().lock().ok()?.get(fabric_path).copied()
+}
+
+pub fn cache_version(fabric_path: &Utf8Path, version: FabricVersion) {
+    if let Ok(mut cache) = cache().lock() {
+        cache.insert(fabric_path.to_owned(), version);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(
+            FabricVersion::parse("v1.4.123"),
+            Some(FabricVersion::new(1, 4, 123))
+        );
+        assert_eq!(
+            FabricVersion::parse("fabric version 1.2"),
+            Some(FabricVersion::new(1, 2, 0))
+        );
+        assert_eq!(
+            FabricVersion::parse("v1.4.123-beta"),
+            Some(FabricVersion::new(1, 4, 123))
+        );
+        assert_eq!(FabricVersion::parse("unknown"), None);
+    }
+
+    #[test]
+    fn test_supports_feature() {
+        let old = Capabilities::new(Some(FabricVersion::new(1, 0, 9)));
+        let new = Capabilities::new(Some(FabricVersion::new(1, 4, 0)));
+        let unknown = Capabilities::new(None);
+
+        assert!(!old.supports(FabricFeature::Contexts));
+        assert!(new.supports(FabricFeature::Contexts));
+        assert!(unknown.supports(FabricFeature::Contexts));
+        assert!(!old.supports(FabricFeature::Seed));
+        assert!(new.supports(FabricFeature::Seed));
+    }
+
+    #[test]
+    fn test_version_cache() {
+        let path = Utf8PathBuf::from("/capabilities/test/fabric-ai");
+        assert_eq!(cached_version(&path), None);
+
+        cache_version(&path, FabricVersion::new(1, 4, 0));
+        assert_eq!(cached_version(&path), Some(FabricVersion::new(1, 4, 0)));
+    }
+}
@@ -0,0 +1,146 @@
+use crate::{Composition, ContentInput};
+
+pub const CHARS_PER_TOKEN: u64 = 4;
+pub const DEFAULT_MAX_TOKENS: u64 = 8000;
+pub const DEFAULT_SELECTION_RATIO: f64 = 0.6;
+const ELLIPSIS: &str = "…";
+
+pub fn compose_content(
+    content: ContentInput,
+    selection: Option<String>,
+    composition: Option<Composition>,
+) -> String {
+    let page = content.into_text();
+    match selection {
+        Some(selection) => compose_selection(&selection, &page, composition.unwrap_or_default()),
+        None => page,
+    }
+}
+
+pub fn compose_selection(selection: &str, page: &str, composition: Composition) -> String {
+    let max_tokens = composition.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS);
+    let ratio = composition
+        .selection_ratio
+        .unwrap_or(DEFAULT_SELECTION_RATIO)
+        .clamp(0.0, 1.0);
+    let budget = usize::try_from(max_tokens.saturating_mul(CHARS_PER_TOKEN)).unwrap_or(usize::MAX);
+
+    let selection = selection.trim();
+    let selection_chars = selection.chars().count();
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    let selection_budget = (budget as f64 * ratio) as usize;
+    let selection = if selection_chars > selection_budget {
+        format!("{}{ELLIPSIS}", take_chars(selection, selection_budget))
+    } else {
+        selection.to_string()
+    };
+    let page_budget = budget.saturating_sub(selection_chars.min(selection_budget));
+
+    let mut composed =
+        format!("=== Selected text ===\n{selection}\n=== End of selected text ===\n");
+    if let Some(excerpt) = excerpt(page.trim(), &selection, page_budget) {
+        composed.push_str(&format!(
+            "\n=== Page context ===\n{excerpt}\n=== End of page context ===\n"
+        ));
+    }
+    composed
+}
+
+fn excerpt(page: &str, selection: &str, budget: usize) -> Option<String> {
+    let page_chars = page.chars().count();
+    if page_chars == 0 || budget == 0 {
+        return None;
+    }
+    if page_chars <= budget {
+        return Some(page.to_string());
+    }
+
+    let center = selection
+        .trim_end_matches(ELLIPSIS)
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .and_then(|line| {
+            let offset = page.find(line)?;
+            Some(page[..offset].chars().count() + line.chars().count() / 2)
+        })
+        .unwrap_or(0);
+    let start = center.saturating_sub(budget / 2).min(page_chars - budget);
+
+    let mut excerpt = page.chars().skip(start).take(budget).collect::<String>();
+    if start > 0 {
+        excerpt.insert_str(0, ELLIPSIS);
+    }
+    if start + budget < page_chars {
+        excerpt.push_str(ELLIPSIS);
+    }
+    Some(excerpt)
+}
+
+fn take_chars(text: &str, count: usize) -> &str {
+    text.char_indices()
+        .nth(count)
+        .map_or(text, |(index, _)| &text[..index])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn composition(max_tokens: u64, selection_ratio: f64) -> Composition {
+        Composition {
+            max_tokens: Some(max_tokens),
+            selection_ratio: Some(selection_ratio),
+        }
+    }
+
+    #[test]
+    fn test_short_inputs_are_kept_whole() {
+        assert_eq!(
+            compose_selection(
+                "  the key line ",
+                "Intro.\nThe key line.\n",
+                Composition::default()
+            ),
+            "=== Selected text ===\nthe key line\n=== End of selected text ===\n\n=== Page context ===\nIntro.\nThe key line.\n=== End of page context ===\n"
+        );
+    }
+
+    #[test]
+    fn test_page_excerpt_is_centered_on_selection() {
+        let page = format!("{}needle{}", "a".repeat(100), "b".repeat(100));
+
+        let composed = compose_selection("needle", &page, composition(5, 0.5));
+
+        assert!(composed.contains(&format!(
+            "=== Page context ===\n…{}needle{}…\n",
+            "a".repeat(4),
+            "b".repeat(4)
+        )));
+    }
+
+    #[test]
+    fn test_selection_is_truncated_to_its_share() {
+        let composed = compose_selection(&"é".repeat(50), "", composition(5, 0.5));
+
+        assert_eq!(
+            composed,
+            format!(
+                "=== Selected text ===\n{}…\n=== End of selected text ===\n",
+                "é".repeat(10)
+            )
+        );
+    }
+
+    #[test]
+    fn test_without_selection_page_is_unchanged() {
+        assert_eq!(
+            compose_content("page".to_string().into(), None, None),
+            "page"
+        );
+    }
+}
@@ -0,0 +1,650 @@
+use std::{collections::BTreeMap, env, fs, io, time::Duration};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Deserializer, de};
+use toml::Table;
+use toml_edit::{Array, DocumentMut, Item, TableLike, Value};
+
+use crate::{
+    HostConfig, HostConfigUpdate, LogLevel, locale::LocaleFormat, ratelimit::RateLimit,
+    secrets::SecretRef, store, usage::Budget,
+};
+
+pub const CONFIG_ENV_VAR: &str = "TAPESTRY_CONFIG";
+const PINNED_KEY: &str = "pinned_patterns";
+const FABRIC_PATH_KEY: &str = "fabric_path";
+const FABRIC_SHA256_KEY: &str = "fabric_sha256";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub fabric_path: Option<Utf8PathBuf>,
+    pub fabric_sha256: Option<String>,
+    pub search_paths: Vec<Utf8PathBuf>,
+    pub binary_names: Vec<String>,
+    pub allowed_executables: Vec<Utf8PathBuf>,
+    pub allowed_cwd_roots: Vec<Utf8PathBuf>,
+    pub env_allowlist: Option<Vec<String>>,
+    pub allowed_request_env: Vec<String>,
+    pub defaults: Defaults,
+    pub limits: Limits,
+    pub timeouts: Timeouts,
+    pub logging: Logging,
+    #[serde(deserialize_with = "deserialize_flag_values")]
+    pub aliases: BTreeMap<String, String>,
+    pub pinned_patterns: Vec<String>,
+    pub secrets: BTreeMap<String, SecretRef>,
+    pub rate_limits: BTreeMap<String, RateLimit>,
+    pub budget: Budget,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Defaults {
+    #[serde(deserialize_with = "deserialize_flag_value")]
+    pub model: Option<String>,
+    #[serde(deserialize_with = "deserialize_flag_value")]
+    pub pattern: Option<String>,
+    pub patterns_dir: Option<Utf8PathBuf>,
+    #[serde(deserialize_with = "deserialize_locale")]
+    pub locale: Option<String>,
+    #[serde(deserialize_with = "deserialize_flag_values")]
+    pub pattern_models: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    pub max_concurrent_processes: Option<usize>,
+    pub max_output_bytes: Option<usize>,
+    pub max_chunked_content_bytes: Option<usize>,
+    pub max_inbound_message_size: Option<usize>,
+    pub max_outbound_message_size: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Timeouts {
+    pub command_ms: Option<u64>,
+    pub request_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Logging {
+    pub level: Option<LogLevel>,
+    pub file: Option<Utf8PathBuf>,
+    pub protocol_trace: Option<Utf8PathBuf>,
+    pub audit_log: Option<Utf8PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RequestDefaults {
+    pub model: Option<String>,
+    pub pattern: Option<String>,
+    pub pattern_models: BTreeMap<String, String>,
+    pub aliases: BTreeMap<String, String>,
+    pub timeout: Option<Duration>,
+    pub locale: Option<String>,
+}
+
+impl RequestDefaults {
+    pub fn resolve_pattern(&self, pattern: Option<String>) -> Option<String> {
+        pattern.map(|pattern| self.aliases.get(&pattern).cloned().unwrap_or(pattern))
+    }
+
+    pub fn model_for(&self, pattern: Option<&str>) -> Option<String> {
+        pattern
+            .and_then(|pattern| self.pattern_models.get(pattern))
+            .or(self.model.as_ref())
+            .cloned()
+    }
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(text)
+    }
+
+    pub fn load(path: &Utf8Path) -> Result<Self, String> {
+        match fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text).map_err(|e| format!("Ignoring {path}: {e}")),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("Ignoring {path}: {e}")),
+        }
+    }
+
+    pub fn host_config(&self) -> HostConfig {
+        HostConfig {
+            default_model: self.defaults.model.clone(),
+            patterns_dir: self.defaults.patterns_dir.clone(),
+            max_concurrent_processes: self.limits.max_concurrent_processes,
+            log_level: self.logging.level,
+        }
+    }
+
+    pub fn command_timeout(&self) -> Option<Duration> {
+        self.timeouts
+            .command_ms
+            .filter(|&millis| millis > 0)
+            .map(Duration::from_millis)
+    }
+
+    pub fn request_defaults(&self) -> RequestDefaults {
+        RequestDefaults {
+            model: self.defaults.model.clone(),
+            pattern: self.defaults.pattern.clone(),
+            pattern_models: self.defaults.pattern_models.clone(),
+            aliases: self.aliases.clone(),
+            timeout: self
+                .timeouts
+                .request_ms
+                .filter(|&millis| millis > 0)
+                .map(Duration::from_millis),
+            locale: self.defaults.locale.clone(),
+        }
+    }
+}
+
+// Model and pattern names from the config reach fabric as flag arguments
+// without going through request validation, so a leading dash is refused here.
+fn flag_value<E: de::Error>(value: String) -> Result<String, E> {
+    if value.starts_with('-') {
+        return Err(E::custom(format!("{value:?} must not start with '-'")));
+    }
+    Ok(value)
+}
+
+fn deserialize_flag_value<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    flag_value(String::deserialize(deserializer)?).map(Some)
+}
+
+fn deserialize_flag_values<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<BTreeMap<String, String>, D::Error> {
+    BTreeMap::<String, String>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(name, value)| Ok((name, flag_value(value)?)))
+        .collect()
+}
+
+fn deserialize_locale<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    let locale = String::deserialize(deserializer)?;
+    match LocaleFormat::from_tag(&locale) {
+        Some(_) => Ok(Some(locale)),
+        None => Err(de::Error::custom(format!("unsupported locale {locale:?}"))),
+    }
+}
+
+pub fn config_path(var: impl Fn(&str) -> Option<String>) -> Option<Utf8PathBuf> {
+    if let Some(path) = var(CONFIG_ENV_VAR).filter(|path| !path.is_empty()) {
+        return Some(Utf8PathBuf::from(path));
+    }
+    let config_home = var("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(Utf8PathBuf::from)
+        .or_else(|| {
+            var("HOME")
+                .or_else(|| var("USERPROFILE"))
+                .map(|home| Utf8PathBuf::from(home).join(".config"))
+        })?;
+    Some(config_home.join("tapestry").join("config.toml"))
+}
+
+pub fn update_file(path: &Utf8Path, update: &HostConfigUpdate) -> Result<Config, String> {
+    edit_file(path, |document| {
+        apply_update(document, update);
+        Ok(())
+    })
+}
+
+pub fn set_pinned(path: &Utf8Path, pattern: &str, pinned: bool) -> Result<Config, String> {
+    edit_file(path, |document| {
+        let mut patterns: Vec<String> = match document.get(PINNED_KEY).and_then(Item::as_array) {
+            Some(items) => items
+                .iter()
+                .filter_map(Value::as_str)
+                .map(str::to_string)
+                .collect(),
+            None => Vec::new(),
+        };
+        patterns.retain(|existing| existing != pattern);
+        if pinned {
+            patterns.push(pattern.to_string());
+        }
+        if patterns.is_empty() {
+            document.remove(PINNED_KEY);
+        } else {
+            let patterns: Array = patterns.into_iter().collect();
+            set_value(document.as_table_mut(), PINNED_KEY, Value::Array(patterns));
+        }
+        Ok(())
+    })
+}
+
+/// Pins `sha256` as the digest of `fabric_path`, refusing if the file no
+/// longer names that binary as its `fabric_path`.
+pub fn set_fabric_sha256(
+    path: &Utf8Path,
+    fabric_path: &Utf8Path,
+    sha256: &str,
+) -> Result<Config, String> {
+    edit_file(path, |document| {
+        if document.get(FABRIC_PATH_KEY).and_then(Item::as_str) != Some(fabric_path.as_str()) {
+            return Err(format!(
+                "{path} no longer sets fabric_path to {fabric_path}; reload the config and pin again"
+            ));
+        }
+        set_value(
+            document.as_table_mut(),
+            FABRIC_SHA256_KEY,
+            Value::from(sha256),
+        );
+        Ok(())
+    })
+}
+
+/// Reads the config file as written, for diagnostics; a missing file is
+/// `Ok(None)`.
+pub fn read_table(path: &Utf8Path) -> Result<Option<Table>, String> {
+    match fs::read_to_string(path) {
+        Ok(text) => text
+            .parse::<Table>()
+            .map(Some)
+            .map_err(|e| format!("Failed to parse {path}: {e}")),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("Failed to read {path}: {e}")),
+    }
+}
+
+/// Applies `edit` to the config file in place. Comments, key order, and
+/// formatting the user wrote are kept, and the file is replaced atomically
+/// so a crash never leaves it half written.
+fn edit_file(
+    path: &Utf8Path,
+    edit: impl FnOnce(&mut DocumentMut) -> Result<(), String>,
+) -> Result<Config, String> {
+    let mut document = match fs::read_to_string(path) {
+        Ok(text) => text
+            .parse::<DocumentMut>()
+            .map_err(|e| format!("Failed to parse {path}: {e}"))?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => DocumentMut::new(),
+        Err(e) => return Err(format!("Failed to read {path}: {e}")),
+    };
+    edit(&mut document)?;
+
+    let text = document.to_string();
+    let config = Config::parse(&text)
+        .map_err(|e| format!("Refusing to write invalid config to {path}: {e}"))?;
+    store::write_atomic(path, text.as_bytes())
+        .map_err(|e| format!("Failed to write {path}: {e}"))?;
+    Ok(config)
+}
+
+fn apply_update(document: &mut DocumentMut, update: &HostConfigUpdate) {
+    set_key(
+        document,
+        "defaults",
+        "model",
+        update
+            .default_model
+            .clone()
+            .map(|model| model.map(Value::from)),
+    );
+    set_key(
+        document,
+        "defaults",
+        "patterns_dir",
+        update
+            .patterns_dir
+            .clone()
+            .map(|dir| dir.map(|dir| Value::from(dir.into_string()))),
+    );
+    set_key(
+        document,
+        "limits",
+        "max_concurrent_processes",
+        update
+            .max_concurrent_processes
+            .map(|limit| limit.map(|limit| Value::from(i64::try_from(limit).unwrap_or(i64::MAX)))),
+    );
+    set_key(
+        document,
+        "logging",
+        "level",
+        update.log_level.map(|level| {
+            level.and_then(|level| {
+                toml::Value::try_from(level)
+                    .ok()
+                    .and_then(|level| level.as_str().map(Value::from))
+            })
+        }),
+    );
+}
+
+fn set_key(document: &mut DocumentMut, section: &str, key: &str, value: Option<Option<Value>>) {
+    match value {
+        None => {}
+        Some(Some(value)) => {
+            if let Some(section) = document
+                .entry(section)
+                .or_insert_with(toml_edit::table)
+                .as_table_like_mut()
+            {
+                set_value(section, key, value);
+            }
+        }
+        Some(None) => {
+            if let Some(section) = document.get_mut(section).and_then(Item::as_table_like_mut) {
+                section.remove(key);
+            }
+        }
+    }
+}
+
+/// Sets `key` to `value`, keeping any comment written around the old value.
+fn set_value(table: &mut dyn TableLike, key: &str, mut value: Value) {
+    if let Some(Item::Value(existing)) = table.get_mut(key) {
+        *value.decor_mut() = existing.decor().clone();
+        *existing = value;
+    } else {
+        table.insert(key, Item::Value(value));
+    }
+}
+
+pub fn load_from_env() -> Result<Config, String> {
+    match config_path(|name| env::var(name).ok()) {
+        Some(path) => Config::load(&path),
+        None => Ok(Config::default()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use camino_tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_full_config() {
+        let config = Config::parse(
+            r#"
+fabric_path = "/opt/fabric/fabric-ai"
+search_paths = ["/opt/fabric/bin"]
+binary_names = ["fabric", "fabric-ai"]
+allowed_executables = ["/opt/fabric/bin"]
+env_allowlist = ["PATH", "HOME", "*_API_KEY"]
+allowed_request_env = ["OLLAMA_HOST"]
+
+[defaults]
+model = "llama3"
+pattern = "summarize"
+locale = "de-DE"
+
+[defaults.pattern_models]
+extract_wisdom = "gpt-4o-mini"
+
+[limits]
+max_concurrent_processes = 2
+max_output_bytes = 1048576
+max_chunked_content_bytes = 33554432
+
+[timeouts]
+command_ms = 5000
+request_ms = 60000
+
+[logging]
+file = "/tmp/tapestry.log"
+protocol_trace = "/tmp/tapestry.trace"
+audit_log = "/tmp/tapestry-audit.jsonl"
+
+[aliases]
+tldr = "create_5_sentence_summary"
+
+[secrets]
+OPENAI_API_KEY = "keychain:openai"
+
+[rate_limits]
+processContent = { per_minute = 30, burst = 5 }
+
+[budget]
+daily_requests = 200
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.fabric_path.as_deref(),
+            Some(Utf8Path::new("/opt/fabric/fabric-ai"))
+        );
+        assert_eq!(
+            config.search_paths,
+            vec![Utf8PathBuf::from("/opt/fabric/bin")]
+        );
+        assert_eq!(config.binary_names, vec!["fabric", "fabric-ai"]);
+        assert_eq!(
+            config.allowed_executables,
+            vec![Utf8PathBuf::from("/opt/fabric/bin")]
+        );
+        assert_eq!(
+            config.env_allowlist,
+            Some(vec![
+                "PATH".to_string(),
+                "HOME".to_string(),
+                "*_API_KEY".to_string()
+            ])
+        );
+        assert_eq!(config.allowed_request_env, vec!["OLLAMA_HOST"]);
+        assert_eq!(config.limits.max_concurrent_processes, Some(2));
+        assert_eq!(config.limits.max_output_bytes, Some(1_048_576));
+        assert_eq!(config.limits.max_chunked_content_bytes, Some(33_554_432));
+        assert_eq!(config.limits.max_inbound_message_size, None);
+        assert_eq!(config.command_timeout(), Some(Duration::from_secs(5)));
+        assert_eq!(
+            config.request_defaults(),
+            RequestDefaults {
+                model: Some("llama3".to_string()),
+                pattern: Some("summarize".to_string()),
+                pattern_models: BTreeMap::from([(
+                    "extract_wisdom".to_string(),
+                    "gpt-4o-mini".to_string()
+                )]),
+                aliases: BTreeMap::from([(
+                    "tldr".to_string(),
+                    "create_5_sentence_summary".to_string()
+                )]),
+                timeout: Some(Duration::from_secs(60)),
+                locale: Some("de-DE".to_string()),
+            }
+        );
+        let defaults = config.request_defaults();
+        assert_eq!(
+            defaults
+                .resolve_pattern(Some("tldr".to_string()))
+                .as_deref(),
+            Some("create_5_sentence_summary")
+        );
+        assert_eq!(
+            defaults
+                .resolve_pattern(Some("summarize".to_string()))
+                .as_deref(),
+            Some("summarize")
+        );
+        assert_eq!(
+            defaults.model_for(Some("extract_wisdom")).as_deref(),
+            Some("gpt-4o-mini")
+        );
+        assert_eq!(
+            defaults.model_for(Some("summarize")).as_deref(),
+            Some("llama3")
+        );
+        assert_eq!(
+            config.logging.file.as_deref(),
+            Some(Utf8Path::new("/tmp/tapestry.log"))
+        );
+        assert_eq!(
+            config.logging.protocol_trace.as_deref(),
+            Some(Utf8Path::new("/tmp/tapestry.trace"))
+        );
+        assert_eq!(
+            config.logging.audit_log.as_deref(),
+            Some(Utf8Path::new("/tmp/tapestry-audit.jsonl"))
+        );
+        assert_eq!(
+            config.secrets,
+            BTreeMap::from([(
+                "OPENAI_API_KEY".to_string(),
+                SecretRef {
+                    name: "openai".to_string()
+                }
+            )])
+        );
+        let limit = config.rate_limits["processContent"];
+        assert_eq!(limit.per_minute.get(), 30);
+        assert_eq!(limit.burst.map(|burst| burst.get()), Some(5));
+        assert_eq!(
+            config.budget,
+            Budget {
+                daily_requests: Some(200),
+                daily_tokens: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown_keys() {
+        assert!(Config::parse("[limits]\nmax_processes = 2\n").is_err());
+        assert!(Config::parse("[secrets]\nOPENAI_API_KEY = \"sk-123\"\n").is_err());
+        assert!(Config::parse("[rate_limits]\nping = { per_minute = 0 }\n").is_err());
+        assert!(Config::parse("[budget]\nmonthly_requests = 10\n").is_err());
+        assert!(Config::parse("[defaults]\nlocale = \"xx-YY\"\n").is_err());
+        assert!(Config::parse("[aliases]\ntldr = \"--dry-run\"\n").is_err());
+        assert!(Config::parse("[defaults]\nmodel = \"-o/tmp/out\"\n").is_err());
+        assert!(Config::parse("[defaults.pattern_models]\nsummarize = \"-h\"\n").is_err());
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn test_load_treats_missing_file_as_empty() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        assert_eq!(Config::load(&path).unwrap(), Config::default());
+
+        fs::write(&path, "fabric_path = 3\n").unwrap();
+        assert!(Config::load(&path).unwrap_err().starts_with("Ignoring"));
+    }
+
+    #[test]
+    fn test_config_path_prefers_env_then_xdg() {
+        let path = config_path(|name| match name {
+            CONFIG_ENV_VAR => Some("/etc/tapestry.toml".to_string()),
+            "XDG_CONFIG_HOME" => Some("/xdg".to_string()),
+            _ => None,
+        });
+        assert_eq!(path.as_deref(), Some(Utf8Path::new("/etc/tapestry.toml")));
+
+        let path = config_path(|name| match name {
+            "XDG_CONFIG_HOME" => Some("/xdg".to_string()),
+            "HOME" => Some("/home/user".to_string()),
+            _ => None,
+        });
+        assert_eq!(
+            path.as_deref(),
+            Some(Utf8Path::new("/xdg/tapestry/config.toml"))
+        );
+
+        let path = config_path(|name| match name {
+            "HOME" => Some("/home/user".to_string()),
+            _ => None,
+        });
+        assert_eq!(
+            path.as_deref(),
+            Some(Utf8Path::new("/home/user/.config/tapestry/config.toml"))
+        );
+        assert_eq!(config_path(|_| None), None);
+    }
+
+    #[test]
+    fn test_update_file_sets_and_clears_keys() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(
+            &path,
+            "fabric_path = \"/opt/fabric\"\n\n[defaults]\nmodel = \"llama3\"\npattern = \"summarize\"\n",
+        )
+        .unwrap();
+
+        let config = update_file(
+            &path,
+            &HostConfigUpdate {
+                default_model: Some(None),
+                max_concurrent_processes: Some(Some(3)),
+                log_level: Some(Some(LogLevel::Warn)),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(Config::load(&path).unwrap(), config);
+        assert_eq!(
+            config.fabric_path.as_deref(),
+            Some(Utf8Path::new("/opt/fabric"))
+        );
+        assert_eq!(config.defaults.model, None);
+        assert_eq!(config.defaults.pattern.as_deref(), Some("summarize"));
+        assert_eq!(config.limits.max_concurrent_processes, Some(3));
+        assert_eq!(config.logging.level, Some(LogLevel::Warn));
+    }
+
+    #[test]
+    fn test_edits_keep_comments_and_key_order() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        let original = "# Where fabric lives\nfabric_path = \"/opt/fabric\"\nfabric_sha256 = \"00\" # pinned by hand\n\n[defaults]\npattern = \"summarize\" # daily driver\nmodel = \"llama3\"\n\n[logging]\n# quiet by default\nlevel = \"info\"\n";
+        fs::write(&path, original).unwrap();
+
+        update_file(
+            &path,
+            &HostConfigUpdate {
+                default_model: Some(Some("gpt-4o".to_string())),
+                log_level: Some(Some(LogLevel::Debug)),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        set_pinned(&path, "extract_wisdom", true).unwrap();
+        set_fabric_sha256(&path, Utf8Path::new("/opt/fabric"), "abc123").unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "# Where fabric lives\nfabric_path = \"/opt/fabric\"\nfabric_sha256 = \"abc123\" # pinned by hand\npinned_patterns = [\"extract_wisdom\"]\n\n[defaults]\npattern = \"summarize\" # daily driver\nmodel = \"gpt-4o\"\n\n[logging]\n# quiet by default\nlevel = \"debug\"\n"
+        );
+        let leftovers: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .filter(|name| name != "config.toml")
+            .collect();
+        assert!(leftovers.is_empty(), "{leftovers:?}");
+    }
+
+    #[test]
+    fn test_update_file_refuses_to_overwrite_invalid_config() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, "[limits]\nmax_processes = 2\n").unwrap();
+
+        let update = HostConfigUpdate {
+            default_model: Some(Some("llama3".to_string())),
+            ..Default::default()
+        };
+        assert!(update_file(&path, &update).is_err());
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "[limits]\nmax_processes = 2\n"
+        );
+    }
+}
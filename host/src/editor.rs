@@ -0,0 +1,87 @@
+use std::{env, process::Stdio};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use tokio::process::Command;
+use uuid::Uuid;
+
+use crate::handlers::HandlerError;
+
+pub const EDITOR_ENV_VAR: &str = "TAPESTRY_EDITOR";
+const EDITOR_FALLBACK_VARS: [&str; 2] = ["VISUAL", "EDITOR"];
+
+pub fn editor_command(var: impl Fn(&str) -> Option<String>) -> Vec<String> {
+    [EDITOR_ENV_VAR]
+        .into_iter()
+        .chain(EDITOR_FALLBACK_VARS)
+        .filter_map(var)
+        .map(|value| {
+            value
+                .split_whitespace()
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        })
+        .find(|command| !command.is_empty())
+        .unwrap_or_else(system_opener)
+}
+
+fn system_opener() -> Vec<String> {
+    let opener: &[&str] = if cfg!(target_os = "macos") {
+        &["open"]
+    } else if cfg!(windows) {
+        &["cmd", "/C", "start", ""]
+    } else {
+        &["xdg-open"]
+    };
+    opener.iter().map(|part| part.to_string()).collect()
+}
+
+pub async fn write_result(request_id: Uuid, content: &str) -> Result<Utf8PathBuf, HandlerError> {
+    let temp_dir =
+        Utf8PathBuf::from_path_buf(env::temp_dir()).map_err(HandlerError::PathNotUtf8)?;
+    let path = temp_dir.join(format!("tapestry-{request_id}.md"));
+    tokio::fs::write(&path, content).await?;
+    Ok(path)
+}
+
+pub fn launch(command: &[String], path: &Utf8Path) -> Result<(), HandlerError> {
+    let Some((program, args)) = command.split_first() else {
+        return Ok(());
+    };
+
+    Command::new(program)
+        .args(args)
+        .arg(path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|source| HandlerError::EditorLaunch {
+            editor: program.clone(),
+            source,
+        })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_editor_command_prefers_configured_editor() {
+        let command = editor_command(|name| match name {
+            EDITOR_ENV_VAR => Some("code --wait".to_string()),
+            "EDITOR" => Some("vim".to_string()),
+            _ => None,
+        });
+        assert_eq!(command, vec!["code", "--wait"]);
+
+        let command = editor_command(|name| match name {
+            "VISUAL" => Some("  ".to_string()),
+            "EDITOR" => Some("vim".to_string()),
+            _ => None,
+        });
+        assert_eq!(command, vec!["vim"]);
+
+        assert_eq!(editor_command(|_| None), system_opener());
+    }
+}
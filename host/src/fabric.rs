@@ -1,11 +1,68 @@
-use std::process::Stdio;
+use std::{env, process::Stdio};
 
-use camino::Utf8Path;
+use camino::{Utf8Path, Utf8PathBuf};
 use tokio::process::Command;
 
+#[cfg(windows)]
+const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+
+pub const DEFAULT_ENV_ALLOWLIST: &[&str] = &[
+    "PATH",
+    "HOME",
+    "USER",
+    "LOGNAME",
+    "SHELL",
+    "LANG",
+    "LC_*",
+    "TZ",
+    "TMPDIR",
+    "TEMP",
+    "TMP",
+    "XDG_*",
+    "USERPROFILE",
+    "APPDATA",
+    "LOCALAPPDATA",
+    "SYSTEMROOT",
+    "WINDIR",
+    "COMSPEC",
+    "PATHEXT",
+    "HTTP_PROXY",
+    "HTTPS_PROXY",
+    "NO_PROXY",
+    "SSL_CERT_FILE",
+    "SSL_CERT_DIR",
+    "DEFAULT_VENDOR",
+    "DEFAULT_MODEL",
+    "DEFAULT_MODEL_CONTEXT_LENGTH",
+    "FABRIC_*",
+    "OLLAMA_*",
+    "*_API_KEY",
+    "*_API_BASE_URL",
+    "*_API_URL",
+];
+
+pub fn env_allowed<S: AsRef<str>>(name: &str, allowlist: &[S]) -> bool {
+    let name = name.to_ascii_uppercase();
+    allowlist.iter().any(|pattern| {
+        let pattern = pattern.as_ref().to_ascii_uppercase();
+        if let Some(prefix) = pattern.strip_suffix('*') {
+            name.starts_with(prefix)
+        } else if let Some(suffix) = pattern.strip_prefix('*') {
+            name.ends_with(suffix)
+        } else {
+            name == pattern
+        }
+    })
+}
+
 pub struct FabricCommandBuilder<'a> {
     fabric_path: &'a Utf8Path,
     args: Vec<String>,
+    system_prompt: Option<String>,
+    prompt: Option<String>,
+    envs: Vec<(String, String)>,
+    current_dir: Option<Utf8PathBuf>,
+    env_allowlist: Option<Vec<String>>,
     stdin: Option<Stdio>,
     stdout: Option<Stdio>,
     stderr: Option<Stdio>,
@@ -16,6 +73,11 @@ impl<'a> FabricCommandBuilder<'a> {
         Self {
             fabric_path,
             args: Vec::new(),
+            system_prompt: None,
+            prompt: None,
+            envs: Vec::new(),
+            current_dir: None,
+            env_allowlist: None,
             stdin: None,
             stdout: None,
             stderr: None,
@@ -60,11 +122,37 @@ impl<'a> FabricCommandBuilder<'a> {
         self
     }
 
+    pub fn temperature(mut self, temperature: f64) -> Self {
+        self.args.push("--temperature".to_string());
+        self.args.push(temperature.to_string());
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.args.push("--seed".to_string());
+        self.args.push(seed.to_string());
+        self
+    }
+
+    /// The prompt is always emitted last, after a `--` separator, so text
+    /// that looks like a flag is never parsed as one.
     pub fn custom_prompt<S: Into<String>>(mut self, prompt: S) -> Self {
-        self.args.push(prompt.into());
+        self.prompt = Some(prompt.into());
         self
     }
 
+    /// Fabric has no flag for extra system instructions, so the system
+    /// prompt is passed as its own argument ahead of the custom prompt and
+    /// leads the message that follows the pattern.
+    pub fn system_prompt<S: Into<String>>(mut self, prompt: S) -> Self {
+        self.system_prompt = Some(prompt.into());
+        self
+    }
+
+    fn prompts(&self) -> impl Iterator<Item = &String> {
+        self.system_prompt.iter().chain(&self.prompt)
+    }
+
     pub fn args<I, S>(mut self, args: I) -> Self
     where
         I: IntoIterator<Item = S>,
@@ -74,6 +162,21 @@ impl<'a> FabricCommandBuilder<'a> {
         self
     }
 
+    pub fn env<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.envs.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn env_allowlist(mut self, allowlist: Vec<String>) -> Self {
+        self.env_allowlist = Some(allowlist);
+        self
+    }
+
+    pub fn current_dir(mut self, dir: Utf8PathBuf) -> Self {
+        self.current_dir = Some(dir);
+        self
+    }
+
     pub fn stdin(mut self, stdin: Stdio) -> Self {
         self.stdin = Some(stdin);
         self
@@ -89,12 +192,38 @@ impl<'a> FabricCommandBuilder<'a> {
         self
     }
 
+    pub fn command_line(&self) -> Vec<String> {
+        let mut command_line = vec![self.fabric_path.to_string()];
+        command_line.extend(self.args.iter().cloned());
+        if self.prompts().next().is_some() {
+            command_line.push("--".to_string());
+            command_line.extend(self.prompts().cloned());
+        }
+        command_line
+    }
+
     pub fn build(self) -> Command {
         let mut command = Command::new(self.fabric_path.as_str());
 
-        for arg in self.args {
+        for arg in &self.args {
             command.arg(arg);
         }
+        if self.prompts().next().is_some() {
+            command.arg("--").args(self.prompts());
+        }
+
+        if let Some(allowlist) = self.env_allowlist {
+            command.env_clear();
+            command.envs(env::vars_os().filter(|(name, _)| {
+                name.to_str()
+                    .is_some_and(|name| env_allowed(name, &allowlist))
+            }));
+        }
+        command.envs(self.envs);
+
+        if let Some(dir) = self.current_dir {
+            command.current_dir(dir);
+        }
 
         if let Some(stdin) = self.stdin {
             command.stdin(stdin);
@@ -108,6 +237,10 @@ impl<'a> FabricCommandBuilder<'a> {
             command.stderr(stderr);
         }
 
+        // Hosts launched by the browser have no console; keep fabric from opening one.
+        #[cfg(windows)]
+        command.creation_flags(CREATE_NO_WINDOW);
+
         command
     }
 }
@@ -132,6 +265,69 @@ mod tests {
         assert!(builder.stderr.is_none());
     }
 
+    #[test]
+    fn test_builder_current_dir() {
+        let path = Utf8PathBuf::from("/usr/bin/fabric-ai");
+        let command = FabricCommandBuilder::new(&path)
+            .current_dir(Utf8PathBuf::from("/projects/site"))
+            .build();
+
+        assert_eq!(
+            command.as_std().get_current_dir(),
+            Some(std::path::Path::new("/projects/site"))
+        );
+    }
+
+    #[test]
+    fn test_env_allowed_matches_exact_prefix_and_suffix_patterns() {
+        let allowlist = ["PATH", "LC_*", "*_API_KEY"];
+        assert!(env_allowed("PATH", &allowlist));
+        assert!(env_allowed("Path", &allowlist));
+        assert!(env_allowed("LC_ALL", &allowlist));
+        assert!(env_allowed("OPENAI_API_KEY", &allowlist));
+        assert!(!env_allowed("PATHEXT", &allowlist));
+        assert!(!env_allowed("AWS_SECRET_ACCESS_KEY", &allowlist));
+        assert!(env_allowed("ANTHROPIC_API_KEY", DEFAULT_ENV_ALLOWLIST));
+    }
+
+    #[test]
+    fn test_builder_env_allowlist_scrubs_inherited_env() {
+        let path = Utf8PathBuf::from("/usr/bin/fabric-ai");
+        let command = FabricCommandBuilder::new(&path)
+            .env_allowlist(vec!["PATH".to_string()])
+            .env("CUSTOM_PATTERNS_DIRECTORY", "/patterns")
+            .build();
+
+        let names: Vec<_> = command.as_std().get_envs().map(|(name, _)| name).collect();
+        assert!(
+            names
+                .iter()
+                .all(|name| *name == "PATH" || *name == "CUSTOM_PATTERNS_DIRECTORY")
+        );
+        assert!(names.contains(&std::ffi::OsStr::new("CUSTOM_PATTERNS_DIRECTORY")));
+        assert_eq!(
+            names.contains(&std::ffi::OsStr::new("PATH")),
+            env::var_os("PATH").is_some()
+        );
+    }
+
+    #[test]
+    fn test_builder_env() {
+        let path = Utf8PathBuf::from("/usr/bin/fabric-ai");
+        let command = FabricCommandBuilder::new(&path)
+            .env("CUSTOM_PATTERNS_DIRECTORY", "/patterns")
+            .build();
+
+        let envs: Vec<_> = command.as_std().get_envs().collect();
+        assert_eq!(
+            envs,
+            vec![(
+                std::ffi::OsStr::new("CUSTOM_PATTERNS_DIRECTORY"),
+                Some(std::ffi::OsStr::new("/patterns"))
+            )]
+        );
+    }
+
     #[test]
     fn test_builder_version() {
         let path = Utf8PathBuf::from("/usr/bin/fabric-ai");
@@ -175,9 +371,65 @@ mod tests {
     #[test]
     fn test_builder_custom_prompt() {
         let path = Utf8PathBuf::from("/usr/bin/fabric-ai");
-        let builder = FabricCommandBuilder::new(&path).custom_prompt("custom prompt");
+        let builder = FabricCommandBuilder::new(&path)
+            .custom_prompt("--pattern evil")
+            .model("gpt-4");
+
+        assert_eq!(
+            builder.command_line(),
+            vec![
+                "/usr/bin/fabric-ai",
+                "--model",
+                "gpt-4",
+                "--",
+                "--pattern evil"
+            ]
+        );
+        let command = builder.build();
+        let args: Vec<_> = command.as_std().get_args().collect();
+        assert_eq!(args, ["--model", "gpt-4", "--", "--pattern evil"]);
+    }
+
+    #[test]
+    fn test_builder_system_prompt_precedes_custom_prompt() {
+        let path = Utf8PathBuf::from("/usr/bin/fabric-ai");
+        let builder = FabricCommandBuilder::new(&path)
+            .custom_prompt("List the key points")
+            .system_prompt("--answer in French")
+            .pattern("summarize");
+
+        let expected = [
+            "--pattern",
+            "summarize",
+            "--",
+            "--answer in French",
+            "List the key points",
+        ];
+        assert_eq!(builder.command_line()[1..], expected);
+        let command = builder.build();
+        let args: Vec<_> = command.as_std().get_args().collect();
+        assert_eq!(args, expected);
+    }
+
+    #[test]
+    fn test_builder_command_line() {
+        let path = Utf8PathBuf::from("/usr/bin/fabric-ai");
+        let builder = FabricCommandBuilder::new(&path)
+            .stream()
+            .pattern("summarize");
+
+        assert_eq!(
+            builder.command_line(),
+            vec!["/usr/bin/fabric-ai", "--stream", "--pattern", "summarize"]
+        );
+    }
+
+    #[test]
+    fn test_builder_sampling() {
+        let path = Utf8PathBuf::from("/usr/bin/fabric-ai");
+        let builder = FabricCommandBuilder::new(&path).temperature(0.0).seed(7);
 
-        assert_eq!(builder.args, vec!["custom prompt"]);
+        assert_eq!(builder.args, vec!["--temperature", "0", "--seed", "7"]);
     }
 
     #[test]
@@ -0,0 +1,50 @@
+use crate::{OutputFormat, plaintext::PlainTextFormatter};
+
+pub enum OutputFormatter {
+    Plaintext(PlainTextFormatter),
+    Json,
+}
+
+impl OutputFormatter {
+    pub fn new(format: OutputFormat) -> Option<Self> {
+        match format {
+            OutputFormat::Markdown => None,
+            OutputFormat::Plaintext => {
+                Some(OutputFormatter::Plaintext(PlainTextFormatter::default()))
+            }
+            OutputFormat::Json => Some(OutputFormatter::Json),
+        }
+    }
+
+    pub fn format_line(&mut self, line: &str) -> String {
+        match self {
+            OutputFormatter::Plaintext(formatter) => formatter.format_line(line),
+            OutputFormatter::Json => {
+                let trimmed = line.trim_start();
+                if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+                    String::new()
+                } else {
+                    line.to_string()
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_output_drops_code_fences() {
+        let mut formatter = OutputFormatter::new(OutputFormat::Json).unwrap();
+
+        let output: String = ["```json\n", "{\"a\": 1}\n", "```\n"]
+            .into_iter()
+            .map(|line| formatter.format_line(line))
+            .collect();
+
+        assert_eq!(output, "{\"a\": 1}\n");
+        assert!(OutputFormatter::new(OutputFormat::Markdown).is_none());
+    }
+}
@@ -0,0 +1,159 @@
+use crate::{GenerationStats, TokenUsage};
+
+pub fn parse_generation_stats(stderr: &str) -> Option<GenerationStats> {
+    let mut prompt_eval_count = None;
+    let mut eval_count = None;
+    let mut eval_duration_ms = None;
+    let mut eval_rate = None;
+
+    for line in stderr.lines() {
+        let line = line.trim().to_ascii_lowercase();
+        if let Some((key, value)) = line.split_once(':')
+            && !key.contains('"')
+        {
+            let value = value.trim();
+            match key.trim() {
+                "prompt eval count" => prompt_eval_count = leading_number(value),
+                "eval count" => eval_count = leading_number(value),
+                "eval duration" => eval_duration_ms = parse_duration_ms(value),
+                "eval rate" => eval_rate = leading_float(value),
+                _ => {}
+            }
+        }
+
+        prompt_eval_count = json_number(&line, "prompt_eval_count").or(prompt_eval_count);
+        eval_count = json_number(&line, "eval_count").or(eval_count);
+        eval_duration_ms = json_number(&line, "eval_duration")
+            .map(|nanos| nanos / 1_000_000)
+            .or(eval_duration_ms);
+    }
+
+    let eval_count = eval_count?;
+    #[allow(clippy::cast_precision_loss)]
+    let tokens_per_second = eval_rate.or_else(|| {
+        eval_duration_ms
+            .filter(|&millis| millis > 0)
+            .map(|millis| eval_count as f64 * 1000.0 / millis as f64)
+    })?;
+
+    Some(GenerationStats {
+        prompt_eval_count,
+        eval_count,
+        eval_duration_ms,
+        tokens_per_second: (tokens_per_second * 100.0).round() / 100.0,
+    })
+}
+
+pub fn measured_usage(stats: &GenerationStats) -> Option<TokenUsage> {
+    Some(TokenUsage {
+        prompt_tokens: stats.prompt_eval_count?,
+        completion_tokens: stats.eval_count,
+        estimated: false,
+    })
+}
+
+fn leading_number(value: &str) -> Option<u64> {
+    let end = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    value[..end].parse().ok()
+}
+
+fn leading_float(value: &str) -> Option<f64> {
+    let end = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    value[..end].parse().ok()
+}
+
+fn json_number(line: &str, key: &str) -> Option<u64> {
+    let pattern = format!("\"{key}\":");
+    let start = line.find(&pattern)? + pattern.len();
+    leading_number(line[start..].trim_start())
+}
+
+fn parse_duration_ms(value: &str) -> Option<u64> {
+    let mut rest = value.trim();
+    let mut total_ms = 0.0;
+    while !rest.is_empty() {
+        let number_end = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        let number: f64 = rest[..number_end].parse().ok()?;
+        rest = &rest[number_end..];
+        let unit_end = rest
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(rest.len());
+        let scale = match &rest[..unit_end] {
+            "h" => 3_600_000.0,
+            "m" => 60_000.0,
+            "s" => 1000.0,
+            "ms" => 1.0,
+            "µs" | "us" => 0.001,
+            "ns" => 0.000_001,
+            _ => return None,
+        };
+        total_ms += number * scale;
+        rest = &rest[unit_end..];
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    Some(total_ms.round() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_ollama_verbose_stats() {
+        let stderr = "\
+total duration:       5.1s
+prompt eval count:    26 token(s)
+prompt eval duration: 123.4ms
+prompt eval rate:     211.38 tokens/s
+eval count:           290 token(s)
+eval duration:        4.5s
+eval rate:            64.44 tokens/s
+";
+
+        let stats = parse_generation_stats(stderr).unwrap();
+
+        assert_eq!(
+            stats,
+            GenerationStats {
+                prompt_eval_count: Some(26),
+                eval_count: 290,
+                eval_duration_ms: Some(4500),
+                tokens_per_second: 64.44,
+            }
+        );
+        assert_eq!(
+            measured_usage(&stats),
+            Some(TokenUsage {
+                prompt_tokens: 26,
+                completion_tokens: 290,
+                estimated: false,
+            })
+        );
+    }
+
+    #[test]
+    fn test_parses_json_stats_and_derives_rate() {
+        let stderr = r#"DEBUG response: {"done":true,"eval_count":120,"eval_duration":1500000000}"#;
+
+        let stats = parse_generation_stats(stderr).unwrap();
+
+        assert_eq!(stats.prompt_eval_count, None);
+        assert_eq!(stats.eval_count, 120);
+        assert_eq!(stats.eval_duration_ms, Some(1500));
+        assert_eq!(stats.tokens_per_second, 80.0);
+        assert_eq!(measured_usage(&stats), None);
+    }
+
+    #[test]
+    fn test_ignores_stderr_without_stats() {
+        assert_eq!(parse_generation_stats("error: model not found\n"), None);
+        assert_eq!(parse_duration_ms("1m2.5s"), Some(62_500));
+    }
+}
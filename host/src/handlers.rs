@@ -1,33 +1,624 @@
-use std::{collections::HashMap, error, io, path::PathBuf, process::Stdio, sync::Arc};
+use std::{
+    any::Any,
+    collections::{BTreeMap, HashMap},
+    env, error,
+    ffi::OsString,
+    io::{self, Read},
+    mem,
+    ops::ControlFlow,
+    path::PathBuf,
+    pin::{Pin, pin},
+    process::Stdio,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
 
 use async_trait::async_trait;
 use camino::{Utf8Path, Utf8PathBuf};
-use futures_util::SinkExt;
+use futures_util::{Sink, SinkExt, StreamExt, future::BoxFuture, stream};
 use thiserror::Error;
 use tokio::{
-    io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader},
-    process::{Child, ChildStdin, ChildStdout},
-    sync::{Mutex, watch},
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    process::{Child, ChildStderr, ChildStdin, ChildStdout},
+    sync::{Mutex, Semaphore, SemaphorePermit, mpsc, watch},
+    time::{Instant, MissedTickBehavior, interval_at, sleep_until},
 };
 use tokio_util::codec::{Encoder, FramedWrite};
 use uuid::Uuid;
 
-use crate::{Request, RequestPayload, Response, ResponsePayload, fabric::FabricCommandBuilder};
+use crate::{
+    BatchContent, BootReport, CacheStates, Compression, ContentInput, DebugState, ErrorDetails,
+    FieldError, HostConfigUpdate, HostStats, JobState, OutputFormat, QuickLook, Request,
+    RequestPayload, ResolvedInputs, Response, ResponsePayload, StreamMode, TokenUsage,
+    capabilities::{self, Capabilities, FabricFeature, FabricVersion},
+    codec::{MalformedFrame, SUPPORTED_COMPRESSION},
+    compose::{self, CHARS_PER_TOKEN},
+    config::{self, Config},
+    editor,
+    fabric::{FabricCommandBuilder, env_allowed},
+    format::OutputFormatter,
+    generation, integrity,
+    jobs::{self, JobRegistry, JobSink},
+    locale::LocaleFormat,
+    metrics::Metrics,
+    replay::ReplaySink,
+    secrets::{self, SecretError, SecretRef, SecretStore},
+    state::HostState,
+    store,
+    usage::{self, QuotaExceeded, UsageTracker},
+    validation,
+};
+
+pub const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_MAX_CONCURRENT_PROCESSES: usize = 4;
+pub const DEFAULT_MAX_OUTPUT_BYTES: usize = 16 * 1024 * 1024;
+pub const DEFAULT_MAX_CHUNKED_CONTENT_BYTES: usize = 256 * 1024 * 1024;
+const CHUNKED_UPLOAD_HINT: &str = "Upload the content as native.contentChunk frames, then send processContent with the same id and empty content";
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+const STDERR_TAIL_SIZE: usize = 4096;
+const COALESCE_MAX_BYTES: usize = 16 * 1024;
+const STDOUT_CHUNK_SIZE: usize = 1024;
+const BATCH_CONCURRENCY: usize = 4;
+const SUMMARY_THRESHOLD_CHARS: u64 = 4000;
+const DETERMINISTIC_TEMPERATURE: f64 = 0.0;
+const DETERMINISTIC_SEED: u64 = 1;
+const SUMMARY_PROMPT: &str =
+    "Write a TL;DR of the following text in at most three sentences. Reply with the TL;DR only.";
+#[cfg(windows)]
+const EXEC_FORMAT_ERROR: i32 = 193;
+#[cfg(not(windows))]
+const EXEC_FORMAT_ERROR: i32 = 8;
+
+struct FailureClass {
+    code: &'static str,
+    summary: &'static str,
+    hint: &'static str,
+    needles: &'static [&'static str],
+}
+
+const FAILURE_CLASSES: &[FailureClass] = &[
+    FailureClass {
+        code: "missingApiKey",
+        summary: "the model provider rejected the request because no valid API key is configured",
+        hint: "Run `fabric --setup` to configure an API key for your model provider",
+        needles: &[
+            "api key",
+            "api_key",
+            "apikey",
+            "401 unauthorized",
+            "status code: 401",
+            "invalid x-api-key",
+            "authentication",
+        ],
+    },
+    FailureClass {
+        code: "unknownPattern",
+        summary: "the selected pattern is not installed",
+        hint: "Run `fabric --updatepatterns` or choose a different pattern",
+        needles: &[
+            "pattern not found",
+            "could not find pattern",
+            "no such pattern",
+            "could not get pattern",
+        ],
+    },
+    FailureClass {
+        code: "noDefaultModel",
+        summary: "no model was selected and fabric has no default model",
+        hint: "Choose a model in the extension or run `fabric --setup` to set a default model",
+        needles: &[
+            "no default model",
+            "model not specified",
+            "could not find vendor",
+            "no model specified",
+        ],
+    },
+    FailureClass {
+        code: "networkFailure",
+        summary: "fabric could not reach the model provider",
+        hint: "Check your network connection, proxy settings, or whether the provider is reachable",
+        needles: &[
+            "connection refused",
+            "no such host",
+            "dial tcp",
+            "i/o timeout",
+            "tls handshake timeout",
+            "network is unreachable",
+            "connection reset",
+        ],
+    },
+];
 
 pub type ProcessRegistry = Arc<Mutex<HashMap<Uuid, watch::Sender<bool>>>>;
+pub type ChunkRegistry = Arc<std::sync::Mutex<HashMap<Uuid, ChunkBuffer>>>;
+
+const CHUNK_TTL: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone)]
+pub struct ProcessSlots {
+    semaphore: Arc<Semaphore>,
+    limits: Arc<std::sync::Mutex<SlotLimits>>,
+    waiting: Arc<AtomicUsize>,
+}
+
+#[derive(Debug)]
+struct SlotLimits {
+    limit: usize,
+    debt: usize,
+}
+
+impl ProcessSlots {
+    pub fn new(limit: usize) -> Self {
+        let limit = limit.max(1);
+        Self {
+            semaphore: Arc::new(Semaphore::new(limit)),
+            limits: Arc::new(std::sync::Mutex::new(SlotLimits { limit, debt: 0 })),
+            waiting: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    fn limits(&self) -> std::sync::MutexGuard<'_, SlotLimits> {
+        self.limits
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limits().limit
+    }
+
+    pub fn available(&self) -> usize {
+        self.semaphore
+            .available_permits()
+            .saturating_sub(self.limits().debt)
+    }
+
+    /// Requests currently queued for a slot.
+    pub fn waiting(&self) -> usize {
+        self.waiting.load(Ordering::Relaxed)
+    }
+
+    pub fn set_limit(&self, limit: usize) {
+        let limit = limit.max(1);
+        let mut limits = self.limits();
+        if limit > limits.limit {
+            let grow = limit - limits.limit;
+            let repaid = grow.min(limits.debt);
+            limits.debt -= repaid;
+            self.semaphore.add_permits(grow - repaid);
+        } else {
+            let shrink = limits.limit - limit;
+            let forgotten = self.semaphore.forget_permits(shrink);
+            limits.debt += shrink - forgotten;
+        }
+        limits.limit = limit;
+    }
+
+    fn repay_debt(&self) -> bool {
+        let mut limits = self.limits();
+        if limits.debt == 0 {
+            return false;
+        }
+        limits.debt -= 1;
+        true
+    }
+
+    async fn acquire<S>(
+        &self,
+        writer: &mut S,
+        request_id: Uuid,
+        mut cancel_rx: watch::Receiver<bool>,
+    ) -> Result<Option<SemaphorePermit<'_>>, HandlerError>
+    where
+        S: Sink<Response> + Unpin,
+        HandlerError: From<S::Error>,
+    {
+        let mut queued = None;
+        loop {
+            let permit = match self.semaphore.try_acquire() {
+                Ok(permit) => permit,
+                Err(_) => {
+                    if queued.is_none() {
+                        queued = Some(WaitingRequest::new(&self.waiting));
+                        writer
+                            .send(Response {
+                                id: request_id,
+                                payload: ResponsePayload::Queued {
+                                    limit: self.limit(),
+                                },
+                            })
+                            .await?;
+                    }
+                    tokio::select! {
+                        permit = self.semaphore.acquire() => match permit {
+                            Ok(permit) => permit,
+                            Err(_) => return Ok(None),
+                        },
+                        _ = cancel_rx.wait_for(|cancelled| *cancelled) => return Ok(None),
+                    }
+                }
+            };
+            // Permits freed after the limit shrank are retired instead of handed out.
+            if self.repay_debt() {
+                permit.forget();
+                continue;
+            }
+            return Ok(Some(permit));
+        }
+    }
+}
+
+struct WaitingRequest<'a>(&'a AtomicUsize);
+
+impl<'a> WaitingRequest<'a> {
+    fn new(waiting: &'a AtomicUsize) -> Self {
+        waiting.fetch_add(1, Ordering::Relaxed);
+        Self(waiting)
+    }
+}
+
+impl Drop for WaitingRequest<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Default for ProcessSlots {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_CONCURRENT_PROCESSES)
+    }
+}
+
+#[derive(Debug)]
+pub struct ChunkBuffer {
+    next_seq: u64,
+    data: String,
+    complete: bool,
+    updated: Instant,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ProcessOptions {
+    pub model: Option<String>,
+    pub pattern: Option<String>,
+    pub context: Option<String>,
+    pub custom_prompt: Option<String>,
+    pub system_prompt: Option<String>,
+    pub locale: Option<String>,
+    pub aggregate: bool,
+    pub summarize: bool,
+    pub summary_model: Option<String>,
+    pub output_format: OutputFormat,
+    pub deterministic: bool,
+    pub quick_look: Option<QuickLook>,
+    pub coalesce_interval: Option<Duration>,
+    pub coalesce_bytes: Option<usize>,
+    pub stream_mode: StreamMode,
+    pub strict: bool,
+    pub structured: bool,
+    pub timeout: Option<Duration>,
+    pub max_output_bytes: Option<usize>,
+    pub max_frame_bytes: Option<usize>,
+    pub heartbeat: Option<Duration>,
+    pub cwd: Option<Utf8PathBuf>,
+    pub env: BTreeMap<String, String>,
+}
 
 #[derive(Debug, Error)]
 pub enum HandlerError {
     #[error("I/O error: {0}")]
     Io(#[from] io::Error),
-    #[error("Failed to find fabric-ai in PATH: {0}")]
+    #[error("Failed to find fabric in PATH or well-known install locations: {0}")]
     FabricNotFound(#[from] which::Error),
     #[error("Path is not UTF-8: {}", .0.display())]
     PathNotUtf8(PathBuf),
+    #[error("{path} is not a usable fabric executable: {reason}")]
+    InvalidExecutable {
+        path: Utf8PathBuf,
+        reason: &'static str,
+    },
     #[error("Codec error: {0}")]
     Codec(#[from] crate::codec::CodecError),
     #[error("Process was cancelled")]
     Cancelled,
+    #[error("Request {0} is already in flight")]
+    DuplicateRequestId(Uuid),
+    #[error("Host panicked while handling the request: {0}")]
+    Panicked(String),
+    #[error("Content is {size} bytes, which exceeds the {limit} byte limit")]
+    ContentTooLarge { size: usize, limit: usize },
+    #[error("Process timed out after {}ms", .0.as_millis())]
+    Timeout(Duration),
+    #[error("Fabric {found} does not support {feature}; {required} or newer is required")]
+    UnsupportedFabricFeature {
+        feature: &'static str,
+        found: FabricVersion,
+        required: FabricVersion,
+    },
+    #[error("Failed to start {path}: {source}")]
+    Spawn {
+        path: Utf8PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Strict mode: {0}")]
+    Strict(String),
+    #[error("Failed to reload configuration: {0}")]
+    ConfigReload(String),
+    #[error("Config file error: {0}")]
+    ConfigFile(String),
+    #[error("Too many {request_type} requests; retry in {}ms", retry_after_ms(*.retry_after))]
+    RateLimited {
+        request_type: &'static str,
+        retry_after: Duration,
+    },
+    #[error(transparent)]
+    QuotaExceeded(#[from] QuotaExceeded),
+    #[error("{0} is not in the executable allowlist")]
+    ExecutableNotAllowed(Utf8PathBuf),
+    #[error("{path} has SHA-256 {found}, but {expected} is pinned")]
+    IntegrityMismatch {
+        path: Utf8PathBuf,
+        expected: String,
+        found: String,
+    },
+    #[error("Working directory {0} is not inside an allowed root")]
+    CwdNotAllowed(Utf8PathBuf),
+    #[error("Environment variable {0} is not allowed")]
+    EnvNotAllowed(String),
+    #[error("Secret store error: {0}")]
+    Secrets(#[from] SecretError),
+    #[error("No secret named {0} is stored")]
+    SecretNotFound(String),
+    #[error("Pattern output is not valid JSON: {0}")]
+    InvalidJson(#[source] serde_json::Error),
+    #[error("Response is {size} bytes, which exceeds the {limit} byte frame limit")]
+    ResponseTooLarge { size: usize, limit: usize },
+    #[error("Failed to launch editor {editor}: {source}")]
+    EditorLaunch {
+        editor: String,
+        #[source]
+        source: io::Error,
+    },
+}
+
+impl From<std::convert::Infallible> for HandlerError {
+    fn from(never: std::convert::Infallible) -> Self {
+        match never {}
+    }
+}
+
+impl HandlerError {
+    fn unsupported(feature: FabricFeature, capabilities: &Capabilities) -> Option<Self> {
+        match capabilities.version() {
+            Some(found) if !capabilities.supports(feature) => {
+                Some(HandlerError::UnsupportedFabricFeature {
+                    feature: feature.name(),
+                    found,
+                    required: feature.min_version(),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            HandlerError::Io(_) => "io",
+            HandlerError::FabricNotFound(_) => "fabricNotFound",
+            HandlerError::PathNotUtf8(_) => "pathNotUtf8",
+            HandlerError::InvalidExecutable { .. } => "invalidExecutable",
+            HandlerError::Codec(_) => "codec",
+            HandlerError::Cancelled => "cancelled",
+            HandlerError::DuplicateRequestId(_) => "duplicateRequestId",
+            HandlerError::Panicked(_) => "internalError",
+            HandlerError::ContentTooLarge { .. } => "contentTooLarge",
+            HandlerError::Timeout(_) => "timeout",
+            HandlerError::UnsupportedFabricFeature { .. } => "unsupportedFabricFeature",
+            HandlerError::Spawn { .. } => "spawnFailed",
+            HandlerError::Strict(_) => "strict",
+            HandlerError::ConfigReload(_) => "configReloadFailed",
+            HandlerError::ConfigFile(_) => "configFileError",
+            HandlerError::RateLimited { .. } => "rateLimited",
+            HandlerError::QuotaExceeded(_) => "quotaExceeded",
+            HandlerError::ExecutableNotAllowed(_) => "executableNotAllowed",
+            HandlerError::IntegrityMismatch { .. } => "integrityMismatch",
+            HandlerError::CwdNotAllowed(_) => "cwdNotAllowed",
+            HandlerError::EnvNotAllowed(_) => "envNotAllowed",
+            HandlerError::Secrets(SecretError::Missing { .. }) => "secretNotFound",
+            HandlerError::Secrets(_) => "secretStoreError",
+            HandlerError::SecretNotFound(_) => "secretNotFound",
+            HandlerError::InvalidJson(_) => "invalidJson",
+            HandlerError::ResponseTooLarge { .. } => "responseTooLarge",
+            HandlerError::EditorLaunch { .. } => "editorLaunchFailed",
+        }
+    }
+
+    pub fn details(&self) -> Option<ErrorDetails> {
+        match self {
+            HandlerError::Spawn { path, source } => Some(spawn_error_details(path, source)),
+            HandlerError::Strict(_) => Some(ErrorDetails {
+                code: self.code().to_string(),
+                hint: Some("Disable strict mode to accept degraded behavior".to_string()),
+                fields: Vec::new(),
+            }),
+            HandlerError::ConfigReload(_) => Some(ErrorDetails {
+                code: self.code().to_string(),
+                hint: Some("Fix the config file; the previous settings stay in effect".to_string()),
+                fields: Vec::new(),
+            }),
+            HandlerError::ConfigFile(_) => Some(ErrorDetails {
+                code: self.code().to_string(),
+                hint: Some(format!(
+                    "Check that the config file is valid TOML, or point {} at a writable location",
+                    config::CONFIG_ENV_VAR
+                )),
+                fields: Vec::new(),
+            }),
+            HandlerError::InvalidExecutable { .. } => Some(ErrorDetails {
+                code: self.code().to_string(),
+                hint: Some(
+                    "Point the request path or fabric_path at the fabric binary itself".to_string(),
+                ),
+                fields: Vec::new(),
+            }),
+            HandlerError::RateLimited { retry_after, .. } => Some(ErrorDetails {
+                code: self.code().to_string(),
+                hint: Some(format!("Retry after {}ms", retry_after_ms(*retry_after))),
+                fields: Vec::new(),
+            }),
+            HandlerError::QuotaExceeded(_) => Some(ErrorDetails {
+                code: self.code().to_string(),
+                hint: Some(
+                    "Budgets reset at midnight UTC, or raise the [budget] limits in the config file"
+                        .to_string(),
+                ),
+                fields: Vec::new(),
+            }),
+            HandlerError::ExecutableNotAllowed(_) => Some(ErrorDetails {
+                code: self.code().to_string(),
+                hint: Some(
+                    "Add the binary or its directory to allowed_executables in the config file"
+                        .to_string(),
+                ),
+                fields: vec![FieldError {
+                    field: "path".to_string(),
+                    message: "is not an allowed executable".to_string(),
+                }],
+            }),
+            HandlerError::IntegrityMismatch { .. } => Some(ErrorDetails {
+                code: self.code().to_string(),
+                hint: Some(
+                    "If you upgraded fabric, send native.pinFabricChecksum to pin the new binary"
+                        .to_string(),
+                ),
+                fields: Vec::new(),
+            }),
+            HandlerError::CwdNotAllowed(_) => Some(ErrorDetails {
+                code: self.code().to_string(),
+                hint: Some(
+                    "Add the directory or one of its parents to allowed_cwd_roots in the config file"
+                        .to_string(),
+                ),
+                fields: vec![FieldError {
+                    field: "cwd".to_string(),
+                    message: "is not inside an allowed root".to_string(),
+                }],
+            }),
+            HandlerError::EnvNotAllowed(name) => Some(ErrorDetails {
+                code: self.code().to_string(),
+                hint: Some(
+                    "Add the variable name to allowed_request_env in the config file".to_string(),
+                ),
+                fields: vec![FieldError {
+                    field: format!("env.{name}"),
+                    message: "is not an allowed variable".to_string(),
+                }],
+            }),
+            HandlerError::Secrets(SecretError::Missing { .. }) => Some(ErrorDetails {
+                code: self.code().to_string(),
+                hint: Some(
+                    "Store it with native.setSecret or fix the [secrets] table in the config file"
+                        .to_string(),
+                ),
+                fields: Vec::new(),
+            }),
+            HandlerError::Secrets(_) => Some(ErrorDetails {
+                code: self.code().to_string(),
+                hint: Some(
+                    "Unlock the OS keychain, or on Linux make sure a Secret Service provider is running"
+                        .to_string(),
+                ),
+                fields: Vec::new(),
+            }),
+            HandlerError::SecretNotFound(_) => Some(ErrorDetails {
+                code: self.code().to_string(),
+                hint: Some("Use native.listSecretNames to see the stored names".to_string()),
+                fields: vec![FieldError {
+                    field: "name".to_string(),
+                    message: "is not a stored secret".to_string(),
+                }],
+            }),
+            HandlerError::DuplicateRequestId(_) => Some(ErrorDetails {
+                code: self.code().to_string(),
+                hint: Some("Use a fresh id for each request".to_string()),
+                fields: Vec::new(),
+            }),
+            HandlerError::Panicked(_) => Some(ErrorDetails {
+                code: self.code().to_string(),
+                hint: Some("This is a host bug; the host log has the backtrace".to_string()),
+                fields: Vec::new(),
+            }),
+            HandlerError::ContentTooLarge { .. } => Some(ErrorDetails {
+                code: self.code().to_string(),
+                hint: Some(CHUNKED_UPLOAD_HINT.to_string()),
+                fields: vec![FieldError {
+                    field: "content".to_string(),
+                    message: self.to_string(),
+                }],
+            }),
+            HandlerError::Timeout(_) => Some(ErrorDetails {
+                code: self.code().to_string(),
+                hint: Some("Increase timeoutMs or choose a faster model".to_string()),
+                fields: Vec::new(),
+            }),
+            HandlerError::InvalidJson(_) => Some(ErrorDetails {
+                code: self.code().to_string(),
+                hint: Some(
+                    "Use a pattern that emits a single JSON document, or disable structured output"
+                        .to_string(),
+                ),
+                fields: Vec::new(),
+            }),
+            HandlerError::ResponseTooLarge { .. } => Some(ErrorDetails {
+                code: self.code().to_string(),
+                hint: Some(
+                    "Negotiate a larger maxMessageSize in hello, or ask the pattern for less output"
+                        .to_string(),
+                ),
+                fields: Vec::new(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+fn retry_after_ms(retry_after: Duration) -> u128 {
+    retry_after.as_micros().div_ceil(1000)
+}
+
+fn spawn_error_details(path: &Utf8Path, error: &io::Error) -> ErrorDetails {
+    let (code, hint) = match error.kind() {
+        io::ErrorKind::NotFound => (
+            "fabricNotFound",
+            Some(format!(
+                "No executable exists at {path}; install fabric or update the fabric-ai path in the extension settings"
+            )),
+        ),
+        io::ErrorKind::PermissionDenied => (
+            "permissionDenied",
+            Some(format!(
+                "{path} is missing execute permission; run chmod +x {path}"
+            )),
+        ),
+        _ if error.raw_os_error() == Some(EXEC_FORMAT_ERROR) => (
+            "execFormat",
+            Some(format!(
+                "{path} is not a valid executable for this system; the binary may be for the wrong architecture"
+            )),
+        ),
+        _ => ("spawnFailed", None),
+    };
+
+    ErrorDetails {
+        code: code.to_string(),
+        hint,
+        fields: Vec::new(),
+    }
 }
 
 #[async_trait]
@@ -36,6 +627,8 @@ pub trait CommandRunner: Send + Sync {
     async fn list_patterns(&self) -> Result<CommandOutput, HandlerError>;
     async fn list_contexts(&self) -> Result<CommandOutput, HandlerError>;
     async fn fabric_path(&self) -> Result<&Utf8Path, HandlerError>;
+    fn fabric_config_dir(&self) -> Option<Utf8PathBuf>;
+    fn open_in_editor(&self, path: &Utf8Path) -> Result<String, HandlerError>;
     async fn spawn_process(
         &self,
         builder: FabricCommandBuilder<'_>,
@@ -49,36 +642,104 @@ pub struct CommandOutput {
     pub stderr: String,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProcessOutput {
+    Stdout(String),
+    Stderr(String),
+}
+
 #[async_trait]
 pub trait ProcessHandle: Send {
-    async fn write_stdin(&mut self, data: &[u8]) -> Result<(), HandlerError>;
-    async fn close_stdin(&mut self) -> Result<(), HandlerError>;
-    async fn read_stdout_line(&mut self) -> Result<Option<String>, HandlerError>;
+    /// Takes the child's stdin and returns a future that writes `data` and
+    /// closes it. Callers poll it alongside `read_output`, so a child that
+    /// stops reading can neither stall them nor deadlock on a full pipe.
+    fn stdin_writer(&mut self, data: Vec<u8>) -> BoxFuture<'static, Result<(), HandlerError>>;
+    async fn read_output(&mut self) -> Result<Option<ProcessOutput>, HandlerError>;
     async fn wait(self: Box<Self>) -> Result<Option<i32>, HandlerError>;
     async fn kill(&mut self) -> Result<(), HandlerError>;
+    fn read_stdout_chunks(&mut self, _chunk_size: usize) {}
+    /// Splits stdout lines longer than `max_len` bytes.
+    fn cap_stdout_line(&mut self, _max_len: usize) {}
 }
 
+const PATTERNS_DIR_ENV_VAR: &str = "CUSTOM_PATTERNS_DIRECTORY";
+pub const DEFAULT_BINARY_NAMES: &[&str] = &["fabric-ai", "fabric"];
+const WELL_KNOWN_HOME_DIRS: &[&[&str]] = &[&["go", "bin"], &[".local", "bin"]];
+const WELL_KNOWN_DIRS: &[&str] = &["/opt/homebrew/bin", "/usr/local/bin"];
+
 pub struct FabricCommandRunner {
     fabric_path: Utf8PathBuf,
+    patterns_dir: Option<Utf8PathBuf>,
+    env_allowlist: Option<Vec<String>>,
+    secret_env: BTreeMap<String, SecretRef>,
+    secrets: Option<Arc<dyn SecretStore>>,
 }
 
 impl FabricCommandRunner {
     pub fn new<P: AsRef<Utf8Path>>(path: P) -> Self {
         Self {
             fabric_path: path.as_ref().to_owned(),
+            patterns_dir: None,
+            env_allowlist: None,
+            secret_env: BTreeMap::new(),
+            secrets: None,
+        }
+    }
+
+    pub fn with_secrets(
+        mut self,
+        secret_env: BTreeMap<String, SecretRef>,
+        secrets: Arc<dyn SecretStore>,
+    ) -> Self {
+        self.secret_env = secret_env;
+        self.secrets = Some(secrets);
+        self
+    }
+
+    pub fn with_env_allowlist(mut self, env_allowlist: Vec<String>) -> Self {
+        self.env_allowlist = Some(env_allowlist);
+        self
+    }
+
+    pub fn with_patterns_dir(mut self, patterns_dir: Option<Utf8PathBuf>) -> Self {
+        self.patterns_dir = patterns_dir;
+        self
+    }
+
+    fn command<'a>(&self, builder: FabricCommandBuilder<'a>) -> FabricCommandBuilder<'a> {
+        let builder = match &self.env_allowlist {
+            Some(allowlist) => builder.env_allowlist(allowlist.clone()),
+            None => builder,
+        };
+        match &self.patterns_dir {
+            Some(patterns_dir) => builder.env(PATTERNS_DIR_ENV_VAR, patterns_dir.as_str()),
+            None => builder,
         }
     }
+
+    async fn secret_vars(&self) -> Result<Vec<(String, String)>, HandlerError> {
+        let Some(store) = self.secrets.clone().filter(|_| !self.secret_env.is_empty()) else {
+            return Ok(Vec::new());
+        };
+        let references = self.secret_env.clone();
+        tokio::task::spawn_blocking(move || secrets::resolve_env(store.as_ref(), &references))
+            .await
+            .map_err(|e| HandlerError::Panicked(e.to_string()))?
+            .map_err(HandlerError::from)
+    }
 }
 
 #[async_trait]
 impl CommandRunner for FabricCommandRunner {
     async fn fabric_version(&self) -> Result<CommandOutput, HandlerError> {
-        let output = FabricCommandBuilder::new(&self.fabric_path)
+        let output = self
+            .command(FabricCommandBuilder::new(&self.fabric_path))
             .version()
             .stdin(Stdio::null())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .build()
+            .kill_on_drop(true)
             .output()
             .await?;
 
@@ -94,12 +755,14 @@ impl CommandRunner for FabricCommandRunner {
     }
 
     async fn list_patterns(&self) -> Result<CommandOutput, HandlerError> {
-        let output = FabricCommandBuilder::new(&self.fabric_path)
+        let output = self
+            .command(FabricCommandBuilder::new(&self.fabric_path))
             .list_patterns()
             .stdin(Stdio::null())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .build()
+            .kill_on_drop(true)
             .output()
             .await?;
 
@@ -111,7 +774,8 @@ impl CommandRunner for FabricCommandRunner {
     }
 
     async fn list_contexts(&self) -> Result<CommandOutput, HandlerError> {
-        let output = FabricCommandBuilder::new(&self.fabric_path)
+        let output = self
+            .command(FabricCommandBuilder::new(&self.fabric_path))
             .list_contexts()
             .stdin(Stdio::null())
             .stdout(Stdio::piped())
@@ -131,52 +795,219 @@ impl CommandRunner for FabricCommandRunner {
         Ok(&self.fabric_path)
     }
 
+    fn fabric_config_dir(&self) -> Option<Utf8PathBuf> {
+        let home = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE"))?;
+        let home = Utf8PathBuf::from_path_buf(PathBuf::from(home)).ok()?;
+        Some(home.join(".config").join("fabric"))
+    }
+
+    fn open_in_editor(&self, path: &Utf8Path) -> Result<String, HandlerError> {
+        let command = editor::editor_command(|name| env::var(name).ok());
+        editor::launch(&command, path)?;
+        Ok(command.join(" "))
+    }
+
     async fn spawn_process(
         &self,
         builder: FabricCommandBuilder<'_>,
     ) -> Result<Box<dyn ProcessHandle>, HandlerError> {
-        let mut child = builder.build().spawn()?;
+        let mut builder = self.command(builder);
+        for (name, secret) in self.secret_vars().await? {
+            builder = builder.env(name, secret);
+        }
+        let mut child = builder
+            .build()
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|source| HandlerError::Spawn {
+                path: self.fabric_path.clone(),
+                source,
+            })?;
 
         let stdin = child.stdin.take();
         let stdout = child.stdout.take();
+        let stderr = child.stderr.take();
 
         Ok(Box::new(RealProcessHandle {
             child,
             stdin,
             stdout_reader: stdout.map(BufReader::new),
+            stdout_buf: Vec::new(),
+            stdout_chunk_size: None,
+            stdout_line_cap: None,
+            stderr_reader: stderr.map(BufReader::new),
+            stderr_buf: Vec::new(),
         }))
     }
 }
 
+struct StreamSummary {
+    exit_code: Option<i32>,
+    output_chars: u64,
+    time_to_first_chunk: Option<Duration>,
+    duration: Duration,
+    content_hash: blake3::Hash,
+    stderr_tail: String,
+    content: Option<String>,
+    timed_out: bool,
+    truncated: bool,
+}
+
+struct OutputOptions {
+    locale: Option<LocaleFormat>,
+    format: OutputFormat,
+    aggregate: bool,
+    deadline: Option<Instant>,
+    coalesce: Option<Duration>,
+    coalesce_bytes: Option<usize>,
+    buffered: bool,
+    timeout: Option<Duration>,
+    max_output_bytes: Option<usize>,
+    heartbeat: Option<Duration>,
+    strict: bool,
+}
+
 struct RealProcessHandle {
     child: Child,
     stdin: Option<ChildStdin>,
     stdout_reader: Option<BufReader<ChildStdout>>,
+    stdout_buf: Vec<u8>,
+    stdout_chunk_size: Option<usize>,
+    stdout_line_cap: Option<usize>,
+    stderr_reader: Option<BufReader<ChildStderr>>,
+    stderr_buf: Vec<u8>,
 }
 
-#[async_trait]
-impl ProcessHandle for RealProcessHandle {
-    async fn write_stdin(&mut self, data: &[u8]) -> Result<(), HandlerError> {
-        if let Some(ref mut stdin) = self.stdin {
-            stdin.write_all(data).await?;
+/// Reads one line, or the first `max_len` bytes of a longer one, so a single
+/// huge line never has to be buffered whole.
+async fn read_line_into<R>(
+    reader: &mut Option<R>,
+    buf: &mut Vec<u8>,
+    max_len: Option<usize>,
+) -> io::Result<Option<String>>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let Some(reader) = reader else {
+        return Ok(None);
+    };
+
+    match max_len {
+        Some(max_len) => {
+            let limit = max_len.saturating_sub(buf.len()).max(1) as u64;
+            reader.take(limit).read_until(b'\n', buf).await?;
         }
-        Ok(())
+        None => {
+            reader.read_until(b'\n', buf).await?;
+        }
+    }
+    if buf.is_empty() {
+        Ok(None)
+    } else {
+        if buf.ends_with(b"\r\n") {
+            buf.remove(buf.len() - 2);
+        }
+        let line = String::from_utf8_lossy(buf).into_owned();
+        buf.clear();
+        Ok(Some(line))
     }
+}
+
+async fn read_chunk_into<R>(
+    reader: &mut Option<R>,
+    buf: &mut Vec<u8>,
+    chunk_size: usize,
+) -> io::Result<Option<String>>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let Some(reader) = reader else {
+        return Ok(None);
+    };
+
+    loop {
+        let available = reader.fill_buf().await?;
+        if available.is_empty() {
+            if buf.is_empty() {
+                return Ok(None);
+            }
+            let chunk = String::from_utf8_lossy(buf).into_owned();
+            buf.clear();
+            return Ok(Some(chunk));
+        }
 
-    async fn close_stdin(&mut self) -> Result<(), HandlerError> {
-        if let Some(mut stdin) = self.stdin.take() {
-            stdin.shutdown().await?;
+        let take = available
+            .len()
+            .min(chunk_size.saturating_sub(buf.len()).max(1));
+        buf.extend_from_slice(&available[..take]);
+        reader.consume(take);
+
+        let mut complete = match std::str::from_utf8(buf) {
+            Ok(_) => buf.len(),
+            Err(e) => e.valid_up_to() + e.error_len().unwrap_or(0),
+        };
+        // Hold back a trailing CR until we know whether an LF follows it.
+        if buf[..complete].ends_with(b"\r") {
+            complete -= 1;
+        }
+        if complete > 0 {
+            let chunk = String::from_utf8_lossy(&buf[..complete]).replace("\r\n", "\n");
+            buf.drain(..complete);
+            return Ok(Some(chunk));
         }
-        Ok(())
     }
+}
 
-    async fn read_stdout_line(&mut self) -> Result<Option<String>, HandlerError> {
-        if let Some(ref mut reader) = self.stdout_reader {
-            let mut buf = String::new();
-            let n = reader.read_line(&mut buf).await?;
-            if n == 0 { Ok(None) } else { Ok(Some(buf)) }
-        } else {
-            Ok(None)
+async fn read_stdout_into<R>(
+    reader: &mut Option<R>,
+    buf: &mut Vec<u8>,
+    chunk_size: Option<usize>,
+    max_line_len: Option<usize>,
+) -> io::Result<Option<String>>
+where
+    R: AsyncBufRead + Unpin,
+{
+    match chunk_size {
+        Some(chunk_size) => read_chunk_into(reader, buf, chunk_size).await,
+        None => read_line_into(reader, buf, max_line_len).await,
+    }
+}
+
+#[async_trait]
+impl ProcessHandle for RealProcessHandle {
+    fn stdin_writer(&mut self, data: Vec<u8>) -> BoxFuture<'static, Result<(), HandlerError>> {
+        let stdin = self.stdin.take();
+        Box::pin(async move {
+            if let Some(mut stdin) = stdin {
+                stdin.write_all(&data).await?;
+                stdin.shutdown().await?;
+            }
+            Ok(())
+        })
+    }
+
+    async fn read_output(&mut self) -> Result<Option<ProcessOutput>, HandlerError> {
+        loop {
+            let stdout_open = self.stdout_reader.is_some();
+            let stderr_open = self.stderr_reader.is_some();
+            if !stdout_open && !stderr_open {
+                return Ok(None);
+            }
+
+            tokio::select! {
+                line = read_stdout_into(&mut self.stdout_reader, &mut self.stdout_buf, self.stdout_chunk_size, self.stdout_line_cap), if stdout_open => {
+                    match line? {
+                        Some(line) => return Ok(Some(ProcessOutput::Stdout(line))),
+                        None => self.stdout_reader = None,
+                    }
+                }
+                line = read_line_into(&mut self.stderr_reader, &mut self.stderr_buf, None), if stderr_open => {
+                    match line? {
+                        Some(line) => return Ok(Some(ProcessOutput::Stderr(line))),
+                        None => self.stderr_reader = None,
+                    }
+                }
+            }
         }
     }
 
@@ -189,169 +1020,484 @@ impl ProcessHandle for RealProcessHandle {
         self.child.kill().await?;
         Ok(())
     }
+
+    fn read_stdout_chunks(&mut self, chunk_size: usize) {
+        self.stdout_chunk_size = Some(chunk_size.max(1));
+    }
+
+    fn cap_stdout_line(&mut self, max_len: usize) {
+        self.stdout_line_cap = Some(max_len);
+    }
 }
 
 pub async fn handle_request<T, E, R, F>(
     writer: &mut FramedWrite<T, E>,
     request: Request,
     runner_factory: F,
-    process_registry: ProcessRegistry,
+    state: &HostState,
 ) -> Result<(), HandlerError>
 where
     T: AsyncWrite + Unpin,
     E: Encoder<Response>,
     <E as Encoder<Response>>::Error: error::Error + Send + Sync + 'static,
-    R: CommandRunner,
+    R: CommandRunner + 'static,
     F: for<'a> FnOnce(&'a Utf8Path) -> R,
     HandlerError: From<<E as Encoder<Response>>::Error>,
 {
     let request_id = request.id;
-    let resolved_path = match resolve_path(request.path) {
-        Ok(path) => path,
-        Err(e) => match request.payload {
-            RequestPayload::Ping => {
-                writer
-                    .send(Response {
-                        id: request_id,
-                        payload: ResponsePayload::Pong {
-                            resolved_path: None,
-                            version: None,
-                            valid: false,
-                        },
-                    })
-                    .await?;
-                return Ok(());
-            }
-            _ => return Err(e),
-        },
-    };
-
-    let runner = runner_factory(resolved_path.as_ref());
-
-    match request.payload {
-        RequestPayload::Ping => handle_ping(writer, request_id, &runner).await,
-        RequestPayload::ListPatterns => handle_list_patterns(writer, request_id, &runner).await,
-        RequestPayload::ListContexts => handle_list_contexts(writer, request_id, &runner).await,
-        RequestPayload::ProcessContent {
-            content,
-            model,
-            pattern,
-            context,
-            custom_prompt,
-        } => {
-            handle_process_content(
-                writer,
-                request_id,
-                &runner,
-                model,
-                pattern,
-                context,
-                custom_prompt,
-                content,
-                process_registry,
-            )
-            .await
-        }
-        RequestPayload::CancelProcess {
-            request_id: target_request_id,
-        } => handle_cancel_process(writer, request_id, target_request_id, process_registry).await,
-    }
-}
-
-#[doc(hidden)]
-pub async fn handle_ping<T, E, R>(
-    writer: &mut FramedWrite<T, E>,
-    request_id: Uuid,
-    runner: &R,
-) -> Result<(), HandlerError>
-where
-    T: AsyncWrite + Unpin,
-    E: Encoder<Response>,
-    <E as Encoder<Response>>::Error: error::Error + Send + Sync + 'static,
-    R: CommandRunner,
-    HandlerError: From<<E as Encoder<Response>>::Error>,
-{
-    let fabric_path = runner.fabric_path().await?;
-    match runner.fabric_version().await {
-        Ok(_output) if _output.status => {
-            writer
-                .send(Response {
-                    id: request_id,
-                    payload: ResponsePayload::Pong {
-                        resolved_path: Some(fabric_path.to_string()),
-                        version: Some(_output.stdout),
-                        valid: true,
-                    },
-                })
-                .await?;
-        }
-        Ok(_output) => {
-            writer
-                .send(Response {
-                    id: request_id,
-                    payload: ResponsePayload::Pong {
-                        resolved_path: Some(fabric_path.to_string()),
-                        version: None,
-                        valid: false,
-                    },
-                })
-                .await?;
-        }
-        Err(_e) => {
-            writer
-                .send(Response {
-                    id: request_id,
-                    payload: ResponsePayload::Pong {
-                        resolved_path: Some(fabric_path.to_string()),
-                        version: None,
-                        valid: false,
-                    },
-                })
-                .await?;
+    tracing::debug!(
+        %request_id,
+        request_type = request.payload.type_name(),
+        validate_only = request.validate_only,
+        "Handling request"
+    );
+    state.metrics.record_request(request.payload.type_name());
+    let result = dispatch_request(writer, request, runner_factory, state).await;
+    if let Err(e) = &result {
+        if let HandlerError::Cancelled = e {
+            tracing::debug!(%request_id, "Request cancelled");
+        } else {
+            tracing::warn!(%request_id, code = e.code(), "Request failed: {e}");
         }
+        state.record_error(request_id, e);
     }
-
-    Ok(())
+    result
 }
 
-#[doc(hidden)]
-pub async fn handle_list_patterns<T, E, R>(
+async fn dispatch_request<T, E, R, F>(
     writer: &mut FramedWrite<T, E>,
-    request_id: Uuid,
-    runner: &R,
+    request: Request,
+    runner_factory: F,
+    state: &HostState,
 ) -> Result<(), HandlerError>
 where
     T: AsyncWrite + Unpin,
     E: Encoder<Response>,
     <E as Encoder<Response>>::Error: error::Error + Send + Sync + 'static,
-    R: CommandRunner,
+    R: CommandRunner + 'static,
+    F: for<'a> FnOnce(&'a Utf8Path) -> R,
     HandlerError: From<<E as Encoder<Response>>::Error>,
 {
-    let output = runner.list_patterns().await?;
+    let request_id = request.id;
+    let mut request = request;
 
-    if !output.status {
+    if state.draining() {
         writer
             .send(Response {
                 id: request_id,
-                payload: ResponsePayload::Error {
-                    message: format!("Failed to list patterns: {}", output.stderr),
-                },
+                payload: ResponsePayload::Cancelled { request_id },
             })
             .await?;
         return Ok(());
     }
 
-    let patterns: Vec<String> = output
-        .stdout
-        .lines()
-        .map(|line| line.trim().to_string())
-        .filter(|line| !line.is_empty())
-        .collect();
+    for message in state.take_startup_warnings() {
+        writer
+            .send(Response {
+                id: request_id,
+                payload: ResponsePayload::Warning { message },
+            })
+            .await?;
+    }
+
+    let mut chunked = false;
+    if let RequestPayload::ProcessContent { content, .. } = &mut request.payload
+        && matches!(content, ContentInput::Text(text) if text.is_empty())
+        && let Some(text) = if request.validate_only {
+            peek_assembled_content(&state.chunks, request_id)
+        } else {
+            take_assembled_content(&state.chunks, request_id)
+        }
+    {
+        *content = ContentInput::Text(text);
+        chunked = true;
+    }
+
+    // Chunked uploads were already held to their own cap as they arrived.
+    if let RequestPayload::ProcessContent { content, .. } = &request.payload
+        && !chunked
+        && content.byte_len() > validation::MAX_CONTENT_BYTES
+    {
+        let error = HandlerError::ContentTooLarge {
+            size: content.byte_len(),
+            limit: validation::MAX_CONTENT_BYTES,
+        };
+        writer
+            .send(Response {
+                id: request_id,
+                payload: ResponsePayload::Error {
+                    message: error.to_string(),
+                    details: error.details(),
+                },
+            })
+            .await?;
+        return Err(error);
+    }
+
+    let content_limit = if chunked {
+        state.max_chunked_content_bytes()
+    } else {
+        validation::MAX_CONTENT_BYTES
+    };
+    if let Err(fields) = validation::validate_with_content_limit(&request.payload, content_limit) {
+        let names: Vec<&str> = fields.iter().map(|error| error.field.as_str()).collect();
+        writer
+            .send(Response {
+                id: request_id,
+                payload: ResponsePayload::Error {
+                    message: format!("Invalid request: {}", names.join(", ")),
+                    details: Some(ErrorDetails {
+                        code: "invalidRequest".to_string(),
+                        hint: None,
+                        fields,
+                    }),
+                },
+            })
+            .await?;
+        return Ok(());
+    }
+
+    if !request.validate_only
+        && let Err(e) = check_rate_limit(&request.payload, state)
+            .and_then(|()| check_budget(&request.payload, state))
+    {
+        return send_error(writer, request_id, e).await;
+    }
+
+    let payload = if request.validate_only {
+        request.payload
+    } else {
+        match dispatch_host_request(writer, request_id, request.payload, &request.path, state).await
+        {
+            ControlFlow::Break(result) => return result,
+            ControlFlow::Continue(payload) => payload,
+        }
+    };
+
+    let resolved_path = match resolve_fabric(request.path, state) {
+        Ok(path) => path,
+        Err(_) if matches!(payload, RequestPayload::Ping) => {
+            writer
+                .send(Response {
+                    id: request_id,
+                    payload: ResponsePayload::Pong {
+                        resolved_path: None,
+                        binary: None,
+                        version: None,
+                        valid: false,
+                        stats: Some(state.stats()),
+                        compression: SUPPORTED_COMPRESSION.to_vec(),
+                    },
+                })
+                .await?;
+            return Ok(());
+        }
+        Err(e) => return send_error(writer, request_id, e).await,
+    };
+
+    let runner = runner_factory(resolved_path.as_ref());
+
+    if request.validate_only {
+        return handle_validate_only(writer, request_id, &runner, payload).await;
+    }
+
+    match payload {
+        RequestPayload::Ping => {
+            handle_ping(
+                writer,
+                request_id,
+                &runner,
+                Some(state.stats()),
+                state.command_timeout(),
+            )
+            .await
+        }
+        RequestPayload::ListPatterns => {
+            handle_list_patterns(
+                writer,
+                request_id,
+                &runner,
+                state.command_timeout(),
+                &state.request_defaults().aliases,
+                &state.pinned_patterns(),
+            )
+            .await
+        }
+        RequestPayload::ListContexts => handle_list_contexts(writer, request_id, &runner).await,
+        RequestPayload::ProcessContent {
+            content,
+            model,
+            pattern,
+            context,
+            custom_prompt,
+            system_prompt,
+            locale,
+            detach,
+            aggregate,
+            summarize,
+            summary_model,
+            output_format,
+            deterministic,
+            quick_look,
+            selection,
+            composition,
+            stream_mode,
+            stream_flush_ms,
+            stream_chunk_bytes,
+            strict,
+            structured,
+            timeout_ms,
+            heartbeat_ms,
+            cwd,
+            env,
+        } => {
+            let cwd = match cwd.map(|cwd| resolve_cwd(&cwd, &state.allowed_cwd_roots())) {
+                Some(Ok(cwd)) => Some(cwd),
+                Some(Err(e)) => return send_error(writer, request_id, e).await,
+                None => None,
+            };
+            let env = env.unwrap_or_default();
+            if let Err(e) = check_request_env(&env, &state.allowed_request_env()) {
+                return send_error(writer, request_id, e).await;
+            }
+            let content = compose::compose_content(content, selection, composition);
+            let defaults = state.request_defaults();
+            let pattern = match (pattern, &custom_prompt) {
+                (None, None) => defaults.pattern,
+                (pattern, _) => pattern,
+            };
+            let base = host_process_options(state);
+            // The Hello or config locale is skipped where rewriting numbers
+            // would break the output; a request locale is validated instead.
+            let locale = locale.or_else(|| {
+                (output_format == OutputFormat::Markdown && stream_mode == StreamMode::Lines)
+                    .then(|| base.locale.clone())
+                    .flatten()
+            });
+            let coalesce_interval = match stream_flush_ms {
+                Some(0) => None,
+                Some(millis) => Some(Duration::from_millis(millis)),
+                None => base.coalesce_interval,
+            };
+            let options = ProcessOptions {
+                model,
+                pattern,
+                context,
+                custom_prompt,
+                system_prompt,
+                locale,
+                aggregate,
+                summarize,
+                summary_model,
+                output_format,
+                deterministic,
+                quick_look,
+                coalesce_interval,
+                coalesce_bytes: stream_chunk_bytes,
+                stream_mode,
+                strict: strict.unwrap_or(base.strict),
+                structured,
+                timeout: timeout_ms.map(Duration::from_millis).or(base.timeout),
+                heartbeat: heartbeat_ms.map(Duration::from_millis),
+                cwd,
+                env,
+                ..base
+            };
+            if detach {
+                return handle_detached_process(
+                    writer, request_id, runner, options, content, state,
+                )
+                .await;
+            }
+            handle_process_content(writer, request_id, &runner, options, content, state).await
+        }
+        RequestPayload::ProcessBatch { items } => {
+            let defaults = state.request_defaults();
+            let items = items
+                .into_iter()
+                .map(|item| {
+                    let pattern = defaults.resolve_pattern(item.pattern);
+                    BatchContent {
+                        model: item
+                            .model
+                            .or_else(|| defaults.model_for(pattern.as_deref())),
+                        pattern,
+                        ..item
+                    }
+                })
+                .collect();
+            handle_process_batch(writer, request_id, &runner, items, state).await
+        }
+        RequestPayload::OpenInEditor {
+            request_id: target_request_id,
+        } => handle_open_in_editor(writer, request_id, &runner, target_request_id, state).await,
+        payload => unreachable!(
+            "{} is answered before fabric is resolved",
+            payload.type_name()
+        ),
+    }
+}
+
+fn resolve_fabric(
+    path: Option<Utf8PathBuf>,
+    state: &HostState,
+) -> Result<Utf8PathBuf, HandlerError> {
+    let path = resolve_path_in(
+        path.or_else(|| state.fabric_path()),
+        &state.search_paths(),
+        &state.binary_names(),
+        &state.allowed_executables(),
+    )?;
+    verify_fabric(&path, state)?;
+    Ok(path)
+}
+
+/// Answers the requests that never run fabric, so they work even when fabric
+/// is missing or fails verification. Anything else is handed back.
+async fn dispatch_host_request<T, E>(
+    writer: &mut FramedWrite<T, E>,
+    request_id: Uuid,
+    payload: RequestPayload,
+    path: &Option<Utf8PathBuf>,
+    state: &HostState,
+) -> ControlFlow<Result<(), HandlerError>, RequestPayload>
+where
+    T: AsyncWrite + Unpin,
+    E: Encoder<Response>,
+    <E as Encoder<Response>>::Error: error::Error + Send + Sync + 'static,
+    HandlerError: From<<E as Encoder<Response>>::Error>,
+{
+    let result = match payload {
+        RequestPayload::Hello {
+            max_message_size,
+            compression,
+            locale,
+        } => {
+            handle_hello(
+                writer,
+                request_id,
+                max_message_size,
+                compression,
+                locale,
+                state,
+            )
+            .await
+        }
+        RequestPayload::Heartbeat => handle_heartbeat(writer, request_id, state).await,
+        RequestPayload::Shutdown => handle_shutdown(writer, request_id).await,
+        RequestPayload::ReloadConfig => handle_reload_config(writer, request_id, state).await,
+        RequestPayload::GetConfig => handle_get_config(writer, request_id, state).await,
+        RequestPayload::SetConfig { config } => {
+            handle_set_config(writer, request_id, config, state).await
+        }
+        RequestPayload::PinPattern { pattern } => {
+            handle_pin_pattern(writer, request_id, &pattern, true, state).await
+        }
+        RequestPayload::UnpinPattern { pattern } => {
+            handle_pin_pattern(writer, request_id, &pattern, false, state).await
+        }
+        RequestPayload::PinFabricChecksum => {
+            handle_pin_fabric_checksum(writer, request_id, state).await
+        }
+        RequestPayload::SetSecret { name, secret } => {
+            handle_set_secret(writer, request_id, name, secret, state).await
+        }
+        RequestPayload::DeleteSecret { name } => {
+            handle_delete_secret(writer, request_id, name, state).await
+        }
+        RequestPayload::ListSecretNames => {
+            handle_list_secret_names(writer, request_id, state).await
+        }
+        RequestPayload::GetMetrics => handle_get_metrics(writer, request_id, state).await,
+        RequestPayload::GetStoreInfo => handle_get_store_info(writer, request_id, state).await,
+        RequestPayload::DebugDump => {
+            let verified = resolve_fabric(path.clone(), state);
+            handle_debug_dump(writer, request_id, verified, state).await
+        }
+        RequestPayload::ContentChunk {
+            request_id: target_request_id,
+            seq,
+            data,
+            last,
+        } => {
+            let payload = accept_chunk(
+                &state.chunks,
+                target_request_id,
+                seq,
+                data,
+                last,
+                state.max_chunked_content_bytes(),
+            );
+            writer
+                .send(Response {
+                    id: request_id,
+                    payload,
+                })
+                .await
+                .map_err(HandlerError::from)
+        }
+        RequestPayload::ReplayRequest {
+            request_id: target_request_id,
+        } => handle_replay_request(writer, request_id, target_request_id, state).await,
+        RequestPayload::GetJobResult { job_id } => {
+            handle_get_job_result(writer, request_id, job_id, state).await
+        }
+        RequestPayload::ListJobs { state: job_state } => {
+            handle_list_jobs(writer, request_id, job_state, state).await
+        }
+        RequestPayload::CancelJob { job_id } => {
+            handle_cancel_job(writer, request_id, job_id, state).await
+        }
+        RequestPayload::DeleteJob { job_id } => {
+            handle_delete_job(writer, request_id, job_id, state).await
+        }
+        RequestPayload::CancelProcess {
+            request_id: target_request_id,
+        } => {
+            handle_cancel_process(
+                writer,
+                request_id,
+                target_request_id,
+                state.process_registry.clone(),
+            )
+            .await
+        }
+        payload @ (RequestPayload::Ping
+        | RequestPayload::ListPatterns
+        | RequestPayload::ListContexts
+        | RequestPayload::ProcessContent { .. }
+        | RequestPayload::ProcessBatch { .. }
+        | RequestPayload::OpenInEditor { .. }) => return ControlFlow::Continue(payload),
+    };
+    ControlFlow::Break(result)
+}
+
+#[doc(hidden)]
+pub async fn handle_hello<T, E>(
+    writer: &mut FramedWrite<T, E>,
+    request_id: Uuid,
+    max_message_size: Option<usize>,
+    compression: Option<Compression>,
+    locale: Option<String>,
+    state: &HostState,
+) -> Result<(), HandlerError>
+where
+    T: AsyncWrite + Unpin,
+    E: Encoder<Response>,
+    <E as Encoder<Response>>::Error: error::Error + Send + Sync + 'static,
+    HandlerError: From<<E as Encoder<Response>>::Error>,
+{
+    let max_message_size = state.negotiate_outbound_limit(max_message_size);
+    let compression = state.negotiate_compression(compression);
+    state.set_client_locale(locale);
 
     writer
         .send(Response {
             id: request_id,
-            payload: ResponsePayload::PatternsList { patterns },
+            payload: ResponsePayload::HelloAck {
+                max_message_size,
+                compression,
+            },
         })
         .await?;
 
@@ -359,100 +1505,115 @@ where
 }
 
 #[doc(hidden)]
-pub async fn handle_list_contexts<T, E, R>(
+pub async fn handle_heartbeat<T, E>(
     writer: &mut FramedWrite<T, E>,
     request_id: Uuid,
-    runner: &R,
+    state: &HostState,
 ) -> Result<(), HandlerError>
 where
     T: AsyncWrite + Unpin,
     E: Encoder<Response>,
     <E as Encoder<Response>>::Error: error::Error + Send + Sync + 'static,
-    R: CommandRunner,
     HandlerError: From<<E as Encoder<Response>>::Error>,
 {
-    let output = runner.list_contexts().await?;
+    writer
+        .send(Response {
+            id: request_id,
+            payload: ResponsePayload::HeartbeatAck {
+                uptime_ms: state.uptime_ms(),
+                active_requests: state.in_flight_count(),
+            },
+        })
+        .await?;
 
-    if !output.status {
+    Ok(())
+}
+
+#[doc(hidden)]
+pub async fn handle_shutdown<T, E>(
+    writer: &mut FramedWrite<T, E>,
+    request_id: Uuid,
+) -> Result<(), HandlerError>
+where
+    T: AsyncWrite + Unpin,
+    E: Encoder<Response>,
+    <E as Encoder<Response>>::Error: error::Error + Send + Sync + 'static,
+    HandlerError: From<<E as Encoder<Response>>::Error>,
+{
+    writer
+        .send(Response {
+            id: request_id,
+            payload: ResponsePayload::ShutdownAck,
+        })
+        .await?;
+
+    Ok(())
+}
+
+#[doc(hidden)]
+pub async fn handle_reload_config<T, E>(
+    writer: &mut FramedWrite<T, E>,
+    request_id: Uuid,
+    state: &HostState,
+) -> Result<(), HandlerError>
+where
+    T: AsyncWrite + Unpin,
+    E: Encoder<Response>,
+    <E as Encoder<Response>>::Error: error::Error + Send + Sync + 'static,
+    HandlerError: From<<E as Encoder<Response>>::Error>,
+{
+    if let Err(message) = state.reload_settings() {
+        let error = HandlerError::ConfigReload(message);
         writer
             .send(Response {
                 id: request_id,
                 payload: ResponsePayload::Error {
-                    message: format!("Failed to list contexts: {}", output.stderr),
+                    message: error.to_string(),
+                    details: error.details(),
                 },
             })
             .await?;
-        return Ok(());
+        return Err(error);
     }
 
-    let contexts: Vec<String> = output
-        .stdout
-        .lines()
-        .map(|line| line.trim().to_string())
-        .filter(|line| !line.is_empty())
-        .collect();
-
     writer
         .send(Response {
             id: request_id,
-            payload: ResponsePayload::ContextsList { contexts },
+            payload: ResponsePayload::ConfigReloaded,
         })
         .await?;
 
     Ok(())
 }
 
-async fn stream_process_responses<T, E>(
+async fn send_error<T, E>(
     writer: &mut FramedWrite<T, E>,
     request_id: Uuid,
-    mut process: Box<dyn ProcessHandle>,
-    content: String,
-    mut cancel_rx: watch::Receiver<bool>,
-) -> Result<Option<i32>, HandlerError>
+    error: HandlerError,
+) -> Result<(), HandlerError>
 where
     T: AsyncWrite + Unpin,
     E: Encoder<Response>,
     <E as Encoder<Response>>::Error: error::Error + Send + Sync + 'static,
     HandlerError: From<<E as Encoder<Response>>::Error>,
 {
-    process.write_stdin(content.as_bytes()).await?;
-    process.close_stdin().await?;
-
-    loop {
-        tokio::select! { biased;
-            _ = cancel_rx.changed() => {
-                if *cancel_rx.borrow() {
-                    let _ = process.kill().await;
-                    let _ = process.wait().await;
-                    return Err(HandlerError::Cancelled);
-                }
-            }
-            line_result = process.read_stdout_line() => {
-                match line_result {
-                    Ok(Some(line)) => {
-                        writer.send(Response {
-                            id: request_id,
-                            payload: ResponsePayload::Content { content: line },
-                        }).await?;
-                    }
-                    Ok(None) => {
-                        return process.wait().await;
-                    }
-                    Err(e) => {
-                        return Err(e);
-                    }
-                }
-            }
-        }
-    }
+    writer
+        .send(Response {
+            id: request_id,
+            payload: ResponsePayload::Error {
+                message: error.to_string(),
+                details: error.details(),
+            },
+        })
+        .await?;
+    Err(error)
 }
 
 #[doc(hidden)]
-pub async fn handle_cancel_process<T, E>(
+pub async fn handle_get_config<T, E>(
     writer: &mut FramedWrite<T, E>,
-    cancel_request_id: Uuid,
-    target_request_id: Uuid,
-    process_registry: ProcessRegistry,
+    request_id: Uuid,
+    state: &HostState,
 ) -> Result<(), HandlerError>
 where
     T: AsyncWrite + Unpin,
@@ -460,520 +1621,7214 @@ where
     <E as Encoder<Response>>::Error: error::Error + Send + Sync + 'static,
     HandlerError: From<<E as Encoder<Response>>::Error>,
 {
-    let cancel_sender = {
-        let registry = process_registry.lock().await;
-        registry.get(&target_request_id).cloned()
+    let path = state.config_path().cloned();
+    let loaded = match &path {
+        Some(path) => Config::load(path),
+        None => Ok(Config::default()),
+    };
+    let config = match loaded {
+        Ok(config) => config,
+        Err(message) => {
+            return send_error(writer, request_id, HandlerError::ConfigFile(message)).await;
+        }
     };
 
-    match cancel_sender {
-        Some(sender) => {
-            if sender.send(true).is_err() {
-                writer
-                    .send(Response {
-                        id: cancel_request_id,
-                        payload: ResponsePayload::Error {
-                            message: format!("Process {} already completed", target_request_id),
-                        },
-                    })
-                    .await?;
-            } else {
-                writer
-                    .send(Response {
-                        id: cancel_request_id,
-                        payload: ResponsePayload::Cancelled {
-                            request_id: target_request_id,
-                        },
-                    })
-                    .await?;
-            }
-        }
-        None => {
-            writer
-                .send(Response {
-                    id: cancel_request_id,
-                    payload: ResponsePayload::Error {
-                        message: format!(
-                            "Process {} not found or already completed",
-                            target_request_id
-                        ),
-                    },
-                })
-                .await?;
+    writer
+        .send(Response {
+            id: request_id,
+            payload: ResponsePayload::Config {
+                path,
+                config: config.host_config(),
+            },
+        })
+        .await?;
+
+    Ok(())
+}
+
+#[doc(hidden)]
+pub async fn handle_set_config<T, E>(
+    writer: &mut FramedWrite<T, E>,
+    request_id: Uuid,
+    update: HostConfigUpdate,
+    state: &HostState,
+) -> Result<(), HandlerError>
+where
+    T: AsyncWrite + Unpin,
+    E: Encoder<Response>,
+    <E as Encoder<Response>>::Error: error::Error + Send + Sync + 'static,
+    HandlerError: From<<E as Encoder<Response>>::Error>,
+{
+    let Some(path) = state.config_path().cloned() else {
+        let error = HandlerError::ConfigFile(format!(
+            "No config file location; set {} or HOME",
+            config::CONFIG_ENV_VAR
+        ));
+        return send_error(writer, request_id, error).await;
+    };
+    let config = match config::update_file(&path, &update) {
+        Ok(config) => config,
+        Err(message) => {
+            return send_error(writer, request_id, HandlerError::ConfigFile(message)).await;
         }
+    };
+    if let Err(message) = state.reload_settings() {
+        return send_error(writer, request_id, HandlerError::ConfigReload(message)).await;
     }
 
+    writer
+        .send(Response {
+            id: request_id,
+            payload: ResponsePayload::Config {
+                path: Some(path),
+                config: config.host_config(),
+            },
+        })
+        .await?;
+
     Ok(())
 }
 
 #[doc(hidden)]
-#[allow(clippy::too_many_arguments)]
-pub async fn handle_process_content<T, E, R>(
+pub async fn handle_pin_pattern<T, E>(
     writer: &mut FramedWrite<T, E>,
     request_id: Uuid,
-    runner: &R,
-    model: Option<String>,
-    pattern: Option<String>,
-    context: Option<String>,
-    custom_prompt: Option<String>,
-    content: String,
-    process_registry: ProcessRegistry,
+    pattern: &str,
+    pinned: bool,
+    state: &HostState,
 ) -> Result<(), HandlerError>
 where
     T: AsyncWrite + Unpin,
     E: Encoder<Response>,
     <E as Encoder<Response>>::Error: error::Error + Send + Sync + 'static,
-    R: CommandRunner,
     HandlerError: From<<E as Encoder<Response>>::Error>,
 {
-    let fabric_path = runner.fabric_path().await?;
-    let mut builder = FabricCommandBuilder::new(fabric_path)
-        .stream()
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped());
-
-    if let Some(model) = model {
-        builder = builder.model(model);
+    let Some(path) = state.config_path().cloned() else {
+        let error = HandlerError::ConfigFile(format!(
+            "No config file location; set {} or HOME",
+            config::CONFIG_ENV_VAR
+        ));
+        return send_error(writer, request_id, error).await;
+    };
+    let config = match config::set_pinned(&path, pattern, pinned) {
+        Ok(config) => config,
+        Err(message) => {
+            return send_error(writer, request_id, HandlerError::ConfigFile(message)).await;
+        }
+    };
+    if let Err(message) = state.reload_settings() {
+        return send_error(writer, request_id, HandlerError::ConfigReload(message)).await;
     }
 
-    if let Some(context) = context {
-        builder = builder.context(context);
-    }
+    writer
+        .send(Response {
+            id: request_id,
+            payload: ResponsePayload::PinnedPatterns {
+                patterns: config.pinned_patterns,
+            },
+        })
+        .await?;
 
-    if let Some(pattern) = pattern {
-        builder = builder.pattern(pattern);
-    } else if let Some(custom_prompt) = custom_prompt {
-        builder = builder.custom_prompt(custom_prompt);
-    }
+    Ok(())
+}
 
-    let process = runner.spawn_process(builder).await?;
+fn check_rate_limit(payload: &RequestPayload, state: &HostState) -> Result<(), HandlerError> {
+    let request_type = payload.type_name();
+    let Some(limit) = state.rate_limit(request_type) else {
+        return Ok(());
+    };
+    state
+        .rate_limiter
+        .acquire(request_type, limit)
+        .map_err(|retry_after| HandlerError::RateLimited {
+            request_type,
+            retry_after,
+        })
+}
 
-    let (cancel_tx, cancel_rx) = watch::channel(false);
+/// Counts model-backed requests against the daily budget; a batch counts
+/// once per item.
+fn check_budget(payload: &RequestPayload, state: &HostState) -> Result<(), HandlerError> {
+    let requests = match payload {
+        RequestPayload::ProcessContent { .. } => 1,
+        RequestPayload::ProcessBatch { items } => items.len() as u64,
+        _ => return Ok(()),
+    };
+    Ok(state.usage.charge(requests, state.budget())?)
+}
 
-    {
-        let mut registry = process_registry.lock().await;
-        registry.insert(request_id, cancel_tx);
+#[doc(hidden)]
+pub fn verify_fabric(path: &Utf8Path, state: &HostState) -> Result<(), HandlerError> {
+    let Some(expected) = state.fabric_sha256() else {
+        return Ok(());
+    };
+    let found = state.digests.sha256(path)?;
+    if integrity::digest_matches(&expected, &found) {
+        Ok(())
+    } else {
+        Err(HandlerError::IntegrityMismatch {
+            path: path.to_owned(),
+            expected,
+            found,
+        })
     }
+}
 
-    let result = stream_process_responses(writer, request_id, process, content, cancel_rx).await;
-
+/// Pins the digest of the configured `fabric_path`. The request `path` is
+/// ignored: a pin only ever covers the binary the config file names.
+#[doc(hidden)]
+pub async fn handle_pin_fabric_checksum<T, E>(
+    writer: &mut FramedWrite<T, E>,
+    request_id: Uuid,
+    state: &HostState,
+) -> Result<(), HandlerError>
+where
+    T: AsyncWrite + Unpin,
+    E: Encoder<Response>,
+    <E as Encoder<Response>>::Error: error::Error + Send + Sync + 'static,
+    HandlerError: From<<E as Encoder<Response>>::Error>,
+{
+    let Some(config_path) = state.config_path().cloned() else {
+        let error = HandlerError::ConfigFile(format!(
+            "No config file location; set {} or HOME",
+            config::CONFIG_ENV_VAR
+        ));
+        return send_error(writer, request_id, error).await;
+    };
+    let Some(path) = state.fabric_path() else {
+        let error = HandlerError::ConfigFile(format!(
+            "Set fabric_path in {config_path} before pinning its checksum"
+        ));
+        return send_error(writer, request_id, error).await;
+    };
+    if let Err(e) = check_allowed_executable(&path, &state.allowed_executables())
+        .and_then(|()| check_executable(&path))
     {
-        let mut registry = process_registry.lock().await;
-        registry.remove(&request_id);
+        return send_error(writer, request_id, e).await;
+    }
+    let sha256 = match integrity::sha256_file(&path) {
+        Ok(sha256) => sha256,
+        Err(e) => return send_error(writer, request_id, e.into()).await,
+    };
+    if let Err(message) = config::set_fabric_sha256(&config_path, &path, &sha256) {
+        return send_error(writer, request_id, HandlerError::ConfigFile(message)).await;
+    }
+    if let Err(message) = state.reload_settings() {
+        return send_error(writer, request_id, HandlerError::ConfigReload(message)).await;
     }
 
-    match result {
-        Ok(exit_code) => {
+    writer
+        .send(Response {
+            id: request_id,
+            payload: ResponsePayload::FabricChecksum { path, sha256 },
+        })
+        .await?;
+
+    Ok(())
+}
+
+async fn with_secret_store<F, V>(state: &HostState, op: F) -> Result<V, HandlerError>
+where
+    F: FnOnce(&dyn SecretStore) -> Result<V, HandlerError> + Send + 'static,
+    V: Send + 'static,
+{
+    let store = state.secrets.clone();
+    tokio::task::spawn_blocking(move || op(store.as_ref()))
+        .await
+        .map_err(|e| HandlerError::Panicked(e.to_string()))?
+}
+
+async fn send_secret_names<T, E>(
+    writer: &mut FramedWrite<T, E>,
+    request_id: Uuid,
+    names: Result<Vec<String>, HandlerError>,
+) -> Result<(), HandlerError>
+where
+    T: AsyncWrite + Unpin,
+    E: Encoder<Response>,
+    <E as Encoder<Response>>::Error: error::Error + Send + Sync + 'static,
+    HandlerError: From<<E as Encoder<Response>>::Error>,
+{
+    match names {
+        Ok(names) => {
             writer
                 .send(Response {
                     id: request_id,
-                    payload: ResponsePayload::Done { exit_code },
+                    payload: ResponsePayload::SecretNames { names },
                 })
                 .await?;
             Ok(())
         }
-        Err(HandlerError::Cancelled) => Ok(()),
-        Err(e) => {
-            writer
-                .send(Response {
-                    id: request_id,
-                    payload: ResponsePayload::Error {
-                        message: e.to_string(),
-                    },
-                })
-                .await?;
-            Err(e)
-        }
+        Err(e) => send_error(writer, request_id, e).await,
     }
 }
 
 #[doc(hidden)]
-pub fn resolve_path<P>(path: Option<P>) -> Result<Utf8PathBuf, HandlerError>
+pub async fn handle_set_secret<T, E>(
+    writer: &mut FramedWrite<T, E>,
+    request_id: Uuid,
+    name: String,
+    secret: String,
+    state: &HostState,
+) -> Result<(), HandlerError>
 where
-    P: AsRef<Utf8Path>,
+    T: AsyncWrite + Unpin,
+    E: Encoder<Response>,
+    <E as Encoder<Response>>::Error: error::Error + Send + Sync + 'static,
+    HandlerError: From<<E as Encoder<Response>>::Error>,
 {
-    if let Some(path) = path {
-        let path_buf = path.as_ref().to_owned();
+    let names = with_secret_store(state, move |store| {
+        store.set(&name, &secret)?;
+        Ok(store.names()?)
+    })
+    .await;
+    send_secret_names(writer, request_id, names).await
+}
 
-        if path_buf.exists() {
-            Ok(path_buf)
-        } else {
-            which::which("fabric-ai")
-                .map_err(HandlerError::from)
-                .and_then(|path| {
-                    Utf8PathBuf::from_path_buf(path).map_err(HandlerError::PathNotUtf8)
-                })
+#[doc(hidden)]
+pub async fn handle_delete_secret<T, E>(
+    writer: &mut FramedWrite<T, E>,
+    request_id: Uuid,
+    name: String,
+    state: &HostState,
+) -> Result<(), HandlerError>
+where
+    T: AsyncWrite + Unpin,
+    E: Encoder<Response>,
+    <E as Encoder<Response>>::Error: error::Error + Send + Sync + 'static,
+    HandlerError: From<<E as Encoder<Response>>::Error>,
+{
+    let names = with_secret_store(state, move |store| {
+        if !store.delete(&name)? {
+            return Err(HandlerError::SecretNotFound(name));
         }
-    } else {
-        which::which("fabric-ai")
-            .map_err(HandlerError::from)
-            .and_then(|path| Utf8PathBuf::from_path_buf(path).map_err(HandlerError::PathNotUtf8))
-    }
+        Ok(store.names()?)
+    })
+    .await;
+    send_secret_names(writer, request_id, names).await
 }
 
-#[cfg(test)]
-mod tests {
-    use std::{
-        io,
-        pin::Pin,
-        sync::{Arc, Mutex},
-        task::{Context, Poll},
-    };
+#[doc(hidden)]
+pub async fn handle_list_secret_names<T, E>(
+    writer: &mut FramedWrite<T, E>,
+    request_id: Uuid,
+    state: &HostState,
+) -> Result<(), HandlerError>
+where
+    T: AsyncWrite + Unpin,
+    E: Encoder<Response>,
+    <E as Encoder<Response>>::Error: error::Error + Send + Sync + 'static,
+    HandlerError: From<<E as Encoder<Response>>::Error>,
+{
+    let names = with_secret_store(state, |store| Ok(store.names()?)).await;
+    send_secret_names(writer, request_id, names).await
+}
 
-    use assert_matches::assert_matches;
-    use bytes::BytesMut;
-    use camino_tempfile::tempdir;
-    use camino_tempfile_ext::prelude::*;
-    use tokio::{io::AsyncWrite, sync::Mutex as TokioMutex};
-    use tokio_util::codec::Encoder;
+#[doc(hidden)]
+pub async fn handle_get_metrics<T, E>(
+    writer: &mut FramedWrite<T, E>,
+    request_id: Uuid,
+    state: &HostState,
+) -> Result<(), HandlerError>
+where
+    T: AsyncWrite + Unpin,
+    E: Encoder<Response>,
+    <E as Encoder<Response>>::Error: error::Error + Send + Sync + 'static,
+    HandlerError: From<<E as Encoder<Response>>::Error>,
+{
+    writer
+        .send(Response {
+            id: request_id,
+            payload: ResponsePayload::Metrics(state.metrics.snapshot(state.uptime_ms())),
+        })
+        .await?;
 
-    use super::*;
+    Ok(())
+}
 
-    struct MockCommandRunner {
-        fabric_path: Utf8PathBuf,
-        version_response: Option<CommandOutput>,
-        patterns_response: Option<CommandOutput>,
-        contexts_response: Option<CommandOutput>,
-        process_handles: Arc<TokioMutex<Vec<MockProcessHandle>>>,
-    }
+#[doc(hidden)]
+pub async fn handle_get_store_info<T, E>(
+    writer: &mut FramedWrite<T, E>,
+    request_id: Uuid,
+    state: &HostState,
+) -> Result<(), HandlerError>
+where
+    T: AsyncWrite + Unpin,
+    E: Encoder<Response>,
+    <E as Encoder<Response>>::Error: error::Error + Send + Sync + 'static,
+    HandlerError: From<<E as Encoder<Response>>::Error>,
+{
+    let stores = vec![
+        store::info("jobs", state.jobs.journal(), &jobs::JOURNAL_SCHEMA),
+        store::info("usage", state.usage.journal(), &usage::JOURNAL_SCHEMA),
+    ];
+    writer
+        .send(Response {
+            id: request_id,
+            payload: ResponsePayload::StoreInfo { stores },
+        })
+        .await?;
 
-    impl Default for MockCommandRunner {
-        fn default() -> Self {
-            Self {
-                fabric_path: Utf8PathBuf::from("/usr/bin/fabric"),
-                version_response: None,
-                patterns_response: None,
-                contexts_response: None,
+    Ok(())
+}
+
+/// Reports host internals for bug triage. Never fails: problems resolving
+/// fabric or reading the config are reported in the dump itself.
+#[doc(hidden)]
+pub async fn handle_debug_dump<T, E>(
+    writer: &mut FramedWrite<T, E>,
+    request_id: Uuid,
+    resolved: Result<Utf8PathBuf, HandlerError>,
+    state: &HostState,
+) -> Result<(), HandlerError>
+where
+    T: AsyncWrite + Unpin,
+    E: Encoder<Response>,
+    <E as Encoder<Response>>::Error: error::Error + Send + Sync + 'static,
+    HandlerError: From<<E as Encoder<Response>>::Error>,
+{
+    let (fabric_path, fabric_error) = match resolved {
+        Ok(path) => (Some(path), None),
+        Err(e) => (None, Some(e.to_string())),
+    };
+    let config_path = state.config_path().cloned();
+    let (config, config_error) = match config_path.as_deref().map(config::read_table) {
+        Some(Ok(Some(table))) => match serde_json::to_value(table) {
+            Ok(value) => (Some(value), None),
+            Err(e) => (None, Some(e.to_string())),
+        },
+        Some(Ok(None)) | None => (None, None),
+        Some(Err(message)) => (None, Some(message)),
+    };
+    let mut running_processes: Vec<Uuid> = state
+        .process_registry
+        .lock()
+        .await
+        .keys()
+        .copied()
+        .collect();
+    running_processes.sort();
+    let pending_chunks = state.chunks.lock().map_or(0, |chunks| chunks.len());
+
+    let dump = DebugState {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        uptime_ms: state.uptime_ms(),
+        active_requests: state.active_requests(),
+        running_processes,
+        fabric_path,
+        fabric_error,
+        config_path,
+        config,
+        config_error,
+        caches: CacheStates {
+            replay_entries: state.replay.len(),
+            pending_chunks,
+            fabric_digests: state.digests.len(),
+            jobs: state.jobs.list(None).len(),
+        },
+        recent_errors: state.recent_errors(),
+    };
+    writer
+        .send(Response {
+            id: request_id,
+            payload: ResponsePayload::DebugState(dump),
+        })
+        .await?;
+
+    Ok(())
+}
+
+#[doc(hidden)]
+pub async fn handle_validate_only<T, E, R>(
+    writer: &mut FramedWrite<T, E>,
+    request_id: Uuid,
+    runner: &R,
+    payload: RequestPayload,
+) -> Result<(), HandlerError>
+where
+    T: AsyncWrite + Unpin,
+    E: Encoder<Response>,
+    <E as Encoder<Response>>::Error: error::Error + Send + Sync + 'static,
+    R: CommandRunner,
+    HandlerError: From<<E as Encoder<Response>>::Error>,
+{
+    let fabric_path = runner.fabric_path().await?;
+    let mut backend = "fabric";
+    let mut command = None;
+    let mut estimated_prompt_tokens = None;
+    let mut warnings = Vec::new();
+
+    match payload {
+        RequestPayload::Ping => {
+            command = Some(
+                FabricCommandBuilder::new(fabric_path)
+                    .version()
+                    .command_line(),
+            );
+        }
+        RequestPayload::ListPatterns => {
+            command = Some(
+                FabricCommandBuilder::new(fabric_path)
+                    .list_patterns()
+                    .command_line(),
+            );
+        }
+        RequestPayload::ListContexts => {
+            command = Some(
+                FabricCommandBuilder::new(fabric_path)
+                    .list_contexts()
+                    .command_line(),
+            );
+        }
+        RequestPayload::ProcessContent {
+            content,
+            model,
+            pattern,
+            context,
+            custom_prompt,
+            system_prompt,
+            deterministic,
+            selection,
+            composition,
+            ..
+        } => {
+            if let Some(payload) = setup_required(runner).await? {
+                writer
+                    .send(Response {
+                        id: request_id,
+                        payload,
+                    })
+                    .await?;
+                return Ok(());
+            }
+
+            let options = ProcessOptions {
+                model,
+                pattern,
+                context,
+                custom_prompt,
+                system_prompt,
+                deterministic,
+                ..Default::default()
+            };
+            let content = compose::compose_content(content, selection, composition);
+            let plan = plan_process(runner, &options, &content).await?;
+            command = Some(plan.builder.command_line());
+            estimated_prompt_tokens = Some(estimate_tokens(plan.prompt_chars));
+            warnings = plan.warnings;
+        }
+        RequestPayload::ProcessBatch { items } => {
+            let prompt_chars = items
+                .iter()
+                .map(|item| item.content.chars().count() as u64)
+                .sum();
+            estimated_prompt_tokens = Some(estimate_tokens(prompt_chars));
+        }
+        RequestPayload::Hello { .. }
+        | RequestPayload::Heartbeat
+        | RequestPayload::Shutdown
+        | RequestPayload::ReloadConfig
+        | RequestPayload::GetConfig
+        | RequestPayload::SetConfig { .. }
+        | RequestPayload::PinPattern { .. }
+        | RequestPayload::UnpinPattern { .. }
+        | RequestPayload::PinFabricChecksum
+        | RequestPayload::SetSecret { .. }
+        | RequestPayload::DeleteSecret { .. }
+        | RequestPayload::ListSecretNames
+        | RequestPayload::GetMetrics
+        | RequestPayload::DebugDump
+        | RequestPayload::GetStoreInfo
+        | RequestPayload::ContentChunk { .. }
+        | RequestPayload::ReplayRequest { .. }
+        | RequestPayload::GetJobResult { .. }
+        | RequestPayload::ListJobs { .. }
+        | RequestPayload::CancelJob { .. }
+        | RequestPayload::DeleteJob { .. }
+        | RequestPayload::CancelProcess { .. }
+        | RequestPayload::OpenInEditor { .. } => backend = "host",
+    }
+
+    writer
+        .send(Response {
+            id: request_id,
+            payload: ResponsePayload::ValidationResult {
+                backend: backend.to_string(),
+                command,
+                estimated_prompt_tokens,
+                warnings,
+            },
+        })
+        .await?;
+
+    Ok(())
+}
+
+#[doc(hidden)]
+pub async fn handle_malformed_frame<T, E>(
+    writer: &mut FramedWrite<T, E>,
+    frame: MalformedFrame,
+) -> Result<(), HandlerError>
+where
+    T: AsyncWrite + Unpin,
+    E: Encoder<Response>,
+    <E as Encoder<Response>>::Error: error::Error + Send + Sync + 'static,
+    HandlerError: From<<E as Encoder<Response>>::Error>,
+{
+    let payload = match frame.limit {
+        Some(limit) => ResponsePayload::Error {
+            message: format!(
+                "Skipped request frame of {} bytes, which exceeds the {limit} byte limit",
+                frame.size
+            ),
+            details: Some(ErrorDetails {
+                code: "contentTooLarge".to_string(),
+                hint: Some(CHUNKED_UPLOAD_HINT.to_string()),
+                fields: Vec::new(),
+            }),
+        },
+        None => ResponsePayload::Error {
+            message: format!(
+                "Skipped malformed request frame ({} bytes): {}",
+                frame.size, frame.error
+            ),
+            details: Some(ErrorDetails {
+                code: "malformedFrame".to_string(),
+                hint: None,
+                fields: Vec::new(),
+            }),
+        },
+    };
+    writer
+        .send(Response {
+            id: frame.id.unwrap_or_else(Uuid::nil),
+            payload,
+        })
+        .await?;
+
+    Ok(())
+}
+
+#[doc(hidden)]
+pub async fn handle_duplicate_request<T, E>(
+    writer: &mut FramedWrite<T, E>,
+    request_id: Uuid,
+) -> Result<(), HandlerError>
+where
+    T: AsyncWrite + Unpin,
+    E: Encoder<Response>,
+    <E as Encoder<Response>>::Error: error::Error + Send + Sync + 'static,
+    HandlerError: From<<E as Encoder<Response>>::Error>,
+{
+    let error = HandlerError::DuplicateRequestId(request_id);
+    writer
+        .send(Response {
+            id: request_id,
+            payload: ResponsePayload::Error {
+                message: error.to_string(),
+                details: error.details(),
+            },
+        })
+        .await?;
+
+    Ok(())
+}
+
+#[doc(hidden)]
+pub async fn handle_panicked_request<T, E>(
+    writer: &mut FramedWrite<T, E>,
+    request_id: Uuid,
+    panic: Box<dyn Any + Send>,
+) -> Result<(), HandlerError>
+where
+    T: AsyncWrite + Unpin,
+    E: Encoder<Response>,
+    <E as Encoder<Response>>::Error: error::Error + Send + Sync + 'static,
+    HandlerError: From<<E as Encoder<Response>>::Error>,
+{
+    let message = panic
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string());
+    let error = HandlerError::Panicked(message);
+    writer
+        .send(Response {
+            id: request_id,
+            payload: ResponsePayload::Error {
+                message: error.to_string(),
+                details: error.details(),
+            },
+        })
+        .await?;
+
+    Ok(())
+}
+
+pub async fn boot_report<R: CommandRunner>(
+    fabric: Result<R, HandlerError>,
+    transports: Vec<String>,
+) -> BootReport {
+    let mut features = Vec::new();
+    if cfg!(feature = "msgpack") {
+        features.push("msgpack".to_string());
+    }
+
+    let mut report = BootReport {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        config_path: None,
+        transports,
+        features,
+        fabric_path: None,
+        fabric_version: None,
+        fabric_error: None,
+    };
+
+    let runner = match fabric {
+        Ok(runner) => runner,
+        Err(e) => {
+            report.fabric_error = Some(e.to_string());
+            return report;
+        }
+    };
+    report.config_path = runner
+        .fabric_config_dir()
+        .map(|config_dir| config_dir.to_string());
+    report.fabric_path = runner.fabric_path().await.ok().map(|path| path.to_string());
+    match runner.fabric_version().await {
+        Ok(output) if output.status => report.fabric_version = Some(output.stdout),
+        Ok(output) if output.stderr.is_empty() => {
+            report.fabric_error = Some("fabric --version exited unsuccessfully".to_string());
+        }
+        Ok(output) => report.fabric_error = Some(output.stderr),
+        Err(e) => report.fabric_error = Some(e.to_string()),
+    }
+
+    report
+}
+
+#[doc(hidden)]
+pub async fn handle_ping<T, E, R>(
+    writer: &mut FramedWrite<T, E>,
+    request_id: Uuid,
+    runner: &R,
+    stats: Option<HostStats>,
+    timeout: Duration,
+) -> Result<(), HandlerError>
+where
+    T: AsyncWrite + Unpin,
+    E: Encoder<Response>,
+    <E as Encoder<Response>>::Error: error::Error + Send + Sync + 'static,
+    R: CommandRunner,
+    HandlerError: From<<E as Encoder<Response>>::Error>,
+{
+    let fabric_path = runner.fabric_path().await?;
+    let binary = fabric_path.file_name().map(str::to_string);
+    match with_command_timeout(timeout, runner.fabric_version()).await {
+        Ok(_output) if _output.status => {
+            if let Some(payload) = setup_required(runner).await? {
+                writer
+                    .send(Response {
+                        id: request_id,
+                        payload,
+                    })
+                    .await?;
+                return Ok(());
+            }
+
+            writer
+                .send(Response {
+                    id: request_id,
+                    payload: ResponsePayload::Pong {
+                        resolved_path: Some(fabric_path.to_string()),
+                        binary: binary.clone(),
+                        version: Some(_output.stdout),
+                        valid: true,
+                        stats,
+                        compression: SUPPORTED_COMPRESSION.to_vec(),
+                    },
+                })
+                .await?;
+        }
+        Ok(_output) => {
+            writer
+                .send(Response {
+                    id: request_id,
+                    payload: ResponsePayload::Pong {
+                        resolved_path: Some(fabric_path.to_string()),
+                        binary: binary.clone(),
+                        version: None,
+                        valid: false,
+                        stats,
+                        compression: SUPPORTED_COMPRESSION.to_vec(),
+                    },
+                })
+                .await?;
+        }
+        Err(_e) => {
+            writer
+                .send(Response {
+                    id: request_id,
+                    payload: ResponsePayload::Pong {
+                        resolved_path: Some(fabric_path.to_string()),
+                        binary: binary.clone(),
+                        version: None,
+                        valid: false,
+                        stats,
+                        compression: SUPPORTED_COMPRESSION.to_vec(),
+                    },
+                })
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[doc(hidden)]
+pub async fn handle_list_patterns<T, E, R>(
+    writer: &mut FramedWrite<T, E>,
+    request_id: Uuid,
+    runner: &R,
+    timeout: Duration,
+    aliases: &BTreeMap<String, String>,
+    pinned: &[String],
+) -> Result<(), HandlerError>
+where
+    T: AsyncWrite + Unpin,
+    E: Encoder<Response>,
+    <E as Encoder<Response>>::Error: error::Error + Send + Sync + 'static,
+    R: CommandRunner,
+    HandlerError: From<<E as Encoder<Response>>::Error>,
+{
+    let output = match with_command_timeout(timeout, runner.list_patterns()).await {
+        Err(e @ HandlerError::Timeout(_)) => {
+            writer
+                .send(Response {
+                    id: request_id,
+                    payload: ResponsePayload::Error {
+                        message: format!("Timed out listing patterns: {e}"),
+                        details: Some(ErrorDetails {
+                            code: e.code().to_string(),
+                            hint: Some(
+                                "Check that fabric-ai is not waiting for interactive input"
+                                    .to_string(),
+                            ),
+                            fields: Vec::new(),
+                        }),
+                    },
+                })
+                .await?;
+            return Err(e);
+        }
+        output => output?,
+    };
+
+    if !output.status {
+        writer
+            .send(Response {
+                id: request_id,
+                payload: command_failure("list patterns", &output.stderr),
+            })
+            .await?;
+        return Ok(());
+    }
+
+    let patterns: Vec<String> = output
+        .stdout
+        .lines()
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect();
+
+    writer
+        .send(Response {
+            id: request_id,
+            payload: ResponsePayload::PatternsList {
+                patterns,
+                aliases: aliases.clone(),
+                pinned: pinned.to_vec(),
+            },
+        })
+        .await?;
+
+    Ok(())
+}
+
+async fn with_command_timeout(
+    timeout: Duration,
+    output: impl Future<Output = Result<CommandOutput, HandlerError>>,
+) -> Result<CommandOutput, HandlerError> {
+    tokio::time::timeout(timeout, output)
+        .await
+        .map_err(|_| HandlerError::Timeout(timeout))?
+}
+
+#[doc(hidden)]
+pub async fn handle_list_contexts<T, E, R>(
+    writer: &mut FramedWrite<T, E>,
+    request_id: Uuid,
+    runner: &R,
+) -> Result<(), HandlerError>
+where
+    T: AsyncWrite + Unpin,
+    E: Encoder<Response>,
+    <E as Encoder<Response>>::Error: error::Error + Send + Sync + 'static,
+    R: CommandRunner,
+    HandlerError: From<<E as Encoder<Response>>::Error>,
+{
+    let capabilities = probe_capabilities(runner).await;
+    if let Some(e) = HandlerError::unsupported(FabricFeature::Contexts, &capabilities) {
+        writer
+            .send(Response {
+                id: request_id,
+                payload: ResponsePayload::Error {
+                    message: e.to_string(),
+                    details: e.details(),
+                },
+            })
+            .await?;
+        return Ok(());
+    }
+
+    let output = runner.list_contexts().await?;
+
+    if !output.status {
+        writer
+            .send(Response {
+                id: request_id,
+                payload: command_failure("list contexts", &output.stderr),
+            })
+            .await?;
+        return Ok(());
+    }
+
+    let contexts: Vec<String> = output
+        .stdout
+        .lines()
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect();
+
+    writer
+        .send(Response {
+            id: request_id,
+            payload: ResponsePayload::ContextsList { contexts },
+        })
+        .await?;
+
+    Ok(())
+}
+
+async fn stream_process_responses<S>(
+    writer: &mut S,
+    request_id: Uuid,
+    mut process: Box<dyn ProcessHandle>,
+    content: String,
+    output_options: OutputOptions,
+    started: Instant,
+    mut cancel_rx: watch::Receiver<bool>,
+) -> Result<StreamSummary, HandlerError>
+where
+    S: Sink<Response> + Unpin,
+    HandlerError: From<S::Error>,
+{
+    if let Some(max_output_bytes) = output_options.max_output_bytes {
+        process.cap_stdout_line(max_output_bytes + 1);
+    }
+    let mut stdin_writer = process.stdin_writer(content.into_bytes());
+    let mut stdin_open = true;
+    let mut bytes_out: u64 = 0;
+    let mut output_chars: u64 = 0;
+    let mut time_to_first_chunk = None;
+    let mut hasher = blake3::Hasher::new();
+    let mut stderr_tail = String::new();
+    let mut aggregated = output_options.aggregate.then(String::new);
+    let mut formatter = OutputFormatter::new(output_options.format);
+    let mut progress = interval_at(started + PROGRESS_INTERVAL, PROGRESS_INTERVAL);
+    progress.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let deadline = output_options.deadline;
+    let mut quick_look = pin!(sleep_until(deadline.unwrap_or(started)));
+    let mut pending = String::new();
+    let mut coalesce = pin!(sleep_until(started));
+    let timeout = output_options.timeout;
+    let mut timeout_sleep = pin!(sleep_until(started + timeout.unwrap_or_default()));
+    let mut forwarded_bytes = 0;
+    let mut truncated = false;
+    let heartbeat_period = output_options.heartbeat.unwrap_or(PROGRESS_INTERVAL);
+    let mut heartbeat = interval_at(started + heartbeat_period, heartbeat_period);
+    heartbeat.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    loop {
+        tokio::select! { biased;
+            _ = cancel_rx.changed() => {
+                if *cancel_rx.borrow() {
+                    let _ = process.kill().await;
+                    let _ = process.wait().await;
+                    return Err(HandlerError::Cancelled);
+                }
+            }
+            // Nothing more will be forwarded, so stop fabric instead of paying
+            // for output that is dropped.
+            () = std::future::ready(()), if truncated => {
+                flush_content(writer, request_id, &mut pending).await?;
+                let _ = process.kill().await;
+                let exit_code = process.wait().await?;
+                return Ok(StreamSummary {
+                    exit_code,
+                    output_chars,
+                    time_to_first_chunk,
+                    duration: started.elapsed(),
+                    content_hash: hasher.finalize(),
+                    stderr_tail,
+                    content: aggregated,
+                    timed_out: false,
+                    truncated,
+                });
+            }
+            _ = &mut timeout_sleep, if timeout.is_some() => {
+                let _ = process.kill().await;
+                let _ = process.wait().await;
+                flush_content(writer, request_id, &mut pending).await?;
+                return Err(HandlerError::Timeout(timeout.unwrap_or_default()));
+            }
+            _ = &mut quick_look, if deadline.is_some() => {
+                let _ = process.kill().await;
+                let _ = process.wait().await;
+                flush_content(writer, request_id, &mut pending).await?;
+                return Ok(StreamSummary {
+                    exit_code: None,
+                    output_chars,
+                    time_to_first_chunk,
+                    duration: started.elapsed(),
+                    content_hash: hasher.finalize(),
+                    stderr_tail,
+                    content: aggregated,
+                    timed_out: true,
+                    truncated,
+                });
+            }
+            _ = &mut coalesce, if !pending.is_empty() && output_options.coalesce.is_some() => {
+                flush_content(writer, request_id, &mut pending).await?;
+            }
+            written = &mut stdin_writer, if stdin_open => {
+                stdin_open = false;
+                if let Err(e) = written {
+                    let _ = process.kill().await;
+                    let _ = process.wait().await;
+                    return Err(e);
+                }
+            }
+            output = process.read_output() => {
+                match output {
+                    Ok(Some(ProcessOutput::Stderr(line))) => {
+                        append_tail(&mut stderr_tail, &line, STDERR_TAIL_SIZE);
+                        flush_content(writer, request_id, &mut pending).await?;
+                        writer.send(Response {
+                            id: request_id,
+                            payload: ResponsePayload::StderrLine { line },
+                        }).await?;
+                    }
+                    Ok(Some(ProcessOutput::Stdout(line))) => {
+                        time_to_first_chunk.get_or_insert_with(|| started.elapsed());
+                        bytes_out += line.len() as u64;
+                        output_chars += line.chars().count() as u64;
+                        let line = match &mut formatter {
+                            Some(formatter) => formatter.format_line(&line),
+                            None => line,
+                        };
+                        if line.is_empty() {
+                            continue;
+                        }
+                        // Normalizing runs per line, so a date or number
+                        // fabric splits across lines is left as written.
+                        let mut line = match &output_options.locale {
+                            Some(locale) => locale.normalize(&line),
+                            None => line,
+                        };
+                        if let Some(max_output_bytes) = output_options.max_output_bytes {
+                            let remaining = max_output_bytes.saturating_sub(forwarded_bytes);
+                            if line.len() > remaining {
+                                truncated = true;
+                                let message = format!(
+                                    "Output exceeded the {max_output_bytes} byte cap; the rest was dropped"
+                                );
+                                flush_content(writer, request_id, &mut pending).await?;
+                                if output_options.strict {
+                                    let _ = process.kill().await;
+                                    let _ = process.wait().await;
+                                    return Err(HandlerError::Strict(message));
+                                }
+                                writer.send(Response {
+                                    id: request_id,
+                                    payload: ResponsePayload::Warning { message },
+                                }).await?;
+                                line.truncate(floor_char_boundary(&line, remaining));
+                                if line.is_empty() {
+                                    continue;
+                                }
+                            }
+                            forwarded_bytes += line.len();
+                            process.cap_stdout_line(max_output_bytes - forwarded_bytes + 1);
+                        }
+                        hasher.update(line.as_bytes());
+                        if let Some(aggregated) = &mut aggregated {
+                            aggregated.push_str(&line);
+                        }
+                        if output_options.buffered {
+                            continue;
+                        }
+                        if output_options.coalesce.is_none() && output_options.coalesce_bytes.is_none() {
+                            writer.send(Response {
+                                id: request_id,
+                                payload: ResponsePayload::Content { content: line },
+                            }).await?;
+                            continue;
+                        }
+                        if pending.is_empty()
+                            && let Some(interval) = output_options.coalesce
+                        {
+                            coalesce.as_mut().reset(Instant::now() + interval);
+                        }
+                        pending.push_str(&line);
+                        if pending.len() >= output_options.coalesce_bytes.unwrap_or(COALESCE_MAX_BYTES) {
+                            flush_content(writer, request_id, &mut pending).await?;
+                        }
+                    }
+                    Ok(None) => {
+                        flush_content(writer, request_id, &mut pending).await?;
+                        let exit_code = process.wait().await?;
+                        return Ok(StreamSummary {
+                            exit_code,
+                            output_chars,
+                            time_to_first_chunk,
+                            duration: started.elapsed(),
+                            content_hash: hasher.finalize(),
+                            stderr_tail,
+                            content: aggregated,
+                            timed_out: false,
+                            truncated,
+                        });
+                    }
+                    Err(e) => {
+                        return Err(e);
+                    }
+                }
+            }
+            _ = progress.tick() => {
+                writer.send(Response {
+                    id: request_id,
+                    payload: ResponsePayload::Progress {
+                        bytes_out,
+                        elapsed_ms: as_millis(started.elapsed()),
+                    },
+                }).await?;
+            }
+            _ = heartbeat.tick(), if output_options.heartbeat.is_some() => {
+                writer.send(Response {
+                    id: request_id,
+                    payload: ResponsePayload::Heartbeat {
+                        elapsed_ms: as_millis(started.elapsed()),
+                    },
+                }).await?;
+            }
+        }
+    }
+}
+
+fn floor_char_boundary(text: &str, index: usize) -> usize {
+    (0..=index.min(text.len()))
+        .rev()
+        .find(|&index| text.is_char_boundary(index))
+        .unwrap_or(0)
+}
+
+fn encoded_size(response: &Response) -> usize {
+    serde_json::to_vec(response).map_or(0, |encoded| encoded.len())
+}
+
+/// Drops the tail of the aggregated content in a `Done` or `PartialResult`
+/// until the whole frame encodes within `limit` bytes, returning whether
+/// anything was dropped.
+fn fit_frame(response: &mut Response, limit: usize) -> bool {
+    let mut dropped = false;
+    loop {
+        let size = encoded_size(response);
+        let (ResponsePayload::Done {
+            content: Some(content),
+            ..
+        }
+        | ResponsePayload::PartialResult { content, .. }) = &mut response.payload
+        else {
+            return dropped;
+        };
+        if size <= limit || content.is_empty() {
+            return dropped;
+        }
+        let end = content.len().saturating_sub(size - limit);
+        content.truncate(floor_char_boundary(content, end));
+        dropped = true;
+    }
+}
+
+async fn flush_content<S>(
+    writer: &mut S,
+    request_id: Uuid,
+    pending: &mut String,
+) -> Result<(), HandlerError>
+where
+    S: Sink<Response> + Unpin,
+    HandlerError: From<S::Error>,
+{
+    if !pending.is_empty() {
+        writer
+            .send(Response {
+                id: request_id,
+                payload: ResponsePayload::Content {
+                    content: mem::take(pending),
+                },
+            })
+            .await?;
+    }
+    Ok(())
+}
+
+#[doc(hidden)]
+pub async fn handle_cancel_process<T, E>(
+    writer: &mut FramedWrite<T, E>,
+    cancel_request_id: Uuid,
+    target_request_id: Uuid,
+    process_registry: ProcessRegistry,
+) -> Result<(), HandlerError>
+where
+    T: AsyncWrite + Unpin,
+    E: Encoder<Response>,
+    <E as Encoder<Response>>::Error: error::Error + Send + Sync + 'static,
+    HandlerError: From<<E as Encoder<Response>>::Error>,
+{
+    let cancel_sender = {
+        let registry = process_registry.lock().await;
+        registry.get(&target_request_id).cloned()
+    };
+
+    match cancel_sender {
+        Some(sender) => {
+            if sender.send(true).is_err() {
+                writer
+                    .send(Response {
+                        id: cancel_request_id,
+                        payload: ResponsePayload::Error {
+                            message: format!("Process {} already completed", target_request_id),
+                            details: None,
+                        },
+                    })
+                    .await?;
+            } else {
+                writer
+                    .send(Response {
+                        id: cancel_request_id,
+                        payload: ResponsePayload::Cancelled {
+                            request_id: target_request_id,
+                        },
+                    })
+                    .await?;
+            }
+        }
+        None => {
+            writer
+                .send(Response {
+                    id: cancel_request_id,
+                    payload: ResponsePayload::Error {
+                        message: format!(
+                            "Process {} not found or already completed",
+                            target_request_id
+                        ),
+                        details: None,
+                    },
+                })
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+fn resolve_pattern_defaults(mut options: ProcessOptions, state: &HostState) -> ProcessOptions {
+    let defaults = state.request_defaults();
+    options.pattern = defaults.resolve_pattern(options.pattern);
+    if options.model.is_none() {
+        options.model = defaults.model_for(options.pattern.as_deref());
+    }
+    options
+}
+
+#[doc(hidden)]
+pub async fn handle_process_content<T, E, R>(
+    writer: &mut FramedWrite<T, E>,
+    request_id: Uuid,
+    runner: &R,
+    options: ProcessOptions,
+    content: String,
+    state: &HostState,
+) -> Result<(), HandlerError>
+where
+    T: AsyncWrite + Unpin,
+    E: Encoder<Response>,
+    <E as Encoder<Response>>::Error: error::Error + Send + Sync + 'static,
+    R: CommandRunner,
+    HandlerError: From<<E as Encoder<Response>>::Error>,
+{
+    let options = resolve_pattern_defaults(options, state);
+    let (cancel_tx, cancel_rx) = watch::channel(false);
+
+    {
+        let mut registry = state.process_registry.lock().await;
+        registry.insert(request_id, cancel_tx);
+    }
+
+    let mut writer = ReplaySink::new(writer, request_id, &state.replay);
+    let result = process_content(
+        &mut writer,
+        request_id,
+        runner,
+        &state.process_slots,
+        &state.usage,
+        &state.metrics,
+        options,
+        content,
+        cancel_rx,
+    )
+    .await;
+
+    {
+        let mut registry = state.process_registry.lock().await;
+        registry.remove(&request_id);
+    }
+
+    result
+}
+
+fn accept_chunk(
+    chunks: &ChunkRegistry,
+    request_id: Uuid,
+    seq: u64,
+    data: String,
+    last: bool,
+    limit: usize,
+) -> ResponsePayload {
+    let Ok(mut chunks) = chunks.lock() else {
+        return ResponsePayload::Error {
+            message: "Chunk registry unavailable".to_string(),
+            details: None,
+        };
+    };
+    chunks.retain(|_, buffer| buffer.updated.elapsed() < CHUNK_TTL);
+
+    let buffer = chunks.entry(request_id).or_insert_with(|| ChunkBuffer {
+        next_seq: 0,
+        data: String::new(),
+        complete: false,
+        updated: Instant::now(),
+    });
+
+    let error = if buffer.complete {
+        Some(format!(
+            "Content for request {request_id} is already complete"
+        ))
+    } else if seq != buffer.next_seq {
+        Some(format!(
+            "Expected chunk {} for request {request_id}, got {seq}",
+            buffer.next_seq
+        ))
+    } else if buffer.data.len() + data.len() > limit {
+        Some(format!(
+            "Content for request {request_id} exceeds {limit} bytes"
+        ))
+    } else {
+        None
+    };
+
+    if let Some(message) = error {
+        chunks.remove(&request_id);
+        return ResponsePayload::Error {
+            message,
+            details: Some(ErrorDetails {
+                code: "invalidChunk".to_string(),
+                hint: Some("Restart the upload from seq 0".to_string()),
+                fields: Vec::new(),
+            }),
+        };
+    }
+
+    buffer.data.push_str(&data);
+    buffer.next_seq += 1;
+    buffer.complete = last;
+    buffer.updated = Instant::now();
+
+    ResponsePayload::ChunkAccepted {
+        request_id,
+        seq,
+        complete: last,
+    }
+}
+
+fn peek_assembled_content(chunks: &ChunkRegistry, request_id: Uuid) -> Option<String> {
+    let chunks = chunks.lock().ok()?;
+    let buffer = chunks.get(&request_id)?;
+    buffer.complete.then(|| buffer.data.clone())
+}
+
+fn take_assembled_content(chunks: &ChunkRegistry, request_id: Uuid) -> Option<String> {
+    let mut chunks = chunks.lock().ok()?;
+    if !chunks.get(&request_id)?.complete {
+        return None;
+    }
+    chunks.remove(&request_id).map(|buffer| buffer.data)
+}
+
+#[doc(hidden)]
+pub async fn handle_replay_request<T, E>(
+    writer: &mut FramedWrite<T, E>,
+    request_id: Uuid,
+    target_request_id: Uuid,
+    state: &HostState,
+) -> Result<(), HandlerError>
+where
+    T: AsyncWrite + Unpin,
+    E: Encoder<Response>,
+    <E as Encoder<Response>>::Error: error::Error + Send + Sync + 'static,
+    HandlerError: From<<E as Encoder<Response>>::Error>,
+{
+    let Some(payloads) = state.replay.get(target_request_id) else {
+        writer
+            .send(Response {
+                id: request_id,
+                payload: ResponsePayload::Error {
+                    message: format!("No buffered output for request {target_request_id}"),
+                    details: None,
+                },
+            })
+            .await?;
+        return Ok(());
+    };
+
+    for payload in payloads {
+        writer
+            .feed(Response {
+                id: request_id,
+                payload,
+            })
+            .await?;
+    }
+    writer.flush().await?;
+
+    Ok(())
+}
+
+#[doc(hidden)]
+pub async fn handle_open_in_editor<T, E, R>(
+    writer: &mut FramedWrite<T, E>,
+    request_id: Uuid,
+    runner: &R,
+    target_request_id: Uuid,
+    state: &HostState,
+) -> Result<(), HandlerError>
+where
+    T: AsyncWrite + Unpin,
+    E: Encoder<Response>,
+    <E as Encoder<Response>>::Error: error::Error + Send + Sync + 'static,
+    R: CommandRunner,
+    HandlerError: From<<E as Encoder<Response>>::Error>,
+{
+    let content = match state.jobs.get(target_request_id) {
+        Some(job) => Some(job.content),
+        None => state.replay.get(target_request_id).map(|payloads| {
+            payloads
+                .into_iter()
+                .filter_map(|payload| match payload {
+                    ResponsePayload::Content { content } => Some(content),
+                    _ => None,
+                })
+                .collect::<String>()
+        }),
+    };
+    let Some(content) = content.filter(|content| !content.is_empty()) else {
+        writer
+            .send(Response {
+                id: request_id,
+                payload: ResponsePayload::Error {
+                    message: format!("No result available for request {target_request_id}"),
+                    details: None,
+                },
+            })
+            .await?;
+        return Ok(());
+    };
+
+    let path = editor::write_result(target_request_id, &content).await?;
+    let editor = runner.open_in_editor(&path)?;
+
+    writer
+        .send(Response {
+            id: request_id,
+            payload: ResponsePayload::EditorOpened {
+                request_id: target_request_id,
+                path: path.to_string(),
+                editor,
+            },
+        })
+        .await?;
+
+    Ok(())
+}
+
+#[doc(hidden)]
+pub async fn handle_detached_process<T, E, R>(
+    writer: &mut FramedWrite<T, E>,
+    request_id: Uuid,
+    runner: R,
+    options: ProcessOptions,
+    content: String,
+    state: &HostState,
+) -> Result<(), HandlerError>
+where
+    T: AsyncWrite + Unpin,
+    E: Encoder<Response>,
+    <E as Encoder<Response>>::Error: error::Error + Send + Sync + 'static,
+    R: CommandRunner + 'static,
+    HandlerError: From<<E as Encoder<Response>>::Error>,
+{
+    let options = resolve_pattern_defaults(options, state);
+    let job_id = Uuid::new_v4();
+    let (cancel_tx, cancel_rx) = watch::channel(false);
+
+    state.jobs.insert(job_id);
+    {
+        let mut registry = state.process_registry.lock().await;
+        registry.insert(job_id, cancel_tx);
+    }
+
+    let jobs = state.jobs.clone();
+    let process_registry = state.process_registry.clone();
+    tokio::spawn(run_detached_job(
+        job_id,
+        runner,
+        state.process_slots.clone(),
+        state.usage.clone(),
+        state.metrics.clone(),
+        options,
+        content,
+        cancel_rx,
+        jobs,
+        process_registry,
+    ));
+
+    writer
+        .send(Response {
+            id: request_id,
+            payload: ResponsePayload::JobAccepted { job_id },
+        })
+        .await?;
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn run_detached_job<R: CommandRunner>(
+    job_id: Uuid,
+    runner: R,
+    slots: ProcessSlots,
+    usage: Arc<UsageTracker>,
+    metrics: Arc<Metrics>,
+    options: ProcessOptions,
+    content: String,
+    cancel_rx: watch::Receiver<bool>,
+    jobs: Arc<JobRegistry>,
+    process_registry: ProcessRegistry,
+) {
+    let mut sink = JobSink::new(job_id, jobs);
+    let cancelled = cancel_rx.clone();
+    let result = process_content(
+        &mut sink, job_id, &runner, &slots, &usage, &metrics, options, content, cancel_rx,
+    )
+    .await;
+
+    {
+        let mut registry = process_registry.lock().await;
+        registry.remove(&job_id);
+    }
+
+    match result {
+        _ if *cancelled.borrow() => sink.finish(JobState::Cancelled),
+        Err(_) => sink.finish(JobState::Failed),
+        Ok(()) => sink.finish(JobState::Completed),
+    }
+}
+
+#[doc(hidden)]
+pub async fn handle_get_job_result<T, E>(
+    writer: &mut FramedWrite<T, E>,
+    request_id: Uuid,
+    job_id: Uuid,
+    state: &HostState,
+) -> Result<(), HandlerError>
+where
+    T: AsyncWrite + Unpin,
+    E: Encoder<Response>,
+    <E as Encoder<Response>>::Error: error::Error + Send + Sync + 'static,
+    HandlerError: From<<E as Encoder<Response>>::Error>,
+{
+    let payload = match state.jobs.get(job_id) {
+        Some(job) => jobs::result_payload(job_id, &job),
+        None => job_not_found(job_id),
+    };
+
+    writer
+        .send(Response {
+            id: request_id,
+            payload,
+        })
+        .await?;
+
+    Ok(())
+}
+
+#[doc(hidden)]
+pub async fn handle_list_jobs<T, E>(
+    writer: &mut FramedWrite<T, E>,
+    request_id: Uuid,
+    job_state: Option<JobState>,
+    state: &HostState,
+) -> Result<(), HandlerError>
+where
+    T: AsyncWrite + Unpin,
+    E: Encoder<Response>,
+    <E as Encoder<Response>>::Error: error::Error + Send + Sync + 'static,
+    HandlerError: From<<E as Encoder<Response>>::Error>,
+{
+    writer
+        .send(Response {
+            id: request_id,
+            payload: ResponsePayload::JobsList {
+                jobs: state.jobs.list(job_state),
+            },
+        })
+        .await?;
+
+    Ok(())
+}
+
+#[doc(hidden)]
+pub async fn handle_cancel_job<T, E>(
+    writer: &mut FramedWrite<T, E>,
+    request_id: Uuid,
+    job_id: Uuid,
+    state: &HostState,
+) -> Result<(), HandlerError>
+where
+    T: AsyncWrite + Unpin,
+    E: Encoder<Response>,
+    <E as Encoder<Response>>::Error: error::Error + Send + Sync + 'static,
+    HandlerError: From<<E as Encoder<Response>>::Error>,
+{
+    let cancel_sender = {
+        let registry = state.process_registry.lock().await;
+        registry.get(&job_id).cloned()
+    };
+
+    let payload = match (cancel_sender, state.jobs.get(job_id)) {
+        (Some(sender), Some(_)) if sender.send(true).is_ok() => {
+            ResponsePayload::Cancelled { request_id: job_id }
+        }
+        (_, Some(_)) => ResponsePayload::Error {
+            message: format!("Job {job_id} already finished"),
+            details: None,
+        },
+        (_, None) => job_not_found(job_id),
+    };
+
+    writer
+        .send(Response {
+            id: request_id,
+            payload,
+        })
+        .await?;
+
+    Ok(())
+}
+
+#[doc(hidden)]
+pub async fn handle_delete_job<T, E>(
+    writer: &mut FramedWrite<T, E>,
+    request_id: Uuid,
+    job_id: Uuid,
+    state: &HostState,
+) -> Result<(), HandlerError>
+where
+    T: AsyncWrite + Unpin,
+    E: Encoder<Response>,
+    <E as Encoder<Response>>::Error: error::Error + Send + Sync + 'static,
+    HandlerError: From<<E as Encoder<Response>>::Error>,
+{
+    let payload = match state.jobs.get(job_id) {
+        Some(job) if job.state == JobState::Running => ResponsePayload::Error {
+            message: format!("Job {job_id} is still running; cancel it before deleting"),
+            details: None,
+        },
+        Some(_) => {
+            state.jobs.remove(job_id);
+            ResponsePayload::JobDeleted { job_id }
+        }
+        None => job_not_found(job_id),
+    };
+
+    writer
+        .send(Response {
+            id: request_id,
+            payload,
+        })
+        .await?;
+
+    Ok(())
+}
+
+fn job_not_found(job_id: Uuid) -> ResponsePayload {
+    ResponsePayload::Error {
+        message: format!("Job {job_id} not found"),
+        details: None,
+    }
+}
+
+/// The options every fabric run starts from: host-wide limits and defaults,
+/// before a request's own fields are applied.
+fn host_process_options(state: &HostState) -> ProcessOptions {
+    ProcessOptions {
+        coalesce_interval: state.coalesce_interval(),
+        strict: state.strict(),
+        timeout: state.request_defaults().timeout,
+        max_output_bytes: state.max_output_bytes(),
+        max_frame_bytes: Some(state.outbound_limit()),
+        locale: state
+            .client_locale()
+            .or_else(|| state.request_defaults().locale),
+        ..ProcessOptions::default()
+    }
+}
+
+#[doc(hidden)]
+pub async fn handle_process_batch<T, E, R>(
+    writer: &mut FramedWrite<T, E>,
+    request_id: Uuid,
+    runner: &R,
+    items: Vec<BatchContent>,
+    state: &HostState,
+) -> Result<(), HandlerError>
+where
+    T: AsyncWrite + Unpin,
+    E: Encoder<Response>,
+    <E as Encoder<Response>>::Error: error::Error + Send + Sync + 'static,
+    R: CommandRunner,
+    HandlerError: From<<E as Encoder<Response>>::Error>,
+{
+    let (cancel_tx, cancel_rx) = watch::channel(false);
+
+    {
+        let mut registry = state.process_registry.lock().await;
+        registry.insert(request_id, cancel_tx);
+    }
+
+    let total = items.len();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+
+    let run_items = run_batch_items(request_id, runner, state, items, cancel_rx, tx);
+
+    let forward = async {
+        while let Some(response) = rx.recv().await {
+            writer.send(response).await?;
+        }
+        Ok::<_, HandlerError>(())
+    };
+
+    let (failed, forwarded) = tokio::join!(run_items, forward);
+
+    {
+        let mut registry = state.process_registry.lock().await;
+        registry.remove(&request_id);
+    }
+
+    forwarded?;
+    writer
+        .send(Response {
+            id: request_id,
+            payload: ResponsePayload::BatchDone { total, failed },
+        })
+        .await?;
+
+    Ok(())
+}
+
+async fn run_batch_items<R: CommandRunner>(
+    batch_id: Uuid,
+    runner: &R,
+    state: &HostState,
+    items: Vec<BatchContent>,
+    cancel_rx: watch::Receiver<bool>,
+    tx: mpsc::UnboundedSender<Response>,
+) -> usize {
+    stream::iter(items.into_iter().enumerate())
+        .map(|(index, item)| {
+            let mut sink = BatchItemSink {
+                batch_id,
+                index,
+                tx: tx.clone(),
+            };
+            let cancel_rx = cancel_rx.clone();
+            async move {
+                if *cancel_rx.borrow() {
+                    return true;
+                }
+
+                let options = ProcessOptions {
+                    model: item.model,
+                    pattern: item.pattern,
+                    ..host_process_options(state)
+                };
+                process_content(
+                    &mut sink,
+                    batch_id,
+                    runner,
+                    &state.process_slots,
+                    &state.usage,
+                    &state.metrics,
+                    options,
+                    item.content,
+                    cancel_rx,
+                )
+                .await
+                .is_ok()
+            }
+        })
+        .buffer_unordered(BATCH_CONCURRENCY)
+        .fold(0, |failed, ok| async move { failed + usize::from(!ok) })
+        .await
+}
+
+struct BatchItemSink {
+    batch_id: Uuid,
+    index: usize,
+    tx: mpsc::UnboundedSender<Response>,
+}
+
+impl Sink<Response> for BatchItemSink {
+    type Error = HandlerError;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, response: Response) -> Result<(), Self::Error> {
+        self.tx
+            .send(Response {
+                id: self.batch_id,
+                payload: ResponsePayload::BatchItem {
+                    index: self.index,
+                    item: Box::new(response.payload),
+                },
+            })
+            .map_err(|_| HandlerError::Io(io::Error::other("Batch output channel closed")))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+struct ProcessPlan<'a> {
+    builder: FabricCommandBuilder<'a>,
+    warnings: Vec<String>,
+    inputs: Option<ResolvedInputs>,
+    prompt_chars: u64,
+}
+
+async fn plan_process<'a, R: CommandRunner>(
+    runner: &'a R,
+    options: &ProcessOptions,
+    content: &str,
+) -> Result<ProcessPlan<'a>, HandlerError> {
+    let prompt_chars = content.chars().count() as u64
+        + [&options.custom_prompt, &options.system_prompt]
+            .into_iter()
+            .flatten()
+            .map(|prompt| prompt.chars().count() as u64)
+            .sum::<u64>();
+
+    let fabric_path = runner.fabric_path().await?;
+    let mut builder = FabricCommandBuilder::new(fabric_path).stream();
+    let mut warnings = Vec::new();
+    let mut applied_context = None;
+
+    if let Some(model) = &options.model {
+        builder = builder.model(model);
+    }
+
+    if let Some(cwd) = &options.cwd {
+        builder = builder.current_dir(cwd.clone());
+    }
+
+    for (name, value) in &options.env {
+        builder = builder.env(name, value);
+    }
+
+    if let Some(context) = &options.context {
+        let capabilities = probe_capabilities(runner).await;
+        match HandlerError::unsupported(FabricFeature::Contexts, &capabilities) {
+            Some(e) => warnings.push(format!("Ignoring context '{context}': {e}")),
+            None => {
+                applied_context = Some(context.clone());
+                builder = builder.context(context);
+            }
+        }
+    }
+
+    let custom_prompt = match &options.pattern {
+        Some(pattern) => {
+            builder = builder.pattern(pattern);
+            None
+        }
+        None => options.custom_prompt.as_deref(),
+    };
+
+    let system_prompt = options
+        .system_prompt
+        .as_deref()
+        .filter(|prompt| !prompt.is_empty());
+    let custom_prompt = custom_prompt.filter(|prompt| !prompt.is_empty());
+
+    let inputs = if options.deterministic {
+        let capabilities = probe_capabilities(runner).await;
+        let seed = capabilities
+            .supports(FabricFeature::Seed)
+            .then_some(DETERMINISTIC_SEED);
+        if seed.is_none() {
+            let reason = HandlerError::unsupported(FabricFeature::Seed, &capabilities).map_or_else(
+                || "the fabric version could not be determined".to_string(),
+                |e| e.to_string(),
+            );
+            warnings.push(format!("Running without a fixed seed: {reason}"));
+        }
+        builder = builder.temperature(DETERMINISTIC_TEMPERATURE);
+        if let Some(seed) = seed {
+            builder = builder.seed(seed);
+        }
+        Some(ResolvedInputs {
+            model: options.model.clone(),
+            pattern: options.pattern.clone(),
+            context: applied_context,
+            prompt: (system_prompt.is_some() || custom_prompt.is_some()).then(|| {
+                [system_prompt, custom_prompt]
+                    .into_iter()
+                    .flatten()
+                    .collect::<Vec<_>>()
+                    .join("\n\n")
+            }),
+            temperature: DETERMINISTIC_TEMPERATURE,
+            seed,
+            input_hash: blake3::hash(content.as_bytes()).to_hex().to_string(),
+            fabric_version: capabilities.version().map(|version| version.to_string()),
+        })
+    } else {
+        None
+    };
+
+    if let Some(system_prompt) = system_prompt {
+        builder = builder.system_prompt(system_prompt);
+    }
+    if let Some(custom_prompt) = custom_prompt {
+        builder = builder.custom_prompt(custom_prompt);
+    }
+
+    Ok(ProcessPlan {
+        builder,
+        warnings,
+        inputs,
+        prompt_chars,
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn process_content<S, R>(
+    writer: &mut S,
+    request_id: Uuid,
+    runner: &R,
+    slots: &ProcessSlots,
+    tracker: &UsageTracker,
+    metrics: &Metrics,
+    options: ProcessOptions,
+    content: String,
+    cancel_rx: watch::Receiver<bool>,
+) -> Result<(), HandlerError>
+where
+    S: Sink<Response> + Unpin,
+    R: CommandRunner,
+    HandlerError: From<S::Error>,
+{
+    if let Some(payload) = setup_required(runner).await? {
+        writer
+            .send(Response {
+                id: request_id,
+                payload,
+            })
+            .await?;
+        return Ok(());
+    }
+
+    let plan = plan_process(runner, &options, &content).await?;
+    if options.strict
+        && let Some(message) = plan.warnings.first()
+    {
+        return Err(send_strict_error(writer, request_id, message.clone()).await?);
+    }
+    for message in plan.warnings {
+        writer
+            .send(Response {
+                id: request_id,
+                payload: ResponsePayload::Warning { message },
+            })
+            .await?;
+    }
+    let builder = plan
+        .builder
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let prompt_chars = plan.prompt_chars;
+
+    let Some(permit) = slots.acquire(writer, request_id, cancel_rx.clone()).await? else {
+        return Ok(());
+    };
+
+    let stream_start = ResponsePayload::StreamStart {
+        model: options.model,
+        pattern: options.pattern,
+        inputs: plan.inputs,
+        output_format: options.output_format,
+    };
+
+    let started = Instant::now();
+    let output_options = OutputOptions {
+        locale: options.locale.as_deref().and_then(LocaleFormat::from_tag),
+        format: options.output_format,
+        aggregate: options.aggregate
+            || options.summarize
+            || options.structured
+            || options.quick_look.is_some(),
+        deadline: options
+            .quick_look
+            .map(|quick_look| started + Duration::from_secs(quick_look.max_seconds)),
+        coalesce: options.coalesce_interval,
+        coalesce_bytes: options.coalesce_bytes,
+        buffered: options.structured,
+        timeout: options.timeout,
+        max_output_bytes: options.max_output_bytes,
+        heartbeat: options.heartbeat,
+        strict: options.strict,
+    };
+    let mut process = match runner.spawn_process(builder).await {
+        Ok(process) => process,
+        Err(e) => {
+            writer
+                .send(Response {
+                    id: request_id,
+                    payload: ResponsePayload::Error {
+                        message: e.to_string(),
+                        details: e.details(),
+                    },
+                })
+                .await?;
+            return Err(e);
+        }
+    };
+    let spawn_time = started.elapsed();
+    metrics.record_spawn_time(spawn_time);
+    tracing::debug!(
+        %request_id,
+        spawn_ms = as_millis(spawn_time),
+        "Started fabric"
+    );
+    if options.stream_mode == StreamMode::Chunks {
+        process.read_stdout_chunks(STDOUT_CHUNK_SIZE);
+    }
+
+    writer
+        .send(Response {
+            id: request_id,
+            payload: stream_start,
+        })
+        .await?;
+
+    let result = stream_process_responses(
+        writer,
+        request_id,
+        process,
+        content,
+        output_options,
+        started,
+        cancel_rx.clone(),
+    )
+    .await;
+
+    match result {
+        Ok(summary) if summary.timed_out => {
+            let mut partial = Response {
+                id: request_id,
+                payload: ResponsePayload::PartialResult {
+                    content: summary.content.unwrap_or_default(),
+                    elapsed_ms: as_millis(summary.duration),
+                },
+            };
+            if let Some(limit) = options.max_frame_bytes {
+                fit_frame(&mut partial, limit);
+            }
+            writer.send(partial).await?;
+            Ok(())
+        }
+        Ok(summary) => {
+            let generation = generation::parse_generation_stats(&summary.stderr_tail);
+            let stderr_tail = match summary.exit_code {
+                Some(0) => None,
+                _ if summary.stderr_tail.is_empty() => None,
+                _ => Some(summary.stderr_tail),
+            };
+            let error = stderr_tail
+                .as_deref()
+                .and_then(classify_failure)
+                .map(failure_details);
+            let usage = generation
+                .as_ref()
+                .and_then(generation::measured_usage)
+                .unwrap_or(TokenUsage {
+                    prompt_tokens: estimate_tokens(prompt_chars),
+                    completion_tokens: estimate_tokens(summary.output_chars),
+                    estimated: true,
+                });
+            tracker.record_tokens(&usage);
+            if let Some(time_to_first_chunk) = summary.time_to_first_chunk {
+                metrics.record_time_to_first_chunk(time_to_first_chunk);
+            }
+            if options.structured && summary.exit_code == Some(0) {
+                let output = summary.content.as_deref().unwrap_or_default();
+                match serde_json::from_str(output) {
+                    Ok(value) => {
+                        let json = Response {
+                            id: request_id,
+                            payload: ResponsePayload::Json { value },
+                        };
+                        let size = encoded_size(&json);
+                        if let Some(limit) = options.max_frame_bytes
+                            && size > limit
+                        {
+                            let error = HandlerError::ResponseTooLarge { size, limit };
+                            writer
+                                .send(Response {
+                                    id: request_id,
+                                    payload: ResponsePayload::Error {
+                                        message: error.to_string(),
+                                        details: error.details(),
+                                    },
+                                })
+                                .await?;
+                            return Err(error);
+                        }
+                        writer.send(json).await?;
+                    }
+                    Err(e) => {
+                        let error = HandlerError::InvalidJson(e);
+                        writer
+                            .send(Response {
+                                id: request_id,
+                                payload: ResponsePayload::Error {
+                                    message: error.to_string(),
+                                    details: error.details(),
+                                },
+                            })
+                            .await?;
+                        return Err(error);
+                    }
+                }
+            }
+            let tldr = match summary.content.as_deref() {
+                Some(output)
+                    if options.summarize
+                        && summary.exit_code == Some(0)
+                        && summary.output_chars > SUMMARY_THRESHOLD_CHARS =>
+                {
+                    let timeout = options
+                        .timeout
+                        .map(|timeout| timeout.saturating_sub(started.elapsed()));
+                    match summarize_output(
+                        runner,
+                        &permit,
+                        tracker,
+                        metrics,
+                        options.summary_model,
+                        output,
+                        timeout,
+                        options.max_output_bytes,
+                        cancel_rx,
+                    )
+                    .await
+                    {
+                        Ok(tldr) => Some(tldr),
+                        Err(HandlerError::Cancelled) => return Ok(()),
+                        Err(e) if options.strict => {
+                            let message = format!("Could not summarize output: {e}");
+                            return Err(send_strict_error(writer, request_id, message).await?);
+                        }
+                        Err(e) => {
+                            writer
+                                .send(Response {
+                                    id: request_id,
+                                    payload: ResponsePayload::Warning {
+                                        message: format!("Could not summarize output: {e}"),
+                                    },
+                                })
+                                .await?;
+                            None
+                        }
+                    }
+                }
+                _ => None,
+            };
+            let mut done = Response {
+                id: request_id,
+                payload: ResponsePayload::Done {
+                    exit_code: summary.exit_code,
+                    usage: Some(usage),
+                    generation,
+                    duration_ms: Some(as_millis(summary.duration)),
+                    time_to_first_chunk_ms: summary.time_to_first_chunk.map(as_millis),
+                    spawn_time_ms: Some(as_millis(spawn_time)),
+                    content_hash: Some(summary.content_hash.to_hex().to_string()),
+                    stderr_tail,
+                    error,
+                    content: summary.content.filter(|_| options.aggregate),
+                    summary: tldr,
+                    output_format: Some(options.output_format),
+                    truncated: summary.truncated,
+                },
+            };
+            if let Some(limit) = options.max_frame_bytes
+                && fit_frame(&mut done, limit)
+                && let ResponsePayload::Done { truncated, .. } = &mut done.payload
+            {
+                *truncated = true;
+            }
+            writer.send(done).await?;
+            Ok(())
+        }
+        Err(HandlerError::Cancelled) => Ok(()),
+        Err(e) => {
+            writer
+                .send(Response {
+                    id: request_id,
+                    payload: ResponsePayload::Error {
+                        message: e.to_string(),
+                        details: e.details(),
+                    },
+                })
+                .await?;
+            Err(e)
+        }
+    }
+}
+
+async fn send_strict_error<S>(
+    writer: &mut S,
+    request_id: Uuid,
+    message: String,
+) -> Result<HandlerError, HandlerError>
+where
+    S: Sink<Response> + Unpin,
+    HandlerError: From<S::Error>,
+{
+    let error = HandlerError::Strict(message);
+    writer
+        .send(Response {
+            id: request_id,
+            payload: ResponsePayload::Error {
+                message: error.to_string(),
+                details: error.details(),
+            },
+        })
+        .await?;
+    Ok(error)
+}
+
+/// Runs the summary through the same stream loop as the main request, so
+/// cancellation, the request timeout, and the output cap all apply, and
+/// records its usage. It runs in the slot the request already holds.
+#[allow(clippy::too_many_arguments)]
+async fn summarize_output<R: CommandRunner>(
+    runner: &R,
+    _slot: &SemaphorePermit<'_>,
+    tracker: &UsageTracker,
+    metrics: &Metrics,
+    model: Option<String>,
+    output: &str,
+    timeout: Option<Duration>,
+    max_output_bytes: Option<usize>,
+    cancel_rx: watch::Receiver<bool>,
+) -> Result<String, HandlerError> {
+    let fabric_path = runner.fabric_path().await?;
+    let mut builder = FabricCommandBuilder::new(fabric_path)
+        .custom_prompt(SUMMARY_PROMPT)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if let Some(model) = model {
+        builder = builder.model(model);
+    }
+
+    let started = Instant::now();
+    let process = runner.spawn_process(builder).await?;
+    metrics.record_spawn_time(started.elapsed());
+    let output_options = OutputOptions {
+        locale: None,
+        format: OutputFormat::Markdown,
+        aggregate: true,
+        deadline: None,
+        coalesce: None,
+        coalesce_bytes: None,
+        buffered: true,
+        timeout,
+        max_output_bytes,
+        heartbeat: None,
+        strict: false,
+    };
+    let summary = stream_process_responses(
+        &mut futures_util::sink::drain(),
+        Uuid::nil(),
+        process,
+        output.to_string(),
+        output_options,
+        started,
+        cancel_rx,
+    )
+    .await?;
+
+    let usage = generation::parse_generation_stats(&summary.stderr_tail)
+        .as_ref()
+        .and_then(generation::measured_usage)
+        .unwrap_or(TokenUsage {
+            prompt_tokens: estimate_tokens(
+                (output.chars().count() + SUMMARY_PROMPT.chars().count()) as u64,
+            ),
+            completion_tokens: estimate_tokens(summary.output_chars),
+            estimated: true,
+        });
+    tracker.record_tokens(&usage);
+
+    let tldr = summary.content.unwrap_or_default();
+    match summary.exit_code {
+        Some(0) if !tldr.trim().is_empty() => Ok(tldr.trim().to_string()),
+        Some(0) => Err(HandlerError::Io(io::Error::other(
+            "fabric returned an empty summary",
+        ))),
+        _ if !summary.stderr_tail.trim().is_empty() => Err(HandlerError::Io(io::Error::other(
+            summary.stderr_tail.trim().to_string(),
+        ))),
+        exit_code => Err(HandlerError::Io(io::Error::other(format!(
+            "fabric exited with {exit_code:?}"
+        )))),
+    }
+}
+
+fn classify_failure(stderr: &str) -> Option<&'static FailureClass> {
+    let stderr = stderr.to_lowercase();
+    FAILURE_CLASSES
+        .iter()
+        .find(|class| class.needles.iter().any(|needle| stderr.contains(needle)))
+}
+
+fn failure_details(class: &FailureClass) -> ErrorDetails {
+    ErrorDetails {
+        code: class.code.to_string(),
+        hint: Some(class.hint.to_string()),
+        fields: Vec::new(),
+    }
+}
+
+fn command_failure(action: &str, stderr: &str) -> ResponsePayload {
+    match classify_failure(stderr) {
+        Some(class) => ResponsePayload::Error {
+            message: format!("Failed to {action}: {}", class.summary),
+            details: Some(failure_details(class)),
+        },
+        None => ResponsePayload::Error {
+            message: format!("Failed to {action}: {stderr}"),
+            details: None,
+        },
+    }
+}
+
+fn append_tail(tail: &mut String, text: &str, max_len: usize) {
+    tail.push_str(text);
+    if tail.len() > max_len {
+        let mut start = tail.len() - max_len;
+        while !tail.is_char_boundary(start) {
+            start += 1;
+        }
+        tail.drain(..start);
+    }
+}
+
+async fn setup_required<R: CommandRunner>(
+    runner: &R,
+) -> Result<Option<ResponsePayload>, HandlerError> {
+    let Some(config_dir) = runner.fabric_config_dir() else {
+        return Ok(None);
+    };
+
+    if tokio::fs::metadata(&config_dir)
+        .await
+        .is_ok_and(|metadata| metadata.is_dir())
+    {
+        return Ok(None);
+    }
+
+    let fabric_path = runner.fabric_path().await?;
+    let command = fabric_path.file_name().unwrap_or("fabric");
+    Ok(Some(ResponsePayload::SetupRequired {
+        instructions: format!(
+            "Fabric has not been configured yet. Run `{command} --setup` in a terminal to choose a model provider and API key, then try again."
+        ),
+        config_dir: config_dir.to_string(),
+    }))
+}
+
+async fn probe_capabilities<R: CommandRunner>(runner: &R) -> Capabilities {
+    let Ok(fabric_path) = runner.fabric_path().await else {
+        return Capabilities::new(None);
+    };
+
+    if let Some(version) = capabilities::cached_version(fabric_path) {
+        return Capabilities::new(Some(version));
+    }
+
+    let version = match runner.fabric_version().await {
+        Ok(output) if output.status => FabricVersion::parse(&output.stdout),
+        _ => None,
+    };
+
+    if let Some(version) = version {
+        capabilities::cache_version(fabric_path, version);
+    }
+
+    Capabilities::new(version)
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn as_millis(duration: Duration) -> u64 {
+    duration.as_millis() as u64
+}
+
+fn estimate_tokens(chars: u64) -> u64 {
+    chars.div_ceil(CHARS_PER_TOKEN)
+}
+
+#[doc(hidden)]
+pub fn resolve_path<P>(path: Option<P>) -> Result<Utf8PathBuf, HandlerError>
+where
+    P: AsRef<Utf8Path>,
+{
+    resolve_path_in(path, &[], &[], &[])
+}
+
+#[doc(hidden)]
+pub fn resolve_path_in<P>(
+    path: Option<P>,
+    extra_dirs: &[Utf8PathBuf],
+    binary_names: &[String],
+    allowed: &[Utf8PathBuf],
+) -> Result<Utf8PathBuf, HandlerError>
+where
+    P: AsRef<Utf8Path>,
+{
+    if let Some(path) = path {
+        let path_buf = path.as_ref().to_owned();
+        if path_buf.exists() {
+            check_allowed_executable(&path_buf, allowed)?;
+            check_executable(&path_buf)?;
+            return Ok(path_buf);
+        }
+    }
+
+    let home = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE"));
+    let dirs = search_dirs(env::var_os("PATH"), home.map(PathBuf::from), extra_dirs);
+    let found = if binary_names.is_empty() {
+        find_fabric(&dirs, DEFAULT_BINARY_NAMES)
+    } else {
+        find_fabric(&dirs, binary_names)
+    }?;
+    check_allowed_executable(&found, allowed)?;
+    Ok(found)
+}
+
+/// An empty allowlist permits any executable. Otherwise the path must be a
+/// listed file or sit under a listed directory. Directory symlinks are
+/// resolved on both sides, but the final path component is compared as-is so
+/// package-manager symlinks like `/opt/homebrew/bin/fabric` can be listed.
+fn check_allowed_executable(path: &Utf8Path, allowed: &[Utf8PathBuf]) -> Result<(), HandlerError> {
+    if allowed.is_empty() {
+        return Ok(());
+    }
+
+    let not_allowed = || HandlerError::ExecutableNotAllowed(path.to_owned());
+    let resolved = canonicalize_parent(path).ok_or_else(not_allowed)?;
+    let permitted = allowed.iter().any(|entry| {
+        if entry.is_dir() {
+            entry
+                .canonicalize_utf8()
+                .is_ok_and(|dir| resolved.starts_with(dir))
+        } else {
+            canonicalize_parent(entry).is_some_and(|file| file == resolved)
+        }
+    });
+    if permitted {
+        Ok(())
+    } else {
+        Err(not_allowed())
+    }
+}
+
+fn canonicalize_parent(path: &Utf8Path) -> Option<Utf8PathBuf> {
+    let file_name = path.file_name()?;
+    let parent = path
+        .parent()
+        .filter(|parent| !parent.as_str().is_empty())
+        .unwrap_or(Utf8Path::new("."));
+    Some(parent.canonicalize_utf8().ok()?.join(file_name))
+}
+
+fn resolve_cwd(cwd: &Utf8Path, roots: &[Utf8PathBuf]) -> Result<Utf8PathBuf, HandlerError> {
+    let not_allowed = || HandlerError::CwdNotAllowed(cwd.to_owned());
+    let resolved = cwd.canonicalize_utf8().map_err(|_| not_allowed())?;
+    let allowed = resolved.is_dir()
+        && roots
+            .iter()
+            .filter_map(|root| root.canonicalize_utf8().ok())
+            .any(|root| resolved.starts_with(root));
+    if allowed {
+        Ok(resolved)
+    } else {
+        Err(not_allowed())
+    }
+}
+
+fn check_request_env(
+    env: &BTreeMap<String, String>,
+    allowed: &[String],
+) -> Result<(), HandlerError> {
+    match env.keys().find(|name| !env_allowed(name, allowed)) {
+        Some(name) => Err(HandlerError::EnvNotAllowed(name.clone())),
+        None => Ok(()),
+    }
+}
+
+fn check_executable(path: &Utf8Path) -> Result<(), HandlerError> {
+    let invalid = |reason| HandlerError::InvalidExecutable {
+        path: path.to_owned(),
+        reason,
+    };
+    let metadata = std::fs::metadata(path)?;
+    if !metadata.is_file() {
+        return Err(invalid("not a regular file"));
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        if metadata.permissions().mode() & 0o111 == 0 {
+            return Err(invalid("missing execute permission"));
+        }
+    }
+
+    let mut magic = [0; 4];
+    let read = std::fs::File::open(path)?.read(&mut magic)?;
+    if is_executable_format(&magic[..read]) || is_windows_script(path) {
+        Ok(())
+    } else {
+        Err(invalid("not an ELF, Mach-O, PE, or script file"))
+    }
+}
+
+fn is_executable_format(magic: &[u8]) -> bool {
+    magic.starts_with(b"\x7fELF")
+        || magic.starts_with(b"MZ")
+        || magic.starts_with(b"#!")
+        || matches!(
+            magic,
+            [0xfe, 0xed, 0xfa, 0xce | 0xcf]
+                | [0xce | 0xcf, 0xfa, 0xed, 0xfe]
+                | [0xca, 0xfe, 0xba, 0xbe]
+        )
+}
+
+fn is_windows_script(path: &Utf8Path) -> bool {
+    cfg!(windows)
+        && path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("cmd") || ext.eq_ignore_ascii_case("bat"))
+}
+
+fn search_dirs(
+    path_var: Option<OsString>,
+    home: Option<PathBuf>,
+    extra_dirs: &[Utf8PathBuf],
+) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = path_var
+        .map(|path_var| env::split_paths(&path_var).collect())
+        .unwrap_or_default();
+    dirs.extend(extra_dirs.iter().map(|dir| dir.clone().into_std_path_buf()));
+    if let Some(home) = home {
+        dirs.extend(
+            WELL_KNOWN_HOME_DIRS
+                .iter()
+                .map(|parts| parts.iter().fold(home.clone(), |dir, part| dir.join(part))),
+        );
+    }
+    dirs.extend(WELL_KNOWN_DIRS.iter().map(PathBuf::from));
+    dirs
+}
+
+fn find_fabric<S: AsRef<str>>(
+    dirs: &[PathBuf],
+    binary_names: &[S],
+) -> Result<Utf8PathBuf, HandlerError> {
+    let search_path = env::join_paths(dirs).unwrap_or_default();
+    let cwd = env::current_dir().unwrap_or_default();
+    let mut result = Err(which::Error::CannotFindBinaryPath);
+    for name in binary_names {
+        result = which::which_in(name.as_ref(), Some(&search_path), &cwd);
+        if result.is_ok() {
+            break;
+        }
+    }
+    result
+        .map_err(HandlerError::from)
+        .and_then(|path| Utf8PathBuf::from_path_buf(path).map_err(HandlerError::PathNotUtf8))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        pin::Pin,
+        sync::{Arc, Mutex},
+        task::{Context, Poll},
+    };
+
+    use assert_matches::assert_matches;
+    use bytes::BytesMut;
+    use camino_tempfile::tempdir;
+    use camino_tempfile_ext::prelude::*;
+    use tokio::{io::AsyncWrite, sync::Mutex as TokioMutex};
+    use tokio_util::codec::Encoder;
+
+    use super::*;
+    use crate::{
+        GenerationStats, HostConfig, LogLevel,
+        config::RequestDefaults,
+        ratelimit::RateLimit,
+        secrets::MemorySecretStore,
+        state::HostSettings,
+        usage::{Budget, Quota},
+    };
+
+    struct MockCommandRunner {
+        fabric_path: Utf8PathBuf,
+        version_response: Option<CommandOutput>,
+        patterns_response: Option<CommandOutput>,
+        contexts_response: Option<CommandOutput>,
+        process_handles: Arc<TokioMutex<Vec<MockProcessHandle>>>,
+        spawn_error: Option<io::ErrorKind>,
+        config_dir: Option<Utf8PathBuf>,
+        spawned_args: Arc<TokioMutex<Vec<Vec<String>>>>,
+        opened_paths: Arc<Mutex<Vec<Utf8PathBuf>>>,
+        command_delay: Option<Duration>,
+    }
+
+    impl Default for MockCommandRunner {
+        fn default() -> Self {
+            Self {
+                fabric_path: Utf8PathBuf::from("/usr/bin/fabric"),
+                version_response: None,
+                patterns_response: None,
+                contexts_response: None,
                 process_handles: Arc::new(TokioMutex::new(Vec::new())),
+                spawn_error: None,
+                config_dir: None,
+                spawned_args: Arc::new(TokioMutex::new(Vec::new())),
+                opened_paths: Arc::new(Mutex::new(Vec::new())),
+                command_delay: None,
+            }
+        }
+    }
+
+    impl MockCommandRunner {
+        fn with_fabric_path<P: Into<Utf8PathBuf>>(mut self, path: P) -> Self {
+            self.fabric_path = path.into();
+            self
+        }
+
+        fn with_version_response(mut self, output: CommandOutput) -> Self {
+            self.version_response = Some(output);
+            self
+        }
+
+        fn with_patterns_response(mut self, output: CommandOutput) -> Self {
+            self.patterns_response = Some(output);
+            self
+        }
+
+        fn with_contexts_response(mut self, output: CommandOutput) -> Self {
+            self.contexts_response = Some(output);
+            self
+        }
+
+        fn with_config_dir<P: Into<Utf8PathBuf>>(mut self, path: P) -> Self {
+            self.config_dir = Some(path.into());
+            self
+        }
+
+        fn with_command_delay(mut self, delay: Duration) -> Self {
+            self.command_delay = Some(delay);
+            self
+        }
+
+        fn with_spawn_error(mut self, kind: io::ErrorKind) -> Self {
+            self.spawn_error = Some(kind);
+            self
+        }
+
+        async fn with_process_handle(self, handle: MockProcessHandle) -> Self {
+            self.process_handles.lock().await.push(handle);
+            self
+        }
+    }
+
+    #[async_trait]
+    impl CommandRunner for MockCommandRunner {
+        async fn fabric_version(&self) -> Result<CommandOutput, HandlerError> {
+            use std::io;
+            if let Some(delay) = self.command_delay {
+                tokio::time::sleep(delay).await;
+            }
+            self.version_response
+                .clone()
+                .ok_or_else(|| HandlerError::Io(io::Error::other("No mock response")))
+        }
+
+        async fn list_patterns(&self) -> Result<CommandOutput, HandlerError> {
+            use std::io;
+            if let Some(delay) = self.command_delay {
+                tokio::time::sleep(delay).await;
+            }
+            self.patterns_response
+                .clone()
+                .ok_or_else(|| HandlerError::Io(io::Error::other("No mock response")))
+        }
+
+        async fn list_contexts(&self) -> Result<CommandOutput, HandlerError> {
+            use std::io;
+            self.contexts_response
+                .clone()
+                .ok_or_else(|| HandlerError::Io(io::Error::other("No mock response")))
+        }
+
+        async fn fabric_path(&self) -> Result<&Utf8Path, HandlerError> {
+            Ok(&self.fabric_path)
+        }
+
+        fn fabric_config_dir(&self) -> Option<Utf8PathBuf> {
+            self.config_dir.clone()
+        }
+
+        fn open_in_editor(&self, path: &Utf8Path) -> Result<String, HandlerError> {
+            self.opened_paths.lock().unwrap().push(path.to_owned());
+            Ok("mock-editor".to_string())
+        }
+
+        async fn spawn_process(
+            &self,
+            builder: FabricCommandBuilder<'_>,
+        ) -> Result<Box<dyn ProcessHandle>, HandlerError> {
+            use std::io;
+            let args = builder
+                .build()
+                .as_std()
+                .get_args()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect();
+            self.spawned_args.lock().await.push(args);
+            if let Some(kind) = self.spawn_error {
+                return Err(HandlerError::Spawn {
+                    path: self.fabric_path.clone(),
+                    source: io::Error::from(kind),
+                });
+            }
+            let mut handles = self.process_handles.lock().await;
+            if let Some(handle) = handles.pop() {
+                Ok(Box::new(handle))
+            } else {
+                Err(HandlerError::Io(io::Error::other(
+                    "No mock process handle available",
+                )))
+            }
+        }
+    }
+
+    struct MockProcessHandle {
+        stdin_data: Arc<TokioMutex<Vec<u8>>>,
+        stdout_lines: Arc<TokioMutex<Vec<String>>>,
+        stderr_lines: Arc<TokioMutex<Vec<String>>>,
+        exit_code: Option<i32>,
+        stdin_error: Option<io::Error>,
+        stdout_error: Option<io::Error>,
+        wait_error: Option<io::Error>,
+        line_delay: Option<Duration>,
+        line_ready_at: Option<Instant>,
+        stdin_stalled: bool,
+        killed: Arc<std::sync::atomic::AtomicBool>,
+    }
+
+    impl MockProcessHandle {
+        fn new(stdout_lines: Vec<String>, exit_code: Option<i32>) -> Self {
+            Self {
+                stdin_data: Arc::new(TokioMutex::new(Vec::new())),
+                stdout_lines: Arc::new(TokioMutex::new(stdout_lines)),
+                stderr_lines: Arc::new(TokioMutex::new(Vec::new())),
+                exit_code,
+                stdin_error: None,
+                stdout_error: None,
+                wait_error: None,
+                line_delay: None,
+                line_ready_at: None,
+                stdin_stalled: false,
+                killed: Arc::default(),
+            }
+        }
+
+        fn with_stalled_stdin(self) -> Self {
+            Self {
+                stdin_stalled: true,
+                ..self
+            }
+        }
+
+        fn with_line_delay(mut self, delay: Duration) -> Self {
+            self.line_delay = Some(delay);
+            self
+        }
+
+        fn with_stderr_lines(self, stderr_lines: Vec<String>) -> Self {
+            Self {
+                stderr_lines: Arc::new(TokioMutex::new(stderr_lines)),
+                ..self
+            }
+        }
+    }
+
+    #[async_trait]
+    impl ProcessHandle for MockProcessHandle {
+        fn stdin_writer(&mut self, data: Vec<u8>) -> BoxFuture<'static, Result<(), HandlerError>> {
+            let error = self.stdin_error.as_ref().map(io::Error::kind);
+            let stdin_data = self.stdin_data.clone();
+            let stalled = self.stdin_stalled;
+            Box::pin(async move {
+                if stalled {
+                    return std::future::pending().await;
+                }
+                if let Some(kind) = error {
+                    return Err(HandlerError::Io(io::Error::new(kind, "Mock stdin error")));
+                }
+                stdin_data.lock().await.extend_from_slice(&data);
+                Ok(())
+            })
+        }
+
+        async fn read_output(&mut self) -> Result<Option<ProcessOutput>, HandlerError> {
+            if let Some(error) = &self.stdout_error {
+                return Err(HandlerError::Io(io::Error::new(
+                    error.kind(),
+                    "Mock stdout error",
+                )));
+            }
+            if let Some(delay) = self.line_delay {
+                let ready_at = *self
+                    .line_ready_at
+                    .get_or_insert_with(|| Instant::now() + delay);
+                tokio::time::sleep_until(ready_at).await;
+                self.line_ready_at = None;
+            }
+            let mut lines = self.stdout_lines.lock().await;
+            if !lines.is_empty() {
+                return Ok(Some(ProcessOutput::Stdout(lines.remove(0))));
+            }
+            let mut lines = self.stderr_lines.lock().await;
+            if lines.is_empty() {
+                Ok(None)
+            } else {
+                Ok(Some(ProcessOutput::Stderr(lines.remove(0))))
+            }
+        }
+
+        async fn wait(self: Box<Self>) -> Result<Option<i32>, HandlerError> {
+            if let Some(error) = &self.wait_error {
+                return Err(HandlerError::Io(io::Error::new(
+                    error.kind(),
+                    "Mock wait error",
+                )));
+            }
+            Ok(self.exit_code)
+        }
+
+        async fn kill(&mut self) -> Result<(), HandlerError> {
+            self.killed.store(true, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    struct TestWriter {
+        messages: Arc<Mutex<Vec<Response>>>,
+    }
+
+    impl TestWriter {
+        fn new() -> Self {
+            Self {
+                messages: Arc::new(Mutex::new(Vec::new())),
+            }
+        }
+    }
+
+    impl AsyncWrite for TestWriter {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            _buf: &[u8],
+        ) -> Poll<Result<usize, io::Error>> {
+            Poll::Ready(Ok(0))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Result<(), io::Error>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    struct TestEncoder {
+        messages: Arc<Mutex<Vec<Response>>>,
+    }
+
+    impl TestEncoder {
+        fn new(messages: Arc<Mutex<Vec<Response>>>) -> Self {
+            Self { messages }
+        }
+    }
+
+    impl Encoder<Response> for TestEncoder {
+        type Error = io::Error;
+
+        fn encode(&mut self, item: Response, _dst: &mut BytesMut) -> Result<(), Self::Error> {
+            self.messages.lock().unwrap().push(item);
+            Ok(())
+        }
+    }
+
+    fn fake_fabric(dir: &Utf8TempDir) -> Utf8PathBuf {
+        let fabric = dir.child("fabric-ai");
+        fabric.write_str("#!/bin/sh\n").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            std::fs::set_permissions(&fabric, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        fabric.to_path_buf()
+    }
+
+    #[tokio::test]
+    async fn test_resolve_path_with_existing_file() {
+        let dir = tempdir().unwrap();
+        let utf8_path = fake_fabric(&dir);
+        let result = resolve_path(Some(&utf8_path));
+
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), utf8_path);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resolve_path_rejects_unusable_files() {
+        use std::{fs, os::unix::fs::PermissionsExt};
+
+        let dir = tempdir().unwrap();
+        assert_matches!(
+            resolve_path(Some(dir.path())),
+            Err(HandlerError::InvalidExecutable {
+                reason: "not a regular file",
+                ..
+            })
+        );
+
+        let script = dir.child("fabric-ai");
+        script.write_str("#!/bin/sh\n").unwrap();
+        assert_matches!(
+            resolve_path(Some(script.as_path())),
+            Err(HandlerError::InvalidExecutable {
+                reason: "missing execute permission",
+                ..
+            })
+        );
+
+        let text = dir.child("notes.txt");
+        text.write_str("hello").unwrap();
+        fs::set_permissions(&text, fs::Permissions::from_mode(0o755)).unwrap();
+        let result = resolve_path(Some(text.as_path()));
+        assert_matches!(result, Err(HandlerError::InvalidExecutable { .. }));
+        assert_eq!(result.unwrap_err().code(), "invalidExecutable");
+
+        assert!(is_executable_format(b"\x7fELF"));
+        assert!(is_executable_format(&[0xcf, 0xfa, 0xed, 0xfe]));
+        assert!(is_executable_format(b"MZ\x90\0"));
+        assert!(!is_executable_format(b""));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resolve_path_enforces_executable_allowlist() {
+        let dir = tempdir().unwrap();
+        let fabric = fake_fabric(&dir);
+        let other = dir.child("other");
+        other.create_dir_all().unwrap();
+        let evil = other.child("evil");
+        std::fs::copy(&fabric, &evil).unwrap();
+        let resolve = |path: &Utf8Path, allowed: &[Utf8PathBuf]| {
+            resolve_path_in(Some(path), &[], &[], allowed)
+        };
+
+        assert!(resolve(&evil, &[]).is_ok());
+        assert_eq!(resolve(&fabric, &[dir.path().to_owned()]).unwrap(), fabric);
+        assert!(resolve(&fabric, std::slice::from_ref(&fabric)).is_ok());
+        assert!(resolve(&other.join("../fabric-ai"), std::slice::from_ref(&fabric)).is_ok());
+
+        let allowed = [fabric.clone()];
+        let result = resolve(&evil, &allowed);
+        assert_matches!(result, Err(HandlerError::ExecutableNotAllowed(ref path)) if path == evil.as_path());
+        assert_eq!(result.unwrap_err().code(), "executableNotAllowed");
+        assert_matches!(
+            resolve(&dir.path().join("other/../other/evil"), &allowed),
+            Err(HandlerError::ExecutableNotAllowed(_))
+        );
+        assert_matches!(
+            resolve(&evil, &[dir.path().join("fabric-ai/..")]),
+            Err(HandlerError::ExecutableNotAllowed(_))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolve_path_with_non_existing_file() {
+        let path = Utf8PathBuf::from("/non/existing/path/fabric-ai");
+        let result = resolve_path(Some(&path));
+
+        assert!(result.is_err() || result.unwrap() != path);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_path_with_no_path() {
+        let result = resolve_path::<Utf8PathBuf>(None);
+
+        assert!(result.is_err() || result.unwrap().to_string().contains("fabric-ai"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_fabric_runner_injects_secrets_into_child_env() {
+        use std::{fs, os::unix::fs::PermissionsExt};
+
+        let dir = tempdir().unwrap();
+        let fabric = dir.child("fabric-ai");
+        fabric
+            .write_str("#!/bin/sh\nprintf '%s\\n' \"$OPENAI_API_KEY\"\n")
+            .unwrap();
+        fs::set_permissions(&fabric, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let store = MemorySecretStore::default();
+        store.set("openai", "sk-123").unwrap();
+        let secret_env = BTreeMap::from([(
+            "OPENAI_API_KEY".to_string(),
+            SecretRef {
+                name: "openai".to_string(),
+            },
+        )]);
+        let runner = FabricCommandRunner::new(&fabric)
+            .with_env_allowlist(vec!["PATH".to_string()])
+            .with_secrets(secret_env.clone(), Arc::new(store));
+        let builder = FabricCommandBuilder::new(&fabric)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let mut process = runner.spawn_process(builder).await.unwrap();
+
+        assert_matches!(
+            process.read_output().await.unwrap(),
+            Some(ProcessOutput::Stdout(line)) if line.trim_end() == "sk-123"
+        );
+
+        let runner = FabricCommandRunner::new(&fabric)
+            .with_secrets(secret_env, Arc::new(MemorySecretStore::default()));
+        let Err(error) = runner
+            .spawn_process(FabricCommandBuilder::new(&fabric))
+            .await
+        else {
+            panic!("Expected a missing secret error");
+        };
+        assert_matches!(error, HandlerError::Secrets(SecretError::Missing { .. }));
+        assert_eq!(error.code(), "secretNotFound");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_find_fabric_searches_extra_and_well_known_dirs() {
+        use std::{fs, os::unix::fs::PermissionsExt};
+
+        let dir = tempdir().unwrap();
+        let bin = dir.child("bin");
+        bin.create_dir_all().unwrap();
+        let fabric = bin.child("fabric");
+        fabric.touch().unwrap();
+        fs::set_permissions(&fabric, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let dirs = search_dirs(
+            Some(OsString::from("/nonexistent")),
+            Some(PathBuf::from("/home/user")),
+            &[bin.to_path_buf()],
+        );
+        assert_eq!(
+            dirs,
+            [
+                "/nonexistent",
+                bin.as_str(),
+                "/home/user/go/bin",
+                "/home/user/.local/bin",
+                "/opt/homebrew/bin",
+                "/usr/local/bin",
+            ]
+            .map(PathBuf::from)
+        );
+        assert_eq!(
+            find_fabric(&dirs[..2], DEFAULT_BINARY_NAMES).unwrap(),
+            fabric.as_path()
+        );
+        assert_matches!(
+            find_fabric(&dirs[..2], &["fabric-ai"]),
+            Err(HandlerError::FabricNotFound(_))
+        );
+        assert_matches!(
+            find_fabric(&[PathBuf::from("/nonexistent")], DEFAULT_BINARY_NAMES),
+            Err(HandlerError::FabricNotFound(_))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_ping_success() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.child("fabric-ai");
+        file_path.touch().unwrap();
+
+        let runner = MockCommandRunner::default().with_version_response(CommandOutput {
+            status: true,
+            stdout: "fabric-ai version 1.0.0".to_string(),
+            stderr: String::new(),
+        });
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let request_id = Uuid::new_v4();
+        let result = handle_ping(
+            &mut writer,
+            request_id,
+            &runner,
+            None,
+            DEFAULT_COMMAND_TIMEOUT,
+        )
+        .await;
+        assert!(result.is_ok());
+
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages.len(), 1);
+
+        if let ResponsePayload::Pong {
+            valid,
+            version,
+            binary,
+            ..
+        } = &messages[0].payload
+        {
+            assert!(valid);
+            assert_eq!(version.as_deref(), Some("fabric-ai version 1.0.0"));
+            assert_eq!(binary.as_deref(), Some("fabric"));
+        } else {
+            panic!("Expected Pong response");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_boot_report_includes_fabric_detection() {
+        let runner = MockCommandRunner::default()
+            .with_fabric_path("/usr/bin/fabric-ai")
+            .with_config_dir("/home/user/.config/fabric")
+            .with_version_response(CommandOutput {
+                status: true,
+                stdout: "v1.4.0".to_string(),
+                stderr: String::new(),
+            });
+
+        let report = boot_report(Ok(runner), vec!["stdio".to_string()]).await;
+
+        assert_eq!(report.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(report.transports, vec!["stdio"]);
+        assert_eq!(
+            report.config_path.as_deref(),
+            Some("/home/user/.config/fabric")
+        );
+        assert_eq!(report.fabric_path.as_deref(), Some("/usr/bin/fabric-ai"));
+        assert_eq!(report.fabric_version.as_deref(), Some("v1.4.0"));
+        assert_eq!(report.fabric_error, None);
+    }
+
+    #[tokio::test]
+    async fn test_boot_report_records_detection_failure() {
+        let report = boot_report::<MockCommandRunner>(
+            Err(HandlerError::Io(io::Error::other("fabric-ai not found"))),
+            vec!["stdio".to_string()],
+        )
+        .await;
+
+        assert_eq!(report.fabric_path, None);
+        assert_eq!(report.fabric_version, None);
+        assert_eq!(
+            report.fabric_error.as_deref(),
+            Some("I/O error: fabric-ai not found")
+        );
+
+        let runner = MockCommandRunner::default().with_version_response(CommandOutput {
+            status: false,
+            stdout: String::new(),
+            stderr: "unknown flag".to_string(),
+        });
+        let report = boot_report(Ok(runner), Vec::new()).await;
+        assert_eq!(report.fabric_error.as_deref(), Some("unknown flag"));
+    }
+
+    #[tokio::test]
+    async fn test_handle_ping_failure() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.child("fabric-ai");
+        file_path.touch().unwrap();
+
+        let runner = MockCommandRunner::default().with_version_response(CommandOutput {
+            status: false,
+            stdout: String::new(),
+            stderr: "command not found".to_string(),
+        });
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+        let request_id = Uuid::new_v4();
+
+        let result = handle_ping(
+            &mut writer,
+            request_id,
+            &runner,
+            None,
+            DEFAULT_COMMAND_TIMEOUT,
+        )
+        .await;
+        assert!(result.is_ok());
+
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages.len(), 1);
+
+        if let ResponsePayload::Pong { valid, version, .. } = &messages[0].payload {
+            assert!(!valid);
+            assert!(version.is_none());
+        } else {
+            panic!("Expected Pong response");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_list_patterns_success() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.child("fabric-ai");
+        file_path.touch().unwrap();
+
+        let runner = MockCommandRunner::default().with_patterns_response(CommandOutput {
+            status: true,
+            stdout: "pattern1\npattern2\npattern3\n".to_string(),
+            stderr: String::new(),
+        });
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+        let request_id = Uuid::new_v4();
+
+        let aliases = BTreeMap::from([("tldr".to_string(), "pattern1".to_string())]);
+        let result = handle_list_patterns(
+            &mut writer,
+            request_id,
+            &runner,
+            DEFAULT_COMMAND_TIMEOUT,
+            &aliases,
+            &["pattern2".to_string()],
+        )
+        .await;
+        assert!(result.is_ok());
+
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages.len(), 1);
+
+        if let ResponsePayload::PatternsList {
+            patterns,
+            aliases: listed,
+            pinned,
+        } = &messages[0].payload
+        {
+            assert_eq!(patterns, &["pattern1", "pattern2", "pattern3"]);
+            assert_eq!(listed, &aliases);
+            assert_eq!(pinned, &["pattern2"]);
+        } else {
+            panic!("Expected PatternsList response");
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_hung_fabric_commands_time_out() {
+        let output = CommandOutput {
+            status: true,
+            stdout: "pattern1\n".to_string(),
+            stderr: String::new(),
+        };
+        let runner = MockCommandRunner::default()
+            .with_version_response(output.clone())
+            .with_patterns_response(output)
+            .with_command_delay(Duration::from_secs(60));
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+        let timeout = Duration::from_secs(2);
+
+        let result = handle_ping(&mut writer, Uuid::new_v4(), &runner, None, timeout).await;
+        assert!(result.is_ok());
+        let result = handle_list_patterns(
+            &mut writer,
+            Uuid::new_v4(),
+            &runner,
+            timeout,
+            &BTreeMap::new(),
+            &[],
+        )
+        .await;
+        assert_matches!(result, Err(HandlerError::Timeout(elapsed)) if elapsed == timeout);
+
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_matches!(
+            &messages[0].payload,
+            ResponsePayload::Pong {
+                version: None,
+                valid: false,
+                ..
+            }
+        );
+        assert_matches!(
+            &messages[1].payload,
+            ResponsePayload::Error { message, details: Some(ErrorDetails { code, .. }) }
+                if message == "Timed out listing patterns: Process timed out after 2000ms" && code == "timeout"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_list_patterns_failure() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.child("fabric-ai");
+        file_path.touch().unwrap();
+
+        let runner = MockCommandRunner::default().with_patterns_response(CommandOutput {
+            status: false,
+            stdout: String::new(),
+            stderr: "Failed to list patterns".to_string(),
+        });
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+        let request_id = Uuid::new_v4();
+
+        let result = handle_list_patterns(
+            &mut writer,
+            request_id,
+            &runner,
+            DEFAULT_COMMAND_TIMEOUT,
+            &BTreeMap::new(),
+            &[],
+        )
+        .await;
+        assert!(result.is_ok());
+
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages.len(), 1);
+
+        if let ResponsePayload::Error { message, .. } = &messages[0].payload {
+            assert!(message.contains("Failed to list patterns"));
+        } else {
+            panic!("Expected Error response");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_list_patterns_classified_failure() {
+        let runner = MockCommandRunner::default().with_patterns_response(CommandOutput {
+            status: false,
+            stdout: String::new(),
+            stderr: "dial tcp 127.0.0.1:11434: connect: connection refused".to_string(),
+        });
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let result = handle_list_patterns(
+            &mut writer,
+            Uuid::new_v4(),
+            &runner,
+            DEFAULT_COMMAND_TIMEOUT,
+            &BTreeMap::new(),
+            &[],
+        )
+        .await;
+        assert!(result.is_ok());
+
+        let messages = messages.lock().unwrap();
+        assert_matches!(
+            &messages[0].payload,
+            ResponsePayload::Error {
+                message,
+                details: Some(ErrorDetails { code, hint: Some(_), .. }),
+            } if code == "networkFailure" && !message.contains("127.0.0.1")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_process_content() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.child("fabric-ai");
+        file_path.touch().unwrap();
+
+        let stdout_lines = vec![
+            "Processing line 1\n".to_string(),
+            "Processing line 2\n".to_string(),
+            "Done\n".to_string(),
+        ];
+
+        let process_handle = MockProcessHandle::new(stdout_lines, Some(0));
+        let runner = MockCommandRunner::default()
+            .with_process_handle(process_handle)
+            .await;
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let request_id = Uuid::new_v4();
+        let options = ProcessOptions {
+            model: Some("gpt-4".to_string()),
+            pattern: Some("summarize".to_string()),
+            ..Default::default()
+        };
+        let content = "Test content to process".to_string();
+
+        let state = HostState::new();
+        let result =
+            handle_process_content(&mut writer, request_id, &runner, options, content, &state)
+                .await;
+        assert!(result.is_ok());
+
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages.len(), 5);
+
+        assert_matches!(
+            &messages[0].payload,
+            ResponsePayload::StreamStart { model: Some(model), pattern: Some(pattern), inputs: None, output_format: OutputFormat::Markdown }
+                if model == "gpt-4" && pattern == "summarize"
+        );
+        assert_matches!(&messages[1].payload, ResponsePayload::Content { content } if content == "Processing line 1\n");
+        assert_matches!(&messages[2].payload, ResponsePayload::Content { content } if content == "Processing line 2\n"
+        );
+        assert_matches!(&messages[3].payload, ResponsePayload::Content { content } if content == "Done\n"
+        );
+        assert_matches!(
+            &messages[4].payload,
+            ResponsePayload::Done {
+                exit_code: Some(0),
+                ..
+            }
+        );
+
+        let expected_hash = blake3::hash(b"Processing line 1\nProcessing line 2\nDone\n");
+        assert_matches!(
+            &messages[4].payload,
+            ResponsePayload::Done { content_hash: Some(hash), .. } if *hash == expected_hash.to_hex().as_str()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_ping_no_path() {
+        use tokio_util::codec::FramedWrite;
+
+        let runner = MockCommandRunner::default().with_version_response(CommandOutput {
+            status: false,
+            stdout: String::new(),
+            stderr: "Mock error".to_string(),
+        });
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+        let request_id = Uuid::new_v4();
+
+        let result = handle_ping(
+            &mut writer,
+            request_id,
+            &runner,
+            None,
+            DEFAULT_COMMAND_TIMEOUT,
+        )
+        .await;
+        assert!(result.is_ok());
+
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages.len(), 1);
+
+        if let ResponsePayload::Pong { valid, .. } = &messages[0].payload {
+            assert!(!valid);
+        } else {
+            panic!("Expected Pong response");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_process_content_error() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.child("fabric-ai");
+        file_path.touch().unwrap();
+
+        let runner = MockCommandRunner::default();
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let request_id = Uuid::new_v4();
+        let content = "Test content".to_string();
+
+        let state = HostState::new();
+        let result = handle_process_content(
+            &mut writer,
+            request_id,
+            &runner,
+            ProcessOptions {
+                custom_prompt: Some("custom prompt".to_string()),
+                ..Default::default()
+            },
+            content,
+            &state,
+        )
+        .await;
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_process_handle_stdin_error() {
+        let mut mock_process = MockProcessHandle::new(vec![], Some(0));
+        mock_process.set_stdin_error(io::Error::new(io::ErrorKind::BrokenPipe, "Stdin closed"));
+
+        let result = mock_process.stdin_writer(b"test data".to_vec()).await;
+        assert!(result.is_err());
+        assert_matches!(result.unwrap_err(), HandlerError::Io(_));
+    }
+
+    #[tokio::test]
+    async fn test_process_handle_stdout_read_error() {
+        let mut mock_process = MockProcessHandle::new(vec![], Some(0));
+        mock_process.set_stdout_error(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Stdout closed",
+        ));
+
+        let result = mock_process.read_output().await;
+        assert!(result.is_err());
+        assert_matches!(result.unwrap_err(), HandlerError::Io(_));
+    }
+
+    #[tokio::test]
+    async fn test_process_handle_wait_error() {
+        let mut mock_process = MockProcessHandle::new(vec![], Some(0));
+        mock_process.set_wait_error(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Process wait failed",
+        ));
+
+        let result = Box::new(mock_process).wait().await;
+        assert!(result.is_err());
+        assert_matches!(result.unwrap_err(), HandlerError::Io(_));
+    }
+
+    #[tokio::test]
+    async fn test_handle_list_contexts_success() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.child("fabric-ai");
+        file_path.touch().unwrap();
+
+        let runner = MockCommandRunner::default().with_contexts_response(CommandOutput {
+            status: true,
+            stdout: "context1\ncontext2\ncontext3\n".to_string(),
+            stderr: String::new(),
+        });
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+        let request_id = Uuid::new_v4();
+
+        let result = handle_list_contexts(&mut writer, request_id, &runner).await;
+        assert!(result.is_ok());
+
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages.len(), 1);
+
+        if let ResponsePayload::ContextsList { contexts } = &messages[0].payload {
+            assert_eq!(contexts, &["context1", "context2", "context3"]);
+        } else {
+            panic!("Expected ContextsList response");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_list_contexts_failure() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.child("fabric-ai");
+        file_path.touch().unwrap();
+
+        let runner = MockCommandRunner::default().with_contexts_response(CommandOutput {
+            status: false,
+            stdout: String::new(),
+            stderr: "Failed to list contexts".to_string(),
+        });
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+        let request_id = Uuid::new_v4();
+
+        let result = handle_list_contexts(&mut writer, request_id, &runner).await;
+        assert!(result.is_ok());
+
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages.len(), 1);
+
+        if let ResponsePayload::Error { message, .. } = &messages[0].payload {
+            assert!(message.contains("Failed to list contexts"));
+        } else {
+            panic!("Expected Error response");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_process_content_with_context() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.child("fabric-ai");
+        file_path.touch().unwrap();
+
+        let stdout_lines = vec![
+            "Processing with context line 1\n".to_string(),
+            "Processing with context line 2\n".to_string(),
+            "Done\n".to_string(),
+        ];
+
+        let process_handle = MockProcessHandle::new(stdout_lines, Some(0));
+        let runner = MockCommandRunner::default()
+            .with_process_handle(process_handle)
+            .await;
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let request_id = Uuid::new_v4();
+        let options = ProcessOptions {
+            model: Some("gpt-4".to_string()),
+            pattern: Some("summarize".to_string()),
+            context: Some("tapestry".to_string()),
+            ..Default::default()
+        };
+        let content = "Test content to process with context".to_string();
+
+        let state = HostState::new();
+        let result =
+            handle_process_content(&mut writer, request_id, &runner, options, content, &state)
+                .await;
+        assert!(result.is_ok());
+
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages.len(), 5);
+
+        assert_matches!(&messages[1].payload, ResponsePayload::Content { content } if content == "Processing with context line 1\n");
+        assert_matches!(&messages[2].payload, ResponsePayload::Content { content } if content == "Processing with context line 2\n");
+        assert_matches!(&messages[3].payload, ResponsePayload::Content { content } if content == "Done\n");
+        assert_matches!(
+            &messages[4].payload,
+            ResponsePayload::Done {
+                exit_code: Some(0),
+                ..
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_process_content_with_locale() {
+        let stdout_lines = vec!["Total: 1,234.5 on 2024-03-15\n".to_string()];
+        let process_handle = MockProcessHandle::new(stdout_lines, Some(0));
+        let runner = MockCommandRunner::default()
+            .with_process_handle(process_handle)
+            .await;
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let options = ProcessOptions {
+            pattern: Some("summarize".to_string()),
+            locale: Some("de-DE".to_string()),
+            ..Default::default()
+        };
+
+        let state = HostState::new();
+        let result = handle_process_content(
+            &mut writer,
+            Uuid::new_v4(),
+            &runner,
+            options,
+            "content".to_string(),
+            &state,
+        )
+        .await;
+        assert!(result.is_ok());
+
+        let messages = messages.lock().unwrap();
+        assert_matches!(&messages[1].payload, ResponsePayload::Content { content } if content == "Total: 1.234,5 on 15.03.2024\n");
+    }
+
+    #[tokio::test]
+    async fn test_handle_process_content_normalizes_locale_per_line() {
+        let process_handle =
+            MockProcessHandle::new(vec!["Pi is 3.\n".to_string(), "14\n".to_string()], Some(0));
+        let runner = MockCommandRunner::default()
+            .with_process_handle(process_handle)
+            .await;
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let options = ProcessOptions {
+            locale: Some("de-DE".to_string()),
+            aggregate: true,
+            ..Default::default()
+        };
+        handle_process_content(
+            &mut writer,
+            Uuid::new_v4(),
+            &runner,
+            options,
+            "content".to_string(),
+            &HostState::new(),
+        )
+        .await
+        .unwrap();
+
+        let messages = messages.lock().unwrap();
+        assert_matches!(
+            &messages.last().unwrap().payload,
+            ResponsePayload::Done { content: Some(content), .. } if content == "Pi is 3.\n14\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_process_content_plaintext_output() {
+        let stdout_lines = vec![
+            "## Summary\n".to_string(),
+            "```\n".to_string(),
+            "* [Docs](https://example.com)\n".to_string(),
+        ];
+        let process_handle = MockProcessHandle::new(stdout_lines, Some(0));
+        let runner = MockCommandRunner::default()
+            .with_process_handle(process_handle)
+            .await;
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let options = ProcessOptions {
+            output_format: OutputFormat::Plaintext,
+            ..Default::default()
+        };
+
+        let state = HostState::new();
+        handle_process_content(
+            &mut writer,
+            Uuid::new_v4(),
+            &runner,
+            options,
+            "content".to_string(),
+            &state,
+        )
+        .await
+        .unwrap();
+
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages.len(), 4);
+        assert_matches!(
+            &messages[0].payload,
+            ResponsePayload::StreamStart {
+                output_format: OutputFormat::Plaintext,
+                ..
+            }
+        );
+        assert_matches!(&messages[1].payload, ResponsePayload::Content { content } if content == "SUMMARY\n");
+        assert_matches!(&messages[2].payload, ResponsePayload::Content { content } if content == "* [Docs](https://example.com)\n");
+        assert_matches!(
+            &messages[3].payload,
+            ResponsePayload::Done {
+                output_format: Some(OutputFormat::Plaintext),
+                ..
+            }
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_process_slots_resize_while_permits_are_held() {
+        let slots = ProcessSlots::new(2);
+        let first = slots.semaphore.try_acquire().unwrap();
+        let second = slots.semaphore.try_acquire().unwrap();
+
+        slots.set_limit(1);
+        assert_eq!(slots.limit(), 1);
+        drop(first);
+        assert_eq!(slots.available(), 0);
+
+        let (_cancel_tx, cancel_rx) = watch::channel(false);
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+        let request_id = Uuid::new_v4();
+        let blocked = tokio::time::timeout(
+            Duration::from_millis(10),
+            slots.acquire(&mut writer, request_id, cancel_rx.clone()),
+        )
+        .await;
+        assert!(blocked.is_err());
+        assert_eq!(slots.waiting(), 0);
+        assert_eq!(
+            messages.lock().unwrap()[0].payload,
+            ResponsePayload::Queued { limit: 1 }
+        );
+
+        drop(second);
+        let permit = slots
+            .acquire(&mut writer, request_id, cancel_rx)
+            .await
+            .unwrap();
+        assert!(permit.is_some());
+
+        slots.set_limit(3);
+        assert_eq!(slots.available(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_handle_reload_config_applies_loaded_settings() {
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let state = HostState::new().with_settings_loader(Box::new(|| {
+            Ok(HostSettings {
+                max_concurrent_processes: 2,
+                max_output_bytes: None,
+                request_defaults: RequestDefaults {
+                    model: Some("llama3".to_string()),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+        }));
+        let request_id = Uuid::new_v4();
+        handle_reload_config(&mut writer, request_id, &state)
+            .await
+            .unwrap();
+
+        assert_eq!(state.process_slots.limit(), 2);
+        assert_eq!(state.max_output_bytes(), None);
+        assert_eq!(state.request_defaults().model.as_deref(), Some("llama3"));
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages[0].id, request_id);
+        assert_eq!(messages[0].payload, ResponsePayload::ConfigReloaded);
+    }
+
+    #[tokio::test]
+    async fn test_handle_reload_config_keeps_settings_on_error() {
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let state = HostState::new()
+            .with_command_timeout(Duration::from_secs(3))
+            .with_settings_loader(Box::new(|| Err("Ignoring config.toml: bad".to_string())));
+        let result = handle_reload_config(&mut writer, Uuid::new_v4(), &state).await;
+
+        assert_matches!(result, Err(HandlerError::ConfigReload(_)));
+        assert_eq!(state.command_timeout(), Duration::from_secs(3));
+        let messages = messages.lock().unwrap();
+        assert_matches!(
+            &messages[0].payload,
+            ResponsePayload::Error { message, details: Some(ErrorDetails { code, .. }) }
+                if message == "Failed to reload configuration: Ignoring config.toml: bad"
+                    && code == "configReloadFailed"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_set_config_persists_and_applies_settings() {
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("tapestry").join("config.toml");
+        let loader_path = path.clone();
+        let state = HostState::new()
+            .with_config_path(Some(path.clone()))
+            .with_settings_loader(Box::new(move || {
+                Config::load(&loader_path).map(|config| HostSettings {
+                    max_concurrent_processes: config
+                        .limits
+                        .max_concurrent_processes
+                        .unwrap_or(DEFAULT_MAX_CONCURRENT_PROCESSES),
+                    log_level: config.logging.level.unwrap_or_default(),
+                    ..Default::default()
+                })
+            }));
+
+        let update = HostConfigUpdate {
+            default_model: Some(Some("llama3".to_string())),
+            max_concurrent_processes: Some(Some(2)),
+            log_level: Some(Some(LogLevel::Debug)),
+            ..Default::default()
+        };
+        handle_set_config(&mut writer, Uuid::new_v4(), update, &state)
+            .await
+            .unwrap();
+        assert_eq!(state.process_slots.limit(), 2);
+        assert!(state.log_enabled(LogLevel::Debug));
+
+        let get_id = Uuid::new_v4();
+        handle_get_config(&mut writer, get_id, &state)
+            .await
+            .unwrap();
+
+        let expected = ResponsePayload::Config {
+            path: Some(path),
+            config: HostConfig {
+                default_model: Some("llama3".to_string()),
+                patterns_dir: None,
+                max_concurrent_processes: Some(2),
+                log_level: Some(LogLevel::Debug),
+            },
+        };
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages[0].payload, expected);
+        assert_eq!(messages[1].id, get_id);
+        assert_eq!(messages[1].payload, expected);
+    }
+
+    #[tokio::test]
+    async fn test_handle_pin_pattern_persists_pinned_list() {
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        let loader_path = path.clone();
+        let state = HostState::new()
+            .with_config_path(Some(path.clone()))
+            .with_settings_loader(Box::new(move || {
+                Config::load(&loader_path).map(|config| HostSettings {
+                    pinned_patterns: config.pinned_patterns,
+                    ..Default::default()
+                })
+            }));
+
+        for (pattern, pinned) in [("summarize", true), ("tldr", true), ("summarize", false)] {
+            handle_pin_pattern(&mut writer, Uuid::new_v4(), pattern, pinned, &state)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(state.pinned_patterns(), vec!["tldr"]);
+        assert_eq!(Config::load(&path).unwrap().pinned_patterns, vec!["tldr"]);
+        let messages = messages.lock().unwrap();
+        assert_eq!(
+            messages[1].payload,
+            ResponsePayload::PinnedPatterns {
+                patterns: vec!["summarize".to_string(), "tldr".to_string()],
+            }
+        );
+        assert_eq!(
+            messages[2].payload,
+            ResponsePayload::PinnedPatterns {
+                patterns: vec!["tldr".to_string()],
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_secret_requests_manage_store() {
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let state = HostState::new().with_secrets(MemorySecretStore::default());
+        for name in ["openai", "anthropic"] {
+            handle_set_secret(
+                &mut writer,
+                Uuid::new_v4(),
+                name.to_string(),
+                format!("sk-{name}"),
+                &state,
+            )
+            .await
+            .unwrap();
+        }
+        handle_delete_secret(&mut writer, Uuid::new_v4(), "openai".to_string(), &state)
+            .await
+            .unwrap();
+        let result =
+            handle_delete_secret(&mut writer, Uuid::new_v4(), "openai".to_string(), &state).await;
+        assert_matches!(result, Err(HandlerError::SecretNotFound(name)) if name == "openai");
+        handle_list_secret_names(&mut writer, Uuid::new_v4(), &state)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            state.secrets.get("anthropic").unwrap().as_deref(),
+            Some("sk-anthropic")
+        );
+        let messages = messages.lock().unwrap();
+        assert_eq!(
+            messages[1].payload,
+            ResponsePayload::SecretNames {
+                names: vec!["anthropic".to_string(), "openai".to_string()],
+            }
+        );
+        assert_eq!(
+            messages[2].payload,
+            ResponsePayload::SecretNames {
+                names: vec!["anthropic".to_string()],
+            }
+        );
+        assert_matches!(
+            &messages[3].payload,
+            ResponsePayload::Error { details: Some(ErrorDetails { code, .. }), .. }
+                if code == "secretNotFound"
+        );
+        assert_eq!(messages[4].payload, messages[2].payload);
+    }
+
+    #[tokio::test]
+    async fn test_handle_set_config_without_path_reports_error() {
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let state = HostState::new();
+        let result = handle_set_config(
+            &mut writer,
+            Uuid::new_v4(),
+            HostConfigUpdate::default(),
+            &state,
+        )
+        .await;
+
+        assert_matches!(result, Err(HandlerError::ConfigFile(_)));
+        let messages = messages.lock().unwrap();
+        assert_matches!(
+            &messages[0].payload,
+            ResponsePayload::Error { details: Some(ErrorDetails { code, .. }), .. }
+                if code == "configFileError"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_queued_process_content_can_be_cancelled() {
+        let runner = MockCommandRunner::default()
+            .with_process_handle(MockProcessHandle::new(vec!["line\n".to_string()], Some(0)))
+            .await;
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let state = HostState::new().with_max_concurrent_processes(1);
+        let _busy = state.process_slots.semaphore.try_acquire().unwrap();
+        let request_id = Uuid::new_v4();
+        let cancel = async {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            let registry = state.process_registry.lock().await;
+            registry.get(&request_id).unwrap().send(true).unwrap();
+        };
+        let (result, ()) = tokio::join!(
+            handle_process_content(
+                &mut writer,
+                request_id,
+                &runner,
+                ProcessOptions::default(),
+                "content".to_string(),
+                &state,
+            ),
+            cancel
+        );
+
+        assert!(result.is_ok());
+        assert!(state.process_registry.lock().await.is_empty());
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_matches!(&messages[0].payload, ResponsePayload::Queued { limit: 1 });
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_handle_process_content_times_out() {
+        let stdout_lines = vec!["fast line\n".to_string(), "slow line\n".to_string()];
+        let process_handle = MockProcessHandle::new(stdout_lines, Some(0))
+            .with_line_delay(Duration::from_millis(400));
+        let runner = MockCommandRunner::default()
+            .with_process_handle(process_handle)
+            .await;
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let state = HostState::new();
+        let options = ProcessOptions {
+            timeout: Some(Duration::from_millis(600)),
+            ..Default::default()
+        };
+        let result = handle_process_content(
+            &mut writer,
+            Uuid::new_v4(),
+            &runner,
+            options,
+            "content".to_string(),
+            &state,
+        )
+        .await;
+
+        assert_matches!(result, Err(HandlerError::Timeout(timeout)) if timeout == Duration::from_millis(600));
+        assert!(state.process_registry.lock().await.is_empty());
+        let messages = messages.lock().unwrap();
+        assert!(messages.iter().any(|message| matches!(
+            &message.payload,
+            ResponsePayload::Content { content } if content == "fast line\n"
+        )));
+        assert_matches!(
+            &messages.last().unwrap().payload,
+            ResponsePayload::Error { message, details: Some(ErrorDetails { code, .. }) }
+                if message == "Process timed out after 600ms" && code == "timeout"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_handle_process_content_times_out_while_stdin_is_stuck() {
+        let process_handle = MockProcessHandle::new(vec!["never\n".to_string()], Some(0))
+            .with_line_delay(Duration::from_secs(60))
+            .with_stalled_stdin();
+        let runner = MockCommandRunner::default()
+            .with_process_handle(process_handle)
+            .await;
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let options = ProcessOptions {
+            timeout: Some(Duration::from_millis(500)),
+            ..Default::default()
+        };
+        let result = handle_process_content(
+            &mut writer,
+            Uuid::new_v4(),
+            &runner,
+            options,
+            "content".to_string(),
+            &HostState::new(),
+        )
+        .await;
+
+        assert_matches!(result, Err(HandlerError::Timeout(timeout)) if timeout == Duration::from_millis(500));
+        assert_matches!(
+            &messages.lock().unwrap().last().unwrap().payload,
+            ResponsePayload::Error { details: Some(ErrorDetails { code, .. }), .. } if code == "timeout"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_handle_process_content_emits_progress() {
+        let stdout_lines = vec!["slow line\n".to_string()];
+        let process_handle = MockProcessHandle::new(stdout_lines, Some(0))
+            .with_line_delay(Duration::from_millis(2500));
+        let runner = MockCommandRunner::default()
+            .with_process_handle(process_handle)
+            .await;
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let state = HostState::new();
+        let result = handle_process_content(
+            &mut writer,
+            Uuid::new_v4(),
+            &runner,
+            ProcessOptions::default(),
+            "content".to_string(),
+            &state,
+        )
+        .await;
+        assert!(result.is_ok());
+
+        let messages = messages.lock().unwrap();
+        assert!(messages.len() >= 4);
+        assert_matches!(
+            &messages[1].payload,
+            ResponsePayload::Progress {
+                bytes_out: 0,
+                elapsed_ms: 1000
+            }
+        );
+        assert_matches!(
+            &messages[2].payload,
+            ResponsePayload::Progress {
+                bytes_out: 0,
+                elapsed_ms: 2000
+            }
+        );
+        assert_matches!(&messages[3].payload, ResponsePayload::Content { content } if content == "slow line\n");
+        assert_matches!(
+            &messages.last().unwrap().payload,
+            ResponsePayload::Done {
+                time_to_first_chunk_ms: Some(2500),
+                spawn_time_ms: Some(0),
+                ..
+            }
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_handle_process_content_emits_heartbeats() {
+        let process_handle = MockProcessHandle::new(vec!["slow line\n".to_string()], Some(0))
+            .with_line_delay(Duration::from_millis(1300));
+        let runner = MockCommandRunner::default()
+            .with_process_handle(process_handle)
+            .await;
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let options = ProcessOptions {
+            heartbeat: Some(Duration::from_millis(500)),
+            ..Default::default()
+        };
+        let result = handle_process_content(
+            &mut writer,
+            Uuid::new_v4(),
+            &runner,
+            options,
+            "content".to_string(),
+            &HostState::new(),
+        )
+        .await;
+        assert!(result.is_ok());
+
+        let messages = messages.lock().unwrap();
+        let heartbeats: Vec<u64> = messages
+            .iter()
+            .filter_map(|message| match message.payload {
+                ResponsePayload::Heartbeat { elapsed_ms } => Some(elapsed_ms),
+                _ => None,
+            })
+            .collect();
+        assert!(heartbeats.len() >= 2);
+        assert!(heartbeats.iter().zip(1..).all(|(&ms, n)| ms == n * 500));
+    }
+
+    async fn coalesced_payloads(
+        process_handle: MockProcessHandle,
+        coalesce_interval: Option<Duration>,
+        coalesce_bytes: Option<usize>,
+    ) -> Vec<ResponsePayload> {
+        let runner = MockCommandRunner::default()
+            .with_process_handle(process_handle)
+            .await;
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let options = ProcessOptions {
+            coalesce_interval,
+            coalesce_bytes,
+            ..Default::default()
+        };
+        handle_process_content(
+            &mut writer,
+            Uuid::new_v4(),
+            &runner,
+            options,
+            "content".to_string(),
+            &HostState::new(),
+        )
+        .await
+        .unwrap();
+
+        messages
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|message| {
+                matches!(
+                    message.payload,
+                    ResponsePayload::Content { .. } | ResponsePayload::StderrLine { .. }
+                )
+            })
+            .map(|message| message.payload.clone())
+            .collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_coalesces_rapid_content_lines() {
+        let stdout_lines = vec!["one\n".to_string(), "two\n".to_string()];
+        let process_handle = MockProcessHandle::new(stdout_lines, Some(0))
+            .with_stderr_lines(vec!["warning".to_string()]);
+
+        let payloads =
+            coalesced_payloads(process_handle, Some(Duration::from_millis(5)), None).await;
+
+        assert_eq!(payloads.len(), 2);
+        assert_matches!(&payloads[0], ResponsePayload::Content { content } if content == "one\ntwo\n");
+        assert_matches!(&payloads[1], ResponsePayload::StderrLine { line } if line == "warning");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_coalescing_flushes_after_interval() {
+        let stdout_lines = vec!["one\n".to_string(), "two\n".to_string()];
+        let process_handle = MockProcessHandle::new(stdout_lines, Some(0))
+            .with_line_delay(Duration::from_millis(10));
+
+        let payloads =
+            coalesced_payloads(process_handle, Some(Duration::from_millis(5)), None).await;
+
+        assert_eq!(payloads.len(), 2);
+        assert_matches!(&payloads[0], ResponsePayload::Content { content } if content == "one\n");
+        assert_matches!(&payloads[1], ResponsePayload::Content { content } if content == "two\n");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_coalescing_flushes_at_chunk_bytes() {
+        let stdout_lines = vec!["ab\n".to_string(), "cd\n".to_string(), "ef\n".to_string()];
+        let process_handle =
+            MockProcessHandle::new(stdout_lines, Some(0)).with_line_delay(Duration::from_secs(1));
+
+        let payloads = coalesced_payloads(process_handle, None, Some(6)).await;
+
+        assert_eq!(payloads.len(), 2);
+        assert_matches!(&payloads[0], ResponsePayload::Content { content } if content == "ab\ncd\n");
+        assert_matches!(&payloads[1], ResponsePayload::Content { content } if content == "ef\n");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_handle_process_content_quick_look_returns_partial_result() {
+        let stdout_lines = vec!["first\n".to_string(), "second\n".to_string()];
+        let process_handle = MockProcessHandle::new(stdout_lines, Some(0))
+            .with_line_delay(Duration::from_millis(1500));
+        let runner = MockCommandRunner::default()
+            .with_process_handle(process_handle)
+            .await;
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let state = HostState::new();
+        let options = ProcessOptions {
+            quick_look: Some(QuickLook { max_seconds: 2 }),
+            ..Default::default()
+        };
+        let result = handle_process_content(
+            &mut writer,
+            Uuid::new_v4(),
+            &runner,
+            options,
+            "content".to_string(),
+            &state,
+        )
+        .await;
+        assert!(result.is_ok());
+
+        let messages = messages.lock().unwrap();
+        assert_matches!(&messages[2].payload, ResponsePayload::Content { content } if content == "first\n");
+        assert_eq!(
+            messages.last().unwrap().payload,
+            ResponsePayload::PartialResult {
+                content: "first\n".to_string(),
+                elapsed_ms: 2000,
+            }
+        );
+        assert!(
+            !messages
+                .iter()
+                .any(|message| matches!(message.payload, ResponsePayload::Done { .. }))
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_handle_process_content_quick_look_fits_partial_result_in_one_frame() {
+        let stdout_lines = vec![
+            format!("{}\n", "a".repeat(1_200_000)),
+            "second\n".to_string(),
+        ];
+        let process_handle = MockProcessHandle::new(stdout_lines, Some(0))
+            .with_line_delay(Duration::from_millis(1500));
+        let runner = MockCommandRunner::default()
+            .with_process_handle(process_handle)
+            .await;
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let options = ProcessOptions {
+            quick_look: Some(QuickLook { max_seconds: 2 }),
+            max_frame_bytes: Some(crate::codec::MAX_OUTBOUND_MESSAGE_SIZE),
+            ..Default::default()
+        };
+        handle_process_content(
+            &mut writer,
+            Uuid::new_v4(),
+            &runner,
+            options,
+            "content".to_string(),
+            &HostState::new(),
+        )
+        .await
+        .unwrap();
+
+        let partial = messages.lock().unwrap().last().unwrap().clone();
+        let ResponsePayload::PartialResult { content, .. } = &partial.payload else {
+            panic!("expected a PartialResult");
+        };
+        assert!(content.len() > 512 * 1024 && content.len() < 1_200_000);
+        assert!(content.bytes().all(|byte| byte == b'a'));
+        let mut codec = crate::codec::NativeMessagingCodec::<Response>::default();
+        codec.encode(partial, &mut BytesMut::new()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_handle_process_content_reports_estimated_usage() {
+        let stdout_lines = vec!["12345678\n".to_string()];
+        let process_handle = MockProcessHandle::new(stdout_lines, Some(0));
+        let runner = MockCommandRunner::default()
+            .with_process_handle(process_handle)
+            .await;
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let state = HostState::new();
+        let result = handle_process_content(
+            &mut writer,
+            Uuid::new_v4(),
+            &runner,
+            ProcessOptions {
+                custom_prompt: Some("abcd".to_string()),
+                ..Default::default()
+            },
+            "0123456789ab".to_string(),
+            &state,
+        )
+        .await;
+        assert!(result.is_ok());
+
+        let messages = messages.lock().unwrap();
+        assert_matches!(
+            &messages[2].payload,
+            ResponsePayload::Done {
+                usage: Some(TokenUsage {
+                    prompt_tokens: 4,
+                    completion_tokens: 3,
+                    estimated: true,
+                }),
+                ..
+            }
+        );
+        assert_eq!(state.usage.today().tokens(), 7);
+    }
+
+    #[tokio::test]
+    async fn test_handle_process_content_reports_backend_generation_stats() {
+        let process_handle = MockProcessHandle::new(vec!["answer\n".to_string()], Some(0))
+            .with_stderr_lines(vec![
+                "prompt eval count:    26 token(s)\n".to_string(),
+                "eval count:           290 token(s)\n".to_string(),
+                "eval duration:        4.5s\n".to_string(),
+                "eval rate:            64.44 tokens/s\n".to_string(),
+            ]);
+        let runner = MockCommandRunner::default()
+            .with_process_handle(process_handle)
+            .await;
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        handle_process_content(
+            &mut writer,
+            Uuid::new_v4(),
+            &runner,
+            ProcessOptions::default(),
+            "content".to_string(),
+            &HostState::new(),
+        )
+        .await
+        .unwrap();
+
+        let messages = messages.lock().unwrap();
+        assert_matches!(
+            &messages.last().unwrap().payload,
+            ResponsePayload::Done {
+                usage: Some(TokenUsage {
+                    prompt_tokens: 26,
+                    completion_tokens: 290,
+                    estimated: false,
+                }),
+                generation: Some(GenerationStats {
+                    eval_duration_ms: Some(4500),
+                    tokens_per_second,
+                    ..
+                }),
+                stderr_tail: None,
+                ..
+            } if *tokens_per_second == 64.44
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_process_content_streams_stderr() {
+        let process_handle = MockProcessHandle::new(vec!["output\n".to_string()], Some(1))
+            .with_stderr_lines(vec!["warning: slow model\n".to_string()]);
+        let runner = MockCommandRunner::default()
+            .with_process_handle(process_handle)
+            .await;
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let state = HostState::new();
+        let result = handle_process_content(
+            &mut writer,
+            Uuid::new_v4(),
+            &runner,
+            ProcessOptions::default(),
+            "content".to_string(),
+            &state,
+        )
+        .await;
+        assert!(result.is_ok());
+
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages.len(), 4);
+        assert_matches!(&messages[1].payload, ResponsePayload::Content { content } if content == "output\n");
+        assert_matches!(&messages[2].payload, ResponsePayload::StderrLine { line } if line == "warning: slow model\n");
+        assert_matches!(
+            &messages[3].payload,
+            ResponsePayload::Done {
+                exit_code: Some(1),
+                stderr_tail: Some(tail),
+                ..
+            } if tail == "warning: slow model\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_process_content_omits_stderr_tail_on_success() {
+        let process_handle = MockProcessHandle::new(vec!["output\n".to_string()], Some(0))
+            .with_stderr_lines(vec!["info: using cached model\n".to_string()]);
+        let runner = MockCommandRunner::default()
+            .with_process_handle(process_handle)
+            .await;
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let state = HostState::new();
+        let result = handle_process_content(
+            &mut writer,
+            Uuid::new_v4(),
+            &runner,
+            ProcessOptions::default(),
+            "content".to_string(),
+            &state,
+        )
+        .await;
+        assert!(result.is_ok());
+
+        let messages = messages.lock().unwrap();
+        assert_matches!(
+            messages.last().map(|m| &m.payload),
+            Some(ResponsePayload::Done {
+                exit_code: Some(0),
+                stderr_tail: None,
+                ..
+            })
+        );
+    }
+
+    #[test]
+    fn test_classify_failure() {
+        let code = |stderr: &str| classify_failure(stderr).map(|class| class.code);
+
+        assert_eq!(
+            code("error: OPENAI_API_KEY is not set; missing API key"),
+            Some("missingApiKey")
+        );
+        assert_eq!(
+            code("could not get pattern summarize_all: pattern not found"),
+            Some("unknownPattern")
+        );
+        assert_eq!(
+            code("could not find vendor.\n Model = gpt-9"),
+            Some("noDefaultModel")
+        );
+        assert_eq!(
+            code(
+                "Post \"https://api.openai.com/v1/chat\": dial tcp: lookup api.openai.com: no such host"
+            ),
+            Some("networkFailure")
+        );
+        assert_eq!(code("panic: something unexpected"), None);
+    }
+
+    #[tokio::test]
+    async fn test_handle_process_content_classifies_failure() {
+        let process_handle = MockProcessHandle::new(vec![], Some(1)).with_stderr_lines(vec![
+            "could not get pattern nope: pattern not found\n".to_string(),
+        ]);
+        let runner = MockCommandRunner::default()
+            .with_process_handle(process_handle)
+            .await;
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let state = HostState::new();
+        let result = handle_process_content(
+            &mut writer,
+            Uuid::new_v4(),
+            &runner,
+            ProcessOptions::default(),
+            "content".to_string(),
+            &state,
+        )
+        .await;
+        assert!(result.is_ok());
+
+        let messages = messages.lock().unwrap();
+        assert_matches!(
+            messages.last().map(|m| &m.payload),
+            Some(ResponsePayload::Done {
+                exit_code: Some(1),
+                error: Some(ErrorDetails { code, .. }),
+                ..
+            }) if code == "unknownPattern"
+        );
+    }
+
+    #[test]
+    fn test_append_tail_keeps_last_bytes() {
+        let mut tail = String::new();
+        append_tail(&mut tail, "first line\n", 15);
+        append_tail(&mut tail, "missing OPENAI_API_KEY\n", 15);
+        assert_eq!(tail, "OPENAI_API_KEY\n");
+
+        let mut tail = String::new();
+        append_tail(&mut tail, "ééé", 3);
+        assert_eq!(tail, "é");
+    }
+
+    #[tokio::test]
+    async fn test_read_chunk_respects_utf8_boundaries() {
+        let mut reader = Some(BufReader::new(&b"ab\xe6\x97\xa5\xe6\x9c\xac\xff\xe6"[..]));
+        let mut buf = Vec::new();
+
+        let mut chunks = Vec::new();
+        while let Some(chunk) = read_chunk_into(&mut reader, &mut buf, 2).await.unwrap() {
+            chunks.push(chunk);
+        }
+
+        assert_eq!(chunks, vec!["ab", "日", "本", "\u{fffd}", "\u{fffd}"]);
+    }
+
+    #[tokio::test]
+    async fn test_read_line_splits_lines_over_the_cap() {
+        let mut reader = Some(BufReader::new(&b"abcdefgh\nij\n"[..]));
+        let mut buf = Vec::new();
+        let mut lines = Vec::new();
+        while let Some(line) = read_line_into(&mut reader, &mut buf, Some(3))
+            .await
+            .unwrap()
+        {
+            lines.push(line);
+        }
+        assert_eq!(lines, vec!["abc", "def", "gh\n", "ij\n"]);
+    }
+
+    #[tokio::test]
+    async fn test_reads_normalize_crlf_line_endings() {
+        let mut reader = Some(BufReader::new(&b"one\r\ntwo\rthree\r\n"[..]));
+        let mut buf = Vec::new();
+        let mut lines = Vec::new();
+        while let Some(line) = read_line_into(&mut reader, &mut buf, None).await.unwrap() {
+            lines.push(line);
+        }
+        assert_eq!(lines, vec!["one\n", "two\rthree\n"]);
+
+        let mut reader = Some(BufReader::new(&b"a\r\nb\r"[..]));
+        let mut chunks = Vec::new();
+        while let Some(chunk) = read_chunk_into(&mut reader, &mut buf, 2).await.unwrap() {
+            chunks.push(chunk);
+        }
+        assert_eq!(chunks, vec!["a", "\n", "b", "\r"]);
+    }
+
+    #[tokio::test]
+    async fn test_handle_ping_setup_required() {
+        let dir = tempdir().unwrap();
+        let runner = MockCommandRunner::default()
+            .with_config_dir(dir.path().join(".config").join("fabric"))
+            .with_version_response(CommandOutput {
+                status: true,
+                stdout: "v1.4.0".to_string(),
+                stderr: String::new(),
+            });
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let result = handle_ping(
+            &mut writer,
+            Uuid::new_v4(),
+            &runner,
+            None,
+            DEFAULT_COMMAND_TIMEOUT,
+        )
+        .await;
+        assert!(result.is_ok());
+
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_matches!(
+            &messages[0].payload,
+            ResponsePayload::SetupRequired { instructions, .. } if instructions.contains("fabric --setup")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_process_content_setup_required() {
+        let dir = tempdir().unwrap();
+        let process_handle = MockProcessHandle::new(vec!["output\n".to_string()], Some(0));
+        let runner = MockCommandRunner::default()
+            .with_config_dir(dir.path().join("missing"))
+            .with_process_handle(process_handle)
+            .await;
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let state = HostState::new();
+        let result = handle_process_content(
+            &mut writer,
+            Uuid::new_v4(),
+            &runner,
+            ProcessOptions::default(),
+            "content".to_string(),
+            &state,
+        )
+        .await;
+        assert!(result.is_ok());
+
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_matches!(&messages[0].payload, ResponsePayload::SetupRequired { .. });
+    }
+
+    #[tokio::test]
+    async fn test_handle_process_content_configured_fabric() {
+        let dir = tempdir().unwrap();
+        let process_handle = MockProcessHandle::new(vec!["output\n".to_string()], Some(0));
+        let runner = MockCommandRunner::default()
+            .with_config_dir(dir.path())
+            .with_process_handle(process_handle)
+            .await;
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let state = HostState::new();
+        let result = handle_process_content(
+            &mut writer,
+            Uuid::new_v4(),
+            &runner,
+            ProcessOptions::default(),
+            "content".to_string(),
+            &state,
+        )
+        .await;
+        assert!(result.is_ok());
+
+        let messages = messages.lock().unwrap();
+        assert_matches!(&messages[1].payload, ResponsePayload::Content { .. });
+    }
+
+    async fn spawned_args_for(options: ProcessOptions) -> Vec<String> {
+        let process_handle = MockProcessHandle::new(vec!["output\n".to_string()], Some(0));
+        let runner = MockCommandRunner::default()
+            .with_process_handle(process_handle)
+            .await;
+
+        let test_writer = TestWriter::new();
+        let encoder = TestEncoder::new(test_writer.messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let state = HostState::new();
+        handle_process_content(
+            &mut writer,
+            Uuid::new_v4(),
+            &runner,
+            options,
+            "content".to_string(),
+            &state,
+        )
+        .await
+        .unwrap();
+
+        runner.spawned_args.lock().await.remove(0)
+    }
+
+    #[tokio::test]
+    async fn test_handle_process_content_system_prompt_with_pattern() {
+        let args = spawned_args_for(ProcessOptions {
+            pattern: Some("summarize".to_string()),
+            custom_prompt: Some("ignored".to_string()),
+            system_prompt: Some("Answer in French".to_string()),
+            ..Default::default()
+        })
+        .await;
+
+        assert_eq!(
+            args,
+            vec![
+                "--stream",
+                "--pattern",
+                "summarize",
+                "--",
+                "Answer in French"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_process_content_system_prompt_standalone() {
+        let args = spawned_args_for(ProcessOptions {
+            custom_prompt: Some("List the key points".to_string()),
+            system_prompt: Some("Answer in French".to_string()),
+            ..Default::default()
+        })
+        .await;
+
+        assert_eq!(
+            args,
+            vec!["--stream", "--", "Answer in French", "List the key points"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_process_content_deterministic() {
+        let process_handle = MockProcessHandle::new(vec!["output\n".to_string()], Some(0));
+        let runner = MockCommandRunner::default()
+            .with_fabric_path("/old/fabric/deterministic")
+            .with_version_response(CommandOutput {
+                status: true,
+                stdout: "v1.2.0".to_string(),
+                stderr: String::new(),
+            })
+            .with_process_handle(process_handle)
+            .await;
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let options = ProcessOptions {
+            model: Some("gpt-4o".to_string()),
+            custom_prompt: Some("List the key points".to_string()),
+            deterministic: true,
+            ..Default::default()
+        };
+        let state = HostState::new();
+        handle_process_content(
+            &mut writer,
+            Uuid::new_v4(),
+            &runner,
+            options,
+            "content".to_string(),
+            &state,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            runner.spawned_args.lock().await[0],
+            vec![
+                "--stream",
+                "--model",
+                "gpt-4o",
+                "--temperature",
+                "0",
+                "--",
+                "List the key points"
+            ]
+        );
+
+        let messages = messages.lock().unwrap();
+        let expected = ResolvedInputs {
+            model: Some("gpt-4o".to_string()),
+            pattern: None,
+            context: None,
+            prompt: Some("List the key points".to_string()),
+            temperature: 0.0,
+            seed: None,
+            input_hash: blake3::hash(b"content").to_hex().to_string(),
+            fabric_version: Some(FabricVersion::new(1, 2, 0).to_string()),
+        };
+        assert_matches!(
+            &messages[0].payload,
+            ResponsePayload::Warning { message } if message.starts_with("Running without a fixed seed")
+        );
+        assert_matches!(
+            &messages[1].payload,
+            ResponsePayload::StreamStart { inputs: Some(inputs), .. } if *inputs == expected
+        );
+    }
+
+    #[tokio::test]
+    async fn test_strict_mode_rejects_deterministic_run_without_seed() {
+        let process_handle = MockProcessHandle::new(vec!["output\n".to_string()], Some(0));
+        let runner = MockCommandRunner::default()
+            .with_version_response(CommandOutput {
+                status: true,
+                stdout: "v1.2.0".to_string(),
+                stderr: String::new(),
+            })
+            .with_process_handle(process_handle)
+            .await;
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let result = handle_process_content(
+            &mut writer,
+            Uuid::new_v4(),
+            &runner,
+            ProcessOptions {
+                deterministic: true,
+                strict: true,
+                ..Default::default()
+            },
+            "content".to_string(),
+            &HostState::new(),
+        )
+        .await;
+
+        assert_matches!(result, Err(HandlerError::Strict(message)) if message.starts_with("Running without a fixed seed"));
+        assert!(runner.spawned_args.lock().await.is_empty());
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_matches!(
+            &messages[0].payload,
+            ResponsePayload::Error { details: Some(ErrorDetails { code, .. }), .. } if code == "strict"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_malformed_frame_reports_error() {
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let request_id = Uuid::new_v4();
+        handle_malformed_frame(
+            &mut writer,
+            MalformedFrame {
+                id: Some(request_id),
+                size: 12,
+                error: "missing field `type`".to_string(),
+                limit: None,
+            },
+        )
+        .await
+        .unwrap();
+
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages[0].id, request_id);
+        assert_matches!(
+            &messages[0].payload,
+            ResponsePayload::Error { details: Some(ErrorDetails { code, .. }), .. } if code == "malformedFrame"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_oversized_input_reports_content_too_large() {
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let frame_id = Uuid::new_v4();
+        handle_malformed_frame(
+            &mut writer,
+            MalformedFrame {
+                id: Some(frame_id),
+                size: 200,
+                error: "Message size 200 exceeds limit 100".to_string(),
+                limit: Some(100),
+            },
+        )
+        .await
+        .unwrap();
+
+        let request_id = Uuid::new_v4();
+        let size = validation::MAX_CONTENT_BYTES + 1;
+        let result = handle_request(
+            &mut writer,
+            Request {
+                id: request_id,
+                path: None,
+                validate_only: false,
+                payload: RequestPayload::ProcessContent {
+                    content: "a".repeat(size).into(),
+                    model: None,
+                    pattern: None,
+                    context: None,
+                    custom_prompt: None,
+                    system_prompt: None,
+                    locale: None,
+                    detach: false,
+                    aggregate: false,
+                    summarize: false,
+                    summary_model: None,
+                    output_format: OutputFormat::Markdown,
+                    deterministic: false,
+                    quick_look: None,
+                    selection: None,
+                    composition: None,
+                    stream_mode: StreamMode::Lines,
+                    stream_flush_ms: None,
+                    stream_chunk_bytes: None,
+                    strict: None,
+                    structured: false,
+                    timeout_ms: None,
+                    heartbeat_ms: None,
+                    cwd: None,
+                    env: None,
+                },
+            },
+            |_| MockCommandRunner::default(),
+            &HostState::new(),
+        )
+        .await;
+
+        assert_matches!(result, Err(HandlerError::ContentTooLarge { size: found, .. }) if found == size);
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages[0].id, frame_id);
+        assert_matches!(
+            &messages[0].payload,
+            ResponsePayload::Error { message, details: Some(ErrorDetails { code, hint: Some(hint), .. }) }
+                if message.contains("exceeds the 100 byte limit") && code == "contentTooLarge" && hint.contains("native.contentChunk")
+        );
+        assert_eq!(messages[1].id, request_id);
+        assert_matches!(
+            &messages[1].payload,
+            ResponsePayload::Error { message, details: Some(ErrorDetails { code, hint: Some(hint), .. }) }
+                if *message == format!("Content is {size} bytes, which exceeds the {} byte limit", validation::MAX_CONTENT_BYTES)
+                    && code == "contentTooLarge"
+                    && hint == CHUNKED_UPLOAD_HINT
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_while_draining_reports_cancelled() {
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let state = HostState::new();
+        state.begin_drain().await;
+        let request_id = Uuid::new_v4();
+        handle_request(
+            &mut writer,
+            Request {
+                id: request_id,
+                path: None,
+                validate_only: false,
+                payload: RequestPayload::ListPatterns,
+            },
+            |_| MockCommandRunner::default(),
+            &state,
+        )
+        .await
+        .unwrap();
+
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_matches!(
+            &messages[0].payload,
+            ResponsePayload::Cancelled { request_id: cancelled } if *cancelled == request_id
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_duplicate_request_reports_error() {
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let request_id = Uuid::new_v4();
+        handle_duplicate_request(&mut writer, request_id)
+            .await
+            .unwrap();
+
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages[0].id, request_id);
+        assert_matches!(
+            &messages[0].payload,
+            ResponsePayload::Error { message, details: Some(ErrorDetails { code, .. }) }
+                if *message == format!("Request {request_id} is already in flight") && code == "duplicateRequestId"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_answers_heartbeat() {
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let state = HostState::new();
+        let request_id = Uuid::new_v4();
+        let _tracked = state.track_request(request_id);
+        handle_request(
+            &mut writer,
+            Request {
+                id: request_id,
+                path: None,
+                validate_only: false,
+                payload: RequestPayload::Heartbeat,
+            },
+            |_| MockCommandRunner::default(),
+            &state,
+        )
+        .await
+        .unwrap();
+
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].id, request_id);
+        assert_matches!(
+            &messages[0].payload,
+            ResponsePayload::HeartbeatAck {
+                active_requests: 1,
+                ..
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_shutdown_acknowledges_request() {
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let request_id = Uuid::new_v4();
+        handle_shutdown(&mut writer, request_id).await.unwrap();
+
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages[0].id, request_id);
+        assert_eq!(messages[0].payload, ResponsePayload::ShutdownAck);
+        assert_eq!(
+            serde_json::to_value(&messages[0]).unwrap()["type"],
+            "native.shutdownAck"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_panicked_request_reports_internal_error() {
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let request_id = Uuid::new_v4();
+        let panic = tokio::spawn(async { panic!("stream state {}", 7) })
+            .await
+            .unwrap_err()
+            .into_panic();
+        handle_panicked_request(&mut writer, request_id, panic)
+            .await
+            .unwrap();
+        handle_panicked_request(&mut writer, request_id, Box::new(42_u32))
+            .await
+            .unwrap();
+
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages[0].id, request_id);
+        assert_matches!(
+            &messages[0].payload,
+            ResponsePayload::Error { message, details: Some(ErrorDetails { code, .. }) }
+                if message == "Host panicked while handling the request: stream state 7" && code == "internalError"
+        );
+        assert_matches!(
+            &messages[1].payload,
+            ResponsePayload::Error { message, .. } if message.ends_with("unknown panic")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_applies_configured_defaults() {
+        let dir = tempdir().unwrap();
+        let fabric = fake_fabric(&dir);
+        let runner = MockCommandRunner::default()
+            .with_process_handle(MockProcessHandle::new(Vec::new(), Some(0)))
+            .await;
+        let spawned_args = runner.spawned_args.clone();
+
+        let test_writer = TestWriter::new();
+        let encoder = TestEncoder::new(test_writer.messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let state = HostState::new()
+            .with_fabric_path(Some(fabric.to_path_buf()))
+            .with_request_defaults(RequestDefaults {
+                model: Some("llama3".to_string()),
+                pattern: Some("summarize".to_string()),
+                ..Default::default()
+            });
+        let request: Request = serde_json::from_str(
+            r#"{"id":"550e8400-e29b-41d4-a716-446655440000","type":"native.processContent","content":"text"}"#,
+        )
+        .unwrap();
+        handle_request(
+            &mut writer,
+            request,
+            |path| {
+                assert_eq!(path, fabric.as_path());
+                runner
+            },
+            &state,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            spawned_args.lock().await[0],
+            vec!["--stream", "--model", "llama3", "--pattern", "summarize"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_reports_pattern_model_default() {
+        let dir = tempdir().unwrap();
+        let fabric = fake_fabric(&dir);
+        let runner = MockCommandRunner::default()
+            .with_process_handle(MockProcessHandle::new(Vec::new(), Some(0)))
+            .await;
+        let spawned_args = runner.spawned_args.clone();
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let state = HostState::new()
+            .with_fabric_path(Some(fabric.to_path_buf()))
+            .with_request_defaults(RequestDefaults {
+                model: Some("llama3".to_string()),
+                pattern_models: [("summarize".to_string(), "gpt-4o-mini".to_string())].into(),
+                ..Default::default()
+            });
+        let request: Request = serde_json::from_str(
+            r#"{"id":"550e8400-e29b-41d4-a716-446655440000","type":"native.processContent","content":"text","pattern":"summarize"}"#,
+        )
+        .unwrap();
+        handle_request(&mut writer, request, |_| runner, &state)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            spawned_args.lock().await[0],
+            vec![
+                "--stream",
+                "--model",
+                "gpt-4o-mini",
+                "--pattern",
+                "summarize"
+            ]
+        );
+        let messages = messages.lock().unwrap();
+        assert_matches!(
+            &messages[0].payload,
+            ResponsePayload::StreamStart { model: Some(model), pattern: Some(pattern), .. }
+                if model == "gpt-4o-mini" && pattern == "summarize"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_process_content_resolves_pattern_alias() {
+        let runner = MockCommandRunner::default()
+            .with_process_handle(MockProcessHandle::new(Vec::new(), Some(0)))
+            .await;
+        let spawned_args = runner.spawned_args.clone();
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let state = HostState::new().with_request_defaults(RequestDefaults {
+            pattern_models: [(
+                "create_5_sentence_summary".to_string(),
+                "gpt-4o-mini".to_string(),
+            )]
+            .into(),
+            aliases: [("tldr".to_string(), "create_5_sentence_summary".to_string())].into(),
+            ..Default::default()
+        });
+        let options = ProcessOptions {
+            pattern: Some("tldr".to_string()),
+            ..Default::default()
+        };
+        handle_process_content(
+            &mut writer,
+            Uuid::new_v4(),
+            &runner,
+            options,
+            "text".to_string(),
+            &state,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            spawned_args.lock().await[0],
+            vec![
+                "--stream",
+                "--model",
+                "gpt-4o-mini",
+                "--pattern",
+                "create_5_sentence_summary"
+            ]
+        );
+        let messages = messages.lock().unwrap();
+        assert_matches!(
+            &messages[0].payload,
+            ResponsePayload::StreamStart { pattern: Some(pattern), .. }
+                if pattern == "create_5_sentence_summary"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_applies_pattern_models_to_batch_items() {
+        let dir = tempdir().unwrap();
+        let fabric = fake_fabric(&dir);
+        let runner = MockCommandRunner::default()
+            .with_process_handle(MockProcessHandle::new(Vec::new(), Some(0)))
+            .await
+            .with_process_handle(MockProcessHandle::new(Vec::new(), Some(0)))
+            .await;
+        let spawned_args = runner.spawned_args.clone();
+
+        let test_writer = TestWriter::new();
+        let encoder = TestEncoder::new(test_writer.messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let state = HostState::new()
+            .with_fabric_path(Some(fabric.to_path_buf()))
+            .with_request_defaults(RequestDefaults {
+                pattern_models: [("summarize".to_string(), "gpt-4o-mini".to_string())].into(),
+                ..Default::default()
+            });
+        let request: Request = serde_json::from_str(
+            r#"{"id":"550e8400-e29b-41d4-a716-446655440000","type":"native.processBatch","items":[
+                {"content":"a","pattern":"summarize"},
+                {"content":"b","pattern":"summarize","model":"llama3"}
+            ]}"#,
+        )
+        .unwrap();
+        handle_request(&mut writer, request, |_| runner, &state)
+            .await
+            .unwrap();
+
+        let mut spawned_args = spawned_args.lock().await.clone();
+        spawned_args.sort();
+        assert_eq!(
+            spawned_args,
+            vec![
+                vec![
+                    "--stream",
+                    "--model",
+                    "gpt-4o-mini",
+                    "--pattern",
+                    "summarize"
+                ],
+                vec!["--stream", "--model", "llama3", "--pattern", "summarize"],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resolve_cwd_requires_allowed_root() {
+        let dir = tempdir().unwrap();
+        let root = dir.child("projects");
+        let project = root.child("site");
+        project.create_dir_all().unwrap();
+        let outside = dir.child("other");
+        outside.create_dir_all().unwrap();
+        let roots = [root.to_path_buf()];
+
+        assert_eq!(
+            resolve_cwd(project.as_path(), &roots).unwrap(),
+            project.canonicalize_utf8().unwrap()
+        );
+        let escaped = project.join("..").join("..").join("other");
+        for cwd in [
+            outside.as_path(),
+            escaped.as_path(),
+            &project.join("missing"),
+        ] {
+            assert_matches!(
+                resolve_cwd(cwd, &roots),
+                Err(HandlerError::CwdNotAllowed(path)) if path == cwd
+            );
+        }
+        assert_matches!(
+            resolve_cwd(project.as_path(), &[]),
+            Err(HandlerError::CwdNotAllowed(_))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_reports_disallowed_cwd() {
+        let dir = tempdir().unwrap();
+        let fabric = fake_fabric(&dir);
+        let runner = MockCommandRunner::default();
+        let spawned_args = runner.spawned_args.clone();
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let state = HostState::new().with_fabric_path(Some(fabric));
+        let request: Request = serde_json::from_value(serde_json::json!({
+            "id": "550e8400-e29b-41d4-a716-446655440000",
+            "type": "native.processContent",
+            "content": "text",
+            "cwd": dir.path(),
+        }))
+        .unwrap();
+        let result = handle_request(&mut writer, request, |_| runner, &state).await;
+
+        assert_matches!(result, Err(HandlerError::CwdNotAllowed(_)));
+        assert!(spawned_args.lock().await.is_empty());
+        let messages = messages.lock().unwrap();
+        assert_matches!(
+            &messages[0].payload,
+            ResponsePayload::Error { details: Some(ErrorDetails { code, fields, .. }), .. }
+                if code == "cwdNotAllowed" && fields[0].field == "cwd"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_reports_disallowed_env() {
+        let dir = tempdir().unwrap();
+        let fabric = fake_fabric(&dir);
+        let runner = MockCommandRunner::default();
+        let spawned_args = runner.spawned_args.clone();
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let state = HostState::new().with_settings(HostSettings {
+            fabric_path: Some(fabric),
+            allowed_request_env: vec!["OLLAMA_HOST".to_string()],
+            ..HostSettings::default()
+        });
+        let request: Request = serde_json::from_value(serde_json::json!({
+            "id": "550e8400-e29b-41d4-a716-446655440000",
+            "type": "native.processContent",
+            "content": "text",
+            "env": {"OLLAMA_HOST": "http://gpu:11434", "LD_PRELOAD": "/tmp/evil.so"},
+        }))
+        .unwrap();
+        let result = handle_request(&mut writer, request, |_| runner, &state).await;
+
+        assert_matches!(result, Err(HandlerError::EnvNotAllowed(name)) if name == "LD_PRELOAD");
+        assert!(spawned_args.lock().await.is_empty());
+        let messages = messages.lock().unwrap();
+        assert_matches!(
+            &messages[0].payload,
+            ResponsePayload::Error { details: Some(ErrorDetails { code, fields, .. }), .. }
+                if code == "envNotAllowed" && fields[0].field == "env.LD_PRELOAD"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_answers_host_requests_without_fabric() {
+        let dir = tempdir().unwrap();
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let state = HostState::new().with_settings(HostSettings {
+            search_paths: vec![dir.path().to_path_buf()],
+            binary_names: vec!["no-such-fabric".to_string()],
+            ..HostSettings::default()
+        });
+        for request in [
+            serde_json::json!({"id": Uuid::new_v4(), "type": "native.listJobs"}),
+            serde_json::json!({
+                "id": Uuid::new_v4(),
+                "type": "native.contentChunk",
+                "requestId": Uuid::new_v4(),
+                "seq": 0,
+                "data": "text",
+                "last": false,
+            }),
+        ] {
+            let request: Request = serde_json::from_value(request).unwrap();
+            handle_request(
+                &mut writer,
+                request,
+                |_| MockCommandRunner::default(),
+                &state,
+            )
+            .await
+            .unwrap();
+        }
+        let request: Request = serde_json::from_value(serde_json::json!({
+            "id": Uuid::new_v4(),
+            "type": "native.listPatterns",
+        }))
+        .unwrap();
+        let result = handle_request(
+            &mut writer,
+            request,
+            |_| MockCommandRunner::default(),
+            &state,
+        )
+        .await;
+
+        assert_matches!(result, Err(HandlerError::FabricNotFound(_)));
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages.len(), 3);
+        assert_matches!(&messages[0].payload, ResponsePayload::JobsList { .. });
+        assert_matches!(&messages[1].payload, ResponsePayload::ChunkAccepted { .. });
+        assert_matches!(
+            &messages[2].payload,
+            ResponsePayload::Error { message, .. } if message.starts_with("Failed to find fabric")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_locale_falls_back_to_hello_then_config() {
+        let dir = tempdir().unwrap();
+        let fabric = fake_fabric(&dir);
+        let state = HostState::new()
+            .with_fabric_path(Some(fabric))
+            .with_request_defaults(RequestDefaults {
+                locale: Some("de-DE".to_string()),
+                ..RequestDefaults::default()
+            });
+
+        let mut outputs = Vec::new();
+        for (hello, locale) in [
+            (None, None),
+            (Some("en-US"), None),
+            (Some("en-US"), Some("fr-FR")),
+        ] {
+            let test_writer = TestWriter::new();
+            let messages = test_writer.messages.clone();
+            let encoder = TestEncoder::new(messages.clone());
+            let mut writer = FramedWrite::new(test_writer, encoder);
+
+            state.set_client_locale(hello.map(str::to_string));
+            let runner = MockCommandRunner::default()
+                .with_process_handle(MockProcessHandle::new(vec!["3.14\n".to_string()], Some(0)))
+                .await;
+            let request: Request = serde_json::from_value(serde_json::json!({
+                "id": Uuid::new_v4(),
+                "type": "native.processContent",
+                "content": "text",
+                "locale": locale,
+                "aggregate": true,
+            }))
+            .unwrap();
+            handle_request(&mut writer, request, |_| runner, &state)
+                .await
+                .unwrap();
+
+            let messages = messages.lock().unwrap();
+            match &messages.last().unwrap().payload {
+                ResponsePayload::Done {
+                    content: Some(content),
+                    ..
+                } => outputs.push(content.clone()),
+                payload => panic!("unexpected {payload:?}"),
+            }
+        }
+
+        assert_eq!(outputs, vec!["3,14\n", "3.14\n", "3,14\n"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_handle_request_rate_limits_by_request_type() {
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let limit: RateLimit = toml::from_str("per_minute = 6\nburst = 1").unwrap();
+        let dir = tempdir().unwrap();
+        let state = HostState::new().with_settings(HostSettings {
+            fabric_path: Some(fake_fabric(&dir)),
+            rate_limits: BTreeMap::from([("heartbeat".to_string(), limit)]),
+            ..HostSettings::default()
+        });
+        let heartbeat = || Request {
+            id: Uuid::new_v4(),
+            path: None,
+            validate_only: false,
+            payload: RequestPayload::Heartbeat,
+        };
+
+        handle_request(
+            &mut writer,
+            heartbeat(),
+            |_| MockCommandRunner::default(),
+            &state,
+        )
+        .await
+        .unwrap();
+        let result = handle_request(
+            &mut writer,
+            heartbeat(),
+            |_| MockCommandRunner::default(),
+            &state,
+        )
+        .await;
+        assert_matches!(
+            result,
+            Err(HandlerError::RateLimited { request_type: "native.heartbeat", retry_after })
+                if retry_after == Duration::from_secs(10)
+        );
+        let validate_only = Request {
+            validate_only: true,
+            ..heartbeat()
+        };
+        handle_request(
+            &mut writer,
+            validate_only,
+            |_| MockCommandRunner::default(),
+            &state,
+        )
+        .await
+        .unwrap();
+
+        tokio::time::advance(Duration::from_secs(10)).await;
+        handle_request(
+            &mut writer,
+            heartbeat(),
+            |_| MockCommandRunner::default(),
+            &state,
+        )
+        .await
+        .unwrap();
+
+        let messages = messages.lock().unwrap();
+        assert_matches!(
+            &messages[1].payload,
+            ResponsePayload::Error { details: Some(ErrorDetails { code, hint: Some(hint), .. }), .. }
+                if code == "rateLimited" && hint == "Retry after 10000ms"
+        );
+        assert_matches!(&messages[3].payload, ResponsePayload::HeartbeatAck { .. });
+    }
+
+    #[tokio::test]
+    async fn test_handle_get_metrics_reports_requests_and_failures() {
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let dir = tempdir().unwrap();
+        let state = HostState::new()
+            .with_secrets(MemorySecretStore::default())
+            .with_settings(HostSettings {
+                fabric_path: Some(fake_fabric(&dir)),
+                ..HostSettings::default()
+            });
+        for payload in [
+            RequestPayload::Heartbeat,
+            RequestPayload::DeleteSecret {
+                name: "openai".to_string(),
+            },
+            RequestPayload::GetMetrics,
+        ] {
+            let request = Request {
+                id: Uuid::new_v4(),
+                path: None,
+                validate_only: false,
+                payload,
+            };
+            let _ = handle_request(
+                &mut writer,
+                request,
+                |_| MockCommandRunner::default(),
+                &state,
+            )
+            .await;
+        }
+
+        let messages = messages.lock().unwrap();
+        let Some(ResponsePayload::Metrics(metrics)) = messages.last().map(|m| &m.payload) else {
+            panic!("expected metrics, got {messages:?}");
+        };
+        assert_eq!(
+            metrics.requests,
+            BTreeMap::from([
+                ("native.deleteSecret".to_string(), 1),
+                ("native.getMetrics".to_string(), 1),
+                ("native.heartbeat".to_string(), 1),
+            ])
+        );
+        assert_eq!(
+            metrics.failures,
+            BTreeMap::from([("secretNotFound".to_string(), 1)])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_quarantined_store_is_reported_on_the_first_request() {
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let dir = tempdir().unwrap();
+        let jobs_path = dir.path().join("jobs.json");
+        std::fs::write(&jobs_path, "{ not json").unwrap();
+        let (jobs, warning) = JobRegistry::open(jobs_path).unwrap();
+        let warning = warning.unwrap();
+        let state = HostState::new()
+            .with_jobs(jobs)
+            .with_startup_warning(warning.clone());
+
+        for _ in 0..2 {
+            let request = Request {
+                id: Uuid::new_v4(),
+                path: None,
+                validate_only: false,
+                payload: RequestPayload::Ping,
+            };
+            handle_request(
+                &mut writer,
+                request,
+                |_| MockCommandRunner::default(),
+                &state,
+            )
+            .await
+            .unwrap();
+        }
+
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(
+            messages[0].payload,
+            ResponsePayload::Warning { message: warning }
+        );
+        assert_matches!(messages[1].payload, ResponsePayload::Pong { .. });
+        assert_matches!(messages[2].payload, ResponsePayload::Pong { .. });
+    }
+
+    #[tokio::test]
+    async fn test_handle_get_store_info_reports_schema_versions() {
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let dir = tempdir().unwrap();
+        let jobs_path = dir.path().join("jobs.json");
+        let usage_path = dir.path().join("usage.json");
+        let (jobs, _) = JobRegistry::open(jobs_path.clone()).unwrap();
+        let (usage, _) = UsageTracker::open(usage_path.clone()).unwrap();
+        let state = HostState::new()
+            .with_jobs(jobs)
+            .with_usage(usage)
+            .with_settings(HostSettings {
+                fabric_path: Some(dir.path().join("missing-fabric")),
+                ..HostSettings::default()
+            });
+        let request = Request {
+            id: Uuid::new_v4(),
+            path: None,
+            validate_only: false,
+            payload: RequestPayload::GetStoreInfo,
+        };
+        handle_request(
+            &mut writer,
+            request,
+            |_| MockCommandRunner::default(),
+            &state,
+        )
+        .await
+        .unwrap();
+
+        let messages = messages.lock().unwrap();
+        assert_eq!(
+            messages.last().unwrap().payload,
+            ResponsePayload::StoreInfo {
+                stores: vec![
+                    crate::StoreInfo {
+                        name: "jobs".to_string(),
+                        path: Some(jobs_path),
+                        version: Some(jobs::JOURNAL_SCHEMA.version()),
+                        supported_version: jobs::JOURNAL_SCHEMA.version(),
+                    },
+                    crate::StoreInfo {
+                        name: "usage".to_string(),
+                        path: Some(usage_path),
+                        version: None,
+                        supported_version: usage::JOURNAL_SCHEMA.version(),
+                    },
+                ],
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_debug_dump_reports_state_without_fabric() {
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("config.toml");
+        std::fs::write(&config_path, "[defaults]\nmodel = \"gpt-4o\"\n").unwrap();
+        let state = HostState::new()
+            .with_secrets(MemorySecretStore::default())
+            .with_config_path(Some(config_path.clone()))
+            .with_settings(HostSettings {
+                fabric_path: Some(dir.path().join("missing-fabric")),
+                ..HostSettings::default()
+            });
+        let failed_id = Uuid::new_v4();
+        for (id, payload) in [
+            (
+                failed_id,
+                RequestPayload::DeleteSecret {
+                    name: "openai".to_string(),
+                },
+            ),
+            (Uuid::new_v4(), RequestPayload::DebugDump),
+        ] {
+            let request = Request {
+                id,
+                path: None,
+                validate_only: false,
+                payload,
+            };
+            let _ = handle_request(
+                &mut writer,
+                request,
+                |_| MockCommandRunner::default(),
+                &state,
+            )
+            .await;
+        }
+
+        let messages = messages.lock().unwrap();
+        let Some(ResponsePayload::DebugState(dump)) = messages.last().map(|m| &m.payload) else {
+            panic!("expected debug state, got {messages:?}");
+        };
+        assert_eq!(dump.fabric_path, None);
+        assert!(dump.fabric_error.is_some());
+        assert_eq!(dump.config_path.as_ref(), Some(&config_path));
+        assert_eq!(
+            dump.config,
+            Some(serde_json::json!({"defaults": {"model": "gpt-4o"}}))
+        );
+        assert_eq!(dump.config_error, None);
+        assert_eq!(dump.caches, CacheStates::default());
+        assert_eq!(dump.recent_errors.len(), 1);
+        assert_eq!(dump.recent_errors[0].request_id, failed_id);
+        assert_eq!(dump.recent_errors[0].code, "secretNotFound");
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_enforces_daily_request_budget() {
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let dir = tempdir().unwrap();
+        let state = HostState::new().with_settings(HostSettings {
+            fabric_path: Some(fake_fabric(&dir)),
+            budget: Budget {
+                daily_requests: Some(2),
+                daily_tokens: None,
+            },
+            ..HostSettings::default()
+        });
+        let item = BatchContent {
+            content: "tab".to_string(),
+            pattern: Some("summarize".to_string()),
+            model: None,
+        };
+        let request = Request {
+            id: Uuid::new_v4(),
+            path: None,
+            validate_only: false,
+            payload: RequestPayload::ProcessBatch {
+                items: vec![item.clone(), item.clone(), item],
+            },
+        };
+
+        let result = handle_request(
+            &mut writer,
+            request,
+            |_| MockCommandRunner::default(),
+            &state,
+        )
+        .await;
+        assert_matches!(
+            result,
+            Err(HandlerError::QuotaExceeded(QuotaExceeded {
+                quota: Quota::Requests,
+                limit: 2
+            }))
+        );
+        assert_eq!(state.usage.today().requests, 0);
+
+        let messages = messages.lock().unwrap();
+        assert_matches!(
+            &messages[0].payload,
+            ResponsePayload::Error { message, details: Some(ErrorDetails { code, .. }) }
+                if code == "quotaExceeded" && message == "Daily request budget of 2 is used up"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pin_fabric_checksum_only_pins_configured_fabric() {
+        let dir = tempdir().unwrap();
+        let fabric = fake_fabric(&dir);
+        let other_dir = tempdir().unwrap();
+        let other = fake_fabric(&other_dir);
+        std::fs::write(&other, "#!/bin/sh\necho other\n").unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, format!("fabric_path = {fabric:?}\n")).unwrap();
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let loader_path = path.clone();
+        let load = move || {
+            Config::load(&loader_path).map(|config| HostSettings {
+                fabric_path: config.fabric_path,
+                fabric_sha256: config.fabric_sha256,
+                ..Default::default()
+            })
+        };
+        let state = HostState::new()
+            .with_settings(load().unwrap())
+            .with_config_path(Some(path.clone()))
+            .with_settings_loader(Box::new(load));
+        let pin = Request {
+            id: Uuid::new_v4(),
+            path: Some(other.clone()),
+            validate_only: false,
+            payload: RequestPayload::PinFabricChecksum,
+        };
+
+        handle_request(
+            &mut writer,
+            pin.clone(),
+            |_| MockCommandRunner::default(),
+            &state,
+        )
+        .await
+        .unwrap();
+        let sha256 = integrity::sha256_file(&fabric).unwrap();
+        assert_eq!(
+            Config::load(&path).unwrap().fabric_sha256,
+            Some(sha256.clone())
+        );
+
+        std::fs::write(&path, format!("fabric_path = {other:?}\n")).unwrap();
+        let result =
+            handle_request(&mut writer, pin, |_| MockCommandRunner::default(), &state).await;
+        assert_matches!(result, Err(HandlerError::ConfigFile(_)));
+        assert_eq!(Config::load(&path).unwrap().fabric_sha256, None);
+
+        let messages = messages.lock().unwrap();
+        assert_eq!(
+            messages[0].payload,
+            ResponsePayload::FabricChecksum {
+                path: fabric,
+                sha256
+            }
+        );
+        assert_matches!(
+            &messages[1].payload,
+            ResponsePayload::Error { message, details: Some(ErrorDetails { code, .. }) }
+                if code == "configFileError" && message.contains("no longer sets fabric_path")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_verifies_pinned_fabric_checksum() {
+        let dir = tempdir().unwrap();
+        let fabric = fake_fabric(&dir);
+        let path = dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            format!("fabric_path = {fabric:?}\nfabric_sha256 = \"00\"\n"),
+        )
+        .unwrap();
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let loader_path = path.clone();
+        let load = move || {
+            Config::load(&loader_path).map(|config| HostSettings {
+                fabric_path: config.fabric_path,
+                fabric_sha256: config.fabric_sha256,
+                ..Default::default()
+            })
+        };
+        let state = HostState::new()
+            .with_settings(load().unwrap())
+            .with_config_path(Some(path.clone()))
+            .with_settings_loader(Box::new(load));
+        let request = |payload| Request {
+            id: Uuid::new_v4(),
+            path: None,
+            validate_only: false,
+            payload,
+        };
+
+        let runner = MockCommandRunner::default();
+        let spawned_args = runner.spawned_args.clone();
+        let result = handle_request(
+            &mut writer,
+            request(RequestPayload::ListContexts),
+            |_| runner,
+            &state,
+        )
+        .await;
+        assert_matches!(result, Err(HandlerError::IntegrityMismatch { expected, .. }) if expected == "00");
+        assert!(spawned_args.lock().await.is_empty());
+
+        handle_request(
+            &mut writer,
+            request(RequestPayload::PinFabricChecksum),
+            |_| MockCommandRunner::default(),
+            &state,
+        )
+        .await
+        .unwrap();
+        let sha256 = integrity::sha256_file(&fabric).unwrap();
+        assert_eq!(
+            Config::load(&path).unwrap().fabric_sha256,
+            Some(sha256.clone())
+        );
+        assert_eq!(state.fabric_sha256(), Some(sha256.clone()));
+
+        let runner = MockCommandRunner::default().with_contexts_response(CommandOutput {
+            status: true,
+            stdout: "work\n".to_string(),
+            stderr: String::new(),
+        });
+        handle_request(
+            &mut writer,
+            request(RequestPayload::ListContexts),
+            |_| runner,
+            &state,
+        )
+        .await
+        .unwrap();
+
+        let messages = messages.lock().unwrap();
+        assert_matches!(
+            &messages[0].payload,
+            ResponsePayload::Error { details: Some(ErrorDetails { code, .. }), .. }
+                if code == "integrityMismatch"
+        );
+        assert_eq!(
+            messages[1].payload,
+            ResponsePayload::FabricChecksum {
+                path: fabric,
+                sha256
             }
-        }
+        );
+        assert_matches!(&messages[2].payload, ResponsePayload::ContextsList { .. });
     }
 
-    impl MockCommandRunner {
-        fn with_version_response(mut self, output: CommandOutput) -> Self {
-            self.version_response = Some(output);
-            self
-        }
+    #[tokio::test]
+    async fn test_plan_process_applies_request_env() {
+        let runner = MockCommandRunner::default();
+        let options = ProcessOptions {
+            env: BTreeMap::from([("OLLAMA_HOST".to_string(), "http://gpu:11434".to_string())]),
+            ..Default::default()
+        };
 
-        fn with_patterns_response(mut self, output: CommandOutput) -> Self {
-            self.patterns_response = Some(output);
-            self
-        }
+        let plan = plan_process(&runner, &options, "content").await.unwrap();
+        let command = plan.builder.build();
 
-        fn with_contexts_response(mut self, output: CommandOutput) -> Self {
-            self.contexts_response = Some(output);
-            self
-        }
+        assert!(command.as_std().get_envs().any(|(name, value)| {
+            name == "OLLAMA_HOST" && value == Some(std::ffi::OsStr::new("http://gpu:11434"))
+        }));
+    }
 
-        async fn with_process_handle(self, handle: MockProcessHandle) -> Self {
-            self.process_handles.lock().await.push(handle);
-            self
-        }
+    #[tokio::test]
+    async fn test_handle_request_rejects_invalid_fields() {
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let request = Request {
+            id: Uuid::new_v4(),
+            path: None,
+            validate_only: false,
+            payload: RequestPayload::ProcessContent {
+                content: String::new().into(),
+                model: None,
+                pattern: Some("summarize".to_string()),
+                context: None,
+                custom_prompt: Some("Summarize".to_string()),
+                system_prompt: None,
+                locale: None,
+                detach: false,
+                aggregate: false,
+                summarize: false,
+                summary_model: None,
+                output_format: OutputFormat::Markdown,
+                deterministic: false,
+                quick_look: None,
+                selection: None,
+                composition: None,
+                stream_mode: StreamMode::Lines,
+                stream_flush_ms: None,
+                stream_chunk_bytes: None,
+                strict: None,
+                structured: false,
+                timeout_ms: None,
+                heartbeat_ms: None,
+                cwd: None,
+                env: None,
+            },
+        };
+
+        let state = HostState::new();
+        let result = handle_request(
+            &mut writer,
+            request,
+            |_| MockCommandRunner::default().with_fabric_path("/usr/bin/fabric-ai"),
+            &state,
+        )
+        .await;
+        assert!(result.is_ok());
+
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_matches!(
+            &messages[0].payload,
+            ResponsePayload::Error {
+                message,
+                details: Some(ErrorDetails { code, fields, .. }),
+            } if code == "invalidRequest"
+                && message == "Invalid request: content, pattern, custom_prompt"
+                && fields.len() == 3
+        );
     }
 
-    #[async_trait]
-    impl CommandRunner for MockCommandRunner {
-        async fn fabric_version(&self) -> Result<CommandOutput, HandlerError> {
-            use std::io;
-            self.version_response
-                .clone()
-                .ok_or_else(|| HandlerError::Io(io::Error::other("No mock response")))
-        }
+    #[tokio::test]
+    async fn test_handle_request_validate_only_has_no_side_effects() {
+        let dir = tempdir().unwrap();
+        let fabric_path = fake_fabric(&dir);
 
-        async fn list_patterns(&self) -> Result<CommandOutput, HandlerError> {
-            use std::io;
-            self.patterns_response
-                .clone()
-                .ok_or_else(|| HandlerError::Io(io::Error::other("No mock response")))
-        }
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
 
-        async fn list_contexts(&self) -> Result<CommandOutput, HandlerError> {
-            use std::io;
-            self.contexts_response
-                .clone()
-                .ok_or_else(|| HandlerError::Io(io::Error::other("No mock response")))
-        }
+        let request = Request {
+            id: Uuid::new_v4(),
+            path: Some(fabric_path.to_path_buf()),
+            validate_only: true,
+            payload: RequestPayload::ProcessContent {
+                content: "x".repeat(40).into(),
+                model: Some("gpt-4o".to_string()),
+                pattern: Some("summarize".to_string()),
+                context: None,
+                custom_prompt: None,
+                system_prompt: None,
+                locale: None,
+                detach: true,
+                aggregate: false,
+                summarize: false,
+                summary_model: None,
+                output_format: OutputFormat::Markdown,
+                deterministic: false,
+                quick_look: None,
+                selection: None,
+                composition: None,
+                stream_mode: StreamMode::Lines,
+                stream_flush_ms: None,
+                stream_chunk_bytes: None,
+                strict: None,
+                structured: false,
+                timeout_ms: None,
+                heartbeat_ms: None,
+                cwd: None,
+                env: None,
+            },
+        };
+
+        let runner = MockCommandRunner::default().with_fabric_path("/usr/bin/fabric-ai");
+        let spawned_args = runner.spawned_args.clone();
+        let state = HostState::new();
+        let result = handle_request(&mut writer, request, move |_| runner, &state).await;
+        assert!(result.is_ok());
 
-        async fn fabric_path(&self) -> Result<&Utf8Path, HandlerError> {
-            Ok(&self.fabric_path)
+        assert_eq!(
+            messages.lock().unwrap()[0].payload,
+            ResponsePayload::ValidationResult {
+                backend: "fabric".to_string(),
+                command: Some(vec![
+                    "/usr/bin/fabric-ai".to_string(),
+                    "--stream".to_string(),
+                    "--model".to_string(),
+                    "gpt-4o".to_string(),
+                    "--pattern".to_string(),
+                    "summarize".to_string(),
+                ]),
+                estimated_prompt_tokens: Some(10),
+                warnings: Vec::new(),
+            }
+        );
+        assert_eq!(messages.lock().unwrap().len(), 1);
+        assert!(spawned_args.lock().await.is_empty());
+        assert!(state.jobs.list(None).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_detached_process_result_can_be_fetched_later() {
+        let process_handle = MockProcessHandle::new(
+            vec!["line 1\n".to_string(), "line 2\n".to_string()],
+            Some(0),
+        );
+        let runner = MockCommandRunner::default()
+            .with_process_handle(process_handle)
+            .await;
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let state = HostState::new();
+        let options = ProcessOptions {
+            pattern: Some("summarize".to_string()),
+            ..ProcessOptions::default()
+        };
+        handle_detached_process(
+            &mut writer,
+            Uuid::new_v4(),
+            runner,
+            options,
+            "content".to_string(),
+            &state,
+        )
+        .await
+        .unwrap();
+
+        let job_id = match &messages.lock().unwrap()[0].payload {
+            ResponsePayload::JobAccepted { job_id } => *job_id,
+            other => panic!("Expected JobAccepted, got {other:?}"),
+        };
+
+        while state.jobs.get(job_id).unwrap().state == JobState::Running {
+            tokio::task::yield_now().await;
         }
 
-        async fn spawn_process(
-            &self,
-            _builder: FabricCommandBuilder<'_>,
-        ) -> Result<Box<dyn ProcessHandle>, HandlerError> {
-            use std::io;
-            let mut handles = self.process_handles.lock().await;
-            if let Some(handle) = handles.pop() {
-                Ok(Box::new(handle))
-            } else {
-                Err(HandlerError::Io(io::Error::other(
-                    "No mock process handle available",
-                )))
-            }
+        handle_get_job_result(&mut writer, Uuid::new_v4(), job_id, &state)
+            .await
+            .unwrap();
+
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_matches!(
+            &messages[1].payload,
+            ResponsePayload::JobResult {
+                state: JobState::Completed,
+                content,
+                exit_code: Some(0),
+                error: None,
+                ..
+            } if content == "line 1\nline 2\n"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cancel_and_delete_detached_job() {
+        let process_handle = MockProcessHandle::new(vec!["slow line\n".to_string()], Some(0))
+            .with_line_delay(Duration::from_secs(60));
+        let runner = MockCommandRunner::default()
+            .with_process_handle(process_handle)
+            .await;
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let state = HostState::new();
+        handle_detached_process(
+            &mut writer,
+            Uuid::new_v4(),
+            runner,
+            ProcessOptions::default(),
+            "content".to_string(),
+            &state,
+        )
+        .await
+        .unwrap();
+
+        let job_id = match &messages.lock().unwrap()[0].payload {
+            ResponsePayload::JobAccepted { job_id } => *job_id,
+            other => panic!("Expected JobAccepted, got {other:?}"),
+        };
+
+        handle_delete_job(&mut writer, Uuid::new_v4(), job_id, &state)
+            .await
+            .unwrap();
+        handle_cancel_job(&mut writer, Uuid::new_v4(), job_id, &state)
+            .await
+            .unwrap();
+
+        while state.jobs.get(job_id).unwrap().state == JobState::Running {
+            tokio::task::yield_now().await;
         }
+        assert_eq!(state.jobs.list(Some(JobState::Cancelled))[0].job_id, job_id);
+
+        handle_cancel_job(&mut writer, Uuid::new_v4(), job_id, &state)
+            .await
+            .unwrap();
+        handle_delete_job(&mut writer, Uuid::new_v4(), job_id, &state)
+            .await
+            .unwrap();
+        handle_delete_job(&mut writer, Uuid::new_v4(), job_id, &state)
+            .await
+            .unwrap();
+
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages.len(), 6);
+        assert_matches!(&messages[1].payload, ResponsePayload::Error { message, .. } if message.contains("still running"));
+        assert_matches!(&messages[2].payload, ResponsePayload::Cancelled { request_id } if *request_id == job_id);
+        assert_matches!(&messages[3].payload, ResponsePayload::Error { message, .. } if message.contains("already finished"));
+        assert_matches!(&messages[4].payload, ResponsePayload::JobDeleted { job_id: deleted } if *deleted == job_id);
+        assert_matches!(&messages[5].payload, ResponsePayload::Error { message, .. } if message.contains("not found"));
     }
 
-    struct MockProcessHandle {
-        stdin_data: Arc<TokioMutex<Vec<u8>>>,
-        stdout_lines: Arc<TokioMutex<Vec<String>>>,
-        exit_code: Option<i32>,
-        stdin_error: Option<io::Error>,
-        stdout_error: Option<io::Error>,
-        wait_error: Option<io::Error>,
+    #[tokio::test]
+    async fn test_handle_process_content_aggregates_output_in_done() {
+        let process_handle = MockProcessHandle::new(
+            vec!["Total: 1,234.5\n".to_string(), "Done\n".to_string()],
+            Some(0),
+        );
+        let runner = MockCommandRunner::default()
+            .with_process_handle(process_handle)
+            .await;
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let options = ProcessOptions {
+            locale: Some("de-DE".to_string()),
+            aggregate: true,
+            ..Default::default()
+        };
+        let state = HostState::new();
+        handle_process_content(
+            &mut writer,
+            Uuid::new_v4(),
+            &runner,
+            options,
+            "content".to_string(),
+            &state,
+        )
+        .await
+        .unwrap();
+
+        let messages = messages.lock().unwrap();
+        assert_matches!(
+            &messages.last().unwrap().payload,
+            ResponsePayload::Done { content: Some(content), .. } if content == "Total: 1.234,5\nDone\n"
+        );
     }
 
-    impl MockProcessHandle {
-        fn new(stdout_lines: Vec<String>, exit_code: Option<i32>) -> Self {
-            Self {
-                stdin_data: Arc::new(TokioMutex::new(Vec::new())),
-                stdout_lines: Arc::new(TokioMutex::new(stdout_lines)),
-                exit_code,
-                stdin_error: None,
-                stdout_error: None,
-                wait_error: None,
-            }
-        }
+    #[tokio::test]
+    async fn test_handle_process_content_truncates_output_at_cap() {
+        let process_handle = MockProcessHandle::new(
+            vec!["hello wörld\n".to_string(), "more\n".to_string()],
+            Some(0),
+        );
+        let unread = process_handle.stdout_lines.clone();
+        let killed = process_handle.killed.clone();
+        let runner = MockCommandRunner::default()
+            .with_process_handle(process_handle)
+            .await;
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let options = ProcessOptions {
+            aggregate: true,
+            max_output_bytes: Some(8),
+            ..Default::default()
+        };
+        handle_process_content(
+            &mut writer,
+            Uuid::new_v4(),
+            &runner,
+            options,
+            "content".to_string(),
+            &HostState::new(),
+        )
+        .await
+        .unwrap();
+
+        assert!(killed.load(Ordering::Relaxed));
+        assert_eq!(*unread.lock().await, vec!["more\n"]);
+        let messages = messages.lock().unwrap();
+        let content: Vec<_> = messages
+            .iter()
+            .filter_map(|message| match &message.payload {
+                ResponsePayload::Content { content } => Some(content.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(content, vec!["hello w"]);
+        assert!(messages.iter().any(|message| matches!(
+            &message.payload,
+            ResponsePayload::Warning { message } if message.contains("8 byte cap")
+        )));
+        assert_matches!(
+            &messages.last().unwrap().payload,
+            ResponsePayload::Done { content: Some(content), truncated: true, exit_code: Some(0), .. } if content == "hello w"
+        );
     }
 
-    #[async_trait]
-    impl ProcessHandle for MockProcessHandle {
-        async fn write_stdin(&mut self, data: &[u8]) -> Result<(), HandlerError> {
-            if let Some(error) = &self.stdin_error {
-                return Err(HandlerError::Io(io::Error::new(
-                    error.kind(),
-                    "Mock stdin error",
-                )));
-            }
-            self.stdin_data.lock().await.extend_from_slice(data);
-            Ok(())
-        }
+    #[tokio::test]
+    async fn test_strict_mode_rejects_truncated_output() {
+        let runner = MockCommandRunner::default()
+            .with_process_handle(MockProcessHandle::new(
+                vec!["hello wörld\n".to_string(), "more\n".to_string()],
+                Some(0),
+            ))
+            .await;
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let options = ProcessOptions {
+            max_output_bytes: Some(8),
+            strict: true,
+            ..Default::default()
+        };
+        let result = handle_process_content(
+            &mut writer,
+            Uuid::new_v4(),
+            &runner,
+            options,
+            "content".to_string(),
+            &HostState::new(),
+        )
+        .await;
+
+        assert_matches!(result, Err(HandlerError::Strict(_)));
+        let messages = messages.lock().unwrap();
+        assert!(!messages.iter().any(|message| matches!(
+            &message.payload,
+            ResponsePayload::Warning { .. } | ResponsePayload::Done { .. }
+        )));
+        assert_matches!(
+            &messages.last().unwrap().payload,
+            ResponsePayload::Error { details: Some(details), .. } if details.code == "strict"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_process_content_fits_aggregated_done_in_one_frame() {
+        let line = format!("{}\n", "é\"".repeat(30_000));
+        let runner = MockCommandRunner::default()
+            .with_process_handle(MockProcessHandle::new(vec![line; 16], Some(0)))
+            .await;
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let options = ProcessOptions {
+            aggregate: true,
+            max_frame_bytes: Some(crate::codec::MAX_OUTBOUND_MESSAGE_SIZE),
+            ..Default::default()
+        };
+        handle_process_content(
+            &mut writer,
+            Uuid::new_v4(),
+            &runner,
+            options,
+            "content".to_string(),
+            &HostState::new(),
+        )
+        .await
+        .unwrap();
+
+        let done = messages.lock().unwrap().last().unwrap().clone();
+        let ResponsePayload::Done {
+            content: Some(content),
+            truncated,
+            ..
+        } = &done.payload
+        else {
+            panic!("expected an aggregated Done");
+        };
+        assert!(*truncated);
+        assert!(content.len() > 512 * 1024);
+        assert!(content.starts_with("é\"é\""));
+        let mut codec = crate::codec::NativeMessagingCodec::<Response>::default();
+        codec.encode(done, &mut BytesMut::new()).unwrap();
+    }
+
+    async fn structured_messages(lines: &[&str]) -> (Result<(), HandlerError>, Vec<Response>) {
+        let runner = MockCommandRunner::default()
+            .with_process_handle(MockProcessHandle::new(
+                lines.iter().map(|line| line.to_string()).collect(),
+                Some(0),
+            ))
+            .await;
+
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
 
-        async fn close_stdin(&mut self) -> Result<(), HandlerError> {
-            if let Some(error) = &self.stdin_error {
-                return Err(HandlerError::Io(io::Error::new(
-                    error.kind(),
-                    "Mock stdin close error",
-                )));
-            }
-            Ok(())
-        }
+        let options = ProcessOptions {
+            output_format: OutputFormat::Json,
+            structured: true,
+            max_frame_bytes: Some(crate::codec::MAX_OUTBOUND_MESSAGE_SIZE),
+            ..Default::default()
+        };
+        let result = handle_process_content(
+            &mut writer,
+            Uuid::new_v4(),
+            &runner,
+            options,
+            "content".to_string(),
+            &HostState::new(),
+        )
+        .await;
 
-        async fn read_stdout_line(&mut self) -> Result<Option<String>, HandlerError> {
-            if let Some(error) = &self.stdout_error {
-                return Err(HandlerError::Io(io::Error::new(
-                    error.kind(),
-                    "Mock stdout error",
-                )));
-            }
-            let mut lines = self.stdout_lines.lock().await;
-            if lines.is_empty() {
-                Ok(None)
-            } else {
-                Ok(Some(lines.remove(0)))
-            }
-        }
+        let messages = messages.lock().unwrap().clone();
+        (result, messages)
+    }
 
-        async fn wait(self: Box<Self>) -> Result<Option<i32>, HandlerError> {
-            if let Some(error) = &self.wait_error {
-                return Err(HandlerError::Io(io::Error::new(
-                    error.kind(),
-                    "Mock wait error",
-                )));
-            }
-            Ok(self.exit_code)
-        }
+    #[tokio::test]
+    async fn test_structured_output_is_returned_as_json() {
+        let (result, messages) = structured_messages(&[
+            "```json\n",
+            "{\"ideas\": [\"one\",\n",
+            "\"two\"]}\n",
+            "```\n",
+        ])
+        .await;
 
-        async fn kill(&mut self) -> Result<(), HandlerError> {
-            Ok(())
-        }
+        assert!(result.is_ok());
+        assert!(
+            !messages
+                .iter()
+                .any(|message| matches!(message.payload, ResponsePayload::Content { .. }))
+        );
+        let payloads: Vec<_> = messages.iter().map(|message| &message.payload).collect();
+        assert_matches!(
+            payloads[payloads.len() - 2],
+            ResponsePayload::Json { value } if *value == serde_json::json!({"ideas": ["one", "two"]})
+        );
+        assert_matches!(
+            payloads[payloads.len() - 1],
+            ResponsePayload::Done { content: None, .. }
+        );
     }
 
-    struct TestWriter {
-        messages: Arc<Mutex<Vec<Response>>>,
+    #[tokio::test]
+    async fn test_structured_output_reports_invalid_json() {
+        let (result, messages) = structured_messages(&["Here are the ideas:\n", "- one\n"]).await;
+
+        assert_matches!(result, Err(HandlerError::InvalidJson(_)));
+        assert_matches!(
+            &messages.last().unwrap().payload,
+            ResponsePayload::Error { message, details: Some(ErrorDetails { code, .. }) }
+                if message.starts_with("Pattern output is not valid JSON") && code == "invalidJson"
+        );
+        assert!(
+            !messages
+                .iter()
+                .any(|message| matches!(message.payload, ResponsePayload::Done { .. }))
+        );
     }
 
-    impl TestWriter {
-        fn new() -> Self {
-            Self {
-                messages: Arc::new(Mutex::new(Vec::new())),
-            }
-        }
+    #[tokio::test]
+    async fn test_structured_output_reports_json_over_frame_limit() {
+        let line = format!("{{\"text\": \"{}\"}}\n", "a".repeat(1_100_000));
+        let (result, messages) = structured_messages(&[&line]).await;
+
+        assert_matches!(
+            result,
+            Err(HandlerError::ResponseTooLarge { size, limit })
+                if size > 1_100_000 && limit == crate::codec::MAX_OUTBOUND_MESSAGE_SIZE
+        );
+        assert_matches!(
+            &messages.last().unwrap().payload,
+            ResponsePayload::Error { details: Some(ErrorDetails { code, .. }), .. }
+                if code == "responseTooLarge"
+        );
+        assert!(
+            !messages
+                .iter()
+                .any(|message| matches!(message.payload, ResponsePayload::Json { .. }))
+        );
     }
 
-    impl AsyncWrite for TestWriter {
-        fn poll_write(
-            self: Pin<&mut Self>,
-            _cx: &mut Context<'_>,
-            _buf: &[u8],
-        ) -> Poll<Result<usize, io::Error>> {
-            Poll::Ready(Ok(0))
-        }
+    #[tokio::test]
+    async fn test_handle_process_content_summarizes_long_output() {
+        let long_line = format!("{}\n", "a".repeat(SUMMARY_THRESHOLD_CHARS as usize));
+        let runner = MockCommandRunner::default()
+            .with_process_handle(MockProcessHandle::new(
+                vec!["Short version.\n".to_string()],
+                Some(0),
+            ))
+            .await
+            .with_process_handle(MockProcessHandle::new(vec![long_line], Some(0)))
+            .await;
 
-        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
-            Poll::Ready(Ok(()))
-        }
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
 
-        fn poll_shutdown(
-            self: Pin<&mut Self>,
-            _cx: &mut Context<'_>,
-        ) -> Poll<Result<(), io::Error>> {
-            Poll::Ready(Ok(()))
-        }
-    }
+        let options = ProcessOptions {
+            summarize: true,
+            summary_model: Some("gpt-4o-mini".to_string()),
+            ..Default::default()
+        };
+        let state = HostState::new();
+        handle_process_content(
+            &mut writer,
+            Uuid::new_v4(),
+            &runner,
+            options,
+            "content".to_string(),
+            &state,
+        )
+        .await
+        .unwrap();
+
+        let payload = messages.lock().unwrap().last().unwrap().payload.clone();
+        let ResponsePayload::Done {
+            content: None,
+            summary: Some(summary),
+            usage: Some(usage),
+            ..
+        } = payload
+        else {
+            panic!("Expected Done with a summary, got {payload:?}");
+        };
+        assert_eq!(summary, "Short version.");
+        assert!(
+            state.usage.today().tokens() > usage.prompt_tokens + usage.completion_tokens,
+            "summary tokens are recorded too"
+        );
 
-    struct TestEncoder {
-        messages: Arc<Mutex<Vec<Response>>>,
+        let spawned_args = runner.spawned_args.lock().await;
+        assert_eq!(spawned_args.len(), 2);
+        assert_eq!(
+            spawned_args[1],
+            vec!["--model", "gpt-4o-mini", "--", SUMMARY_PROMPT]
+        );
     }
 
-    impl TestEncoder {
-        fn new(messages: Arc<Mutex<Vec<Response>>>) -> Self {
-            Self { messages }
-        }
-    }
+    #[tokio::test(start_paused = true)]
+    async fn test_summary_run_is_held_to_the_request_timeout() {
+        let long_line = format!("{}\n", "a".repeat(SUMMARY_THRESHOLD_CHARS as usize));
+        let runner = MockCommandRunner::default()
+            .with_process_handle(
+                MockProcessHandle::new(vec!["Too late.\n".to_string()], Some(0))
+                    .with_line_delay(Duration::from_secs(60)),
+            )
+            .await
+            .with_process_handle(MockProcessHandle::new(vec![long_line], Some(0)))
+            .await;
 
-    impl Encoder<Response> for TestEncoder {
-        type Error = io::Error;
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
 
-        fn encode(&mut self, item: Response, _dst: &mut BytesMut) -> Result<(), Self::Error> {
-            self.messages.lock().unwrap().push(item);
-            Ok(())
-        }
+        let options = ProcessOptions {
+            summarize: true,
+            timeout: Some(Duration::from_secs(5)),
+            ..Default::default()
+        };
+        handle_process_content(
+            &mut writer,
+            Uuid::new_v4(),
+            &runner,
+            options,
+            "content".to_string(),
+            &HostState::new(),
+        )
+        .await
+        .unwrap();
+
+        let messages = messages.lock().unwrap();
+        assert!(messages.iter().any(|message| matches!(
+            &message.payload,
+            ResponsePayload::Warning { message } if message.starts_with("Could not summarize output: Process timed out")
+        )));
+        assert_matches!(
+            &messages.last().unwrap().payload,
+            ResponsePayload::Done { summary: None, .. }
+        );
     }
 
-    #[tokio::test]
-    async fn test_resolve_path_with_existing_file() {
-        let dir = tempdir().unwrap();
-        let file_path = dir.child("fabric-ai");
-        file_path.touch().unwrap();
+    #[tokio::test(start_paused = true)]
+    async fn test_summary_run_can_be_cancelled() {
+        let long_line = format!("{}\n", "a".repeat(SUMMARY_THRESHOLD_CHARS as usize));
+        let runner = MockCommandRunner::default()
+            .with_process_handle(
+                MockProcessHandle::new(vec!["Too late.\n".to_string()], Some(0))
+                    .with_line_delay(Duration::from_secs(60)),
+            )
+            .await
+            .with_process_handle(MockProcessHandle::new(vec![long_line], Some(0)))
+            .await;
 
-        let utf8_path = file_path.as_path().to_owned();
-        let result = resolve_path(Some(&utf8_path));
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
+
+        let state = HostState::new();
+        let request_id = Uuid::new_v4();
+        let cancel = async {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            let registry = state.process_registry.lock().await;
+            registry.get(&request_id).unwrap().send(true).unwrap();
+        };
+        let options = ProcessOptions {
+            summarize: true,
+            ..Default::default()
+        };
+        let (result, ()) = tokio::join!(
+            handle_process_content(
+                &mut writer,
+                request_id,
+                &runner,
+                options,
+                "content".to_string(),
+                &state,
+            ),
+            cancel
+        );
 
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), utf8_path);
+        assert!(state.process_slots.semaphore.try_acquire().is_ok());
+        assert!(
+            !messages
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|message| matches!(message.payload, ResponsePayload::Done { .. }))
+        );
     }
 
     #[tokio::test]
-    async fn test_resolve_path_with_non_existing_file() {
-        let path = Utf8PathBuf::from("/non/existing/path/fabric-ai");
-        let result = resolve_path(Some(&path));
+    async fn test_replay_request_reemits_buffered_output() {
+        let process_handle = MockProcessHandle::new(
+            vec!["line 1\n".to_string(), "line 2\n".to_string()],
+            Some(0),
+        );
+        let runner = MockCommandRunner::default()
+            .with_process_handle(process_handle)
+            .await;
 
-        assert!(result.is_err() || result.unwrap() != path);
-    }
+        let test_writer = TestWriter::new();
+        let messages = test_writer.messages.clone();
+        let encoder = TestEncoder::new(messages.clone());
+        let mut writer = FramedWrite::new(test_writer, encoder);
 
-    #[tokio::test]
-    async fn test_resolve_path_with_no_path() {
-        let result = resolve_path::<Utf8PathBuf>(None);
+        let state = HostState::new();
+        let request_id = Uuid::new_v4();
+        handle_process_content(
+            &mut writer,
+            request_id,
+            &runner,
+            ProcessOptions::default(),
+            "content".to_string(),
+            &state,
+        )
+        .await
+        .unwrap();
 
-        assert!(result.is_err() || result.unwrap().to_string().contains("fabric-ai"));
+        let replay_id = Uuid::new_v4();
+        handle_replay_request(&mut writer, replay_id, request_id, &state)
+            .await
+            .unwrap();
+        handle_replay_request(&mut writer, replay_id, Uuid::new_v4(), &state)
+            .await
+            .unwrap();
+
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages.len(), 9);
+        let (original, rest) = messages.split_at(4);
+        let (replayed, unknown) = rest.split_at(4);
+        for (original, replayed) in original.iter().zip(replayed) {
+            assert_eq!(replayed.id, replay_id);
+            assert_eq!(replayed.payload, original.payload);
+        }
+        assert_matches!(&unknown[0].payload, ResponsePayload::Error { message, .. } if message.contains("No buffered output"));
     }
 
     #[tokio::test]
-    async fn test_handle_ping_success() {
-        let dir = tempdir().unwrap();
-        let file_path = dir.child("fabric-ai");
-        file_path.touch().unwrap();
-
-        let runner = MockCommandRunner::default().with_version_response(CommandOutput {
-            status: true,
-            stdout: "fabric-ai version 1.0.0".to_string(),
-            stderr: String::new(),
-        });
+    async fn test_open_in_editor_writes_result_to_temp_file() {
+        let process_handle = MockProcessHandle::new(
+            vec!["line 1\n".to_string(), "line 2\n".to_string()],
+            Some(0),
+        );
+        let runner = MockCommandRunner::default()
+            .with_process_handle(process_handle)
+            .await;
 
         let test_writer = TestWriter::new();
         let messages = test_writer.messages.clone();
         let encoder = TestEncoder::new(messages.clone());
         let mut writer = FramedWrite::new(test_writer, encoder);
 
+        let state = HostState::new();
         let request_id = Uuid::new_v4();
-        let result = handle_ping(&mut writer, request_id, &runner).await;
-        assert!(result.is_ok());
+        handle_process_content(
+            &mut writer,
+            request_id,
+            &runner,
+            ProcessOptions::default(),
+            "content".to_string(),
+            &state,
+        )
+        .await
+        .unwrap();
+        messages.lock().unwrap().clear();
+
+        handle_open_in_editor(&mut writer, Uuid::new_v4(), &runner, request_id, &state)
+            .await
+            .unwrap();
+        handle_open_in_editor(&mut writer, Uuid::new_v4(), &runner, Uuid::new_v4(), &state)
+            .await
+            .unwrap();
+
+        let path = runner.opened_paths.lock().unwrap().remove(0);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "line 1\nline 2\n");
+        std::fs::remove_file(&path).unwrap();
 
         let messages = messages.lock().unwrap();
-        assert_eq!(messages.len(), 1);
+        assert_eq!(messages.len(), 2);
+        assert_matches!(
+            &messages[0].payload,
+            ResponsePayload::EditorOpened { request_id: opened, path: opened_path, editor }
+                if *opened == request_id && *opened_path == path.as_str() && editor == "mock-editor"
+        );
+        assert_matches!(&messages[1].payload, ResponsePayload::Error { message, .. } if message.contains("No result available"));
+    }
 
-        if let ResponsePayload::Pong { valid, version, .. } = &messages[0].payload {
-            assert!(valid);
-            assert_eq!(version.as_deref(), Some("fabric-ai version 1.0.0"));
-        } else {
-            panic!("Expected Pong response");
-        }
+    #[test]
+    fn test_content_chunks_are_reassembled_in_order() {
+        let chunks = ChunkRegistry::default();
+        let request_id = Uuid::new_v4();
+
+        assert_matches!(
+            accept_chunk(
+                &chunks,
+                request_id,
+                0,
+                "Hello, ".to_string(),
+                false,
+                usize::MAX
+            ),
+            ResponsePayload::ChunkAccepted {
+                seq: 0,
+                complete: false,
+                ..
+            }
+        );
+        assert!(take_assembled_content(&chunks, request_id).is_none());
+        assert_matches!(
+            accept_chunk(
+                &chunks,
+                request_id,
+                1,
+                "world".to_string(),
+                true,
+                usize::MAX
+            ),
+            ResponsePayload::ChunkAccepted {
+                seq: 1,
+                complete: true,
+                ..
+            }
+        );
+
+        assert_eq!(
+            peek_assembled_content(&chunks, request_id).as_deref(),
+            Some("Hello, world")
+        );
+        assert_eq!(
+            take_assembled_content(&chunks, request_id).as_deref(),
+            Some("Hello, world")
+        );
+        assert!(take_assembled_content(&chunks, request_id).is_none());
+    }
+
+    #[test]
+    fn test_content_chunk_out_of_order_resets_upload() {
+        let chunks = ChunkRegistry::default();
+        let request_id = Uuid::new_v4();
+
+        accept_chunk(
+            &chunks,
+            request_id,
+            0,
+            "first".to_string(),
+            false,
+            usize::MAX,
+        );
+        assert_matches!(
+            accept_chunk(&chunks, request_id, 2, "third".to_string(), true, usize::MAX),
+            ResponsePayload::Error { details: Some(details), .. } if details.code == "invalidChunk"
+        );
+        assert_matches!(
+            accept_chunk(
+                &chunks,
+                request_id,
+                0,
+                "again".to_string(),
+                true,
+                usize::MAX
+            ),
+            ResponsePayload::ChunkAccepted {
+                seq: 0,
+                complete: true,
+                ..
+            }
+        );
+        assert_eq!(
+            take_assembled_content(&chunks, request_id).as_deref(),
+            Some("again")
+        );
     }
 
     #[tokio::test]
-    async fn test_handle_ping_failure() {
+    async fn test_chunked_content_may_exceed_inline_limit() {
         let dir = tempdir().unwrap();
-        let file_path = dir.child("fabric-ai");
-        file_path.touch().unwrap();
-
-        let runner = MockCommandRunner::default().with_version_response(CommandOutput {
-            status: false,
-            stdout: String::new(),
-            stderr: "command not found".to_string(),
-        });
+        let fabric_path = fake_fabric(&dir);
+        let process_handle = MockProcessHandle::new(vec!["done\n".to_string()], Some(0));
+        let stdin_data = process_handle.stdin_data.clone();
+        let runner = MockCommandRunner::default()
+            .with_process_handle(process_handle)
+            .await;
 
         let test_writer = TestWriter::new();
         let messages = test_writer.messages.clone();
         let encoder = TestEncoder::new(messages.clone());
         let mut writer = FramedWrite::new(test_writer, encoder);
-        let request_id = Uuid::new_v4();
 
-        let result = handle_ping(&mut writer, request_id, &runner).await;
-        assert!(result.is_ok());
+        let state = HostState::new();
+        let request_id = Uuid::new_v4();
+        let chunk = "a".repeat(validation::MAX_CONTENT_BYTES / 2);
+        for seq in 0..3 {
+            handle_request(
+                &mut writer,
+                Request {
+                    id: Uuid::new_v4(),
+                    path: Some(fabric_path.clone()),
+                    validate_only: false,
+                    payload: RequestPayload::ContentChunk {
+                        request_id,
+                        seq,
+                        data: chunk.clone(),
+                        last: seq == 2,
+                    },
+                },
+                |_| MockCommandRunner::default(),
+                &state,
+            )
+            .await
+            .unwrap();
+        }
+        handle_request(
+            &mut writer,
+            Request {
+                id: request_id,
+                path: Some(fabric_path),
+                validate_only: false,
+                payload: RequestPayload::ProcessContent {
+                    content: String::new().into(),
+                    model: None,
+                    pattern: Some("summarize".to_string()),
+                    context: None,
+                    custom_prompt: None,
+                    system_prompt: None,
+                    locale: None,
+                    detach: false,
+                    aggregate: false,
+                    summarize: false,
+                    summary_model: None,
+                    output_format: OutputFormat::Markdown,
+                    deterministic: false,
+                    quick_look: None,
+                    selection: None,
+                    composition: None,
+                    stream_mode: StreamMode::Lines,
+                    stream_flush_ms: None,
+                    stream_chunk_bytes: None,
+                    strict: None,
+                    structured: false,
+                    timeout_ms: None,
+                    heartbeat_ms: None,
+                    cwd: None,
+                    env: None,
+                },
+            },
+            move |_| runner,
+            &state,
+        )
+        .await
+        .unwrap();
 
+        assert_eq!(stdin_data.lock().await.len(), chunk.len() * 3);
         let messages = messages.lock().unwrap();
-        assert_eq!(messages.len(), 1);
-
-        if let ResponsePayload::Pong { valid, version, .. } = &messages[0].payload {
-            assert!(!valid);
-            assert!(version.is_none());
-        } else {
-            panic!("Expected Pong response");
-        }
+        assert!(
+            messages[..3]
+                .iter()
+                .all(|message| matches!(message.payload, ResponsePayload::ChunkAccepted { .. }))
+        );
+        assert_matches!(
+            &messages.last().unwrap().payload,
+            ResponsePayload::Done {
+                exit_code: Some(0),
+                ..
+            }
+        );
     }
 
-    #[tokio::test]
-    async fn test_handle_list_patterns_success() {
-        let dir = tempdir().unwrap();
-        let file_path = dir.child("fabric-ai");
-        file_path.touch().unwrap();
+    #[test]
+    fn test_content_chunks_are_held_to_their_cap() {
+        let chunks = ChunkRegistry::default();
+        let request_id = Uuid::new_v4();
 
-        let runner = MockCommandRunner::default().with_patterns_response(CommandOutput {
-            status: true,
-            stdout: "pattern1\npattern2\npattern3\n".to_string(),
-            stderr: String::new(),
-        });
+        accept_chunk(&chunks, request_id, 0, "first".to_string(), false, 8);
+        assert_matches!(
+            accept_chunk(&chunks, request_id, 1, "second".to_string(), true, 8),
+            ResponsePayload::Error { message, details: Some(details) }
+                if details.code == "invalidChunk" && message.ends_with("exceeds 8 bytes")
+        );
+        assert!(take_assembled_content(&chunks, request_id).is_none());
+    }
 
+    #[tokio::test]
+    async fn test_get_job_result_unknown_job() {
         let test_writer = TestWriter::new();
         let messages = test_writer.messages.clone();
         let encoder = TestEncoder::new(messages.clone());
         let mut writer = FramedWrite::new(test_writer, encoder);
-        let request_id = Uuid::new_v4();
 
-        let result = handle_list_patterns(&mut writer, request_id, &runner).await;
-        assert!(result.is_ok());
+        let state = HostState::new();
+        handle_get_job_result(&mut writer, Uuid::new_v4(), Uuid::new_v4(), &state)
+            .await
+            .unwrap();
 
         let messages = messages.lock().unwrap();
-        assert_eq!(messages.len(), 1);
-
-        if let ResponsePayload::PatternsList { patterns } = &messages[0].payload {
-            assert_eq!(patterns, &["pattern1", "pattern2", "pattern3"]);
-        } else {
-            panic!("Expected PatternsList response");
-        }
+        assert_matches!(&messages[0].payload, ResponsePayload::Error { message, .. } if message.contains("not found"));
     }
 
     #[tokio::test]
-    async fn test_handle_list_patterns_failure() {
-        let dir = tempdir().unwrap();
-        let file_path = dir.child("fabric-ai");
-        file_path.touch().unwrap();
-
-        let runner = MockCommandRunner::default().with_patterns_response(CommandOutput {
-            status: false,
-            stdout: String::new(),
-            stderr: "Failed to list patterns".to_string(),
-        });
+    async fn test_handle_process_batch() {
+        let runner = MockCommandRunner::default()
+            .with_process_handle(MockProcessHandle::new(vec!["first\n".to_string()], Some(0)))
+            .await
+            .with_process_handle(MockProcessHandle::new(
+                vec!["second\n".to_string()],
+                Some(0),
+            ))
+            .await;
 
         let test_writer = TestWriter::new();
         let messages = test_writer.messages.clone();
@@ -981,34 +8836,66 @@ mod tests {
         let mut writer = FramedWrite::new(test_writer, encoder);
         let request_id = Uuid::new_v4();
 
-        let result = handle_list_patterns(&mut writer, request_id, &runner).await;
+        let items = vec![
+            BatchContent {
+                content: "tab one".to_string(),
+                pattern: Some("summarize".to_string()),
+                model: None,
+            },
+            BatchContent {
+                content: "tab two".to_string(),
+                pattern: Some("summarize".to_string()),
+                model: None,
+            },
+        ];
+
+        let state = HostState::new();
+        let result = handle_process_batch(&mut writer, request_id, &runner, items, &state).await;
         assert!(result.is_ok());
+        assert!(state.process_registry.lock().await.is_empty());
+        assert_eq!(state.metrics.snapshot(0).spawn_time.count, 2);
 
         let messages = messages.lock().unwrap();
-        assert_eq!(messages.len(), 1);
+        assert!(messages.iter().all(|m| m.id == request_id));
+
+        let mut contents: Vec<(usize, String)> = messages
+            .iter()
+            .filter_map(|m| match &m.payload {
+                ResponsePayload::BatchItem { index, item } => match item.as_ref() {
+                    ResponsePayload::Content { content } => Some((*index, content.clone())),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect();
+        contents.sort();
+        assert_eq!(contents.len(), 2);
+        assert_ne!(contents[0].1, contents[1].1);
+
+        let done_count = messages
+            .iter()
+            .filter(|m| {
+                matches!(&m.payload, ResponsePayload::BatchItem { item, .. } if matches!(item.as_ref(), ResponsePayload::Done { .. }))
+            })
+            .count();
+        assert_eq!(done_count, 2);
 
-        if let ResponsePayload::Error { message } = &messages[0].payload {
-            assert!(message.contains("Failed to list patterns"));
-        } else {
-            panic!("Expected Error response");
-        }
+        assert_matches!(
+            messages.last().map(|m| &m.payload),
+            Some(ResponsePayload::BatchDone {
+                total: 2,
+                failed: 0
+            })
+        );
     }
 
     #[tokio::test]
-    async fn test_handle_process_content() {
-        let dir = tempdir().unwrap();
-        let file_path = dir.child("fabric-ai");
-        file_path.touch().unwrap();
-
-        let stdout_lines = vec![
-            "Processing line 1\n".to_string(),
-            "Processing line 2\n".to_string(),
-            "Done\n".to_string(),
-        ];
-
-        let process_handle = MockProcessHandle::new(stdout_lines, Some(0));
+    async fn test_handle_process_batch_items_get_host_limits() {
         let runner = MockCommandRunner::default()
-            .with_process_handle(process_handle)
+            .with_process_handle(MockProcessHandle::new(
+                vec!["longer than the cap\n".to_string()],
+                Some(0),
+            ))
             .await;
 
         let test_writer = TestWriter::new();
@@ -1016,228 +8903,208 @@ mod tests {
         let encoder = TestEncoder::new(messages.clone());
         let mut writer = FramedWrite::new(test_writer, encoder);
 
-        let request_id = Uuid::new_v4();
-        let model = Some("gpt-4".to_string());
-        let pattern = Some("summarize".to_string());
-        let custom_prompt = None;
-        let content = "Test content to process".to_string();
-
-        let process_registry: ProcessRegistry = Arc::new(TokioMutex::new(HashMap::new()));
-        let result = handle_process_content(
-            &mut writer,
-            request_id,
-            &runner,
-            model,
-            pattern,
-            None,
-            custom_prompt,
-            content,
-            process_registry,
-        )
-        .await;
-        assert!(result.is_ok());
+        let items = vec![BatchContent {
+            content: "tab".to_string(),
+            pattern: Some("summarize".to_string()),
+            model: None,
+        }];
+        let state = HostState::new().with_max_output_bytes(Some(6));
+        handle_process_batch(&mut writer, Uuid::new_v4(), &runner, items, &state)
+            .await
+            .unwrap();
 
         let messages = messages.lock().unwrap();
-        assert_eq!(messages.len(), 4);
-
-        assert_matches!(&messages[0].payload, ResponsePayload::Content { content } if content == "Processing line 1\n");
-        assert_matches!(&messages[1].payload, ResponsePayload::Content { content } if content == "Processing line 2\n"
-        );
-        assert_matches!(&messages[2].payload, ResponsePayload::Content { content } if content == "Done\n"
+        let items: Vec<_> = messages
+            .iter()
+            .filter_map(|m| match &m.payload {
+                ResponsePayload::BatchItem { item, .. } => Some(item.as_ref()),
+                _ => None,
+            })
+            .collect();
+        assert!(items.contains(&&ResponsePayload::Content {
+            content: "longer".to_string()
+        }));
+        assert!(
+            items
+                .iter()
+                .any(|item| matches!(item, ResponsePayload::Warning { .. }))
         );
         assert_matches!(
-            &messages[3].payload,
-            ResponsePayload::Done { exit_code: Some(0) }
+            items.last(),
+            Some(ResponsePayload::Done {
+                truncated: true,
+                ..
+            })
         );
     }
 
     #[tokio::test]
-    async fn test_handle_ping_no_path() {
-        use tokio_util::codec::FramedWrite;
-
-        let runner = MockCommandRunner::default().with_version_response(CommandOutput {
-            status: false,
-            stdout: String::new(),
-            stderr: "Mock error".to_string(),
-        });
+    async fn test_handle_process_batch_reports_failed_items() {
+        let runner = MockCommandRunner::default()
+            .with_process_handle(MockProcessHandle::new(vec!["only\n".to_string()], Some(0)))
+            .await;
 
         let test_writer = TestWriter::new();
         let messages = test_writer.messages.clone();
         let encoder = TestEncoder::new(messages.clone());
         let mut writer = FramedWrite::new(test_writer, encoder);
-        let request_id = Uuid::new_v4();
 
-        let result = handle_ping(&mut writer, request_id, &runner).await;
+        let items = (0..2)
+            .map(|i| BatchContent {
+                content: format!("tab {i}"),
+                pattern: None,
+                model: None,
+            })
+            .collect();
+
+        let result = handle_process_batch(
+            &mut writer,
+            Uuid::new_v4(),
+            &runner,
+            items,
+            &HostState::new(),
+        )
+        .await;
         assert!(result.is_ok());
 
         let messages = messages.lock().unwrap();
-        assert_eq!(messages.len(), 1);
-
-        if let ResponsePayload::Pong { valid, .. } = &messages[0].payload {
-            assert!(!valid);
-        } else {
-            panic!("Expected Pong response");
-        }
+        assert!(messages.iter().any(|m| {
+            matches!(&m.payload, ResponsePayload::BatchItem { item, .. } if matches!(item.as_ref(), ResponsePayload::Error { .. }))
+        }));
+        assert_matches!(
+            messages.last().map(|m| &m.payload),
+            Some(ResponsePayload::BatchDone {
+                total: 2,
+                failed: 1
+            })
+        );
     }
 
     #[tokio::test]
-    async fn test_handle_process_content_error() {
-        let dir = tempdir().unwrap();
-        let file_path = dir.child("fabric-ai");
-        file_path.touch().unwrap();
-
-        let runner = MockCommandRunner::default();
+    async fn test_handle_process_content_spawn_failure_hint() {
+        let runner = MockCommandRunner::default()
+            .with_fabric_path("/opt/fabric/fabric-ai")
+            .with_spawn_error(io::ErrorKind::PermissionDenied);
 
         let test_writer = TestWriter::new();
         let messages = test_writer.messages.clone();
         let encoder = TestEncoder::new(messages.clone());
         let mut writer = FramedWrite::new(test_writer, encoder);
 
-        let request_id = Uuid::new_v4();
-        let content = "Test content".to_string();
-
-        let process_registry: ProcessRegistry = Arc::new(TokioMutex::new(HashMap::new()));
+        let state = HostState::new();
         let result = handle_process_content(
             &mut writer,
-            request_id,
+            Uuid::new_v4(),
             &runner,
-            None,
-            None,
-            None,
-            Some("custom prompt".to_string()),
-            content,
-            process_registry,
+            ProcessOptions::default(),
+            "content".to_string(),
+            &state,
         )
         .await;
+        assert_matches!(result, Err(HandlerError::Spawn { .. }));
 
-        assert!(result.is_err());
-    }
-
-    #[tokio::test]
-    async fn test_process_handle_stdin_error() {
-        let mut mock_process = MockProcessHandle::new(vec![], Some(0));
-        mock_process.set_stdin_error(io::Error::new(io::ErrorKind::BrokenPipe, "Stdin closed"));
-
-        let result = mock_process.write_stdin(b"test data").await;
-        assert!(result.is_err());
-        assert_matches!(result.unwrap_err(), HandlerError::Io(_));
-    }
-
-    #[tokio::test]
-    async fn test_process_handle_stdout_read_error() {
-        let mut mock_process = MockProcessHandle::new(vec![], Some(0));
-        mock_process.set_stdout_error(io::Error::new(
-            io::ErrorKind::UnexpectedEof,
-            "Stdout closed",
-        ));
-
-        let result = mock_process.read_stdout_line().await;
-        assert!(result.is_err());
-        assert_matches!(result.unwrap_err(), HandlerError::Io(_));
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_matches!(
+            &messages[0].payload,
+            ResponsePayload::Error {
+                details: Some(ErrorDetails { code, hint: Some(hint), .. }),
+                ..
+            } if code == "permissionDenied" && hint.contains("chmod +x /opt/fabric/fabric-ai")
+        );
     }
 
-    #[tokio::test]
-    async fn test_process_handle_close_stdin_error() {
-        let mut mock_process = MockProcessHandle::new(vec![], Some(0));
-        mock_process.set_stdin_error(io::Error::new(
-            io::ErrorKind::BrokenPipe,
-            "Cannot close stdin",
-        ));
+    #[test]
+    fn test_spawn_error_details() {
+        let path = Utf8Path::new("/usr/local/bin/fabric-ai");
 
-        let result = mock_process.close_stdin().await;
-        assert!(result.is_err());
-        assert_matches!(result.unwrap_err(), HandlerError::Io(_));
-    }
+        let details = spawn_error_details(path, &io::Error::from(io::ErrorKind::NotFound));
+        assert_eq!(details.code, "fabricNotFound");
 
-    #[tokio::test]
-    async fn test_process_handle_wait_error() {
-        let mut mock_process = MockProcessHandle::new(vec![], Some(0));
-        mock_process.set_wait_error(io::Error::new(
-            io::ErrorKind::InvalidInput,
-            "Process wait failed",
-        ));
+        let details = spawn_error_details(path, &io::Error::from_raw_os_error(EXEC_FORMAT_ERROR));
+        assert_eq!(details.code, "execFormat");
+        assert!(details.hint.unwrap().contains("wrong architecture"));
 
-        let result = Box::new(mock_process).wait().await;
-        assert!(result.is_err());
-        assert_matches!(result.unwrap_err(), HandlerError::Io(_));
+        let details = spawn_error_details(path, &io::Error::other("boom"));
+        assert_eq!(details.code, "spawnFailed");
+        assert_eq!(details.hint, None);
     }
 
     #[tokio::test]
-    async fn test_handle_list_contexts_success() {
-        let dir = tempdir().unwrap();
-        let file_path = dir.child("fabric-ai");
-        file_path.touch().unwrap();
-
-        let runner = MockCommandRunner::default().with_contexts_response(CommandOutput {
-            status: true,
-            stdout: "context1\ncontext2\ncontext3\n".to_string(),
-            stderr: String::new(),
-        });
+    async fn test_handle_list_contexts_unsupported_fabric() {
+        let runner = MockCommandRunner::default()
+            .with_fabric_path("/old/fabric/list_contexts")
+            .with_version_response(CommandOutput {
+                status: true,
+                stdout: "v1.0.3".to_string(),
+                stderr: String::new(),
+            })
+            .with_contexts_response(CommandOutput {
+                status: true,
+                stdout: "context1\n".to_string(),
+                stderr: String::new(),
+            });
 
         let test_writer = TestWriter::new();
         let messages = test_writer.messages.clone();
         let encoder = TestEncoder::new(messages.clone());
         let mut writer = FramedWrite::new(test_writer, encoder);
-        let request_id = Uuid::new_v4();
 
-        let result = handle_list_contexts(&mut writer, request_id, &runner).await;
+        let result = handle_list_contexts(&mut writer, Uuid::new_v4(), &runner).await;
         assert!(result.is_ok());
 
         let messages = messages.lock().unwrap();
         assert_eq!(messages.len(), 1);
-
-        if let ResponsePayload::ContextsList { contexts } = &messages[0].payload {
-            assert_eq!(contexts, &["context1", "context2", "context3"]);
-        } else {
-            panic!("Expected ContextsList response");
-        }
+        assert_matches!(&messages[0].payload, ResponsePayload::Error { message, .. } if message.contains("v1.1.0 or newer"));
     }
 
     #[tokio::test]
-    async fn test_handle_list_contexts_failure() {
-        let dir = tempdir().unwrap();
-        let file_path = dir.child("fabric-ai");
-        file_path.touch().unwrap();
-
-        let runner = MockCommandRunner::default().with_contexts_response(CommandOutput {
-            status: false,
-            stdout: String::new(),
-            stderr: "Failed to list contexts".to_string(),
-        });
+    async fn test_handle_process_content_degrades_unsupported_context() {
+        let process_handle = MockProcessHandle::new(vec!["output\n".to_string()], Some(0));
+        let runner = MockCommandRunner::default()
+            .with_fabric_path("/old/fabric/process_content")
+            .with_version_response(CommandOutput {
+                status: true,
+                stdout: "v1.0.3".to_string(),
+                stderr: String::new(),
+            })
+            .with_process_handle(process_handle)
+            .await;
 
         let test_writer = TestWriter::new();
         let messages = test_writer.messages.clone();
         let encoder = TestEncoder::new(messages.clone());
         let mut writer = FramedWrite::new(test_writer, encoder);
-        let request_id = Uuid::new_v4();
 
-        let result = handle_list_contexts(&mut writer, request_id, &runner).await;
+        let state = HostState::new();
+        let result = handle_process_content(
+            &mut writer,
+            Uuid::new_v4(),
+            &runner,
+            ProcessOptions {
+                context: Some("tapestry".to_string()),
+                ..Default::default()
+            },
+            "content".to_string(),
+            &state,
+        )
+        .await;
         assert!(result.is_ok());
 
         let messages = messages.lock().unwrap();
-        assert_eq!(messages.len(), 1);
-
-        if let ResponsePayload::Error { message } = &messages[0].payload {
-            assert!(message.contains("Failed to list contexts"));
-        } else {
-            panic!("Expected Error response");
-        }
+        assert_matches!(&messages[0].payload, ResponsePayload::Warning { message } if message.contains("Ignoring context 'tapestry'"));
+        assert_matches!(&messages[2].payload, ResponsePayload::Content { .. });
     }
 
     #[tokio::test]
-    async fn test_handle_process_content_with_context() {
-        let dir = tempdir().unwrap();
-        let file_path = dir.child("fabric-ai");
-        file_path.touch().unwrap();
-
-        let stdout_lines = vec![
-            "Processing with context line 1\n".to_string(),
-            "Processing with context line 2\n".to_string(),
-            "Done\n".to_string(),
-        ];
-
-        let process_handle = MockProcessHandle::new(stdout_lines, Some(0));
+    async fn test_strict_mode_rejects_degraded_context() {
+        let process_handle = MockProcessHandle::new(vec!["output\n".to_string()], Some(0));
         let runner = MockCommandRunner::default()
+            .with_version_response(CommandOutput {
+                status: true,
+                stdout: "v1.0.3".to_string(),
+                stderr: String::new(),
+            })
             .with_process_handle(process_handle)
             .await;
 
@@ -1246,37 +9113,27 @@ mod tests {
         let encoder = TestEncoder::new(messages.clone());
         let mut writer = FramedWrite::new(test_writer, encoder);
 
-        let request_id = Uuid::new_v4();
-        let model = Some("gpt-4".to_string());
-        let pattern = Some("summarize".to_string());
-        let context = Some("tapestry".to_string());
-        let custom_prompt = None;
-        let content = "Test content to process with context".to_string();
-
-        let process_registry: ProcessRegistry = Arc::new(TokioMutex::new(HashMap::new()));
         let result = handle_process_content(
             &mut writer,
-            request_id,
+            Uuid::new_v4(),
             &runner,
-            model,
-            pattern,
-            context,
-            custom_prompt,
-            content,
-            process_registry,
+            ProcessOptions {
+                context: Some("tapestry".to_string()),
+                strict: true,
+                ..Default::default()
+            },
+            "content".to_string(),
+            &HostState::new(),
         )
         .await;
-        assert!(result.is_ok());
 
+        assert_matches!(result, Err(HandlerError::Strict(message)) if message.contains("Ignoring context 'tapestry'"));
+        assert!(runner.spawned_args.lock().await.is_empty());
         let messages = messages.lock().unwrap();
-        assert_eq!(messages.len(), 4);
-
-        assert_matches!(&messages[0].payload, ResponsePayload::Content { content } if content == "Processing with context line 1\n");
-        assert_matches!(&messages[1].payload, ResponsePayload::Content { content } if content == "Processing with context line 2\n");
-        assert_matches!(&messages[2].payload, ResponsePayload::Content { content } if content == "Done\n");
+        assert_eq!(messages.len(), 1);
         assert_matches!(
-            &messages[3].payload,
-            ResponsePayload::Done { exit_code: Some(0) }
+            &messages[0].payload,
+            ResponsePayload::Error { details: Some(ErrorDetails { code, .. }), .. } if code == "strict"
         );
     }
 
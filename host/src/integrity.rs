@@ -0,0 +1,165 @@
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    fs::{File, Metadata},
+    io,
+    sync::{Mutex, PoisonError},
+    time::SystemTime,
+};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use sha2::{Digest, Sha256};
+
+pub fn sha256_file(path: &Utf8Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .fold(String::with_capacity(64), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        }))
+}
+
+pub fn digest_matches(pinned: &str, digest: &str) -> bool {
+    pinned.trim().eq_ignore_ascii_case(digest)
+}
+
+/// What a cached digest is keyed on. mtime can be set back with `touch`, so
+/// on Unix the inode and ctime are included too: replacing the file or
+/// writing to it changes one of them, and neither can be set by the user.
+#[derive(PartialEq, Eq)]
+struct FileStamp {
+    modified: SystemTime,
+    len: u64,
+    #[cfg(unix)]
+    inode: (u64, u64),
+    #[cfg(unix)]
+    changed: (i64, i64),
+}
+
+impl FileStamp {
+    fn new(metadata: &Metadata) -> io::Result<Self> {
+        #[cfg(unix)]
+        use std::os::unix::fs::MetadataExt;
+
+        Ok(Self {
+            modified: metadata.modified()?,
+            len: metadata.len(),
+            #[cfg(unix)]
+            inode: (metadata.dev(), metadata.ino()),
+            #[cfg(unix)]
+            changed: (metadata.ctime(), metadata.ctime_nsec()),
+        })
+    }
+}
+
+struct CachedDigest {
+    stamp: FileStamp,
+    sha256: String,
+}
+
+/// Remembers binary digests until the file changes, so the pinned-checksum
+/// check does not rehash fabric on every request.
+#[derive(Default)]
+pub struct DigestCache {
+    entries: Mutex<HashMap<Utf8PathBuf, CachedDigest>>,
+}
+
+impl DigestCache {
+    pub fn sha256(&self, path: &Utf8Path) -> io::Result<String> {
+        let stamp = FileStamp::new(&path.metadata()?)?;
+
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(cached) = entries.get(path)
+            && cached.stamp == stamp
+        {
+            return Ok(cached.sha256.clone());
+        }
+
+        let sha256 = sha256_file(path)?;
+        entries.insert(
+            path.to_owned(),
+            CachedDigest {
+                stamp,
+                sha256: sha256.clone(),
+            },
+        );
+        Ok(sha256)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use camino_tempfile::tempdir;
+    use camino_tempfile_ext::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_sha256_file() {
+        let dir = tempdir().unwrap();
+        let file = dir.child("fabric");
+        file.write_str("abc").unwrap();
+
+        assert_eq!(
+            sha256_file(&file).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_digest_cache_rehashes_changed_files() {
+        let dir = tempdir().unwrap();
+        let file = dir.child("fabric");
+        file.write_str("abc").unwrap();
+        let cache = DigestCache::default();
+
+        let first = cache.sha256(&file).unwrap();
+        assert_eq!(cache.sha256(&file).unwrap(), first);
+
+        file.write_str("abcd").unwrap();
+        assert_ne!(cache.sha256(&file).unwrap(), first);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_digest_cache_rehashes_when_mtime_and_size_are_restored() {
+        let dir = tempdir().unwrap();
+        let file = dir.child("fabric");
+        file.write_str("abc").unwrap();
+        let cache = DigestCache::default();
+        let first = cache.sha256(&file).unwrap();
+        let modified = file.metadata().unwrap().modified().unwrap();
+
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        file.write_str("xyz").unwrap();
+        File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        assert_eq!(file.metadata().unwrap().modified().unwrap(), modified);
+
+        assert_ne!(cache.sha256(&file).unwrap(), first);
+    }
+
+    #[test]
+    fn test_digest_matches_ignores_case_and_whitespace() {
+        assert!(digest_matches(" ABC123\n", "abc123"));
+        assert!(!digest_matches("abc124", "abc123"));
+    }
+}
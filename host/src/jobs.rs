@@ -0,0 +1,506 @@
+use std::{
+    collections::HashMap,
+    env,
+    path::PathBuf,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use futures_util::Sink;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Notify, broadcast};
+use uuid::Uuid;
+
+use crate::{
+    GenerationStats, JobEvent, JobEventKind, JobState, JobSummary, OrphanPolicy, Response,
+    ResponsePayload,
+    handlers::HandlerError,
+    store::{self, Schema, StoreError},
+};
+
+pub const JOURNAL_ENV_VAR: &str = "TAPESTRY_JOB_JOURNAL";
+pub const ORPHAN_POLICY_ENV_VAR: &str = "TAPESTRY_ORPHAN_POLICY";
+
+pub const JOURNAL_SCHEMA: Schema = Schema::UNVERSIONED;
+
+const INTERRUPTED_MESSAGE: &str = "Host exited before the job finished";
+const JOB_EVENT_CAPACITY: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Job {
+    pub state: JobState,
+    pub content: String,
+    pub exit_code: Option<i32>,
+    pub error: Option<String>,
+    pub created_ms: u64,
+    #[serde(default)]
+    pub orphan_policy: Option<OrphanPolicy>,
+    #[serde(default)]
+    pub generation: Option<GenerationStats>,
+}
+
+impl Job {
+    pub fn new() -> Self {
+        Self {
+            state: JobState::Running,
+            content: String::new(),
+            exit_code: None,
+            error: None,
+            orphan_policy: None,
+            generation: None,
+            created_ms: now_ms(),
+        }
+    }
+
+    pub fn record(&mut self, payload: ResponsePayload) {
+        match payload {
+            ResponsePayload::Content { content } => self.content.push_str(&content),
+            ResponsePayload::Done {
+                exit_code,
+                generation,
+                ..
+            } => {
+                self.exit_code = exit_code;
+                self.generation = generation;
+                self.state = match exit_code {
+                    Some(0) | None => JobState::Completed,
+                    Some(_) => JobState::Failed,
+                };
+            }
+            ResponsePayload::Error { message, .. } => {
+                self.error = Some(message);
+                self.state = JobState::Failed;
+            }
+            ResponsePayload::SetupRequired { instructions, .. } => {
+                self.error = Some(instructions);
+                self.state = JobState::Failed;
+            }
+            _ => {}
+        }
+    }
+
+    pub fn summary(&self, job_id: Uuid) -> JobSummary {
+        JobSummary {
+            job_id,
+            state: self.state,
+            exit_code: self.exit_code,
+            error: self.error.clone(),
+            created_ms: self.created_ms,
+            orphan_policy: self.orphan_policy,
+        }
+    }
+}
+
+impl Default for Job {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn result_payload(job_id: Uuid, job: &Job) -> ResponsePayload {
+    ResponsePayload::JobResult {
+        job_id,
+        state: job.state,
+        content: job.content.clone(),
+        exit_code: job.exit_code,
+        error: job.error.clone(),
+        generation: job.generation.clone(),
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| {
+            u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX)
+        })
+}
+
+pub fn journal_path_from_env() -> Option<Utf8PathBuf> {
+    match env::var(JOURNAL_ENV_VAR) {
+        Ok(path) if !path.is_empty() => Some(Utf8PathBuf::from(path)),
+        _ => {
+            let home = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE"))?;
+            let home = Utf8PathBuf::from_path_buf(PathBuf::from(home)).ok()?;
+            Some(home.join(".config").join("tapestry").join("jobs.json"))
+        }
+    }
+}
+
+pub fn orphan_policy_from_env() -> Result<OrphanPolicy, String> {
+    match env::var(ORPHAN_POLICY_ENV_VAR) {
+        Ok(name) if !name.is_empty() => OrphanPolicy::from_name(&name)
+            .ok_or_else(|| format!("Unknown {ORPHAN_POLICY_ENV_VAR} value '{name}'")),
+        _ => Ok(OrphanPolicy::default()),
+    }
+}
+
+#[derive(Debug)]
+pub struct JobRegistry {
+    jobs: Mutex<HashMap<Uuid, Job>>,
+    journal: Option<Utf8PathBuf>,
+    finished: Notify,
+    events: broadcast::Sender<JobEvent>,
+}
+
+impl Default for JobRegistry {
+    fn default() -> Self {
+        Self {
+            jobs: Mutex::default(),
+            journal: None,
+            finished: Notify::new(),
+            events: broadcast::channel(JOB_EVENT_CAPACITY).0,
+        }
+    }
+}
+
+impl JobRegistry {
+    pub fn open(journal: Utf8PathBuf) -> Result<(Self, Option<String>), StoreError> {
+        let loaded = store::load::<HashMap<Uuid, Job>>(&journal, &JOURNAL_SCHEMA)?;
+        let mut jobs = loaded.value;
+        for job in jobs.values_mut() {
+            if job.state == JobState::Running {
+                job.state = JobState::Failed;
+                job.error = Some(INTERRUPTED_MESSAGE.to_string());
+            }
+        }
+
+        let registry = Self {
+            jobs: Mutex::new(jobs),
+            journal: Some(journal),
+            ..Self::default()
+        };
+        registry.persist();
+        Ok((registry, loaded.warning))
+    }
+
+    pub fn journal(&self) -> Option<&Utf8Path> {
+        self.journal.as_deref()
+    }
+
+    pub fn insert(&self, job_id: Uuid) {
+        self.update(|jobs| {
+            jobs.insert(job_id, Job::new());
+        });
+        self.emit(job_id, JobEventKind::Started);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<JobEvent> {
+        self.events.subscribe()
+    }
+
+    pub fn get(&self, job_id: Uuid) -> Option<Job> {
+        self.jobs.lock().ok()?.get(&job_id).cloned()
+    }
+
+    pub fn list(&self, state: Option<JobState>) -> Vec<JobSummary> {
+        let Ok(jobs) = self.jobs.lock() else {
+            return Vec::new();
+        };
+        let mut summaries: Vec<JobSummary> = jobs
+            .iter()
+            .filter(|(_, job)| state.is_none_or(|state| job.state == state))
+            .map(|(job_id, job)| job.summary(*job_id))
+            .collect();
+        summaries.sort_by_key(|summary| (summary.created_ms, summary.job_id));
+        summaries
+    }
+
+    pub fn remove(&self, job_id: Uuid) -> Option<Job> {
+        let mut removed = None;
+        self.update(|jobs| removed = jobs.remove(&job_id));
+        removed
+    }
+
+    pub fn orphan_running(&self, policy: OrphanPolicy) -> Vec<Uuid> {
+        let mut orphaned = Vec::new();
+        self.update(|jobs| {
+            for (job_id, job) in jobs.iter_mut() {
+                if job.state == JobState::Running {
+                    job.orphan_policy = Some(policy);
+                    orphaned.push(*job_id);
+                }
+            }
+        });
+        orphaned
+    }
+
+    pub fn take_orphaned(&self) -> Vec<JobSummary> {
+        let mut summaries = Vec::new();
+        self.update(|jobs| {
+            for (job_id, job) in jobs.iter_mut() {
+                if job.orphan_policy.is_some() && job.state != JobState::Running {
+                    summaries.push(job.summary(*job_id));
+                    job.orphan_policy = None;
+                }
+            }
+        });
+        summaries.sort_by_key(|summary| (summary.created_ms, summary.job_id));
+        summaries
+    }
+
+    pub async fn wait_idle(&self) {
+        loop {
+            let finished = self.finished.notified();
+            tokio::pin!(finished);
+            finished.as_mut().enable();
+
+            if self.list(Some(JobState::Running)).is_empty() {
+                return;
+            }
+            finished.await;
+        }
+    }
+
+    fn record(&self, job_id: Uuid, payload: ResponsePayload) {
+        let Ok(mut jobs) = self.jobs.lock() else {
+            return;
+        };
+        let Some(job) = jobs.get_mut(&job_id) else {
+            return;
+        };
+        let was_running = job.state == JobState::Running;
+        job.record(payload);
+        let state = job.state;
+        drop(jobs);
+
+        if state != JobState::Running {
+            self.persist();
+            if was_running {
+                self.emit_finished(job_id, state);
+            }
+        }
+    }
+
+    fn finish(&self, job_id: Uuid, state: JobState) {
+        let mut finished = false;
+        self.update(|jobs| {
+            if let Some(job) = jobs.get_mut(&job_id)
+                && job.state == JobState::Running
+            {
+                job.state = state;
+                finished = true;
+            }
+        });
+        if finished {
+            self.emit_finished(job_id, state);
+        }
+        self.finished.notify_waiters();
+    }
+
+    fn emit_finished(&self, job_id: Uuid, state: JobState) {
+        let event = match state {
+            JobState::Running => return,
+            JobState::Completed => JobEventKind::Completed,
+            JobState::Failed => JobEventKind::Failed,
+            JobState::Cancelled => JobEventKind::Cancelled,
+        };
+        self.emit(job_id, event);
+    }
+
+    fn emit(&self, job_id: Uuid, event: JobEventKind) {
+        let _ = self.events.send(JobEvent {
+            job_id,
+            event,
+            timestamp_ms: now_ms(),
+        });
+    }
+
+    fn update(&self, f: impl FnOnce(&mut HashMap<Uuid, Job>)) {
+        if let Ok(mut jobs) = self.jobs.lock() {
+            f(&mut jobs);
+        }
+        self.persist();
+    }
+
+    fn persist(&self) {
+        let Some(journal) = &self.journal else {
+            return;
+        };
+        if let Ok(jobs) = self.jobs.lock() {
+            let _ = store::save(journal, &JOURNAL_SCHEMA, &*jobs);
+        }
+    }
+}
+
+pub struct JobSink {
+    job_id: Uuid,
+    jobs: Arc<JobRegistry>,
+}
+
+impl JobSink {
+    pub fn new(job_id: Uuid, jobs: Arc<JobRegistry>) -> Self {
+        Self { job_id, jobs }
+    }
+
+    pub fn finish(&self, state: JobState) {
+        self.jobs.finish(self.job_id, state);
+    }
+}
+
+impl Sink<Response> for JobSink {
+    type Error = HandlerError;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, response: Response) -> Result<(), Self::Error> {
+        self.jobs.record(self.job_id, response.payload);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use camino_tempfile::tempdir;
+
+    use super::*;
+
+    fn done(exit_code: Option<i32>) -> ResponsePayload {
+        ResponsePayload::Done {
+            exit_code,
+            usage: None,
+            generation: None,
+            duration_ms: None,
+            time_to_first_chunk_ms: None,
+            spawn_time_ms: None,
+            content_hash: None,
+            stderr_tail: None,
+            error: None,
+            content: None,
+            summary: None,
+            output_format: None,
+            truncated: false,
+        }
+    }
+
+    #[test]
+    fn test_job_records_content_and_completion() {
+        let mut job = Job::new();
+        job.record(ResponsePayload::Content {
+            content: "hello ".to_string(),
+        });
+        job.record(ResponsePayload::Progress {
+            bytes_out: 6,
+            elapsed_ms: 1000,
+        });
+        job.record(ResponsePayload::Content {
+            content: "world\n".to_string(),
+        });
+        assert_eq!(job.state, JobState::Running);
+
+        job.record(done(Some(0)));
+        assert_eq!(job.state, JobState::Completed);
+        assert_eq!(job.content, "hello world\n");
+        assert_eq!(job.exit_code, Some(0));
+    }
+
+    #[test]
+    fn test_job_sink_finish_keeps_terminal_state() {
+        let jobs = Arc::new(JobRegistry::default());
+        let job_id = Uuid::new_v4();
+        jobs.insert(job_id);
+
+        let sink = JobSink::new(job_id, jobs.clone());
+        jobs.record(
+            job_id,
+            ResponsePayload::Error {
+                message: "boom".to_string(),
+                details: None,
+            },
+        );
+        sink.finish(JobState::Cancelled);
+
+        assert_eq!(jobs.get(job_id).unwrap().state, JobState::Failed);
+    }
+
+    #[test]
+    fn test_list_filters_by_state() {
+        let jobs = JobRegistry::default();
+        let running = Uuid::new_v4();
+        let finished = Uuid::new_v4();
+        jobs.insert(running);
+        jobs.insert(finished);
+        jobs.finish(finished, JobState::Completed);
+
+        let completed = jobs.list(Some(JobState::Completed));
+        assert_eq!(completed.len(), 1);
+        assert_eq!(completed[0].job_id, finished);
+        assert_eq!(jobs.list(None).len(), 2);
+
+        assert!(jobs.remove(finished).is_some());
+        assert!(jobs.list(Some(JobState::Completed)).is_empty());
+    }
+
+    #[test]
+    fn test_journal_survives_restart() {
+        let dir = tempdir().unwrap();
+        let journal = dir.path().join("jobs.json");
+
+        let finished = Uuid::new_v4();
+        let interrupted = Uuid::new_v4();
+        {
+            let (jobs, warning) = JobRegistry::open(journal.clone()).unwrap();
+            assert!(warning.is_none());
+            jobs.insert(finished);
+            jobs.record(
+                finished,
+                ResponsePayload::Content {
+                    content: "summary".to_string(),
+                },
+            );
+            jobs.record(finished, done(Some(0)));
+            jobs.insert(interrupted);
+        }
+
+        let (jobs, _) = JobRegistry::open(journal).unwrap();
+        let job = jobs.get(finished).unwrap();
+        assert_eq!(job.state, JobState::Completed);
+        assert_eq!(job.content, "summary");
+
+        let job = jobs.get(interrupted).unwrap();
+        assert_eq!(job.state, JobState::Failed);
+        assert_eq!(job.error.as_deref(), Some(INTERRUPTED_MESSAGE));
+    }
+
+    #[test]
+    fn test_lifecycle_events_are_broadcast() {
+        let jobs = JobRegistry::default();
+        let mut events = jobs.subscribe();
+        let completed = Uuid::new_v4();
+        let cancelled = Uuid::new_v4();
+
+        jobs.insert(completed);
+        jobs.record(completed, done(Some(0)));
+        jobs.record(completed, done(Some(1)));
+        jobs.finish(completed, JobState::Completed);
+        jobs.insert(cancelled);
+        jobs.finish(cancelled, JobState::Cancelled);
+
+        let received: Vec<(Uuid, JobEventKind)> = std::iter::from_fn(|| events.try_recv().ok())
+            .map(|event| (event.job_id, event.event))
+            .collect();
+        assert_eq!(
+            received,
+            vec![
+                (completed, JobEventKind::Started),
+                (completed, JobEventKind::Completed),
+                (cancelled, JobEventKind::Started),
+                (cancelled, JobEventKind::Cancelled),
+            ]
+        );
+    }
+}
@@ -0,0 +1,256 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DateOrder {
+    DayMonthYear,
+    MonthDayYear,
+    YearMonthDay,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocaleFormat {
+    decimal_separator: char,
+    group_separator: char,
+    date_order: DateOrder,
+    date_separator: char,
+}
+
+impl LocaleFormat {
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        let tag = tag.split(['.', '@']).next()?.replace('_', "-");
+        let mut parts = tag.split('-');
+        let language = parts.next()?.to_ascii_lowercase();
+        let region = parts.next().map(str::to_ascii_uppercase);
+
+        let (decimal_separator, group_separator) = match language.as_str() {
+            "en" | "ja" | "zh" | "ko" | "he" | "th" => ('.', ','),
+            "de" if region.as_deref() == Some("CH") => ('.', '\''),
+            "de" | "es" | "it" | "nl" | "pt" | "da" | "id" | "tr" | "el" => (',', '.'),
+            "fr" | "ru" | "pl" | "cs" | "sk" | "sv" | "fi" | "nb" | "no" | "uk" | "hu" => {
+                (',', '\u{a0}')
+            }
+            _ => return None,
+        };
+
+        let (date_order, date_separator) = match (language.as_str(), region.as_deref()) {
+            ("en", Some("US") | None) => (DateOrder::MonthDayYear, '/'),
+            ("ja" | "zh" | "ko" | "sv" | "hu", _) => (DateOrder::YearMonthDay, '-'),
+            ("de" | "ru" | "pl" | "cs" | "sk" | "fi" | "nb" | "no" | "uk" | "tr", _) => {
+                (DateOrder::DayMonthYear, '.')
+            }
+            ("nl" | "da", _) => (DateOrder::DayMonthYear, '-'),
+            _ => (DateOrder::DayMonthYear, '/'),
+        };
+
+        Some(Self {
+            decimal_separator,
+            group_separator,
+            date_order,
+            date_separator,
+        })
+    }
+
+    pub fn normalize(&self, text: &str) -> String {
+        let bytes = text.as_bytes();
+        let mut output = String::with_capacity(text.len());
+        let mut copied = 0;
+        let mut i = 0;
+
+        while i < bytes.len() {
+            if !bytes[i].is_ascii_digit() || (i > 0 && !is_token_start(bytes[i - 1])) {
+                i += 1;
+                continue;
+            }
+
+            if let Some((end, replacement)) = self
+                .match_date(bytes, i)
+                .or_else(|| self.match_number(bytes, i))
+            {
+                output.push_str(&text[copied..i]);
+                output.push_str(&replacement);
+                copied = end;
+                i = end;
+            } else {
+                i += digit_run(bytes, i);
+            }
+        }
+
+        output.push_str(&text[copied..]);
+        output
+    }
+
+    fn match_date(&self, bytes: &[u8], start: usize) -> Option<(usize, String)> {
+        let end = start + 10;
+        let candidate = bytes.get(start..end)?;
+        if digit_run(candidate, 0) != 4
+            || candidate[4] != b'-'
+            || digit_run(candidate, 5) != 2
+            || candidate[7] != b'-'
+            || digit_run(candidate, 8) != 2
+            || !is_token_end(bytes, end)
+        {
+            return None;
+        }
+
+        let year = std::str::from_utf8(&candidate[0..4]).ok()?;
+        let month = std::str::from_utf8(&candidate[5..7]).ok()?;
+        let day = std::str::from_utf8(&candidate[8..10]).ok()?;
+        if !matches!(month.parse::<u8>(), Ok(1..=12)) || !matches!(day.parse::<u8>(), Ok(1..=31)) {
+            return None;
+        }
+
+        let sep = self.date_separator;
+        let formatted = match self.date_order {
+            DateOrder::DayMonthYear => format!("{day}{sep}{month}{sep}{year}"),
+            DateOrder::MonthDayYear => format!("{month}{sep}{day}{sep}{year}"),
+            DateOrder::YearMonthDay => format!("{year}{sep}{month}{sep}{day}"),
+        };
+        Some((end, formatted))
+    }
+
+    fn match_number(&self, bytes: &[u8], start: usize) -> Option<(usize, String)> {
+        let leading = digit_run(bytes, start);
+        let mut groups = Vec::new();
+        let mut pos = start + leading;
+
+        if leading <= 3 {
+            while bytes.get(pos) == Some(&b',') && digit_run(bytes, pos + 1) == 3 {
+                groups.push(pos + 1);
+                pos += 4;
+            }
+        }
+
+        let mut fraction = None;
+        if bytes.get(pos) == Some(&b'.') {
+            let len = digit_run(bytes, pos + 1);
+            if len > 0 {
+                fraction = Some((pos + 1, len));
+                pos += 1 + len;
+            }
+        }
+
+        if (groups.is_empty() && fraction.is_none()) || !is_token_end(bytes, pos) {
+            return None;
+        }
+
+        let digits = |from: usize, len: usize| std::str::from_utf8(&bytes[from..from + len]).ok();
+        let mut formatted = digits(start, leading)?.to_string();
+        for group in groups {
+            formatted.push(self.group_separator);
+            formatted.push_str(digits(group, 3)?);
+        }
+        if let Some((from, len)) = fraction {
+            formatted.push(self.decimal_separator);
+            formatted.push_str(digits(from, len)?);
+        }
+
+        Some((pos, formatted))
+    }
+}
+
+fn digit_run(bytes: &[u8], start: usize) -> usize {
+    bytes.get(start..).map_or(0, |rest| {
+        rest.iter().take_while(|b| b.is_ascii_digit()).count()
+    })
+}
+
+fn is_token_start(prev: u8) -> bool {
+    !(prev.is_ascii_alphanumeric() || matches!(prev, b'.' | b',' | b'_' | b'/' | b':'))
+}
+
+fn is_token_end(bytes: &[u8], end: usize) -> bool {
+    match bytes.get(end) {
+        None => true,
+        Some(b) if b.is_ascii_alphanumeric() || *b == b'_' => false,
+        Some(b'.' | b',' | b'-' | b':' | b'/') => {
+            !bytes.get(end + 1).is_some_and(u8::is_ascii_digit)
+        }
+        Some(_) => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_tag_variants() {
+        assert!(LocaleFormat::from_tag("de-DE").is_some());
+        assert!(LocaleFormat::from_tag("de_DE.UTF-8").is_some());
+        assert!(LocaleFormat::from_tag("fr").is_some());
+        assert!(LocaleFormat::from_tag("xx-YY").is_none());
+        assert!(LocaleFormat::from_tag("").is_none());
+    }
+
+    #[test]
+    fn test_normalize_grouped_numbers() {
+        let locale = LocaleFormat::from_tag("de-DE").unwrap();
+        assert_eq!(
+            locale.normalize("Revenue was 1,234,567.89 dollars"),
+            "Revenue was 1.234.567,89 dollars"
+        );
+    }
+
+    #[test]
+    fn test_normalize_decimal_numbers() {
+        let locale = LocaleFormat::from_tag("fr-FR").unwrap();
+        assert_eq!(locale.normalize("Pi is 3.14."), "Pi is 3,14.");
+        assert_eq!(locale.normalize("-0.5 degrees"), "-0,5 degrees");
+    }
+
+    #[test]
+    fn test_normalize_leaves_plain_integers() {
+        let locale = LocaleFormat::from_tag("de-DE").unwrap();
+        assert_eq!(
+            locale.normalize("In 2024 there were 15000 users"),
+            "In 2024 there were 15000 users"
+        );
+    }
+
+    #[test]
+    fn test_normalize_skips_versions_and_addresses() {
+        let locale = LocaleFormat::from_tag("de-DE").unwrap();
+        assert_eq!(
+            locale.normalize("fabric v1.4.2 at 10.0.0.1"),
+            "fabric v1.4.2 at 10.0.0.1"
+        );
+        assert_eq!(locale.normalize("version 1.4.2"), "version 1.4.2");
+        assert_eq!(locale.normalize("at 12:30.5"), "at 12:30.5");
+    }
+
+    #[test]
+    fn test_normalize_iso_dates() {
+        let us = LocaleFormat::from_tag("en-US").unwrap();
+        let gb = LocaleFormat::from_tag("en-GB").unwrap();
+        let de = LocaleFormat::from_tag("de").unwrap();
+        let ja = LocaleFormat::from_tag("ja-JP").unwrap();
+
+        assert_eq!(
+            us.normalize("Published 2024-03-15\n"),
+            "Published 03/15/2024\n"
+        );
+        assert_eq!(
+            gb.normalize("Published 2024-03-15\n"),
+            "Published 15/03/2024\n"
+        );
+        assert_eq!(
+            de.normalize("Published 2024-03-15\n"),
+            "Published 15.03.2024\n"
+        );
+        assert_eq!(
+            ja.normalize("Published 2024-03-15\n"),
+            "Published 2024-03-15\n"
+        );
+    }
+
+    #[test]
+    fn test_normalize_rejects_invalid_dates() {
+        let de = LocaleFormat::from_tag("de").unwrap();
+        assert_eq!(de.normalize("id 2024-13-45"), "id 2024-13-45");
+        assert_eq!(de.normalize("2024-03-15T10:00"), "2024-03-15T10:00");
+    }
+
+    #[test]
+    fn test_normalize_preserves_non_ascii_text() {
+        let de = LocaleFormat::from_tag("de").unwrap();
+        assert_eq!(de.normalize("Größe: 2.5 m — ok"), "Größe: 2,5 m — ok");
+    }
+}
@@ -0,0 +1,160 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io,
+    sync::{Mutex, OnceLock},
+};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use tracing_subscriber::{
+    Registry, filter::LevelFilter, fmt::writer::BoxMakeWriter, layer::SubscriberExt, reload,
+    util::SubscriberInitExt,
+};
+
+use crate::LogLevel;
+
+pub const LOG_FILE_ENV_VAR: &str = "TAPESTRY_LOG_FILE";
+
+const LOG_FILE_NAME: &str = "host.log";
+const MAX_LOG_BYTES: u64 = 10 * 1024 * 1024;
+
+static LEVEL: OnceLock<reload::Handle<LevelFilter, Registry>> = OnceLock::new();
+
+/// Resolves the log file from `TAPESTRY_LOG_FILE`, then `[logging] file`,
+/// then `$XDG_STATE_HOME/tapestry/host.log` (falling back to
+/// `~/.local/state/tapestry/host.log`).
+pub fn log_path(
+    configured: Option<&Utf8Path>,
+    var: impl Fn(&str) -> Option<String>,
+) -> Option<Utf8PathBuf> {
+    if let Some(path) = var(LOG_FILE_ENV_VAR).filter(|path| !path.is_empty()) {
+        return Some(Utf8PathBuf::from(path));
+    }
+    if let Some(path) = configured {
+        return Some(path.to_owned());
+    }
+    let state_home = var("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(Utf8PathBuf::from)
+        .or_else(|| {
+            var("HOME")
+                .or_else(|| var("USERPROFILE"))
+                .map(|home| Utf8PathBuf::from(home).join(".local").join("state"))
+        })?;
+    Some(state_home.join("tapestry").join(LOG_FILE_NAME))
+}
+
+/// Installs the global subscriber, appending to `path` or, without one,
+/// writing to stderr. Stdout is never used because it carries the protocol.
+pub fn init(path: Option<&Utf8Path>, level: LogLevel) -> io::Result<()> {
+    let writer = match path {
+        Some(path) => BoxMakeWriter::new(Mutex::new(open_log_file(path)?)),
+        None => BoxMakeWriter::new(io::stderr),
+    };
+    let (filter, handle) = reload::Layer::new(level_filter(level));
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(writer),
+        )
+        .try_init()
+        .map_err(io::Error::other)?;
+    let _ = LEVEL.set(handle);
+    Ok(())
+}
+
+/// Applies a reloaded log level; a no-op until `init` has run.
+pub fn set_level(level: LogLevel) {
+    if let Some(handle) = LEVEL.get() {
+        let _ = handle.modify(|filter| *filter = level_filter(level));
+    }
+}
+
+pub fn level_filter(level: LogLevel) -> LevelFilter {
+    match level {
+        LogLevel::Error => LevelFilter::ERROR,
+        LogLevel::Warn => LevelFilter::WARN,
+        LogLevel::Info => LevelFilter::INFO,
+        LogLevel::Debug => LevelFilter::DEBUG,
+    }
+}
+
+/// Opens the log for appending, first moving a file over `MAX_LOG_BYTES`
+/// aside to `<path>.1` so the log cannot grow without bound.
+fn open_log_file(path: &Utf8Path) -> io::Result<File> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if path
+        .metadata()
+        .is_ok_and(|metadata| metadata.len() > MAX_LOG_BYTES)
+    {
+        fs::rename(path, format!("{path}.1"))?;
+    }
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use camino_tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_log_path_prefers_env_then_config_then_xdg_state() {
+        let path = log_path(
+            Some(Utf8Path::new("/var/log/tapestry.log")),
+            |name| match name {
+                LOG_FILE_ENV_VAR => Some("/tmp/host.log".to_string()),
+                "XDG_STATE_HOME" => Some("/xdg".to_string()),
+                _ => None,
+            },
+        );
+        assert_eq!(path.as_deref(), Some(Utf8Path::new("/tmp/host.log")));
+
+        let path = log_path(Some(Utf8Path::new("/var/log/tapestry.log")), |name| {
+            (name == "XDG_STATE_HOME").then(|| "/xdg".to_string())
+        });
+        assert_eq!(
+            path.as_deref(),
+            Some(Utf8Path::new("/var/log/tapestry.log"))
+        );
+
+        let path = log_path(None, |name| {
+            (name == "XDG_STATE_HOME").then(|| "/xdg".to_string())
+        });
+        assert_eq!(
+            path.as_deref(),
+            Some(Utf8Path::new("/xdg/tapestry/host.log"))
+        );
+
+        let path = log_path(None, |name| {
+            (name == "HOME").then(|| "/home/me".to_string())
+        });
+        assert_eq!(
+            path.as_deref(),
+            Some(Utf8Path::new("/home/me/.local/state/tapestry/host.log"))
+        );
+        assert_eq!(log_path(None, |_| None), None);
+    }
+
+    #[test]
+    fn test_open_log_file_rotates_oversized_log() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("logs").join("host.log");
+        drop(open_log_file(&path).unwrap());
+        assert!(path.exists());
+
+        File::create(&path)
+            .unwrap()
+            .set_len(MAX_LOG_BYTES + 1)
+            .unwrap();
+        let file = open_log_file(&path).unwrap();
+        assert_eq!(file.metadata().unwrap().len(), 0);
+        assert_eq!(
+            fs::metadata(format!("{path}.1")).unwrap().len(),
+            MAX_LOG_BYTES + 1
+        );
+    }
+}
@@ -1,61 +1,528 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{backtrace::Backtrace, env, panic, pin::pin, sync::Arc, time::Duration};
 
-use futures_util::StreamExt;
+use camino::Utf8Path;
+use futures_util::{Sink, SinkExt, StreamExt};
 use tapestry_host::{
-    Request,
-    codec::NativeMessagingCodec,
-    handlers::{FabricCommandRunner, ProcessRegistry, handle_request},
+    LogLevel, OrphanPolicy, Request, Response, ResponsePayload,
+    audit::{AuditEncoder, AuditLog},
+    codec::{
+        ByteOrder, ContentCompression, MAX_INBOUND_MESSAGE_SIZE, MAX_OUTBOUND_MESSAGE_SIZE,
+        NativeMessagingCodec,
+    },
+    config::{self, Config},
+    handlers::{
+        DEFAULT_COMMAND_TIMEOUT, DEFAULT_MAX_CHUNKED_CONTENT_BYTES,
+        DEFAULT_MAX_CONCURRENT_PROCESSES, DEFAULT_MAX_OUTPUT_BYTES, FabricCommandRunner,
+        boot_report, handle_duplicate_request, handle_malformed_frame, handle_panicked_request,
+        handle_request, handle_shutdown, resolve_path_in, verify_fabric,
+    },
+    jobs::{self, JobRegistry},
+    logging,
+    notify::{self, NotificationRouter},
+    state::{HostSettings, HostState},
+    trace::ProtocolTracer,
+    transport::{RetryingWriter, SharedWriter},
+    usage::{self, UsageTracker},
 };
 use tokio::{
     io::{stdin, stdout},
-    sync::Mutex,
+    signal,
+    sync::broadcast::error::RecvError,
+    time::{self, Instant, MissedTickBehavior},
 };
 use tokio_util::codec::{FramedRead, FramedWrite};
+use uuid::Uuid;
+
+const INBOUND_SIZE_ENV_VAR: &str = "TAPESTRY_MAX_INBOUND_MESSAGE_SIZE";
+const OUTBOUND_SIZE_ENV_VAR: &str = "TAPESTRY_MAX_OUTBOUND_MESSAGE_SIZE";
+const BYTE_ORDER_ENV_VAR: &str = "TAPESTRY_LENGTH_BYTE_ORDER";
+const HEARTBEAT_ENV_VAR: &str = "TAPESTRY_HEARTBEAT_INTERVAL_MS";
+const COALESCE_ENV_VAR: &str = "TAPESTRY_COALESCE_INTERVAL_MS";
+const HOST_READY_ENV_VAR: &str = "TAPESTRY_HOST_READY";
+const STRICT_ENV_VAR: &str = "TAPESTRY_STRICT";
+const COMMAND_TIMEOUT_ENV_VAR: &str = "TAPESTRY_COMMAND_TIMEOUT_MS";
+const MAX_PROCESSES_ENV_VAR: &str = "TAPESTRY_MAX_CONCURRENT_PROCESSES";
+const MAX_OUTPUT_ENV_VAR: &str = "TAPESTRY_MAX_OUTPUT_BYTES";
+const LOG_LEVEL_ENV_VAR: &str = "TAPESTRY_LOG_LEVEL";
+
+type Output = FramedWrite<SharedWriter, AuditEncoder<NativeMessagingCodec<Response>>>;
+
+fn usize_from_env(var: &str, default: usize) -> usize {
+    match env::var(var) {
+        Ok(value) if !value.is_empty() => value.trim().parse().unwrap_or_else(|_| {
+            tracing::warn!("Ignoring invalid {var} value '{value}'");
+            default
+        }),
+        _ => default,
+    }
+}
+
+fn byte_order_from_env() -> ByteOrder {
+    match env::var(BYTE_ORDER_ENV_VAR) {
+        Ok(value) if !value.is_empty() => ByteOrder::from_name(&value).unwrap_or_else(|| {
+            tracing::warn!("Ignoring invalid {BYTE_ORDER_ENV_VAR} value '{value}'");
+            ByteOrder::default()
+        }),
+        _ => ByteOrder::default(),
+    }
+}
+
+fn log_level_from_env() -> Option<LogLevel> {
+    match env::var(LOG_LEVEL_ENV_VAR) {
+        Ok(value) if !value.is_empty() => LogLevel::from_name(&value).or_else(|| {
+            tracing::warn!("Ignoring invalid {LOG_LEVEL_ENV_VAR} value '{value}'");
+            None
+        }),
+        _ => None,
+    }
+}
+
+fn interval_from_env(var: &str) -> Option<Duration> {
+    match env::var(var) {
+        Ok(value) if !value.is_empty() => match value.trim().parse() {
+            Ok(0) => None,
+            Ok(millis) => Some(Duration::from_millis(millis)),
+            Err(_) => {
+                tracing::warn!("Ignoring invalid {var} value '{value}'");
+                None
+            }
+        },
+        _ => None,
+    }
+}
+
+fn flag_from_env(var: &str) -> bool {
+    env::var(var)
+        .is_ok_and(|value| matches!(value.trim().to_ascii_lowercase().as_str(), "1" | "true"))
+}
+
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use signal::unix::{SignalKind, signal};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = terminate.recv() => {}
+                    _ = signal::ctrl_c() => {}
+                }
+                return;
+            }
+            Err(e) => tracing::warn!("Failed to install SIGTERM handler: {e}"),
+        }
+    }
+    let _ = signal::ctrl_c().await;
+}
+
+fn reload_on_hangup(state: Arc<HostState>) {
+    #[cfg(unix)]
+    {
+        use signal::unix::{SignalKind, signal};
+
+        match signal(SignalKind::hangup()) {
+            Ok(mut hangup) => {
+                tokio::spawn(async move {
+                    while hangup.recv().await.is_some() {
+                        match state.reload_settings() {
+                            Ok(()) => tracing::info!("Reloaded configuration"),
+                            Err(e) => tracing::error!("Failed to reload configuration: {e}"),
+                        }
+                    }
+                });
+            }
+            Err(e) => tracing::warn!("Failed to install SIGHUP handler: {e}"),
+        }
+    }
+    #[cfg(not(unix))]
+    drop(state);
+}
+
+fn host_settings(config: &Config) -> HostSettings {
+    HostSettings {
+        command_timeout: interval_from_env(COMMAND_TIMEOUT_ENV_VAR)
+            .or(config.command_timeout())
+            .unwrap_or(DEFAULT_COMMAND_TIMEOUT),
+        max_concurrent_processes: usize_from_env(
+            MAX_PROCESSES_ENV_VAR,
+            config
+                .limits
+                .max_concurrent_processes
+                .unwrap_or(DEFAULT_MAX_CONCURRENT_PROCESSES),
+        ),
+        max_output_bytes: Some(usize_from_env(
+            MAX_OUTPUT_ENV_VAR,
+            config
+                .limits
+                .max_output_bytes
+                .unwrap_or(DEFAULT_MAX_OUTPUT_BYTES),
+        ))
+        .filter(|&max| max > 0),
+        max_chunked_content_bytes: config
+            .limits
+            .max_chunked_content_bytes
+            .unwrap_or(DEFAULT_MAX_CHUNKED_CONTENT_BYTES),
+        fabric_path: config.fabric_path.clone(),
+        fabric_sha256: config.fabric_sha256.clone(),
+        search_paths: config.search_paths.clone(),
+        binary_names: config.binary_names.clone(),
+        allowed_executables: config.allowed_executables.clone(),
+        allowed_cwd_roots: config.allowed_cwd_roots.clone(),
+        env_allowlist: config
+            .env_allowlist
+            .clone()
+            .unwrap_or_else(|| HostSettings::default().env_allowlist),
+        allowed_request_env: config.allowed_request_env.clone(),
+        patterns_dir: config.defaults.patterns_dir.clone(),
+        request_defaults: config.request_defaults(),
+        pinned_patterns: config.pinned_patterns.clone(),
+        secret_env: config.secrets.clone(),
+        rate_limits: config.rate_limits.clone(),
+        budget: config.budget,
+        log_level: log_level_from_env()
+            .or(config.logging.level)
+            .unwrap_or_default(),
+    }
+}
+
+fn fabric_runner(path: impl AsRef<Utf8Path>, state: &HostState) -> FabricCommandRunner {
+    FabricCommandRunner::new(path)
+        .with_patterns_dir(state.patterns_dir())
+        .with_env_allowlist(state.env_allowlist())
+        .with_secrets(state.secret_env(), state.secrets.clone())
+}
+
+/// Opens another writer onto the host's stdout. Each writer encodes its own
+/// frames, and every flush reaches stdout whole, so a long stream never
+/// holds up other requests.
+fn fork_output(output: &Output) -> Output {
+    FramedWrite::new(output.get_ref().clone(), output.encoder().clone())
+}
+
+async fn drain<S>(state: &HostState, output: &mut S)
+where
+    S: Sink<Response> + Unpin,
+{
+    let interrupted = state.begin_drain().await;
+    state.disconnect(OrphanPolicy::Cancel).await;
+    for request_id in interrupted {
+        let _ = output
+            .send(Response {
+                id: request_id,
+                payload: ResponsePayload::Cancelled { request_id },
+            })
+            .await;
+    }
+}
+
+// A panicking request is answered with internalError once its task
+// unwinds; aborting would take the whole host down with it.
+#[cfg(panic = "abort")]
+compile_error!("tapestry-host must be built with panic = \"unwind\"");
+
+fn install_panic_hook() {
+    panic::set_hook(Box::new(|info| {
+        tracing::error!("Host panicked: {info}\n{}", Backtrace::force_capture());
+    }));
+}
+
+fn init_logging(config: &Config) {
+    let level = log_level_from_env()
+        .or(config.logging.level)
+        .unwrap_or_default();
+    let path = logging::log_path(config.logging.file.as_deref(), |name| env::var(name).ok());
+    if let Err(e) = logging::init(path.as_deref(), level) {
+        // Without a writable log file, stderr is the only place left to report.
+        let _ = logging::init(None, level);
+        tracing::warn!("Logging to stderr; could not open the log file: {e}");
+    }
+}
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    install_panic_hook();
     let stdin = stdin();
-    let stdout = stdout();
+    let stdout = RetryingWriter::new(stdout());
+    let write_stalls = stdout.stalls();
 
-    let read_codec = NativeMessagingCodec::<Request>::default();
-    let write_codec = NativeMessagingCodec::<tapestry_host::Response>::default();
+    let (config, config_error) = match config::load_from_env() {
+        Ok(config) => (config, None),
+        Err(e) => (Config::default(), Some(e)),
+    };
+    init_logging(&config);
+    if let Some(e) = config_error {
+        tracing::warn!("{e}");
+    }
+
+    let tracer = match ProtocolTracer::from_env()? {
+        Some(tracer) => Some(tracer),
+        None => config
+            .logging
+            .protocol_trace
+            .as_ref()
+            .map(ProtocolTracer::open)
+            .transpose()?,
+    }
+    .map(Arc::new);
+    let audit = match AuditLog::from_env()? {
+        Some(audit) => Some(audit),
+        None => config
+            .logging
+            .audit_log
+            .as_ref()
+            .map(AuditLog::open)
+            .transpose()?,
+    }
+    .map(Arc::new);
+    let compression = ContentCompression::default();
+    let byte_order = byte_order_from_env();
+    let read_codec = NativeMessagingCodec::<Request>::new(usize_from_env(
+        INBOUND_SIZE_ENV_VAR,
+        config
+            .limits
+            .max_inbound_message_size
+            .unwrap_or(MAX_INBOUND_MESSAGE_SIZE),
+    ))
+    .with_compression(compression.clone())
+    .with_byte_order(byte_order)
+    .with_tracer(tracer.clone())
+    .recovering();
+    let write_codec = NativeMessagingCodec::<Response>::new(usize_from_env(
+        OUTBOUND_SIZE_ENV_VAR,
+        config
+            .limits
+            .max_outbound_message_size
+            .unwrap_or(MAX_OUTBOUND_MESSAGE_SIZE),
+    ))
+    .with_compression(compression.clone())
+    .with_byte_order(byte_order)
+    .with_tracer(tracer);
 
     let mut input = FramedRead::new(stdin, read_codec);
-    let output = FramedWrite::new(stdout, write_codec);
-    let output_shared = Arc::new(Mutex::new(output));
+    let outbound_limit = write_codec.size_limit();
+    let mut output = FramedWrite::new(
+        SharedWriter::spawn(stdout),
+        AuditEncoder::new(write_codec, audit.clone()),
+    );
 
-    let process_registry: ProcessRegistry = Arc::new(Mutex::new(HashMap::new()));
+    let mut state = HostState::new()
+        .with_outbound_limit(outbound_limit)
+        .with_compression(compression)
+        .with_write_stalls(write_stalls)
+        .with_coalesce_interval(interval_from_env(COALESCE_ENV_VAR))
+        .with_strict(flag_from_env(STRICT_ENV_VAR))
+        .with_settings(host_settings(&config))
+        .with_config_path(config::config_path(|name| env::var(name).ok()))
+        .with_settings_loader(Box::new(|| {
+            config::load_from_env().map(|config| host_settings(&config))
+        }));
+    if let Some(journal) = jobs::journal_path_from_env() {
+        match JobRegistry::open(journal) {
+            Ok((registry, warning)) => {
+                if let Some(warning) = warning {
+                    tracing::warn!("{warning}");
+                    state = state.with_startup_warning(warning);
+                }
+                state = state.with_jobs(registry);
+            }
+            Err(e) => tracing::warn!("Job journal unavailable: {e}"),
+        }
+    }
+    if let Some(journal) = usage::usage_path_from_env() {
+        match UsageTracker::open(journal) {
+            Ok((tracker, warning)) => {
+                if let Some(warning) = warning {
+                    tracing::warn!("{warning}");
+                    state = state.with_startup_warning(warning);
+                }
+                state = state.with_usage(tracker);
+            }
+            Err(e) => tracing::warn!("Usage journal unavailable: {e}"),
+        }
+    }
+    let state = Arc::new(state);
+    reload_on_hangup(state.clone());
 
-    while let Some(message) = input.next().await {
-        if let Ok(request) = message {
-            let output_clone = output_shared.clone();
-            let process_registry_clone = process_registry.clone();
+    let orphan_policy = jobs::orphan_policy_from_env().unwrap_or_else(|e| {
+        tracing::warn!("{e}");
+        OrphanPolicy::default()
+    });
+
+    let router = Arc::new(notify::router_from_env().unwrap_or_else(|e| {
+        tracing::warn!("{e}");
+        NotificationRouter::default()
+    }));
+
+    let report = boot_report(
+        resolve_path_in(
+            config.fabric_path.as_ref(),
+            &config.search_paths,
+            &config.binary_names,
+            &config.allowed_executables,
+        )
+        .and_then(|path| verify_fabric(&path, &state).map(|()| path))
+        .map(|path| fabric_runner(path, &state)),
+        vec!["stdio".to_string()],
+    )
+    .await;
+    tracing::info!("Boot report: {}", serde_json::to_string(&report)?);
+    if flag_from_env(HOST_READY_ENV_VAR) {
+        output
+            .send(Response {
+                id: Uuid::nil(),
+                payload: ResponsePayload::HostReady(report),
+            })
+            .await?;
+    }
+
+    let orphaned = state.jobs.take_orphaned();
+    if !orphaned.is_empty() {
+        router
+            .dispatch(notify::orphaned_notification(&orphaned), &mut output)
+            .await?;
+        output
+            .send(Response {
+                id: Uuid::nil(),
+                payload: ResponsePayload::OrphanedJobs { jobs: orphaned },
+            })
+            .await?;
+    }
+    let mut job_events = state.jobs.subscribe();
+    let mut events_output = fork_output(&output);
+    let events_state = state.clone();
+    tokio::spawn(async move {
+        loop {
+            match job_events.recv().await {
+                Ok(event) => {
+                    let notification = notify::job_notification(
+                        &event,
+                        events_state.jobs.get(event.job_id).as_ref(),
+                    );
+                    let _ = events_output
+                        .send(Response {
+                            id: Uuid::nil(),
+                            payload: ResponsePayload::JobEvent(event),
+                        })
+                        .await;
+                    if let Some(notification) = notification {
+                        let _ = router.dispatch(notification, &mut events_output).await;
+                    }
+                }
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+
+    if let Some(period) = interval_from_env(HEARTBEAT_ENV_VAR) {
+        let mut heartbeat_output = fork_output(&output);
+        tokio::spawn(async move {
+            let started = Instant::now();
+            let mut ticks = time::interval_at(started + period, period);
+            ticks.set_missed_tick_behavior(MissedTickBehavior::Skip);
+            for sequence in 1.. {
+                ticks.tick().await;
+                let uptime_ms = u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX);
+                let sent = heartbeat_output
+                    .send(Response {
+                        id: Uuid::nil(),
+                        payload: ResponsePayload::HostHeartbeat {
+                            sequence,
+                            uptime_ms,
+                        },
+                    })
+                    .await;
+                if sent.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    let mut shutdown = pin!(shutdown_signal());
+    loop {
+        let message = tokio::select! {
+            message = input.next() => message,
+            () = &mut shutdown => {
+                tracing::info!("Received shutdown signal; draining in-flight requests");
+                drain(&state, &mut output).await;
+                // The blocking stdin read would otherwise hold up runtime shutdown.
+                std::process::exit(0);
+            }
+        };
+        let Some(message) = message else {
+            break;
+        };
+        if let Ok(Err(frame)) = message {
+            if frame.id.is_none() {
+                tracing::warn!(
+                    "Malformed request frame without a recoverable id ({} bytes): {}",
+                    frame.size,
+                    frame.error
+                );
+            }
+            let _ = handle_malformed_frame(&mut output, frame).await;
+        } else if let Ok(Ok(request)) = message {
+            if let Some(audit) = &audit {
+                audit.record_request(&request);
+            }
+            let state_clone = state.clone();
+
+            let Some(in_flight) = state.track_request(request.id) else {
+                let _ = handle_duplicate_request(&mut output, request.id).await;
+                continue;
+            };
+
+            if matches!(request.payload, tapestry_host::RequestPayload::Shutdown)
+                && !request.validate_only
+            {
+                tracing::info!("Received shutdown request; draining in-flight requests");
+                drop(in_flight);
+                drain(&state, &mut output).await;
+                let _ = handle_shutdown(&mut output, request.id).await;
+                std::process::exit(0);
+            }
 
             if let tapestry_host::RequestPayload::CancelProcess {
                 request_id: target_id,
             } = &request.payload
+                && !request.validate_only
             {
                 let target_id = *target_id;
-                let registry = process_registry_clone.lock().await;
+                let registry = state_clone.process_registry.lock().await;
                 if let Some(cancel_sender) = registry.get(&target_id) {
                     let _ = cancel_sender.send(true);
                 }
                 drop(registry);
             }
 
-            tokio::spawn(async move {
-                let mut output_guard = output_clone.lock().await;
-                if let Err(_e) = handle_request(
-                    &mut *output_guard,
+            let request_id = request.id;
+            let mut task_output = fork_output(&output);
+            let task = tokio::spawn(async move {
+                let _ = handle_request(
+                    &mut task_output,
                     request,
-                    |p| FabricCommandRunner::new(p),
-                    process_registry_clone,
+                    |p| fabric_runner(p, &state_clone),
+                    &state_clone,
                 )
-                .await
-                {}
+                .await;
             });
+            let mut panic_output = fork_output(&output);
+            tokio::spawn(async move {
+                if let Err(e) = task.await
+                    && e.is_panic()
+                {
+                    let _ = handle_panicked_request(&mut panic_output, request_id, e.into_panic())
+                        .await;
+                }
+                drop(in_flight);
+            });
+        } else if let Err(e) = message {
+            tracing::warn!("Failed to read request frame: {e}");
         }
     }
 
+    state.disconnect(orphan_policy).await;
+
     Ok(())
 }
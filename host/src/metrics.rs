@@ -0,0 +1,166 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+
+use crate::{Histogram, HistogramBucket, HostMetrics};
+
+const LATENCY_BUCKETS_MS: &[u64] = &[10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10_000, 30_000];
+
+struct LatencyHistogram {
+    counts: [u64; LATENCY_BUCKETS_MS.len() + 1],
+    count: u64,
+    sum_ms: u64,
+    max_ms: u64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self {
+            counts: [0; LATENCY_BUCKETS_MS.len() + 1],
+            count: 0,
+            sum_ms: 0,
+            max_ms: 0,
+        }
+    }
+}
+
+impl LatencyHistogram {
+    fn observe(&mut self, elapsed: Duration) {
+        let millis = u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX);
+        let bucket = LATENCY_BUCKETS_MS.partition_point(|&bound| bound < millis);
+        self.counts[bucket] += 1;
+        self.count += 1;
+        self.sum_ms = self.sum_ms.saturating_add(millis);
+        self.max_ms = self.max_ms.max(millis);
+    }
+
+    fn snapshot(&self) -> Histogram {
+        let bounds = LATENCY_BUCKETS_MS.iter().copied().map(Some).chain([None]);
+        Histogram {
+            count: self.count,
+            sum_ms: self.sum_ms,
+            max_ms: self.max_ms,
+            buckets: bounds
+                .zip(self.counts)
+                .map(|(le_ms, count)| HistogramBucket { le_ms, count })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Counters {
+    requests: BTreeMap<&'static str, u64>,
+    failures: BTreeMap<String, u64>,
+    time_to_first_chunk: LatencyHistogram,
+    spawn_time: LatencyHistogram,
+}
+
+/// In-memory counters and latency histograms since the host started,
+/// reported by `native.getMetrics`.
+#[derive(Default)]
+pub struct Metrics {
+    counters: Mutex<Counters>,
+}
+
+impl Metrics {
+    pub fn record_request(&self, request_type: &'static str) {
+        *self.counters().requests.entry(request_type).or_default() += 1;
+    }
+
+    pub fn record_failure(&self, code: &str) {
+        *self
+            .counters()
+            .failures
+            .entry(code.to_string())
+            .or_default() += 1;
+    }
+
+    pub fn record_spawn_time(&self, elapsed: Duration) {
+        self.counters().spawn_time.observe(elapsed);
+    }
+
+    pub fn record_time_to_first_chunk(&self, elapsed: Duration) {
+        self.counters().time_to_first_chunk.observe(elapsed);
+    }
+
+    pub fn snapshot(&self, uptime_ms: u64) -> HostMetrics {
+        let counters = self.counters();
+        HostMetrics {
+            uptime_ms,
+            requests: counters
+                .requests
+                .iter()
+                .map(|(request_type, count)| (request_type.to_string(), *count))
+                .collect(),
+            failures: counters.failures.clone(),
+            time_to_first_chunk: counters.time_to_first_chunk.snapshot(),
+            spawn_time: counters.spawn_time.snapshot(),
+        }
+    }
+
+    fn counters(&self) -> MutexGuard<'_, Counters> {
+        self.counters.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_buckets_by_upper_bound() {
+        let mut histogram = LatencyHistogram::default();
+        histogram.observe(Duration::from_millis(10));
+        histogram.observe(Duration::from_millis(11));
+        histogram.observe(Duration::from_secs(60));
+
+        let snapshot = histogram.snapshot();
+        assert_eq!(snapshot.count, 3);
+        assert_eq!(snapshot.sum_ms, 60_021);
+        assert_eq!(snapshot.max_ms, 60_000);
+        assert_eq!(snapshot.buckets.len(), LATENCY_BUCKETS_MS.len() + 1);
+        assert_eq!(
+            snapshot.buckets[0],
+            HistogramBucket {
+                le_ms: Some(10),
+                count: 1
+            }
+        );
+        assert_eq!(
+            snapshot.buckets[1],
+            HistogramBucket {
+                le_ms: Some(25),
+                count: 1
+            }
+        );
+        assert_eq!(
+            snapshot.buckets.last(),
+            Some(&HistogramBucket {
+                le_ms: None,
+                count: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_snapshot_counts_requests_and_failures() {
+        let metrics = Metrics::default();
+        metrics.record_request("native.ping");
+        metrics.record_request("native.ping");
+        metrics.record_request("native.processContent");
+        metrics.record_failure("timeout");
+
+        let snapshot = metrics.snapshot(1000);
+        assert_eq!(snapshot.uptime_ms, 1000);
+        assert_eq!(snapshot.requests["native.ping"], 2);
+        assert_eq!(snapshot.requests["native.processContent"], 1);
+        assert_eq!(
+            snapshot.failures,
+            BTreeMap::from([("timeout".to_string(), 1)])
+        );
+        assert_eq!(snapshot.spawn_time.count, 0);
+    }
+}
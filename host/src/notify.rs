@@ -0,0 +1,375 @@
+use std::{env, process::Stdio};
+
+use futures_util::{Sink, SinkExt};
+use tokio::{io::AsyncWriteExt, process::Command};
+use uuid::Uuid;
+
+use crate::{
+    JobEvent, JobEventKind, JobSummary, Notification, NotificationKind, Response, ResponsePayload,
+    Severity, jobs::Job,
+};
+
+pub const ROUTES_ENV_VAR: &str = "TAPESTRY_NOTIFY_ROUTES";
+pub const WEBHOOK_ENV_VAR: &str = "TAPESTRY_NOTIFY_WEBHOOK";
+const NOTIFICATION_TITLE: &str = "Tapestry";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotificationSink {
+    Frame,
+    Desktop,
+    Webhook,
+}
+
+impl NotificationSink {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "frame" => Some(NotificationSink::Frame),
+            "desktop" => Some(NotificationSink::Desktop),
+            "webhook" => Some(NotificationSink::Webhook),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Selector {
+    Any,
+    Kind(NotificationKind),
+    Severity(Severity),
+}
+
+impl Selector {
+    fn from_name(name: &str) -> Option<Self> {
+        let selector = match name {
+            "*" => Selector::Any,
+            "jobCompleted" => Selector::Kind(NotificationKind::JobCompleted),
+            "jobFailed" => Selector::Kind(NotificationKind::JobFailed),
+            "jobCancelled" => Selector::Kind(NotificationKind::JobCancelled),
+            "jobsOrphaned" => Selector::Kind(NotificationKind::JobsOrphaned),
+            "info" => Selector::Severity(Severity::Info),
+            "warning" => Selector::Severity(Severity::Warning),
+            "error" => Selector::Severity(Severity::Error),
+            _ => return None,
+        };
+        Some(selector)
+    }
+
+    fn matches(self, notification: &Notification) -> bool {
+        match self {
+            Selector::Any => true,
+            Selector::Kind(kind) => notification.kind == kind,
+            Selector::Severity(severity) => notification.severity == severity,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct NotificationRouter {
+    routes: Vec<(Selector, Vec<NotificationSink>)>,
+    webhook: Option<String>,
+}
+
+impl NotificationRouter {
+    pub fn parse(routes: &str, webhook: Option<String>) -> Result<Self, String> {
+        let mut parsed = Vec::new();
+        for rule in routes
+            .split(';')
+            .map(str::trim)
+            .filter(|rule| !rule.is_empty())
+        {
+            let (selector, sinks) = rule
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid notification route '{rule}'"))?;
+            let selector = Selector::from_name(selector.trim())
+                .ok_or_else(|| format!("Unknown notification event '{}'", selector.trim()))?;
+            let sinks = sinks
+                .split(',')
+                .map(str::trim)
+                .map(|name| {
+                    NotificationSink::from_name(name)
+                        .ok_or_else(|| format!("Unknown notification sink '{name}'"))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            if sinks.contains(&NotificationSink::Webhook) && webhook.is_none() {
+                return Err(format!("webhook routes require {WEBHOOK_ENV_VAR}"));
+            }
+            parsed.push((selector, sinks));
+        }
+
+        Ok(Self {
+            routes: parsed,
+            webhook,
+        })
+    }
+
+    pub fn sinks(&self, notification: &Notification) -> Vec<NotificationSink> {
+        let mut sinks = Vec::new();
+        for (selector, route_sinks) in &self.routes {
+            if selector.matches(notification) {
+                for sink in route_sinks {
+                    if !sinks.contains(sink) {
+                        sinks.push(*sink);
+                    }
+                }
+            }
+        }
+        sinks
+    }
+
+    pub async fn dispatch<S>(
+        &self,
+        notification: Notification,
+        frames: &mut S,
+    ) -> Result<(), S::Error>
+    where
+        S: Sink<Response> + Unpin,
+    {
+        for sink in self.sinks(&notification) {
+            match sink {
+                NotificationSink::Frame => {
+                    frames
+                        .send(Response {
+                            id: Uuid::nil(),
+                            payload: ResponsePayload::Notification(notification.clone()),
+                        })
+                        .await?;
+                }
+                NotificationSink::Desktop => match desktop_command(&notification) {
+                    Some(command) => spawn_delivery(command, None),
+                    None => {
+                        tracing::warn!("Desktop notifications are not supported on this platform")
+                    }
+                },
+                NotificationSink::Webhook => {
+                    if let Some(url) = &self.webhook {
+                        spawn_delivery(
+                            webhook_command(url),
+                            serde_json::to_vec(&notification).ok(),
+                        );
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn router_from_env() -> Result<NotificationRouter, String> {
+    match env::var(ROUTES_ENV_VAR) {
+        Ok(routes) if !routes.is_empty() => {
+            let webhook = env::var(WEBHOOK_ENV_VAR).ok().filter(|url| !url.is_empty());
+            NotificationRouter::parse(&routes, webhook)
+                .map_err(|e| format!("Ignoring {ROUTES_ENV_VAR}: {e}"))
+        }
+        _ => Ok(NotificationRouter::default()),
+    }
+}
+
+pub fn job_notification(event: &JobEvent, job: Option<&Job>) -> Option<Notification> {
+    let job_id = event.job_id;
+    let (kind, severity, message) = match event.event {
+        JobEventKind::Started => return None,
+        JobEventKind::Completed => (
+            NotificationKind::JobCompleted,
+            Severity::Info,
+            format!("Job {job_id} completed"),
+        ),
+        JobEventKind::Failed => (
+            NotificationKind::JobFailed,
+            Severity::Error,
+            match job.and_then(|job| job.error.as_deref()) {
+                Some(error) => format!("Job {job_id} failed: {error}"),
+                None => format!("Job {job_id} failed"),
+            },
+        ),
+        JobEventKind::Cancelled => (
+            NotificationKind::JobCancelled,
+            Severity::Warning,
+            format!("Job {job_id} was cancelled"),
+        ),
+    };
+
+    Some(Notification {
+        kind,
+        severity,
+        title: NOTIFICATION_TITLE.to_string(),
+        message,
+        job_id: Some(job_id),
+    })
+}
+
+pub fn orphaned_notification(jobs: &[JobSummary]) -> Notification {
+    Notification {
+        kind: NotificationKind::JobsOrphaned,
+        severity: Severity::Warning,
+        title: NOTIFICATION_TITLE.to_string(),
+        message: format!(
+            "{} job(s) were interrupted when the host last exited",
+            jobs.len()
+        ),
+        job_id: None,
+    }
+}
+
+fn desktop_command(notification: &Notification) -> Option<Vec<String>> {
+    if cfg!(target_os = "macos") {
+        Some(vec![
+            "osascript".to_string(),
+            "-e".to_string(),
+            format!(
+                "display notification {:?} with title {:?}",
+                notification.message, notification.title
+            ),
+        ])
+    } else if cfg!(unix) {
+        let urgency = match notification.severity {
+            Severity::Info => "low",
+            Severity::Warning => "normal",
+            Severity::Error => "critical",
+        };
+        Some(vec![
+            "notify-send".to_string(),
+            format!("--urgency={urgency}"),
+            notification.title.clone(),
+            notification.message.clone(),
+        ])
+    } else {
+        None
+    }
+}
+
+fn webhook_command(url: &str) -> Vec<String> {
+    [
+        "curl",
+        "-fsS",
+        "-X",
+        "POST",
+        "-H",
+        "Content-Type: application/json",
+        "--data-binary",
+        "@-",
+        url,
+    ]
+    .into_iter()
+    .map(str::to_string)
+    .collect()
+}
+
+fn spawn_delivery(command: Vec<String>, body: Option<Vec<u8>>) {
+    tokio::spawn(async move {
+        let Some((program, args)) = command.split_first() else {
+            return;
+        };
+        let child = Command::new(program)
+            .args(args)
+            .stdin(if body.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        let result = match child {
+            Ok(mut child) => {
+                if let (Some(mut stdin), Some(body)) = (child.stdin.take(), body) {
+                    let _ = stdin.write_all(&body).await;
+                }
+                child.wait().await.map(|status| status.success())
+            }
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(true) => {}
+            Ok(false) => tracing::warn!("Notification delivery via {program} failed"),
+            Err(e) => tracing::warn!("Notification delivery via {program} failed: {e}"),
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notification(kind: NotificationKind, severity: Severity) -> Notification {
+        Notification {
+            kind,
+            severity,
+            title: NOTIFICATION_TITLE.to_string(),
+            message: "message".to_string(),
+            job_id: None,
+        }
+    }
+
+    #[test]
+    fn test_routes_match_by_kind_and_severity() {
+        let router = NotificationRouter::parse(
+            "jobFailed=desktop,frame; error=frame,webhook; *=frame",
+            Some("https://example.com/hook".to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(
+            router.sinks(&notification(NotificationKind::JobFailed, Severity::Error)),
+            vec![
+                NotificationSink::Desktop,
+                NotificationSink::Frame,
+                NotificationSink::Webhook
+            ]
+        );
+        assert_eq!(
+            router.sinks(&notification(
+                NotificationKind::JobCompleted,
+                Severity::Info
+            )),
+            vec![NotificationSink::Frame]
+        );
+        assert!(
+            NotificationRouter::default()
+                .sinks(&notification(NotificationKind::JobFailed, Severity::Error))
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid_routes() {
+        assert!(NotificationRouter::parse("jobFailed", None).is_err());
+        assert!(NotificationRouter::parse("jobExploded=frame", None).is_err());
+        assert!(NotificationRouter::parse("*=pager", None).is_err());
+        assert!(NotificationRouter::parse("*=webhook", None).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_sends_frames_for_frame_routes() {
+        let router = NotificationRouter::parse("jobCancelled=frame", None).unwrap();
+        let event = JobEvent {
+            job_id: Uuid::new_v4(),
+            event: JobEventKind::Cancelled,
+            timestamp_ms: 0,
+        };
+        let mut frames: Vec<Response> = Vec::new();
+
+        let cancelled = job_notification(&event, None).unwrap();
+        router
+            .dispatch(cancelled.clone(), &mut frames)
+            .await
+            .unwrap();
+        router
+            .dispatch(orphaned_notification(&[]), &mut frames)
+            .await
+            .unwrap();
+
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].payload, ResponsePayload::Notification(cancelled));
+        assert_eq!(
+            job_notification(
+                &JobEvent {
+                    event: JobEventKind::Started,
+                    ..event
+                },
+                None
+            ),
+            None
+        );
+    }
+}
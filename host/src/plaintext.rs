@@ -0,0 +1,222 @@
+#[derive(Debug, Default)]
+pub struct PlainTextFormatter {
+    fence: Option<&'static str>,
+}
+
+impl PlainTextFormatter {
+    pub fn format_line(&mut self, line: &str) -> String {
+        let (body, newline) = match line.strip_suffix('\n') {
+            Some(body) => (body.strip_suffix('\r').unwrap_or(body), "\n"),
+            None => (line, ""),
+        };
+        let trimmed = body.trim_start();
+
+        if let Some(fence) = self.fence {
+            if trimmed.starts_with(fence) {
+                self.fence = None;
+                return String::new();
+            }
+            return line.to_string();
+        }
+        if let Some(fence) = ["```", "~~~"]
+            .into_iter()
+            .find(|fence| trimmed.starts_with(fence))
+        {
+            self.fence = Some(fence);
+            return String::new();
+        }
+
+        match format_block(body) {
+            Some(block) => format!("{block}{newline}"),
+            None => String::new(),
+        }
+    }
+}
+
+fn format_block(body: &str) -> Option<String> {
+    let indent = &body[..body.len() - body.trim_start().len()];
+    let mut rest = body.trim_start();
+
+    while let Some(quoted) = rest.strip_prefix('>') {
+        rest = quoted.trim_start();
+    }
+
+    if is_table_separator(rest) {
+        return None;
+    }
+    if is_rule(rest) {
+        return Some(String::new());
+    }
+
+    let hashes = rest.len() - rest.trim_start_matches('#').len();
+    if (1..=6).contains(&hashes) && (rest.len() == hashes || rest[hashes..].starts_with(' ')) {
+        let heading = rest[hashes..].trim().trim_end_matches('#').trim_end();
+        return Some(format_inline(heading).to_uppercase());
+    }
+
+    if let Some(item) = ["- ", "* ", "+ "]
+        .into_iter()
+        .find_map(|marker| rest.strip_prefix(marker))
+    {
+        let item = item
+            .strip_prefix("[ ] ")
+            .or_else(|| item.strip_prefix("[x] "))
+            .or_else(|| item.strip_prefix("[X] "))
+            .unwrap_or(item);
+        return Some(format!("{indent}- {}", format_inline(item)));
+    }
+
+    if rest.starts_with('|') {
+        let cells: Vec<String> = rest
+            .trim_matches('|')
+            .split('|')
+            .map(|cell| format_inline(cell.trim()))
+            .collect();
+        return Some(format!("{indent}{}", cells.join(", ")));
+    }
+
+    Some(format!("{indent}{}", format_inline(rest)))
+}
+
+fn is_rule(line: &str) -> bool {
+    let line = line.trim_end();
+    ['-', '*', '_', '='].into_iter().any(|marker| {
+        line.chars().filter(|c| *c == marker).count() >= 3
+            && line.chars().all(|c| c == marker || c == ' ')
+    })
+}
+
+fn is_table_separator(line: &str) -> bool {
+    let line = line.trim_end();
+    line.starts_with('|')
+        && line.contains('-')
+        && line.chars().all(|c| matches!(c, '|' | '-' | ':' | ' '))
+}
+
+fn format_inline(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        if let Some(code) = rest.strip_prefix('`')
+            && let Some(end) = code.find('`')
+        {
+            output.push_str(&code[..end]);
+            rest = &code[end + 1..];
+            continue;
+        }
+
+        let link = rest.strip_prefix("![").or_else(|| rest.strip_prefix('['));
+        if let Some((label, url, remaining)) = link.and_then(split_link) {
+            let label = format_inline(label);
+            if label.is_empty() || label == url {
+                output.push_str(url);
+            } else {
+                output.push_str(&format!("{label} ({url})"));
+            }
+            rest = remaining;
+            continue;
+        }
+
+        if let Some(marker) = ["**", "__", "~~"]
+            .into_iter()
+            .find(|marker| rest.starts_with(marker))
+        {
+            rest = &rest[marker.len()..];
+            continue;
+        }
+
+        if c == '*'
+            && !(output.ends_with(char::is_whitespace)
+                && rest[1..].starts_with(char::is_whitespace))
+        {
+            rest = &rest[1..];
+            continue;
+        }
+
+        if c == '\\'
+            && let Some(escaped) = rest[1..].chars().next()
+            && escaped.is_ascii_punctuation()
+        {
+            output.push(escaped);
+            rest = &rest[1 + escaped.len_utf8()..];
+            continue;
+        }
+
+        output.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+
+    output
+}
+
+fn split_link(text: &str) -> Option<(&str, &str, &str)> {
+    let label_end = text.find("](")?;
+    let url_start = label_end + 2;
+    let url_end = url_start + text[url_start..].find(')')?;
+    let url = text[url_start..url_end]
+        .split_once(' ')
+        .map_or(&text[url_start..url_end], |(url, _)| url);
+    Some((&text[..label_end], url, &text[url_end + 1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(text: &str) -> String {
+        let mut formatter = PlainTextFormatter::default();
+        text.split_inclusive('\n')
+            .map(|line| formatter.format_line(line))
+            .collect()
+    }
+
+    #[test]
+    fn test_headings_become_upper_case() {
+        assert_eq!(format("# Key *Points*\n"), "KEY POINTS\n");
+        assert_eq!(format("### Closing ###\n"), "CLOSING\n");
+        assert_eq!(format("#hashtag\n"), "#hashtag\n");
+    }
+
+    #[test]
+    fn test_lists_use_dashes() {
+        assert_eq!(
+            format("* first\n  + nested **bold**\n- [x] done\n1. ordered\n"),
+            "- first\n  - nested bold\n- done\n1. ordered\n"
+        );
+    }
+
+    #[test]
+    fn test_links_and_images_show_urls() {
+        assert_eq!(
+            format("See [the docs](https://example.com \"Docs\") and ![logo](logo.png).\n"),
+            "See the docs (https://example.com) and logo (logo.png).\n"
+        );
+        assert_eq!(
+            format("[https://example.com](https://example.com)\n"),
+            "https://example.com\n"
+        );
+    }
+
+    #[test]
+    fn test_strips_inline_and_block_syntax() {
+        assert_eq!(
+            format(
+                "> Quote with `code`, ~~old~~ and \\*stars\\*\n---\n| a | b |\n|---|:-:|\n| 1 | 2 |\n"
+            ),
+            "Quote with code, old and *stars*\n\na, b\n1, 2\n"
+        );
+        assert_eq!(
+            format("snake_case stays 2 * 3 — *ünïcode*\n"),
+            "snake_case stays 2 * 3 — ünïcode\n"
+        );
+    }
+
+    #[test]
+    fn test_code_blocks_are_kept_verbatim() {
+        assert_eq!(
+            format("```rust\nlet x = **y**;\n```\n# After\n"),
+            "let x = **y**;\nAFTER\n"
+        );
+    }
+}
@@ -0,0 +1,116 @@
+use std::{
+    collections::HashMap,
+    num::NonZeroU32,
+    sync::{Mutex, PoisonError},
+    time::Duration,
+};
+
+use serde::Deserialize;
+use tokio::time::Instant;
+
+/// A `[rate_limits]` entry: `per_minute` tokens refill continuously into a
+/// bucket holding at most `burst` (default `per_minute`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    pub per_minute: NonZeroU32,
+    pub burst: Option<NonZeroU32>,
+}
+
+impl RateLimit {
+    fn capacity(&self) -> f64 {
+        f64::from(self.burst.unwrap_or(self.per_minute).get())
+    }
+
+    fn per_second(&self) -> f64 {
+        f64::from(self.per_minute.get()) / 60.0
+    }
+}
+
+struct Bucket {
+    limit: RateLimit,
+    tokens: f64,
+    updated: Instant,
+}
+
+#[derive(Default)]
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    /// Takes a token from the `key` bucket, or returns how long until one
+    /// is available. A changed limit (e.g. after a config reload) starts a
+    /// fresh, full bucket.
+    pub fn acquire(&self, key: &str, limit: RateLimit) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            limit,
+            tokens: limit.capacity(),
+            updated: now,
+        });
+        if bucket.limit != limit {
+            *bucket = Bucket {
+                limit,
+                tokens: limit.capacity(),
+                updated: now,
+            };
+        }
+
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * limit.per_second()).min(limit.capacity());
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / limit.per_second(),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit(per_minute: u32, burst: u32) -> RateLimit {
+        RateLimit {
+            per_minute: NonZeroU32::new(per_minute).unwrap(),
+            burst: NonZeroU32::new(burst),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_acquire_allows_burst_then_refills() {
+        let limiter = RateLimiter::default();
+        let limit = limit(60, 2);
+
+        assert!(limiter.acquire("native.processContent", limit).is_ok());
+        assert!(limiter.acquire("native.processContent", limit).is_ok());
+        let retry_after = limiter.acquire("native.processContent", limit).unwrap_err();
+        assert_eq!(retry_after, Duration::from_secs(1));
+        assert!(limiter.acquire("native.listPatterns", limit).is_ok());
+
+        tokio::time::advance(Duration::from_millis(500)).await;
+        assert_eq!(
+            limiter.acquire("native.processContent", limit),
+            Err(Duration::from_millis(500))
+        );
+
+        tokio::time::advance(Duration::from_millis(500)).await;
+        assert!(limiter.acquire("native.processContent", limit).is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_acquire_resets_bucket_when_limit_changes() {
+        let limiter = RateLimiter::default();
+
+        assert!(limiter.acquire("native.ping", limit(1, 1)).is_ok());
+        assert!(limiter.acquire("native.ping", limit(1, 1)).is_err());
+        assert!(limiter.acquire("native.ping", limit(10, 1)).is_ok());
+    }
+}
@@ -0,0 +1,159 @@
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    sync::Mutex,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures_util::Sink;
+use tokio::time::Instant;
+use uuid::Uuid;
+
+use crate::{Response, ResponsePayload};
+
+const REPLAY_CAPACITY: usize = 16;
+const REPLAY_TTL: Duration = Duration::from_secs(300);
+
+struct ReplayEntry {
+    request_id: Uuid,
+    recorded: Instant,
+    payloads: Vec<ResponsePayload>,
+}
+
+#[derive(Default)]
+pub struct ReplayBuffer {
+    entries: Mutex<VecDeque<ReplayEntry>>,
+}
+
+impl ReplayBuffer {
+    pub fn start(&self, request_id: Uuid) {
+        if let Ok(mut entries) = self.entries.lock() {
+            prune(&mut entries);
+            if entries.len() == REPLAY_CAPACITY {
+                entries.pop_front();
+            }
+            entries.push_back(ReplayEntry {
+                request_id,
+                recorded: Instant::now(),
+                payloads: Vec::new(),
+            });
+        }
+    }
+
+    pub fn record(&self, request_id: Uuid, payload: &ResponsePayload) {
+        if matches!(payload, ResponsePayload::Progress { .. }) {
+            return;
+        }
+
+        if let Ok(mut entries) = self.entries.lock()
+            && let Some(entry) = entries
+                .iter_mut()
+                .find(|entry| entry.request_id == request_id)
+        {
+            entry.recorded = Instant::now();
+            entry.payloads.push(payload.clone());
+        }
+    }
+
+    pub fn get(&self, request_id: Uuid) -> Option<Vec<ResponsePayload>> {
+        let mut entries = self.entries.lock().ok()?;
+        prune(&mut entries);
+        entries
+            .iter()
+            .find(|entry| entry.request_id == request_id)
+            .map(|entry| entry.payloads.clone())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().map_or(0, |mut entries| {
+            prune(&mut entries);
+            entries.len()
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn prune(entries: &mut VecDeque<ReplayEntry>) {
+    entries.retain(|entry| entry.recorded.elapsed() < REPLAY_TTL);
+}
+
+pub struct ReplaySink<'a, S> {
+    inner: &'a mut S,
+    request_id: Uuid,
+    buffer: &'a ReplayBuffer,
+}
+
+impl<'a, S> ReplaySink<'a, S> {
+    pub fn new(inner: &'a mut S, request_id: Uuid, buffer: &'a ReplayBuffer) -> Self {
+        buffer.start(request_id);
+        Self {
+            inner,
+            request_id,
+            buffer,
+        }
+    }
+}
+
+impl<S> Sink<Response> for ReplaySink<'_, S>
+where
+    S: Sink<Response> + Unpin,
+{
+    type Error = S::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut *self.inner).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, response: Response) -> Result<(), Self::Error> {
+        self.buffer.record(self.request_id, &response.payload);
+        Pin::new(&mut *self.inner).start_send(response)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut *self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut *self.inner).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_buffer_expires_and_evicts() {
+        let buffer = ReplayBuffer::default();
+        let request_id = Uuid::new_v4();
+        buffer.start(request_id);
+        buffer.record(
+            request_id,
+            &ResponsePayload::Content {
+                content: "chunk".to_string(),
+            },
+        );
+        buffer.record(
+            request_id,
+            &ResponsePayload::Progress {
+                bytes_out: 5,
+                elapsed_ms: 1000,
+            },
+        );
+        assert_eq!(buffer.get(request_id).unwrap().len(), 1);
+
+        tokio::time::advance(REPLAY_TTL).await;
+        assert!(buffer.get(request_id).is_none());
+
+        let first = Uuid::new_v4();
+        buffer.start(first);
+        for _ in 0..REPLAY_CAPACITY {
+            buffer.start(Uuid::new_v4());
+        }
+        assert!(buffer.get(first).is_none());
+    }
+}
@@ -0,0 +1,268 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+use serde::Deserialize;
+use thiserror::Error;
+
+pub const KEYCHAIN_SERVICE: &str = "tapestry";
+const KEYCHAIN_SCHEME: &str = "keychain:";
+
+// Platform keychains cannot enumerate entries portably, so the stored names
+// are tracked in one extra entry that a valid secret name can never collide with.
+const INDEX_ENTRY: &str = ".names";
+
+#[derive(Debug, Error)]
+pub enum SecretError {
+    #[error("Keychain error: {0}")]
+    Keychain(#[from] keyring::Error),
+    #[error("Secret index is corrupt: {0}")]
+    Index(#[from] serde_json::Error),
+    #[error("{var} references {reference}, which is not stored")]
+    Missing { var: String, reference: SecretRef },
+}
+
+/// A `[secrets]` config value such as `keychain:openai`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct SecretRef {
+    pub name: String,
+}
+
+impl TryFrom<String> for SecretRef {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.strip_prefix(KEYCHAIN_SCHEME) {
+            Some(name) if valid_name(name) => Ok(Self {
+                name: name.to_string(),
+            }),
+            _ => Err(format!(
+                "invalid secret reference {value:?}, expected {KEYCHAIN_SCHEME}<name>"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SecretRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{KEYCHAIN_SCHEME}{}", self.name)
+    }
+}
+
+/// Backing store for API keys and other credentials handed to fabric.
+///
+/// Calls may block on the platform keychain (and prompt the user on some
+/// systems), so async callers should run them on a blocking thread.
+pub trait SecretStore: Send + Sync {
+    fn get(&self, name: &str) -> Result<Option<String>, SecretError>;
+    fn set(&self, name: &str, secret: &str) -> Result<(), SecretError>;
+    /// Returns whether a secret with this name existed.
+    fn delete(&self, name: &str) -> Result<bool, SecretError>;
+    fn names(&self) -> Result<Vec<String>, SecretError>;
+}
+
+/// Secrets held in the OS keychain: Keychain on macOS, Secret Service on
+/// Linux, and Credential Manager on Windows.
+pub struct KeychainStore {
+    service: String,
+    index: Mutex<()>,
+}
+
+impl Default for KeychainStore {
+    fn default() -> Self {
+        Self::new(KEYCHAIN_SERVICE)
+    }
+}
+
+impl KeychainStore {
+    pub fn new<S: Into<String>>(service: S) -> Self {
+        Self {
+            service: service.into(),
+            index: Mutex::new(()),
+        }
+    }
+
+    fn entry(&self, name: &str) -> Result<keyring::Entry, SecretError> {
+        Ok(keyring::Entry::new(&self.service, name)?)
+    }
+
+    fn read(&self, name: &str) -> Result<Option<String>, SecretError> {
+        match self.entry(name)?.get_password() {
+            Ok(secret) => Ok(Some(secret)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn update_index(&self, update: impl FnOnce(&mut Vec<String>)) -> Result<(), SecretError> {
+        let _guard = self.index.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut names = self.read_index()?;
+        update(&mut names);
+        names.sort();
+        names.dedup();
+        self.entry(INDEX_ENTRY)?
+            .set_password(&serde_json::to_string(&names)?)?;
+        Ok(())
+    }
+
+    fn read_index(&self) -> Result<Vec<String>, SecretError> {
+        match self.read(INDEX_ENTRY)? {
+            Some(index) => Ok(serde_json::from_str(&index)?),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+impl SecretStore for KeychainStore {
+    fn get(&self, name: &str) -> Result<Option<String>, SecretError> {
+        self.read(name)
+    }
+
+    fn set(&self, name: &str, secret: &str) -> Result<(), SecretError> {
+        self.entry(name)?.set_password(secret)?;
+        self.update_index(|names| names.push(name.to_string()))
+    }
+
+    fn delete(&self, name: &str) -> Result<bool, SecretError> {
+        let existed = match self.entry(name)?.delete_credential() {
+            Ok(()) => true,
+            Err(keyring::Error::NoEntry) => false,
+            Err(e) => return Err(e.into()),
+        };
+        self.update_index(|names| names.retain(|stored| stored != name))?;
+        Ok(existed)
+    }
+
+    fn names(&self) -> Result<Vec<String>, SecretError> {
+        let _guard = self.index.lock().unwrap_or_else(PoisonError::into_inner);
+        self.read_index()
+    }
+}
+
+/// Process-local store for tests and hosts without a usable keychain.
+#[derive(Default)]
+pub struct MemorySecretStore {
+    secrets: Mutex<BTreeMap<String, String>>,
+}
+
+impl MemorySecretStore {
+    fn secrets(&self) -> MutexGuard<'_, BTreeMap<String, String>> {
+        self.secrets.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl SecretStore for MemorySecretStore {
+    fn get(&self, name: &str) -> Result<Option<String>, SecretError> {
+        Ok(self.secrets().get(name).cloned())
+    }
+
+    fn set(&self, name: &str, secret: &str) -> Result<(), SecretError> {
+        self.secrets().insert(name.to_string(), secret.to_string());
+        Ok(())
+    }
+
+    fn delete(&self, name: &str) -> Result<bool, SecretError> {
+        Ok(self.secrets().remove(name).is_some())
+    }
+
+    fn names(&self) -> Result<Vec<String>, SecretError> {
+        Ok(self.secrets().keys().cloned().collect())
+    }
+}
+
+/// Looks up each referenced secret, returning the variables to set on a fabric child.
+pub fn resolve_env(
+    store: &dyn SecretStore,
+    references: &BTreeMap<String, SecretRef>,
+) -> Result<Vec<(String, String)>, SecretError> {
+    references
+        .iter()
+        .map(|(var, reference)| match store.get(&reference.name)? {
+            Some(secret) => Ok((var.clone(), secret)),
+            None => Err(SecretError::Missing {
+                var: var.clone(),
+                reference: reference.clone(),
+            }),
+        })
+        .collect()
+}
+
+pub fn valid_name(name: &str) -> bool {
+    name.chars()
+        .next()
+        .is_some_and(|first| first.is_ascii_alphanumeric())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_name() {
+        assert!(valid_name("openai"));
+        assert!(valid_name("OPENAI_API_KEY"));
+        assert!(valid_name("anthropic.work-2"));
+        assert!(!valid_name(""));
+        assert!(!valid_name(INDEX_ENTRY));
+        assert!(!valid_name("open ai"));
+        assert!(!valid_name("keychain:openai"));
+    }
+
+    #[test]
+    fn test_secret_ref_parses_keychain_scheme() {
+        let reference = SecretRef::try_from("keychain:openai".to_string()).unwrap();
+        assert_eq!(reference.name, "openai");
+        assert_eq!(reference.to_string(), "keychain:openai");
+        assert!(SecretRef::try_from("openai".to_string()).is_err());
+        assert!(SecretRef::try_from("keychain:".to_string()).is_err());
+        assert!(SecretRef::try_from("env:OPENAI_API_KEY".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_resolve_env_reports_missing_secret() {
+        let store = MemorySecretStore::default();
+        store.set("openai", "sk-1").unwrap();
+        let openai = SecretRef {
+            name: "openai".to_string(),
+        };
+        let mut references = BTreeMap::from([("OPENAI_API_KEY".to_string(), openai)]);
+
+        assert_eq!(
+            resolve_env(&store, &references).unwrap(),
+            vec![("OPENAI_API_KEY".to_string(), "sk-1".to_string())]
+        );
+
+        references.insert(
+            "ANTHROPIC_API_KEY".to_string(),
+            SecretRef {
+                name: "anthropic".to_string(),
+            },
+        );
+        let error = resolve_env(&store, &references).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "ANTHROPIC_API_KEY references keychain:anthropic, which is not stored"
+        );
+    }
+
+    #[test]
+    fn test_memory_store_round_trip() {
+        let store = MemorySecretStore::default();
+        store.set("openai", "sk-1").unwrap();
+        store.set("anthropic", "sk-2").unwrap();
+        store.set("openai", "sk-3").unwrap();
+
+        assert_eq!(store.get("openai").unwrap().as_deref(), Some("sk-3"));
+        assert_eq!(store.names().unwrap(), vec!["anthropic", "openai"]);
+        assert!(store.delete("openai").unwrap());
+        assert!(!store.delete("openai").unwrap());
+        assert_eq!(store.get("openai").unwrap(), None);
+        assert_eq!(store.names().unwrap(), vec!["anthropic"]);
+    }
+}
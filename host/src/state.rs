@@ -0,0 +1,723 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    sync::{
+        Arc, Mutex, MutexGuard, PoisonError,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use camino::Utf8PathBuf;
+use tokio::{sync::Mutex as TokioMutex, time::Instant};
+use uuid::Uuid;
+
+use crate::{
+    Compression, HostStats, LogLevel, OrphanPolicy, RecentError,
+    codec::{
+        ContentCompression, MAX_INBOUND_MESSAGE_SIZE, MAX_OUTBOUND_MESSAGE_SIZE, MessageSizeLimit,
+    },
+    config::RequestDefaults,
+    fabric::DEFAULT_ENV_ALLOWLIST,
+    handlers::{
+        ChunkRegistry, DEFAULT_COMMAND_TIMEOUT, DEFAULT_MAX_CHUNKED_CONTENT_BYTES,
+        DEFAULT_MAX_CONCURRENT_PROCESSES, DEFAULT_MAX_OUTPUT_BYTES, HandlerError, ProcessRegistry,
+        ProcessSlots,
+    },
+    integrity::DigestCache,
+    jobs::JobRegistry,
+    logging,
+    metrics::Metrics,
+    ratelimit::{RateLimit, RateLimiter},
+    replay::ReplayBuffer,
+    secrets::{KeychainStore, SecretRef, SecretStore},
+    transport::WriteStalls,
+    usage::{Budget, UsageTracker},
+};
+
+const MIN_NEGOTIATED_MESSAGE_SIZE: usize = 4 * 1024;
+const RECENT_ERRORS_CAPACITY: usize = 20;
+
+pub type SettingsLoader = Box<dyn Fn() -> Result<HostSettings, String> + Send + Sync>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostSettings {
+    pub command_timeout: Duration,
+    pub max_concurrent_processes: usize,
+    pub max_output_bytes: Option<usize>,
+    pub max_chunked_content_bytes: usize,
+    pub fabric_path: Option<Utf8PathBuf>,
+    pub fabric_sha256: Option<String>,
+    pub search_paths: Vec<Utf8PathBuf>,
+    pub binary_names: Vec<String>,
+    pub allowed_executables: Vec<Utf8PathBuf>,
+    pub allowed_cwd_roots: Vec<Utf8PathBuf>,
+    pub env_allowlist: Vec<String>,
+    pub allowed_request_env: Vec<String>,
+    pub patterns_dir: Option<Utf8PathBuf>,
+    pub request_defaults: RequestDefaults,
+    pub pinned_patterns: Vec<String>,
+    pub secret_env: BTreeMap<String, SecretRef>,
+    pub rate_limits: BTreeMap<String, RateLimit>,
+    pub budget: Budget,
+    pub log_level: LogLevel,
+}
+
+impl Default for HostSettings {
+    fn default() -> Self {
+        Self {
+            command_timeout: DEFAULT_COMMAND_TIMEOUT,
+            max_concurrent_processes: DEFAULT_MAX_CONCURRENT_PROCESSES,
+            max_output_bytes: Some(DEFAULT_MAX_OUTPUT_BYTES),
+            max_chunked_content_bytes: DEFAULT_MAX_CHUNKED_CONTENT_BYTES,
+            fabric_path: None,
+            fabric_sha256: None,
+            search_paths: Vec::new(),
+            binary_names: Vec::new(),
+            allowed_executables: Vec::new(),
+            allowed_cwd_roots: Vec::new(),
+            env_allowlist: DEFAULT_ENV_ALLOWLIST
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+            allowed_request_env: Vec::new(),
+            patterns_dir: None,
+            request_defaults: RequestDefaults::default(),
+            pinned_patterns: Vec::new(),
+            secret_env: BTreeMap::new(),
+            rate_limits: BTreeMap::new(),
+            budget: Budget::default(),
+            log_level: LogLevel::default(),
+        }
+    }
+}
+
+pub struct HostState {
+    pub process_registry: ProcessRegistry,
+    pub process_slots: ProcessSlots,
+    pub jobs: Arc<JobRegistry>,
+    pub replay: ReplayBuffer,
+    pub chunks: ChunkRegistry,
+    pub secrets: Arc<dyn SecretStore>,
+    pub digests: DigestCache,
+    pub rate_limiter: RateLimiter,
+    pub usage: Arc<UsageTracker>,
+    pub metrics: Arc<Metrics>,
+    outbound_limit: MessageSizeLimit,
+    compression: ContentCompression,
+    write_stalls: WriteStalls,
+    coalesce_interval: Option<Duration>,
+    strict: bool,
+    client_locale: Mutex<Option<String>>,
+    settings: Mutex<HostSettings>,
+    settings_loader: Option<SettingsLoader>,
+    config_path: Option<Utf8PathBuf>,
+    started: Instant,
+    in_flight: Arc<Mutex<HashSet<Uuid>>>,
+    draining: AtomicBool,
+    recent_errors: Mutex<VecDeque<RecentError>>,
+    startup_warnings: Mutex<Vec<String>>,
+}
+
+impl Default for HostState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HostState {
+    pub fn new() -> Self {
+        Self {
+            process_registry: Arc::new(TokioMutex::new(HashMap::new())),
+            process_slots: ProcessSlots::default(),
+            jobs: Arc::new(JobRegistry::default()),
+            replay: ReplayBuffer::default(),
+            chunks: ChunkRegistry::default(),
+            secrets: Arc::new(KeychainStore::default()),
+            digests: DigestCache::default(),
+            rate_limiter: RateLimiter::default(),
+            usage: Arc::new(UsageTracker::default()),
+            metrics: Arc::new(Metrics::default()),
+            outbound_limit: MessageSizeLimit::new(MAX_OUTBOUND_MESSAGE_SIZE),
+            compression: ContentCompression::default(),
+            write_stalls: WriteStalls::default(),
+            coalesce_interval: None,
+            strict: false,
+            client_locale: Mutex::new(None),
+            settings: Mutex::new(HostSettings::default()),
+            settings_loader: None,
+            config_path: None,
+            started: Instant::now(),
+            in_flight: Arc::new(Mutex::new(HashSet::new())),
+            draining: AtomicBool::new(false),
+            recent_errors: Mutex::new(VecDeque::with_capacity(RECENT_ERRORS_CAPACITY)),
+            startup_warnings: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn with_jobs(mut self, jobs: JobRegistry) -> Self {
+        self.jobs = Arc::new(jobs);
+        self
+    }
+
+    pub fn with_usage(mut self, usage: UsageTracker) -> Self {
+        self.usage = Arc::new(usage);
+        self
+    }
+
+    pub fn with_secrets<S: SecretStore + 'static>(mut self, secrets: S) -> Self {
+        self.secrets = Arc::new(secrets);
+        self
+    }
+
+    pub fn with_max_concurrent_processes(mut self, limit: usize) -> Self {
+        self.process_slots = ProcessSlots::new(limit);
+        self.settings_mut().max_concurrent_processes = limit;
+        self
+    }
+
+    pub fn with_outbound_limit(mut self, outbound_limit: MessageSizeLimit) -> Self {
+        self.outbound_limit = outbound_limit;
+        self
+    }
+
+    pub fn with_compression(mut self, compression: ContentCompression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn with_write_stalls(mut self, write_stalls: WriteStalls) -> Self {
+        self.write_stalls = write_stalls;
+        self
+    }
+
+    pub fn with_coalesce_interval(mut self, coalesce_interval: Option<Duration>) -> Self {
+        self.coalesce_interval = coalesce_interval;
+        self
+    }
+
+    pub fn coalesce_interval(&self) -> Option<Duration> {
+        self.coalesce_interval
+    }
+
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn strict(&self) -> bool {
+        self.strict
+    }
+
+    fn settings_mut(&mut self) -> &mut HostSettings {
+        self.settings
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn settings(&self) -> MutexGuard<'_, HostSettings> {
+        self.settings.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn with_settings(self, settings: HostSettings) -> Self {
+        self.apply_settings(settings);
+        self
+    }
+
+    pub fn apply_settings(&self, settings: HostSettings) {
+        self.process_slots
+            .set_limit(settings.max_concurrent_processes);
+        logging::set_level(settings.log_level);
+        *self.settings() = settings;
+    }
+
+    pub fn with_settings_loader(mut self, loader: SettingsLoader) -> Self {
+        self.settings_loader = Some(loader);
+        self
+    }
+
+    pub fn reload_settings(&self) -> Result<(), String> {
+        let loader = self
+            .settings_loader
+            .as_ref()
+            .ok_or_else(|| "No configuration source to reload".to_string())?;
+        self.apply_settings(loader()?);
+        Ok(())
+    }
+
+    pub fn with_config_path(mut self, config_path: Option<Utf8PathBuf>) -> Self {
+        self.config_path = config_path;
+        self
+    }
+
+    pub fn config_path(&self) -> Option<&Utf8PathBuf> {
+        self.config_path.as_ref()
+    }
+
+    pub fn patterns_dir(&self) -> Option<Utf8PathBuf> {
+        self.settings().patterns_dir.clone()
+    }
+
+    pub fn search_paths(&self) -> Vec<Utf8PathBuf> {
+        self.settings().search_paths.clone()
+    }
+
+    pub fn binary_names(&self) -> Vec<String> {
+        self.settings().binary_names.clone()
+    }
+
+    pub fn allowed_executables(&self) -> Vec<Utf8PathBuf> {
+        self.settings().allowed_executables.clone()
+    }
+
+    pub fn allowed_cwd_roots(&self) -> Vec<Utf8PathBuf> {
+        self.settings().allowed_cwd_roots.clone()
+    }
+
+    pub fn env_allowlist(&self) -> Vec<String> {
+        self.settings().env_allowlist.clone()
+    }
+
+    pub fn allowed_request_env(&self) -> Vec<String> {
+        self.settings().allowed_request_env.clone()
+    }
+
+    /// Config keys omit the `native.` prefix, e.g. `processContent`.
+    pub fn rate_limit(&self, request_type: &str) -> Option<RateLimit> {
+        let key = request_type.strip_prefix("native.").unwrap_or(request_type);
+        self.settings().rate_limits.get(key).copied()
+    }
+
+    pub fn budget(&self) -> Budget {
+        self.settings().budget
+    }
+
+    pub fn secret_env(&self) -> BTreeMap<String, SecretRef> {
+        self.settings().secret_env.clone()
+    }
+
+    pub fn pinned_patterns(&self) -> Vec<String> {
+        self.settings().pinned_patterns.clone()
+    }
+
+    pub fn log_enabled(&self, level: LogLevel) -> bool {
+        level <= self.settings().log_level
+    }
+
+    pub fn with_command_timeout(mut self, command_timeout: Duration) -> Self {
+        self.settings_mut().command_timeout = command_timeout;
+        self
+    }
+
+    pub fn command_timeout(&self) -> Duration {
+        self.settings().command_timeout
+    }
+
+    pub fn with_max_output_bytes(mut self, max_output_bytes: Option<usize>) -> Self {
+        self.settings_mut().max_output_bytes = max_output_bytes;
+        self
+    }
+
+    pub fn max_output_bytes(&self) -> Option<usize> {
+        self.settings().max_output_bytes
+    }
+
+    pub fn with_max_chunked_content_bytes(mut self, max_chunked_content_bytes: usize) -> Self {
+        self.settings_mut().max_chunked_content_bytes = max_chunked_content_bytes;
+        self
+    }
+
+    pub fn max_chunked_content_bytes(&self) -> usize {
+        self.settings().max_chunked_content_bytes
+    }
+
+    pub fn with_fabric_path(mut self, fabric_path: Option<Utf8PathBuf>) -> Self {
+        self.settings_mut().fabric_path = fabric_path;
+        self
+    }
+
+    pub fn fabric_path(&self) -> Option<Utf8PathBuf> {
+        self.settings().fabric_path.clone()
+    }
+
+    pub fn fabric_sha256(&self) -> Option<String> {
+        self.settings().fabric_sha256.clone()
+    }
+
+    pub fn with_request_defaults(mut self, request_defaults: RequestDefaults) -> Self {
+        self.settings_mut().request_defaults = request_defaults;
+        self
+    }
+
+    pub fn request_defaults(&self) -> RequestDefaults {
+        self.settings().request_defaults.clone()
+    }
+
+    pub fn outbound_limit(&self) -> usize {
+        self.outbound_limit.get()
+    }
+
+    pub fn negotiate_outbound_limit(&self, requested: Option<usize>) -> usize {
+        if let Some(requested) = requested {
+            self.outbound_limit
+                .set(requested.clamp(MIN_NEGOTIATED_MESSAGE_SIZE, MAX_INBOUND_MESSAGE_SIZE));
+        }
+        self.outbound_limit.get()
+    }
+
+    pub fn negotiate_compression(&self, requested: Option<Compression>) -> Option<Compression> {
+        self.compression.set(requested);
+        self.compression.get()
+    }
+
+    /// Records the locale sent in `native.hello`; a later hello replaces it.
+    pub fn set_client_locale(&self, locale: Option<String>) {
+        *self
+            .client_locale
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = locale;
+    }
+
+    pub fn client_locale(&self) -> Option<String> {
+        self.client_locale
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    pub fn track_request(&self, id: Uuid) -> Option<InFlightRequest> {
+        let mut in_flight = self
+            .in_flight
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        in_flight.insert(id).then(|| InFlightRequest {
+            in_flight: self.in_flight.clone(),
+            id,
+        })
+    }
+
+    pub fn active_requests(&self) -> Vec<Uuid> {
+        let mut ids: Vec<Uuid> = self
+            .in_flight
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .copied()
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    pub fn record_error(&self, request_id: Uuid, error: &HandlerError) {
+        let code = error
+            .details()
+            .map_or_else(|| error.code().to_string(), |details| details.code);
+        self.metrics.record_failure(&code);
+        let mut recent_errors = self
+            .recent_errors
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if recent_errors.len() == RECENT_ERRORS_CAPACITY {
+            recent_errors.pop_front();
+        }
+        recent_errors.push_back(RecentError {
+            request_id,
+            code,
+            message: error.to_string(),
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| {
+                    u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX)
+                }),
+        });
+    }
+
+    /// Problems found while starting up, such as a quarantined store,
+    /// held until there is a request to report them on.
+    pub fn with_startup_warning(mut self, warning: String) -> Self {
+        self.startup_warnings
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(warning);
+        self
+    }
+
+    pub fn take_startup_warnings(&self) -> Vec<String> {
+        std::mem::take(
+            &mut self
+                .startup_warnings
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner),
+        )
+    }
+
+    pub fn recent_errors(&self) -> Vec<RecentError> {
+        self.recent_errors
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    pub fn draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    /// Stops intake and returns every foreground request still owed a
+    /// reply: those running fabric and those accepted but not yet running
+    /// (queued for a slot or still being prepared).
+    pub async fn begin_drain(&self) -> Vec<Uuid> {
+        self.draining.store(true, Ordering::Relaxed);
+        let mut interrupted = self.active_requests();
+        let registry = self.process_registry.lock().await;
+        interrupted.extend(registry.keys().copied());
+        interrupted.retain(|id| self.jobs.get(*id).is_none());
+        interrupted.sort();
+        interrupted.dedup();
+        interrupted
+    }
+
+    pub async fn disconnect(&self, policy: OrphanPolicy) {
+        let orphaned = self.jobs.orphan_running(policy);
+        {
+            let registry = self.process_registry.lock().await;
+            for (id, cancel_sender) in registry.iter() {
+                if policy == OrphanPolicy::Cancel || !orphaned.contains(id) {
+                    let _ = cancel_sender.send(true);
+                }
+            }
+        }
+        self.jobs.wait_idle().await;
+    }
+
+    pub fn uptime_ms(&self) -> u64 {
+        u64::try_from(self.started.elapsed().as_millis()).unwrap_or(u64::MAX)
+    }
+
+    pub fn in_flight_count(&self) -> usize {
+        self.in_flight
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len()
+    }
+
+    /// `active_requests` counts the same in-flight ids `native.debugDump`
+    /// lists, so detached jobs are left out and the asking request is counted.
+    pub fn stats(&self) -> HostStats {
+        HostStats {
+            uptime_ms: self.uptime_ms(),
+            active_requests: self.in_flight_count(),
+            queued_requests: self.process_slots.waiting(),
+            last_error_code: self
+                .recent_errors
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .back()
+                .map(|error| error.code.clone()),
+            write_stalls: self.write_stalls.stalls(),
+            write_stalled_ms: self.write_stalls.stalled_ms(),
+        }
+    }
+}
+
+pub struct InFlightRequest {
+    in_flight: Arc<Mutex<HashSet<Uuid>>>,
+    id: Uuid,
+}
+
+impl Drop for InFlightRequest {
+    fn drop(&mut self) {
+        self.in_flight
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use tokio::sync::watch;
+
+    use super::*;
+    use crate::{JobState, jobs::JobSink};
+
+    #[tokio::test]
+    async fn test_stats_count_in_flight_requests_but_not_jobs() {
+        let state = HostState::new();
+        let request_id = Uuid::new_v4();
+        let job_id = Uuid::new_v4();
+        state.jobs.insert(job_id);
+        {
+            let mut registry = state.process_registry.lock().await;
+            registry.insert(request_id, watch::channel(false).0);
+            registry.insert(job_id, watch::channel(false).0);
+        }
+        let _tracked = state.track_request(request_id);
+
+        let stats = state.stats();
+        assert_eq!(stats.queued_requests, 0);
+        assert_eq!(stats.active_requests, 1);
+        assert_eq!(stats.active_requests, state.active_requests().len());
+        assert_eq!(stats.last_error_code, None);
+    }
+
+    #[test]
+    fn test_track_request_rejects_in_flight_ids() {
+        let state = HostState::new();
+        let id = Uuid::new_v4();
+
+        let tracked = state.track_request(id);
+        assert!(tracked.is_some());
+        assert!(state.track_request(id).is_none());
+        assert!(state.track_request(Uuid::new_v4()).is_some());
+
+        drop(tracked);
+        assert!(state.track_request(id).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_record_error_prefers_detail_code() {
+        let state = HostState::new();
+
+        state.record_error(
+            Uuid::new_v4(),
+            &HandlerError::Io(io::Error::other("broken pipe")),
+        );
+        assert_eq!(state.stats().last_error_code.as_deref(), Some("io"));
+
+        let request_id = Uuid::new_v4();
+        state.record_error(
+            request_id,
+            &HandlerError::Spawn {
+                path: "/usr/bin/fabric-ai".into(),
+                source: io::Error::from(io::ErrorKind::PermissionDenied),
+            },
+        );
+        assert_eq!(
+            state.stats().last_error_code.as_deref(),
+            Some("permissionDenied")
+        );
+
+        let recent_errors = state.recent_errors();
+        assert_eq!(recent_errors.len(), 2);
+        assert_eq!(recent_errors[1].request_id, request_id);
+        assert_eq!(recent_errors[1].code, "permissionDenied");
+    }
+
+    #[test]
+    fn test_recent_errors_keep_newest() {
+        let state = HostState::new();
+        for _ in 0..RECENT_ERRORS_CAPACITY + 5 {
+            state.record_error(Uuid::new_v4(), &HandlerError::Cancelled);
+        }
+        let last = Uuid::new_v4();
+        state.record_error(last, &HandlerError::Io(io::Error::other("broken pipe")));
+
+        let recent_errors = state.recent_errors();
+        assert_eq!(recent_errors.len(), RECENT_ERRORS_CAPACITY);
+        assert_eq!(
+            recent_errors.last().map(|error| error.request_id),
+            Some(last)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_cancels_orphaned_jobs() {
+        let state = HostState::new();
+        let job_id = Uuid::new_v4();
+        state.jobs.insert(job_id);
+        let (cancel_tx, mut cancel_rx) = watch::channel(false);
+        state
+            .process_registry
+            .lock()
+            .await
+            .insert(job_id, cancel_tx);
+
+        let sink = JobSink::new(job_id, state.jobs.clone());
+        tokio::spawn(async move {
+            let _ = cancel_rx.changed().await;
+            sink.finish(JobState::Cancelled);
+        });
+
+        state.disconnect(OrphanPolicy::Cancel).await;
+
+        let orphaned = state.jobs.take_orphaned();
+        assert_eq!(orphaned.len(), 1);
+        assert_eq!(orphaned[0].state, JobState::Cancelled);
+        assert_eq!(orphaned[0].orphan_policy, Some(OrphanPolicy::Cancel));
+        assert!(state.jobs.take_orphaned().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_cancels_foreground_requests() {
+        let state = HostState::new();
+        let job_id = Uuid::new_v4();
+        state.jobs.insert(job_id);
+        let (job_tx, job_rx) = watch::channel(false);
+        let (request_tx, request_rx) = watch::channel(false);
+        {
+            let mut registry = state.process_registry.lock().await;
+            registry.insert(job_id, job_tx);
+            registry.insert(Uuid::new_v4(), request_tx);
+        }
+        let sink = JobSink::new(job_id, state.jobs.clone());
+        tokio::spawn(async move {
+            tokio::task::yield_now().await;
+            sink.finish(JobState::Completed);
+        });
+
+        state.disconnect(OrphanPolicy::Finish).await;
+
+        assert!(*request_rx.borrow());
+        assert!(!*job_rx.borrow());
+    }
+
+    #[tokio::test]
+    async fn test_begin_drain_lists_foreground_requests() {
+        let state = HostState::new();
+        let job_id = Uuid::new_v4();
+        let request_id = Uuid::new_v4();
+        let waiting_id = Uuid::new_v4();
+        state.jobs.insert(job_id);
+        {
+            let mut registry = state.process_registry.lock().await;
+            registry.insert(job_id, watch::channel(false).0);
+            registry.insert(request_id, watch::channel(false).0);
+        }
+        let _running = state.track_request(request_id);
+        let _waiting = state.track_request(waiting_id);
+
+        assert!(!state.draining());
+        let mut expected = vec![request_id, waiting_id];
+        expected.sort();
+        assert_eq!(state.begin_drain().await, expected);
+        assert!(state.draining());
+    }
+
+    #[test]
+    fn test_negotiate_outbound_limit_clamps_request() {
+        let limit = MessageSizeLimit::new(MAX_OUTBOUND_MESSAGE_SIZE);
+        let state = HostState::new().with_outbound_limit(limit.clone());
+
+        assert_eq!(
+            state.negotiate_outbound_limit(None),
+            MAX_OUTBOUND_MESSAGE_SIZE
+        );
+        assert_eq!(
+            state.negotiate_outbound_limit(Some(8 * 1024 * 1024)),
+            8 * 1024 * 1024
+        );
+        assert_eq!(limit.get(), 8 * 1024 * 1024);
+        assert_eq!(state.outbound_limit(), 8 * 1024 * 1024);
+        assert_eq!(
+            state.negotiate_outbound_limit(Some(1)),
+            MIN_NEGOTIATED_MESSAGE_SIZE
+        );
+        assert_eq!(
+            state.negotiate_outbound_limit(Some(usize::MAX)),
+            MAX_INBOUND_MESSAGE_SIZE
+        );
+    }
+}
@@ -0,0 +1,332 @@
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::Value;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::StoreInfo;
+
+#[derive(Debug, Error)]
+pub enum StoreError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Serialization error: {0}")]
+    Serialize(#[from] serde_json::Error),
+    #[error("{path} uses schema version {found}, but only version {supported} is supported")]
+    UnsupportedVersion {
+        path: Utf8PathBuf,
+        found: u32,
+        supported: u32,
+    },
+}
+
+pub type Migration = fn(Value) -> Value;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Schema {
+    pub migrations: &'static [Migration],
+}
+
+impl Schema {
+    pub const UNVERSIONED: Schema = Schema { migrations: &[] };
+
+    pub fn version(&self) -> u32 {
+        u32::try_from(self.migrations.len()).unwrap_or(u32::MAX)
+    }
+}
+
+#[derive(Debug)]
+pub struct Loaded<T> {
+    pub value: T,
+    pub warning: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    #[serde(default)]
+    version: u32,
+    checksum: String,
+    data: Value,
+}
+
+pub fn write_atomic(path: &Utf8Path, contents: &[u8]) -> io::Result<()> {
+    let dir = path
+        .parent()
+        .filter(|parent| !parent.as_str().is_empty())
+        .unwrap_or(Utf8Path::new("."));
+    fs::create_dir_all(dir)?;
+
+    let file_name = path.file_name().unwrap_or("store");
+    let temp_path = dir.join(format!(".{file_name}.{}.tmp", Uuid::new_v4()));
+
+    let result = File::create(&temp_path).and_then(|mut file| {
+        file.write_all(contents)?;
+        file.sync_all()
+    });
+
+    match result.and_then(|()| fs::rename(&temp_path, path)) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            let _ = fs::remove_file(&temp_path);
+            Err(e)
+        }
+    }
+}
+
+pub fn save<T: Serialize>(path: &Utf8Path, schema: &Schema, value: &T) -> Result<(), StoreError> {
+    write_envelope(path, schema.version(), serde_json::to_value(value)?)
+}
+
+fn write_envelope(path: &Utf8Path, version: u32, data: Value) -> Result<(), StoreError> {
+    let envelope = Envelope {
+        version,
+        checksum: checksum(&data)?,
+        data,
+    };
+    write_atomic(path, &serde_json::to_vec(&envelope)?)?;
+    Ok(())
+}
+
+pub fn schema_version(path: &Utf8Path) -> Option<u32> {
+    let bytes = fs::read(path).ok()?;
+    decode(&bytes).map(|envelope| envelope.version)
+}
+
+/// Reports the schema version `path` is written with next to the one this
+/// build uses, for `native.getStoreInfo`.
+pub fn info(name: &str, path: Option<&Utf8Path>, schema: &Schema) -> StoreInfo {
+    StoreInfo {
+        name: name.to_string(),
+        path: path.map(Utf8Path::to_owned),
+        version: path.and_then(schema_version),
+        supported_version: schema.version(),
+    }
+}
+
+pub fn load<T: DeserializeOwned + Serialize + Default>(
+    path: &Utf8Path,
+    schema: &Schema,
+) -> Result<Loaded<T>, StoreError> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Ok(Loaded {
+                value: T::default(),
+                warning: None,
+            });
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    if let Some(envelope) = decode(&bytes) {
+        if envelope.version > schema.version() {
+            return Err(StoreError::UnsupportedVersion {
+                path: path.to_owned(),
+                found: envelope.version,
+                supported: schema.version(),
+            });
+        }
+
+        let data = if envelope.version < schema.version() {
+            migrate(path, &bytes, envelope, schema)?
+        } else {
+            envelope.data
+        };
+
+        if let Ok(value) = serde_json::from_value(data) {
+            return Ok(Loaded {
+                value,
+                warning: None,
+            });
+        }
+    }
+
+    let quarantined = quarantine(path)?;
+    let value = T::default();
+    save(path, schema, &value)?;
+
+    Ok(Loaded {
+        value,
+        warning: Some(format!(
+            "{path} was corrupted and has been reset; the damaged copy was moved to {quarantined}"
+        )),
+    })
+}
+
+fn decode(bytes: &[u8]) -> Option<Envelope> {
+    let envelope: Envelope = serde_json::from_slice(bytes).ok()?;
+    if checksum(&envelope.data).ok()? != envelope.checksum {
+        return None;
+    }
+    Some(envelope)
+}
+
+fn migrate(
+    path: &Utf8Path,
+    original: &[u8],
+    envelope: Envelope,
+    schema: &Schema,
+) -> Result<Value, StoreError> {
+    let backup = Utf8PathBuf::from(format!("{path}.v{}.bak", envelope.version));
+    write_atomic(&backup, original)?;
+
+    let data = schema.migrations[envelope.version as usize..]
+        .iter()
+        .fold(envelope.data, |data, migration| migration(data));
+    write_envelope(path, schema.version(), data.clone())?;
+    Ok(data)
+}
+
+fn checksum(data: &Value) -> Result<String, serde_json::Error> {
+    let bytes = serde_json::to_vec(data)?;
+    Ok(blake3::hash(&bytes).to_hex().to_string())
+}
+
+fn quarantine(path: &Utf8Path) -> io::Result<Utf8PathBuf> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    let quarantined = Utf8PathBuf::from(format!("{path}.corrupt-{timestamp}"));
+    fs::rename(path, &quarantined)?;
+    Ok(quarantined)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use assert_matches::assert_matches;
+    use camino_tempfile::tempdir;
+
+    use super::*;
+
+    type Presets = BTreeMap<String, String>;
+
+    #[test]
+    fn test_save_and_load_round_trip() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("presets.json");
+
+        let mut presets = Presets::new();
+        presets.insert("summary".to_string(), "summarize".to_string());
+        save(&path, &Schema::UNVERSIONED, &presets).unwrap();
+
+        let loaded: Loaded<Presets> = load(&path, &Schema::UNVERSIONED).unwrap();
+        assert_eq!(loaded.value, presets);
+        assert!(loaded.warning.is_none());
+    }
+
+    #[test]
+    fn test_load_missing_file_returns_default() {
+        let dir = tempdir().unwrap();
+        let loaded: Loaded<Presets> =
+            load(&dir.path().join("missing.json"), &Schema::UNVERSIONED).unwrap();
+        assert!(loaded.value.is_empty());
+        assert!(loaded.warning.is_none());
+    }
+
+    #[test]
+    fn test_load_quarantines_checksum_mismatch() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("presets.json");
+
+        let mut presets = Presets::new();
+        presets.insert("summary".to_string(), "summarize".to_string());
+        save(&path, &Schema::UNVERSIONED, &presets).unwrap();
+
+        let tampered = fs::read_to_string(&path)
+            .unwrap()
+            .replace("summarize", "summarise");
+        fs::write(&path, tampered).unwrap();
+
+        let loaded: Loaded<Presets> = load(&path, &Schema::UNVERSIONED).unwrap();
+        assert!(loaded.value.is_empty());
+        assert!(loaded.warning.unwrap().contains("was corrupted"));
+
+        let quarantined: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_string_lossy().contains(".corrupt-"))
+            .collect();
+        assert_eq!(quarantined.len(), 1);
+
+        let reloaded: Loaded<Presets> = load(&path, &Schema::UNVERSIONED).unwrap();
+        assert!(reloaded.warning.is_none());
+    }
+
+    #[test]
+    fn test_load_quarantines_truncated_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("history.json");
+        fs::write(&path, b"{\"checksum\":\"ab").unwrap();
+
+        let loaded: Loaded<Presets> = load(&path, &Schema::UNVERSIONED).unwrap();
+        assert!(loaded.warning.is_some());
+    }
+
+    fn wrap_in_names(data: Value) -> Value {
+        serde_json::json!({ "names": data })
+    }
+
+    fn rename_names_to_presets(mut data: Value) -> Value {
+        let names = data["names"].take();
+        serde_json::json!({ "presets": names })
+    }
+
+    const PRESETS_SCHEMA: Schema = Schema {
+        migrations: &[wrap_in_names, rename_names_to_presets],
+    };
+
+    #[test]
+    fn test_load_migrates_old_schema_with_backup() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("presets.json");
+        save(&path, &Schema::UNVERSIONED, &vec!["summarize"]).unwrap();
+        assert_eq!(schema_version(&path), Some(0));
+
+        let loaded: Loaded<BTreeMap<String, Vec<String>>> = load(&path, &PRESETS_SCHEMA).unwrap();
+        assert_eq!(loaded.value["presets"], vec!["summarize".to_string()]);
+        assert!(loaded.warning.is_none());
+        assert_eq!(schema_version(&path), Some(2));
+
+        let backup = Utf8PathBuf::from(format!("{path}.v0.bak"));
+        assert_eq!(schema_version(&backup), Some(0));
+    }
+
+    #[test]
+    fn test_load_rejects_newer_schema() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("presets.json");
+        save(&path, &PRESETS_SCHEMA, &Presets::new()).unwrap();
+
+        let result: Result<Loaded<Presets>, _> = load(&path, &Schema::UNVERSIONED);
+        assert_matches!(
+            result,
+            Err(StoreError::UnsupportedVersion {
+                found: 2,
+                supported: 0,
+                ..
+            })
+        );
+        assert_eq!(schema_version(&path), Some(2));
+    }
+
+    #[test]
+    fn test_write_atomic_leaves_no_temp_files() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("nested").join("store.json");
+
+        write_atomic(&path, b"first").unwrap();
+        write_atomic(&path, b"second").unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+        let entries = fs::read_dir(path.parent().unwrap()).unwrap().count();
+        assert_eq!(entries, 1);
+    }
+}
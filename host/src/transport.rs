@@ -0,0 +1,336 @@
+use std::{
+    future::Future,
+    io, mem,
+    pin::Pin,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    task::{Context, Poll, ready},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    sync::{mpsc, oneshot},
+    time::{Instant, Sleep, sleep},
+};
+
+pub const MAX_WRITE_RETRIES: u32 = 5;
+const WRITE_RETRY_BASE_DELAY: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Clone, Default)]
+pub struct WriteStalls(Arc<WriteStallCounters>);
+
+#[derive(Debug, Default)]
+struct WriteStallCounters {
+    stalls: AtomicU64,
+    stalled_ms: AtomicU64,
+    failures: AtomicU64,
+}
+
+impl WriteStalls {
+    pub fn stalls(&self) -> u64 {
+        self.0.stalls.load(Ordering::Relaxed)
+    }
+
+    pub fn stalled_ms(&self) -> u64 {
+        self.0.stalled_ms.load(Ordering::Relaxed)
+    }
+
+    pub fn failures(&self) -> u64 {
+        self.0.failures.load(Ordering::Relaxed)
+    }
+
+    fn record_stall(&self, elapsed: Duration) {
+        self.0.stalls.fetch_add(1, Ordering::Relaxed);
+        self.0.stalled_ms.fetch_add(
+            u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+    }
+
+    fn record_failure(&self) {
+        self.0.failures.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+pub struct RetryingWriter<W> {
+    inner: W,
+    stalls: WriteStalls,
+    attempts: u32,
+    stalled_since: Option<Instant>,
+    backoff: Option<Pin<Box<Sleep>>>,
+}
+
+impl<W> RetryingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            stalls: WriteStalls::default(),
+            attempts: 0,
+            stalled_since: None,
+            backoff: None,
+        }
+    }
+
+    pub fn stalls(&self) -> WriteStalls {
+        self.stalls.clone()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: AsyncWrite + Unpin> RetryingWriter<W> {
+    fn poll_retry<T>(
+        &mut self,
+        cx: &mut Context<'_>,
+        mut op: impl FnMut(Pin<&mut W>, &mut Context<'_>) -> Poll<io::Result<T>>,
+    ) -> Poll<io::Result<T>> {
+        loop {
+            if let Some(backoff) = self.backoff.as_mut() {
+                ready!(backoff.as_mut().poll(cx));
+                self.backoff = None;
+            }
+
+            match ready!(op(Pin::new(&mut self.inner), cx)) {
+                Err(e) if is_transient(e.kind()) && self.attempts < MAX_WRITE_RETRIES => {
+                    self.stalled_since.get_or_insert_with(Instant::now);
+                    let delay = WRITE_RETRY_BASE_DELAY * 2u32.pow(self.attempts);
+                    self.attempts += 1;
+                    self.backoff = Some(Box::pin(sleep(delay)));
+                }
+                result => {
+                    if let Some(stalled_since) = self.stalled_since.take() {
+                        self.stalls.record_stall(stalled_since.elapsed());
+                    }
+                    if result.is_err() && self.attempts > 0 {
+                        self.stalls.record_failure();
+                    }
+                    self.attempts = 0;
+                    return Poll::Ready(result);
+                }
+            }
+        }
+    }
+}
+
+fn is_transient(kind: io::ErrorKind) -> bool {
+    matches!(
+        kind,
+        io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted | io::ErrorKind::TimedOut
+    )
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for RetryingWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut()
+            .poll_retry(cx, |inner, cx| inner.poll_write(cx, buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_retry(cx, AsyncWrite::poll_flush)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+type FrameBatch = (Vec<u8>, oneshot::Sender<io::Result<()>>);
+
+/// A cloneable handle onto one output stream. Each clone buffers what it is
+/// given and, on flush, hands the buffer to a single writer task as one
+/// unit, so frames from concurrent requests never interleave and no request
+/// holds the output for longer than it takes to write its own frames.
+#[derive(Debug)]
+pub struct SharedWriter {
+    buffer: Vec<u8>,
+    batches: mpsc::UnboundedSender<FrameBatch>,
+    pending: Option<oneshot::Receiver<io::Result<()>>>,
+}
+
+impl SharedWriter {
+    /// Spawns the task that owns `inner`. Flushing a handle waits until its
+    /// bytes have been written and flushed to `inner`, so write errors and
+    /// backpressure still reach the sender.
+    pub fn spawn<W>(mut inner: W) -> Self
+    where
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let (batches, mut receiver) = mpsc::unbounded_channel::<FrameBatch>();
+        tokio::spawn(async move {
+            while let Some((bytes, written)) = receiver.recv().await {
+                let result = match inner.write_all(&bytes).await {
+                    Ok(()) => inner.flush().await,
+                    Err(e) => Err(e),
+                };
+                let _ = written.send(result);
+            }
+        });
+
+        Self {
+            buffer: Vec::new(),
+            batches,
+            pending: None,
+        }
+    }
+}
+
+impl Clone for SharedWriter {
+    fn clone(&self) -> Self {
+        Self {
+            buffer: Vec::new(),
+            batches: self.batches.clone(),
+            pending: None,
+        }
+    }
+}
+
+impl AsyncWrite for SharedWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().buffer.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(pending) = this.pending.as_mut() {
+                let result = ready!(Pin::new(pending).poll(cx));
+                this.pending = None;
+                result.unwrap_or_else(|_| Err(io::ErrorKind::BrokenPipe.into()))?;
+            }
+            if this.buffer.is_empty() {
+                return Poll::Ready(Ok(()));
+            }
+
+            let (written, pending) = oneshot::channel();
+            this.batches
+                .send((mem::take(&mut this.buffer), written))
+                .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+            this.pending = Some(pending);
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct FlakyWriter {
+        errors: VecDeque<io::ErrorKind>,
+        written: Vec<u8>,
+    }
+
+    impl FlakyWriter {
+        fn failing(errors: impl IntoIterator<Item = io::ErrorKind>) -> Self {
+            Self {
+                errors: errors.into_iter().collect(),
+                written: Vec::new(),
+            }
+        }
+    }
+
+    impl AsyncWrite for FlakyWriter {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            if let Some(kind) = self.errors.pop_front() {
+                return Poll::Ready(Err(kind.into()));
+            }
+            self.written.extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retries_transient_write_errors() {
+        let mut writer = RetryingWriter::new(FlakyWriter::failing([
+            io::ErrorKind::WouldBlock,
+            io::ErrorKind::Interrupted,
+        ]));
+        let stalls = writer.stalls();
+
+        writer.write_all(b"frame").await.unwrap();
+
+        assert_eq!(stalls.stalls(), 1);
+        assert_eq!(stalls.stalled_ms(), 30);
+        assert_eq!(stalls.failures(), 0);
+        assert_eq!(writer.into_inner().written, b"frame");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_escalates_after_max_retries() {
+        let mut writer = RetryingWriter::new(FlakyWriter::failing(
+            [io::ErrorKind::WouldBlock; MAX_WRITE_RETRIES as usize + 1],
+        ));
+        let stalls = writer.stalls();
+
+        let error = writer.write_all(b"frame").await.unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::WouldBlock);
+        assert_eq!(stalls.stalls(), 1);
+        assert_eq!(stalls.failures(), 1);
+        assert!(writer.into_inner().written.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_shared_writer_keeps_each_flush_whole() {
+        let (mut reader, inner) = tokio::io::duplex(1024);
+        let mut first = SharedWriter::spawn(inner);
+        let mut second = first.clone();
+
+        first.write_all(b"one-").await.unwrap();
+        second.write_all(b"two").await.unwrap();
+        second.flush().await.unwrap();
+        first.write_all(b"done").await.unwrap();
+        first.flush().await.unwrap();
+        drop((first, second));
+
+        let mut written = Vec::new();
+        reader.read_to_end(&mut written).await.unwrap();
+        assert_eq!(written, b"twoone-done");
+    }
+
+    #[tokio::test]
+    async fn test_permanent_errors_are_not_retried() {
+        let mut writer = RetryingWriter::new(FlakyWriter::failing([io::ErrorKind::BrokenPipe]));
+        let stalls = writer.stalls();
+
+        let error = writer.write_all(b"frame").await.unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::BrokenPipe);
+        assert_eq!(stalls.stalls(), 0);
+        assert_eq!(stalls.failures(), 0);
+    }
+}
@@ -0,0 +1,294 @@
+use std::{
+    collections::BTreeMap,
+    env, fmt,
+    path::PathBuf,
+    sync::{Mutex, MutexGuard, PoisonError},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    TokenUsage,
+    store::{self, Schema, StoreError},
+};
+
+pub const USAGE_ENV_VAR: &str = "TAPESTRY_USAGE_FILE";
+pub const JOURNAL_SCHEMA: Schema = Schema::UNVERSIONED;
+
+const RETAINED_DAYS: usize = 31;
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// The `[budget]` config table. Days roll over at midnight UTC.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Budget {
+    pub daily_requests: Option<u64>,
+    pub daily_tokens: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DailyUsage {
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl DailyUsage {
+    pub fn tokens(&self) -> u64 {
+        self.prompt_tokens.saturating_add(self.completion_tokens)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quota {
+    Requests,
+    Tokens,
+}
+
+impl fmt::Display for Quota {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Quota::Requests => write!(f, "request"),
+            Quota::Tokens => write!(f, "token"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("Daily {quota} budget of {limit} is used up")]
+pub struct QuotaExceeded {
+    pub quota: Quota,
+    pub limit: u64,
+}
+
+pub fn usage_path_from_env() -> Option<Utf8PathBuf> {
+    match env::var(USAGE_ENV_VAR) {
+        Ok(path) if !path.is_empty() => Some(Utf8PathBuf::from(path)),
+        _ => {
+            let home = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE"))?;
+            let home = Utf8PathBuf::from_path_buf(PathBuf::from(home)).ok()?;
+            Some(home.join(".config").join("tapestry").join("usage.json"))
+        }
+    }
+}
+
+/// Per-day request and token counts, keyed by UTC date and persisted so
+/// budgets survive host restarts.
+#[derive(Debug, Default)]
+pub struct UsageTracker {
+    days: Mutex<BTreeMap<String, DailyUsage>>,
+    journal: Option<Utf8PathBuf>,
+}
+
+impl UsageTracker {
+    pub fn open(journal: Utf8PathBuf) -> Result<(Self, Option<String>), StoreError> {
+        let loaded = store::load::<BTreeMap<String, DailyUsage>>(&journal, &JOURNAL_SCHEMA)?;
+        let tracker = Self {
+            days: Mutex::new(loaded.value),
+            journal: Some(journal),
+        };
+        Ok((tracker, loaded.warning))
+    }
+
+    pub fn journal(&self) -> Option<&Utf8Path> {
+        self.journal.as_deref()
+    }
+
+    pub fn today(&self) -> DailyUsage {
+        self.days()
+            .get(&utc_date(SystemTime::now()))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Counts `requests` against today's budget, refusing all of them if
+    /// they would not fit. Token counts are only known once fabric finishes,
+    /// so the token budget refuses new requests after it has been reached.
+    pub fn charge(&self, requests: u64, budget: Budget) -> Result<(), QuotaExceeded> {
+        self.charge_on(&utc_date(SystemTime::now()), requests, budget)
+    }
+
+    pub fn record_tokens(&self, usage: &TokenUsage) {
+        self.record_tokens_on(&utc_date(SystemTime::now()), usage);
+    }
+
+    fn charge_on(&self, day: &str, requests: u64, budget: Budget) -> Result<(), QuotaExceeded> {
+        let mut days = self.days();
+        let today = days.get(day).copied().unwrap_or_default();
+        if let Some(limit) = budget.daily_tokens
+            && today.tokens() >= limit
+        {
+            return Err(QuotaExceeded {
+                quota: Quota::Tokens,
+                limit,
+            });
+        }
+        if let Some(limit) = budget.daily_requests
+            && today.requests.saturating_add(requests) > limit
+        {
+            return Err(QuotaExceeded {
+                quota: Quota::Requests,
+                limit,
+            });
+        }
+
+        days.entry(day.to_string()).or_default().requests += requests;
+        self.persist(days);
+        Ok(())
+    }
+
+    fn record_tokens_on(&self, day: &str, usage: &TokenUsage) {
+        let mut days = self.days();
+        let today = days.entry(day.to_string()).or_default();
+        today.prompt_tokens = today.prompt_tokens.saturating_add(usage.prompt_tokens);
+        today.completion_tokens = today
+            .completion_tokens
+            .saturating_add(usage.completion_tokens);
+        self.persist(days);
+    }
+
+    fn days(&self) -> MutexGuard<'_, BTreeMap<String, DailyUsage>> {
+        self.days.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn persist(&self, mut days: MutexGuard<'_, BTreeMap<String, DailyUsage>>) {
+        while days.len() > RETAINED_DAYS {
+            days.pop_first();
+        }
+        if let Some(journal) = &self.journal {
+            let _ = store::save(journal, &JOURNAL_SCHEMA, &*days);
+        }
+    }
+}
+
+/// Formats the UTC calendar date of `time` as `YYYY-MM-DD`, which also keeps
+/// the persisted map in chronological order.
+fn utc_date(time: SystemTime) -> String {
+    let days = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() / SECONDS_PER_DAY);
+
+    // Howard Hinnant's days-to-civil conversion, using eras of 400 years
+    // that start on March 1st.
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use camino_tempfile::tempdir;
+
+    use super::*;
+
+    fn tokens(prompt_tokens: u64, completion_tokens: u64) -> TokenUsage {
+        TokenUsage {
+            prompt_tokens,
+            completion_tokens,
+            estimated: false,
+        }
+    }
+
+    #[test]
+    fn test_utc_date() {
+        assert_eq!(utc_date(UNIX_EPOCH), "1970-01-01");
+        let leap_day = UNIX_EPOCH + Duration::from_secs(1_709_164_800 + 43_200);
+        assert_eq!(utc_date(leap_day), "2024-02-29");
+        let new_year = UNIX_EPOCH + Duration::from_secs(1_735_689_600);
+        assert_eq!(utc_date(new_year), "2025-01-01");
+    }
+
+    #[test]
+    fn test_charge_enforces_request_budget() {
+        let tracker = UsageTracker::default();
+        let budget = Budget {
+            daily_requests: Some(3),
+            daily_tokens: None,
+        };
+
+        assert!(tracker.charge_on("2024-02-29", 2, budget).is_ok());
+        assert_eq!(
+            tracker.charge_on("2024-02-29", 2, budget),
+            Err(QuotaExceeded {
+                quota: Quota::Requests,
+                limit: 3
+            })
+        );
+        assert!(tracker.charge_on("2024-02-29", 1, budget).is_ok());
+        assert!(tracker.charge_on("2024-02-29", 1, budget).is_err());
+        assert!(tracker.charge_on("2024-03-01", 1, budget).is_ok());
+    }
+
+    #[test]
+    fn test_charge_refuses_once_token_budget_is_reached() {
+        let tracker = UsageTracker::default();
+        let budget = Budget {
+            daily_requests: None,
+            daily_tokens: Some(100),
+        };
+
+        assert!(tracker.charge_on("2024-02-29", 1, budget).is_ok());
+        tracker.record_tokens_on("2024-02-29", &tokens(40, 59));
+        assert!(tracker.charge_on("2024-02-29", 1, budget).is_ok());
+        tracker.record_tokens_on("2024-02-29", &tokens(0, 1));
+        assert_eq!(
+            tracker.charge_on("2024-02-29", 1, budget),
+            Err(QuotaExceeded {
+                quota: Quota::Tokens,
+                limit: 100
+            })
+        );
+    }
+
+    #[test]
+    fn test_open_restores_persisted_usage() {
+        let dir = tempdir().unwrap();
+        let journal = dir.path().join("usage.json");
+        let (tracker, warning) = UsageTracker::open(journal.clone()).unwrap();
+        assert_eq!(warning, None);
+        tracker.charge(2, Budget::default()).unwrap();
+        tracker.record_tokens(&tokens(10, 5));
+
+        let (reopened, _) = UsageTracker::open(journal).unwrap();
+        assert_eq!(
+            reopened.today(),
+            DailyUsage {
+                requests: 2,
+                prompt_tokens: 10,
+                completion_tokens: 5,
+            }
+        );
+    }
+
+    #[test]
+    fn test_persist_keeps_recent_days() {
+        let tracker = UsageTracker::default();
+        for day in 0..RETAINED_DAYS as u64 + 2 {
+            let date = utc_date(UNIX_EPOCH + Duration::from_secs(day * SECONDS_PER_DAY));
+            tracker.charge_on(&date, 1, Budget::default()).unwrap();
+        }
+
+        let days = tracker.days();
+        assert_eq!(days.len(), RETAINED_DAYS);
+        assert!(!days.contains_key("1970-01-02"));
+        assert!(days.contains_key("1970-01-03"));
+    }
+}
@@ -0,0 +1,697 @@
+use crate::{
+    ContentInput, FieldError, OutputFormat, RequestPayload, StreamMode, locale::LocaleFormat,
+    secrets,
+};
+
+pub const MAX_CONTENT_BYTES: usize = 8 * 1024 * 1024;
+const MAX_DOCUMENTS: usize = 64;
+const MAX_PROMPT_BYTES: usize = 64 * 1024;
+const MAX_BATCH_ITEMS: usize = 100;
+const MAX_QUICK_LOOK_SECONDS: u64 = 300;
+const MAX_STREAM_FLUSH_MS: u64 = 10_000;
+const MAX_STREAM_CHUNK_BYTES: usize = 1024 * 1024;
+
+pub fn validate(payload: &RequestPayload) -> Result<(), Vec<FieldError>> {
+    validate_with_content_limit(payload, MAX_CONTENT_BYTES)
+}
+
+/// Like [`validate`], but holds `ProcessContent.content` to `content_limit`,
+/// which is larger for content reassembled from chunks.
+pub fn validate_with_content_limit(
+    payload: &RequestPayload,
+    content_limit: usize,
+) -> Result<(), Vec<FieldError>> {
+    let mut errors = Vec::new();
+
+    match payload {
+        RequestPayload::ProcessContent {
+            content,
+            model,
+            pattern,
+            context,
+            custom_prompt,
+            system_prompt,
+            locale,
+            summary_model,
+            detach,
+            output_format,
+            quick_look,
+            selection,
+            composition,
+            stream_mode,
+            stream_flush_ms,
+            stream_chunk_bytes,
+            structured,
+            timeout_ms,
+            heartbeat_ms,
+            cwd,
+            env,
+            ..
+        } => {
+            check_content(&mut errors, "content", content, content_limit);
+            check_not_empty(&mut errors, "model", model.as_deref());
+            check_not_empty(&mut errors, "pattern", pattern.as_deref());
+            check_not_empty(&mut errors, "context", context.as_deref());
+            check_not_empty(&mut errors, "systemPrompt", system_prompt.as_deref());
+            check_not_empty(&mut errors, "summaryModel", summary_model.as_deref());
+            check_flag_value(&mut errors, "model", model.as_deref());
+            check_flag_value(&mut errors, "pattern", pattern.as_deref());
+            check_flag_value(&mut errors, "context", context.as_deref());
+            check_flag_value(&mut errors, "summaryModel", summary_model.as_deref());
+            check_not_empty(&mut errors, "selection", selection.as_deref());
+            check_max_len(
+                &mut errors,
+                "selection",
+                selection.as_deref(),
+                MAX_CONTENT_BYTES,
+            );
+            check_max_len(
+                &mut errors,
+                "custom_prompt",
+                custom_prompt.as_deref(),
+                MAX_PROMPT_BYTES,
+            );
+            check_max_len(
+                &mut errors,
+                "systemPrompt",
+                system_prompt.as_deref(),
+                MAX_PROMPT_BYTES,
+            );
+            check_no_nul(&mut errors, "custom_prompt", custom_prompt.as_deref());
+            check_no_nul(&mut errors, "systemPrompt", system_prompt.as_deref());
+
+            if pattern.is_some() && custom_prompt.is_some() {
+                let message = "pattern and custom_prompt cannot be used together";
+                errors.push(field_error("pattern", message));
+                errors.push(field_error("custom_prompt", message));
+            }
+
+            if let Some(composition) = composition {
+                if composition.max_tokens == Some(0) {
+                    errors.push(field_error("composition.maxTokens", "must be at least 1"));
+                }
+                if composition
+                    .selection_ratio
+                    .is_some_and(|ratio| !(0.0..=1.0).contains(&ratio))
+                {
+                    errors.push(field_error(
+                        "composition.selectionRatio",
+                        "must be between 0 and 1",
+                    ));
+                }
+            }
+
+            if let Some(quick_look) = quick_look {
+                if !(1..=MAX_QUICK_LOOK_SECONDS).contains(&quick_look.max_seconds) {
+                    errors.push(field_error(
+                        "quickLook.maxSeconds",
+                        &format!("must be between 1 and {MAX_QUICK_LOOK_SECONDS}"),
+                    ));
+                }
+                if *detach {
+                    errors.push(field_error(
+                        "quickLook",
+                        "cannot be used with detached jobs",
+                    ));
+                }
+            }
+
+            if *stream_mode == StreamMode::Chunks
+                && (*output_format != OutputFormat::Markdown || locale.is_some())
+            {
+                errors.push(field_error(
+                    "streamMode",
+                    "chunks require markdown output and no locale normalization",
+                ));
+            }
+
+            if *structured && (*output_format != OutputFormat::Json || *detach) {
+                errors.push(field_error(
+                    "structured",
+                    "requires json output and cannot be used with detached jobs",
+                ));
+            }
+
+            if stream_flush_ms.is_some_and(|millis| millis > MAX_STREAM_FLUSH_MS) {
+                errors.push(field_error(
+                    "streamFlushMs",
+                    &format!("must be at most {MAX_STREAM_FLUSH_MS}"),
+                ));
+            }
+            if stream_chunk_bytes
+                .is_some_and(|bytes| !(1..=MAX_STREAM_CHUNK_BYTES).contains(&bytes))
+            {
+                errors.push(field_error(
+                    "streamChunkBytes",
+                    &format!("must be between 1 and {MAX_STREAM_CHUNK_BYTES}"),
+                ));
+            }
+
+            if *timeout_ms == Some(0) {
+                errors.push(field_error("timeoutMs", "must be at least 1"));
+            }
+
+            if *heartbeat_ms == Some(0) {
+                errors.push(field_error("heartbeatMs", "must be at least 1"));
+            }
+
+            if cwd.as_ref().is_some_and(|cwd| !cwd.is_absolute()) {
+                errors.push(field_error("cwd", "must be an absolute path"));
+            }
+
+            for (name, value) in env.iter().flatten() {
+                if name.is_empty() || name.contains(['=', '\0']) {
+                    errors.push(field_error(
+                        &format!("env.{name}"),
+                        "must be a valid variable name",
+                    ));
+                } else if value.contains('\0') {
+                    errors.push(field_error(
+                        &format!("env.{name}"),
+                        "must not contain NUL bytes",
+                    ));
+                }
+            }
+
+            if let Some(locale) = locale
+                && LocaleFormat::from_tag(locale).is_none()
+            {
+                errors.push(field_error(
+                    "locale",
+                    &format!("unsupported locale '{locale}'"),
+                ));
+            }
+        }
+        RequestPayload::ProcessBatch { items } => {
+            if items.is_empty() {
+                errors.push(field_error("items", "must contain at least one item"));
+            }
+            if items.len() > MAX_BATCH_ITEMS {
+                errors.push(field_error(
+                    "items",
+                    &format!("must contain at most {MAX_BATCH_ITEMS} items"),
+                ));
+            }
+
+            for (index, item) in items.iter().enumerate() {
+                if item.content.trim().is_empty() {
+                    errors.push(field_error(
+                        &format!("items[{index}].content"),
+                        "must not be empty",
+                    ));
+                }
+                check_max_len(
+                    &mut errors,
+                    &format!("items[{index}].content"),
+                    Some(&item.content),
+                    MAX_CONTENT_BYTES,
+                );
+                check_not_empty(
+                    &mut errors,
+                    &format!("items[{index}].pattern"),
+                    item.pattern.as_deref(),
+                );
+                check_not_empty(
+                    &mut errors,
+                    &format!("items[{index}].model"),
+                    item.model.as_deref(),
+                );
+                check_flag_value(
+                    &mut errors,
+                    &format!("items[{index}].pattern"),
+                    item.pattern.as_deref(),
+                );
+                check_flag_value(
+                    &mut errors,
+                    &format!("items[{index}].model"),
+                    item.model.as_deref(),
+                );
+            }
+        }
+        RequestPayload::SetConfig { config } => {
+            check_not_empty(
+                &mut errors,
+                "config.defaultModel",
+                config.default_model.as_ref().and_then(Option::as_deref),
+            );
+            check_not_empty(
+                &mut errors,
+                "config.patternsDir",
+                config
+                    .patterns_dir
+                    .as_ref()
+                    .and_then(Option::as_ref)
+                    .map(|dir| dir.as_str()),
+            );
+            if config.max_concurrent_processes == Some(Some(0)) {
+                errors.push(field_error(
+                    "config.maxConcurrentProcesses",
+                    "must be at least 1",
+                ));
+            }
+        }
+        RequestPayload::PinPattern { pattern } | RequestPayload::UnpinPattern { pattern } => {
+            if pattern.trim().is_empty() {
+                errors.push(field_error("pattern", "must not be empty"));
+            }
+        }
+        RequestPayload::SetSecret { name, secret } => {
+            check_secret_name(&mut errors, name);
+            if secret.is_empty() {
+                errors.push(field_error("secret", "must not be empty"));
+            }
+        }
+        RequestPayload::DeleteSecret { name } => check_secret_name(&mut errors, name),
+        RequestPayload::Hello {
+            locale: Some(locale),
+            ..
+        } if LocaleFormat::from_tag(locale).is_none() => {
+            errors.push(field_error(
+                "locale",
+                &format!("unsupported locale '{locale}'"),
+            ));
+        }
+        RequestPayload::Hello { .. }
+        | RequestPayload::Ping
+        | RequestPayload::Heartbeat
+        | RequestPayload::Shutdown
+        | RequestPayload::ReloadConfig
+        | RequestPayload::GetConfig
+        | RequestPayload::ListPatterns
+        | RequestPayload::ListContexts
+        | RequestPayload::ListSecretNames
+        | RequestPayload::GetMetrics
+        | RequestPayload::DebugDump
+        | RequestPayload::GetStoreInfo
+        | RequestPayload::PinFabricChecksum
+        | RequestPayload::GetJobResult { .. }
+        | RequestPayload::ReplayRequest { .. }
+        | RequestPayload::ContentChunk { .. }
+        | RequestPayload::ListJobs { .. }
+        | RequestPayload::CancelJob { .. }
+        | RequestPayload::DeleteJob { .. }
+        | RequestPayload::CancelProcess { .. }
+        | RequestPayload::OpenInEditor { .. } => {}
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+fn check_secret_name(errors: &mut Vec<FieldError>, name: &str) {
+    if !secrets::valid_name(name) {
+        errors.push(field_error(
+            "name",
+            "must start with a letter or digit and contain only letters, digits, '_', '-', or '.'",
+        ));
+    }
+}
+
+fn check_content(errors: &mut Vec<FieldError>, field: &str, content: &ContentInput, limit: usize) {
+    match content {
+        ContentInput::Text(text) => {
+            if text.trim().is_empty() {
+                errors.push(field_error(field, "must not be empty"));
+            }
+            check_max_len(errors, field, Some(text), limit);
+        }
+        ContentInput::Documents(documents) => {
+            if documents.is_empty() {
+                errors.push(field_error(field, "must contain at least one document"));
+            }
+            if documents.len() > MAX_DOCUMENTS {
+                errors.push(field_error(
+                    field,
+                    &format!("must contain at most {MAX_DOCUMENTS} documents"),
+                ));
+            }
+            if content.byte_len() > limit {
+                errors.push(too_long(field, limit));
+            }
+            for (index, document) in documents.iter().enumerate() {
+                if document.text.trim().is_empty() {
+                    errors.push(field_error(
+                        &format!("{field}[{index}].text"),
+                        "must not be empty",
+                    ));
+                }
+            }
+        }
+    }
+}
+
+fn check_not_empty(errors: &mut Vec<FieldError>, field: &str, value: Option<&str>) {
+    if value.is_some_and(|value| value.trim().is_empty()) {
+        errors.push(field_error(field, "must not be empty when provided"));
+    }
+}
+
+// Values passed as the argument of a fabric flag; a leading dash could make
+// fabric read them as another flag.
+fn check_flag_value(errors: &mut Vec<FieldError>, field: &str, value: Option<&str>) {
+    if value.is_some_and(|value| value.starts_with('-')) {
+        errors.push(field_error(field, "must not start with '-'"));
+    }
+}
+
+fn check_no_nul(errors: &mut Vec<FieldError>, field: &str, value: Option<&str>) {
+    if value.is_some_and(|value| value.contains('\0')) {
+        errors.push(field_error(field, "must not contain NUL bytes"));
+    }
+}
+
+fn check_max_len(errors: &mut Vec<FieldError>, field: &str, value: Option<&str>, max: usize) {
+    if value.is_some_and(|value| value.len() > max) {
+        errors.push(too_long(field, max));
+    }
+}
+
+fn too_long(field: &str, max: usize) -> FieldError {
+    field_error(field, &format!("must be at most {max} bytes"))
+}
+
+fn field_error(field: &str, message: &str) -> FieldError {
+    FieldError {
+        field: field.to_string(),
+        message: message.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::{BatchContent, Composition, Document, QuickLook};
+
+    fn process_content(content: ContentInput) -> RequestPayload {
+        RequestPayload::ProcessContent {
+            content,
+            model: None,
+            pattern: None,
+            context: None,
+            custom_prompt: None,
+            system_prompt: None,
+            locale: None,
+            detach: false,
+            aggregate: false,
+            summarize: false,
+            summary_model: None,
+            output_format: OutputFormat::Markdown,
+            deterministic: false,
+            quick_look: None,
+            selection: None,
+            composition: None,
+            stream_mode: StreamMode::Lines,
+            stream_flush_ms: None,
+            stream_chunk_bytes: None,
+            strict: None,
+            structured: false,
+            timeout_ms: None,
+            heartbeat_ms: None,
+            cwd: None,
+            env: None,
+        }
+    }
+
+    fn fields(result: Result<(), Vec<FieldError>>) -> Vec<String> {
+        result
+            .unwrap_err()
+            .into_iter()
+            .map(|error| error.field)
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_process_content() {
+        assert!(validate(&process_content("hello".to_string().into())).is_ok());
+        assert!(validate(&RequestPayload::Ping).is_ok());
+    }
+
+    #[test]
+    fn test_rejects_empty_content() {
+        assert_eq!(
+            fields(validate(&process_content("  \n".to_string().into()))),
+            vec!["content"]
+        );
+        assert_eq!(
+            fields(validate(&process_content(ContentInput::Documents(vec![
+                Document {
+                    title: "First".to_string(),
+                    text: "text".to_string(),
+                },
+                Document {
+                    title: "Second".to_string(),
+                    text: String::new(),
+                },
+            ])))),
+            vec!["content[1].text"]
+        );
+    }
+
+    #[test]
+    fn test_rejects_pattern_with_custom_prompt() {
+        let payload = RequestPayload::ProcessContent {
+            content: "hello".to_string().into(),
+            model: Some(String::new()),
+            pattern: Some("summarize".to_string()),
+            context: None,
+            custom_prompt: Some("Summarize this".to_string()),
+            system_prompt: None,
+            locale: Some("xx-YY".to_string()),
+            detach: false,
+            aggregate: false,
+            summarize: false,
+            summary_model: None,
+            output_format: OutputFormat::Markdown,
+            deterministic: false,
+            quick_look: None,
+            selection: None,
+            composition: None,
+            stream_mode: StreamMode::Lines,
+            stream_flush_ms: None,
+            stream_chunk_bytes: None,
+            strict: None,
+            structured: false,
+            timeout_ms: None,
+            heartbeat_ms: None,
+            cwd: None,
+            env: None,
+        };
+
+        assert_eq!(
+            fields(validate(&payload)),
+            vec!["model", "pattern", "custom_prompt", "locale"]
+        );
+    }
+
+    #[test]
+    fn test_rejects_unsupported_hello_locale() {
+        let hello = |locale: &str| RequestPayload::Hello {
+            max_message_size: None,
+            compression: None,
+            locale: Some(locale.to_string()),
+        };
+
+        assert_eq!(fields(validate(&hello("xx-YY"))), vec!["locale"]);
+        assert!(validate(&hello("de-DE")).is_ok());
+    }
+
+    #[test]
+    fn test_rejects_invalid_process_options() {
+        let payload = RequestPayload::ProcessContent {
+            content: "hello".to_string().into(),
+            model: None,
+            pattern: None,
+            context: None,
+            custom_prompt: None,
+            system_prompt: None,
+            locale: None,
+            detach: true,
+            aggregate: false,
+            summarize: false,
+            summary_model: None,
+            output_format: OutputFormat::Plaintext,
+            deterministic: false,
+            quick_look: Some(QuickLook { max_seconds: 0 }),
+            selection: Some(" ".to_string()),
+            composition: Some(Composition {
+                max_tokens: Some(0),
+                selection_ratio: Some(1.5),
+            }),
+            stream_mode: StreamMode::Chunks,
+            stream_flush_ms: Some(MAX_STREAM_FLUSH_MS + 1),
+            stream_chunk_bytes: Some(0),
+            strict: None,
+            structured: true,
+            timeout_ms: Some(0),
+            heartbeat_ms: None,
+            cwd: Some("projects/site".into()),
+            env: Some(BTreeMap::from([
+                ("OLLAMA_HOST".to_string(), "http://gpu:11434".to_string()),
+                ("BAD=NAME".to_string(), String::new()),
+            ])),
+        };
+
+        assert_eq!(
+            fields(validate(&payload)),
+            vec![
+                "selection",
+                "composition.maxTokens",
+                "composition.selectionRatio",
+                "quickLook.maxSeconds",
+                "quickLook",
+                "streamMode",
+                "structured",
+                "streamFlushMs",
+                "streamChunkBytes",
+                "timeoutMs",
+                "cwd",
+                "env.BAD=NAME"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rejects_invalid_batch_items() {
+        assert_eq!(
+            fields(validate(&RequestPayload::ProcessBatch { items: vec![] })),
+            vec!["items"]
+        );
+
+        let payload = RequestPayload::ProcessBatch {
+            items: vec![
+                BatchContent {
+                    content: "tab".to_string(),
+                    pattern: Some("summarize".to_string()),
+                    model: None,
+                },
+                BatchContent {
+                    content: String::new(),
+                    pattern: Some(" ".to_string()),
+                    model: None,
+                },
+            ],
+        };
+        assert_eq!(
+            fields(validate(&payload)),
+            vec!["items[1].content", "items[1].pattern"]
+        );
+    }
+
+    #[test]
+    fn test_rejects_oversized_fields() {
+        let payload = RequestPayload::ProcessContent {
+            content: "x".repeat(MAX_CONTENT_BYTES + 1).into(),
+            model: None,
+            pattern: None,
+            context: None,
+            custom_prompt: Some("p".repeat(MAX_PROMPT_BYTES + 1)),
+            system_prompt: None,
+            locale: None,
+            detach: false,
+            aggregate: false,
+            summarize: false,
+            summary_model: None,
+            output_format: OutputFormat::Markdown,
+            deterministic: false,
+            quick_look: None,
+            selection: None,
+            composition: None,
+            stream_mode: StreamMode::Lines,
+            stream_flush_ms: None,
+            stream_chunk_bytes: None,
+            strict: None,
+            structured: false,
+            timeout_ms: None,
+            heartbeat_ms: None,
+            cwd: None,
+            env: None,
+        };
+        assert_eq!(fields(validate(&payload)), vec!["content", "custom_prompt"]);
+
+        let documents = (0..=MAX_DOCUMENTS)
+            .map(|index| Document {
+                title: format!("Tab {index}"),
+                text: "text".to_string(),
+            })
+            .collect();
+        assert_eq!(
+            fields(validate(&process_content(ContentInput::Documents(
+                documents
+            )))),
+            vec!["content"]
+        );
+
+        let items = (0..=MAX_BATCH_ITEMS)
+            .map(|_| BatchContent {
+                content: "tab".to_string(),
+                pattern: None,
+                model: None,
+            })
+            .collect();
+        assert_eq!(
+            fields(validate(&RequestPayload::ProcessBatch { items })),
+            vec!["items"]
+        );
+    }
+
+    #[test]
+    fn test_validate_secret_requests() {
+        assert!(
+            validate(&RequestPayload::SetSecret {
+                name: "openai".to_string(),
+                secret: "sk-123".to_string(),
+            })
+            .is_ok()
+        );
+        assert_eq!(
+            fields(validate(&RequestPayload::SetSecret {
+                name: "keychain:openai".to_string(),
+                secret: String::new(),
+            })),
+            vec!["name", "secret"]
+        );
+        assert_eq!(
+            fields(validate(&RequestPayload::DeleteSecret {
+                name: String::new(),
+            })),
+            vec!["name"]
+        );
+    }
+
+    #[test]
+    fn test_rejects_flag_like_arguments() {
+        let mut payload = process_content(ContentInput::Text("text".to_string()));
+        if let RequestPayload::ProcessContent {
+            model,
+            context,
+            custom_prompt,
+            system_prompt,
+            ..
+        } = &mut payload
+        {
+            *model = Some("--pattern".to_string());
+            *context = Some("-x".to_string());
+            *custom_prompt = Some("--help\0".to_string());
+            *system_prompt = Some("--stream is fine here".to_string());
+        }
+        assert_eq!(
+            fields(validate(&payload)),
+            vec!["model", "context", "custom_prompt"]
+        );
+
+        let items = vec![BatchContent {
+            content: "tab".to_string(),
+            pattern: Some("-summarize".to_string()),
+            model: None,
+        }];
+        assert_eq!(
+            fields(validate(&RequestPayload::ProcessBatch { items })),
+            vec!["items[0].pattern"]
+        );
+    }
+}
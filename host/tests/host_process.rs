@@ -0,0 +1,249 @@
+#![cfg(unix)]
+
+use std::{fs, os::unix::fs::PermissionsExt, process::Stdio, time::Duration};
+
+use camino_tempfile::{Utf8TempDir, tempdir};
+use serde_json::{Value, json};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    process::{Child, ChildStdin, ChildStdout, Command},
+    time::timeout,
+};
+use uuid::Uuid;
+
+// Streams one line, then holds the process open so requests overlap.
+const SLOW_FABRIC: &str = r#"#!/bin/sh
+case "$1" in
+    --version) echo v1.4.300; exit 0 ;;
+    --listpatterns) echo summarize; exit 0 ;;
+esac
+cat > /dev/null
+echo first
+sleep 2
+echo second
+"#;
+
+/// The host binary, run over its stdio the way a browser runs it, with a
+/// scripted fabric and a throwaway home directory.
+struct HostProcess {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: ChildStdout,
+    _home: Utf8TempDir,
+}
+
+impl HostProcess {
+    fn spawn(fabric_script: &str, env: &[(&str, &str)]) -> Self {
+        let home = tempdir().unwrap();
+        let fabric = home.path().join("fabric");
+        fs::write(&fabric, fabric_script).unwrap();
+        fs::set_permissions(&fabric, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::create_dir_all(home.path().join(".config").join("fabric")).unwrap();
+        let config = home.path().join("config.toml");
+        fs::write(&config, format!("fabric_path = {:?}\n", fabric.as_str())).unwrap();
+
+        let mut child = Command::new(env!("CARGO_BIN_EXE_tapestry-host"))
+            .env_clear()
+            .env("PATH", "/usr/bin:/bin")
+            .env("HOME", home.path())
+            .env("TAPESTRY_CONFIG", &config)
+            .env("TAPESTRY_LOG_FILE", home.path().join("host.log"))
+            .envs(env.iter().copied())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .unwrap();
+        Self {
+            stdin: child.stdin.take().unwrap(),
+            stdout: child.stdout.take().unwrap(),
+            child,
+            _home: home,
+        }
+    }
+
+    async fn send(&mut self, request: Value) {
+        let frame = serde_json::to_vec(&request).unwrap();
+        let length = u32::try_from(frame.len()).unwrap();
+        self.stdin.write_all(&length.to_ne_bytes()).await.unwrap();
+        self.stdin.write_all(&frame).await.unwrap();
+        self.stdin.flush().await.unwrap();
+    }
+
+    async fn recv(&mut self) -> Value {
+        timeout(Duration::from_secs(10), async {
+            let mut length = [0; 4];
+            self.stdout.read_exact(&mut length).await.unwrap();
+            let mut frame = vec![0; u32::from_ne_bytes(length) as usize];
+            self.stdout.read_exact(&mut frame).await.unwrap();
+            serde_json::from_slice(&frame).unwrap()
+        })
+        .await
+        .expect("timed out waiting for a frame from the host")
+    }
+
+    /// Reads frames up to and including the first one matching `done`.
+    async fn recv_until(&mut self, done: impl Fn(&Value) -> bool) -> Vec<Value> {
+        let mut frames = Vec::new();
+        loop {
+            let frame = self.recv().await;
+            let finished = done(&frame);
+            frames.push(frame);
+            if finished {
+                return frames;
+            }
+        }
+    }
+}
+
+fn process_content(id: Uuid) -> Value {
+    json!({"id": id, "type": "native.processContent", "content": "hello"})
+}
+
+fn is_frame(frame: &Value, id: Uuid, frame_type: &str) -> bool {
+    frame["id"] == json!(id) && frame["type"] == frame_type
+}
+
+fn position(frames: &[Value], id: Uuid, frame_type: &str) -> Option<usize> {
+    frames
+        .iter()
+        .position(|frame| is_frame(frame, id, frame_type))
+}
+
+#[tokio::test]
+async fn test_host_queues_concurrent_requests_beyond_process_limit() {
+    let mut host = HostProcess::spawn(SLOW_FABRIC, &[("TAPESTRY_MAX_CONCURRENT_PROCESSES", "1")]);
+    let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+
+    host.send(process_content(first)).await;
+    let mut frames = host
+        .recv_until(|frame| is_frame(frame, first, "native.streamStart"))
+        .await;
+    host.send(process_content(second)).await;
+    frames.extend(
+        host.recv_until(|frame| is_frame(frame, first, "native.done"))
+            .await,
+    );
+    let queued = position(&frames, second, "native.queued");
+    assert!(
+        queued.is_some(),
+        "second request was not queued: {frames:?}"
+    );
+    assert_eq!(frames[queued.unwrap()]["limit"], 1);
+    assert_eq!(position(&frames, second, "native.streamStart"), None);
+
+    frames.extend(
+        host.recv_until(|frame| is_frame(frame, second, "native.done"))
+            .await,
+    );
+    assert!(position(&frames, second, "native.streamStart").is_some());
+    drop(host.stdin);
+    assert!(host.child.wait().await.unwrap().success());
+}
+
+#[tokio::test]
+async fn test_host_answers_ping_and_heartbeat_during_active_stream() {
+    let mut host = HostProcess::spawn(SLOW_FABRIC, &[("TAPESTRY_HEARTBEAT_INTERVAL_MS", "500")]);
+    let (stream, ping, heartbeat) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+
+    host.send(process_content(stream)).await;
+    let mut frames = host
+        .recv_until(|frame| is_frame(frame, stream, "native.content"))
+        .await;
+    host.send(json!({"id": ping, "type": "native.ping"})).await;
+    host.send(json!({"id": heartbeat, "type": "native.heartbeat"}))
+        .await;
+    frames.extend(
+        host.recv_until(|frame| is_frame(frame, stream, "native.done"))
+            .await,
+    );
+
+    let done = position(&frames, stream, "native.done").unwrap();
+    let pong = position(&frames, ping, "native.pong").expect("no pong");
+    let ack = position(&frames, heartbeat, "native.heartbeatAck").expect("no heartbeat ack");
+    assert!(pong < done, "pong waited for the stream: {frames:?}");
+    assert!(ack < done, "heartbeat waited for the stream: {frames:?}");
+    let host_heartbeat = frames
+        .iter()
+        .position(|frame| frame["type"] == "native.hostHeartbeat");
+    assert!(host_heartbeat.is_some_and(|index| index < done));
+}
+
+#[tokio::test]
+async fn test_host_pong_reports_live_queue_stats() {
+    let mut host = HostProcess::spawn(SLOW_FABRIC, &[("TAPESTRY_MAX_CONCURRENT_PROCESSES", "1")]);
+    let (running, waiting, ping) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+
+    host.send(process_content(running)).await;
+    host.recv_until(|frame| is_frame(frame, running, "native.streamStart"))
+        .await;
+    host.send(process_content(waiting)).await;
+    host.recv_until(|frame| is_frame(frame, waiting, "native.queued"))
+        .await;
+    host.send(json!({"id": ping, "type": "native.ping"})).await;
+    let frames = host
+        .recv_until(|frame| is_frame(frame, ping, "native.pong"))
+        .await;
+
+    let stats = &frames.last().unwrap()["stats"];
+    assert_eq!(stats["queuedRequests"], 1);
+    assert_eq!(stats["activeRequests"], 3);
+    assert_eq!(position(&frames, running, "native.done"), None);
+}
+
+#[tokio::test]
+async fn test_host_answers_debug_dump_during_active_stream() {
+    let mut host = HostProcess::spawn(SLOW_FABRIC, &[]);
+    let (stream, dump) = (Uuid::new_v4(), Uuid::new_v4());
+
+    host.send(process_content(stream)).await;
+    host.recv_until(|frame| is_frame(frame, stream, "native.streamStart"))
+        .await;
+    host.send(json!({"id": dump, "type": "native.debugDump"}))
+        .await;
+    let frames = host
+        .recv_until(|frame| is_frame(frame, dump, "native.debugState"))
+        .await;
+
+    assert_eq!(position(&frames, stream, "native.done"), None);
+    let state = frames.last().unwrap();
+    assert!(
+        state["activeRequests"]
+            .as_array()
+            .unwrap()
+            .contains(&json!(stream))
+    );
+    assert_eq!(state["runningProcesses"], json!([stream]));
+    assert!(state["fabricPath"].is_string());
+}
+
+#[tokio::test]
+async fn test_host_shutdown_cancels_requests_queued_behind_a_stream() {
+    let mut host = HostProcess::spawn(SLOW_FABRIC, &[("TAPESTRY_MAX_CONCURRENT_PROCESSES", "1")]);
+    let (running, waiting, shutdown) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+
+    host.send(process_content(running)).await;
+    host.recv_until(|frame| is_frame(frame, running, "native.streamStart"))
+        .await;
+    host.send(process_content(waiting)).await;
+    host.recv_until(|frame| is_frame(frame, waiting, "native.queued"))
+        .await;
+    host.send(json!({"id": shutdown, "type": "native.shutdown"}))
+        .await;
+    let frames = host
+        .recv_until(|frame| is_frame(frame, shutdown, "native.shutdownAck"))
+        .await;
+
+    for id in [running, waiting] {
+        let cancelled = frames
+            .iter()
+            .filter(|frame| is_frame(frame, id, "native.cancelled"))
+            .count();
+        assert_eq!(
+            cancelled, 1,
+            "expected one cancellation for {id}: {frames:?}"
+        );
+    }
+    assert!(host.child.wait().await.unwrap().success());
+}
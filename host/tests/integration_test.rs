@@ -1,5 +1,5 @@
 use std::{
-    collections::HashMap,
+    collections::BTreeMap,
     pin::Pin,
     sync::Arc,
     task::{Context, Poll},
@@ -10,11 +10,12 @@ use camino::Utf8PathBuf;
 use tapestry_host::{
     Request, RequestPayload, Response, ResponsePayload,
     handlers::{
-        FabricCommandRunner, ProcessRegistry, handle_list_patterns, handle_ping,
-        handle_process_content, handle_request, resolve_path,
+        DEFAULT_COMMAND_TIMEOUT, FabricCommandRunner, ProcessOptions, handle_list_patterns,
+        handle_ping, handle_process_content, handle_request, resolve_path,
     },
+    state::HostState,
 };
-use tokio::{io::AsyncWrite, sync::Mutex};
+use tokio::io::AsyncWrite;
 use tokio_util::codec::{Encoder, FramedWrite};
 use uuid::Uuid;
 
@@ -90,7 +91,14 @@ async fn test_real_command_runner_ping() {
     let mut writer = FramedWrite::new(test_writer, encoder);
 
     let request_id = Uuid::new_v4();
-    let result = handle_ping(&mut writer, request_id, &runner).await;
+    let result = handle_ping(
+        &mut writer,
+        request_id,
+        &runner,
+        None,
+        DEFAULT_COMMAND_TIMEOUT,
+    )
+    .await;
     assert!(result.is_ok());
 
     let messages = messages.lock().unwrap();
@@ -100,6 +108,7 @@ async fn test_real_command_runner_ping() {
         valid,
         resolved_path,
         version,
+        ..
     } = &messages[0].payload
     {
         assert!(valid);
@@ -127,21 +136,29 @@ async fn test_real_command_runner_list_patterns() {
     let mut writer = FramedWrite::new(test_writer, encoder);
 
     let request_id = Uuid::new_v4();
-    let result = handle_list_patterns(&mut writer, request_id, &runner).await;
+    let result = handle_list_patterns(
+        &mut writer,
+        request_id,
+        &runner,
+        DEFAULT_COMMAND_TIMEOUT,
+        &BTreeMap::new(),
+        &[],
+    )
+    .await;
     assert!(result.is_ok());
 
     let messages = messages.lock().unwrap();
     assert_eq!(messages.len(), 1);
 
     match &messages[0].payload {
-        ResponsePayload::PatternsList { patterns } => {
+        ResponsePayload::PatternsList { patterns, .. } => {
             assert!(
                 !patterns.is_empty(),
                 "Expected at least one pattern from fabric-ai"
             );
             eprintln!("Found {} patterns", patterns.len());
         }
-        ResponsePayload::Error { message } => {
+        ResponsePayload::Error { message, .. } => {
             eprintln!("fabric-ai error: {message}");
         }
         _ => panic!("Expected PatternsList or Error response"),
@@ -166,17 +183,17 @@ async fn test_real_command_runner_process_content() {
     let request_id = Uuid::new_v4();
     let content = "This is a test message to summarize.".to_string();
 
-    let process_registry = ProcessRegistry::default();
+    let state = HostState::new();
     let result = handle_process_content(
         &mut writer,
         request_id,
         &runner,
-        None,
-        None,
-        None,
-        Some("Say 'Hello World' and nothing else".to_string()),
+        ProcessOptions {
+            custom_prompt: Some("Say 'Hello World' and nothing else".to_string()),
+            ..Default::default()
+        },
         content,
-        process_registry,
+        &state,
     )
     .await;
 
@@ -211,7 +228,14 @@ async fn test_real_command_runner_invalid_fabric_path() {
 
         let request_id = Uuid::new_v4();
 
-        let result = handle_ping(&mut writer, request_id, &runner).await;
+        let result = handle_ping(
+            &mut writer,
+            request_id,
+            &runner,
+            None,
+            DEFAULT_COMMAND_TIMEOUT,
+        )
+        .await;
         assert!(result.is_ok());
 
         let messages = messages.lock().unwrap();
@@ -266,7 +290,14 @@ async fn test_real_command_runner_with_valid_path() {
 
     let request_id = Uuid::new_v4();
 
-    let result = handle_ping(&mut writer, request_id, &runner).await;
+    let result = handle_ping(
+        &mut writer,
+        request_id,
+        &runner,
+        None,
+        DEFAULT_COMMAND_TIMEOUT,
+    )
+    .await;
     assert!(result.is_ok());
 
     let messages = messages.lock().unwrap();
@@ -276,6 +307,7 @@ async fn test_real_command_runner_with_valid_path() {
         valid,
         resolved_path,
         version,
+        ..
     } = &messages[0].payload
     {
         assert!(valid);
@@ -301,15 +333,16 @@ async fn test_handle_request_with_real_runner() {
     let request = Request {
         id: Uuid::new_v4(),
         path: None,
+        validate_only: false,
         payload: RequestPayload::Ping,
     };
 
-    let process_registry: ProcessRegistry = Arc::new(Mutex::new(HashMap::new()));
+    let state = HostState::new();
     let result = handle_request(
         &mut writer,
         request,
         |path| FabricCommandRunner::new(path),
-        process_registry,
+        &state,
     )
     .await;
     assert!(result.is_ok());
@@ -335,11 +368,19 @@ async fn test_process_content_with_pattern() {
     let mut writer = FramedWrite::new(test_writer, encoder);
 
     let request_id = Uuid::new_v4();
-    let _ = handle_list_patterns(&mut writer, request_id, &runner).await;
+    let _ = handle_list_patterns(
+        &mut writer,
+        request_id,
+        &runner,
+        DEFAULT_COMMAND_TIMEOUT,
+        &BTreeMap::new(),
+        &[],
+    )
+    .await;
 
     let available_patterns = {
         let messages = messages.lock().unwrap();
-        if let ResponsePayload::PatternsList { patterns } = &messages[0].payload {
+        if let ResponsePayload::PatternsList { patterns, .. } = &messages[0].payload {
             patterns.clone()
         } else {
             vec![]
@@ -362,17 +403,17 @@ async fn test_process_content_with_pattern() {
     let request_id = Uuid::new_v4();
     let content = "This is test content for fabric processing.".to_string();
 
-    let process_registry: ProcessRegistry = Arc::new(Mutex::new(HashMap::new()));
+    let state = HostState::new();
     let result = handle_process_content(
         &mut writer2,
         request_id,
         &runner,
-        None,
-        Some(pattern),
-        None,
-        None,
+        ProcessOptions {
+            pattern: Some(pattern),
+            ..Default::default()
+        },
         content,
-        process_registry,
+        &state,
     )
     .await;
 